@@ -1,14 +1,36 @@
-use build::{build_links, install, migrate, pages::Builder, update};
+use build::{build_links, doctor, import_md, install, migrate, pages::Builder, update};
 use persistance::fs::{
-    config::read_config,
-    create_journal_entry,
-    utils::{get_config_location, get_data_dir_location, normalize_wiki_location},
+    config::{read_config, try_read_config},
+    create_journal_entry, path_to_data_structure,
+    utils::{
+        get_config_location, get_data_dir_location, get_file_path, normalize_wiki_location,
+        parse_location, prepare_media_location, set_config_location,
+    },
+};
+use search_engine::{build_search_index, build_search_index_in};
+use std::{
+    path::{Path, PathBuf},
+    process::exit,
+    sync::Arc,
+    time::Instant,
 };
-use search_engine::build_search_index;
-use std::{path::PathBuf, process::exit, sync::Arc, time::Instant};
 use task_queue::process_tasks;
-use task_runners::{git_update, sync, JobQueue};
-use tokio::{fs, sync::Mutex, task::spawn_blocking};
+use task_runners::{
+    git_update,
+    metrics::{Metric, INDEX_READY},
+    sync, JobQueue,
+};
+use tokio::{
+    runtime::{self, Runtime},
+    task::spawn_blocking,
+};
+use wikitext::{
+    parsers::{
+        set_base_path, set_embed_hosts, set_external_links_in_new_tab, set_heading_anchors, Note,
+    },
+    processors::transclusion::{set_max_transclusion_depth, set_note_loader},
+    GlobalBacklinks,
+};
 use www::server;
 
 #[macro_use]
@@ -16,9 +38,61 @@ extern crate lazy_static;
 
 mod task_queue;
 
-#[tokio::main]
-async fn main() {
-    let args = std::env::args().skip(1).collect::<Vec<String>>();
+fn main() {
+    let mut args = std::env::args().skip(1).collect::<Vec<String>>();
+    // The config location has to be settled before anything reads the config
+    match take_config_arg(&mut args) {
+        Ok(Some(path)) => {
+            let init = args.iter().any(|arg| arg == "-i" || arg == "--init");
+            if let Err(e) = set_config_location(&path, init) {
+                eprintln!("Could not use config at {}: {}", path, e);
+                exit(1);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+    // No config exists yet when running `--init`, so fall back to tokio's defaults
+    let worker_threads = try_read_config()
+        .map(|config| config.general.worker_threads)
+        .unwrap_or_default();
+    build_runtime(worker_threads).block_on(run(args))
+}
+
+/// Takes `-c <path>`, `--config <path>`, or `--config=<path>` out of `args`, wherever it is, so
+/// the other options are handled as usual.
+fn take_config_arg(args: &mut Vec<String>) -> Result<Option<String>, String> {
+    let position = match args
+        .iter()
+        .position(|arg| arg == "-c" || arg == "--config" || arg.starts_with("--config="))
+    {
+        Some(position) => position,
+        None => return Ok(None),
+    };
+    let flag = args.remove(position);
+    if let Some(path) = flag.strip_prefix("--config=") {
+        return Ok(Some(path.to_string()));
+    }
+    if position < args.len() {
+        Ok(Some(args.remove(position)))
+    } else {
+        Err(format!("{} needs the path to a config file", flag))
+    }
+}
+
+/// Builds the multithreaded runtime, using one worker per CPU core when `worker_threads` is 0.
+fn build_runtime(worker_threads: usize) -> Runtime {
+    let mut builder = runtime::Builder::new_multi_thread();
+    if worker_threads > 0 {
+        builder.worker_threads(worker_threads);
+    }
+    builder.enable_all().build().unwrap()
+}
+
+async fn run(args: Vec<String>) {
     let mut build_all = false;
     if !args.is_empty() {
         let arg = args[0].as_str();
@@ -29,6 +103,10 @@ async fn main() {
             "-i" | "--init" => return install(),
             "-u" | "--update" => return update(),
             "-m" | "--migrate" => return migrate(),
+            "-d" | "--doctor" => return doctor(args.iter().any(|a| a == "--repair")),
+            "--import-md" => {
+                return import_markdown(args.get(1), args.iter().any(|a| a == "--dry-run"))
+            }
             _ => {
                 if arg.starts_with('-') {
                     eprintln!("unknown option: {}", arg);
@@ -48,16 +126,39 @@ async fn main() {
     }
     let config = read_config();
     let location = normalize_wiki_location(&config.general.wiki_location);
+    set_base_path(&config.general.base_path);
+    set_external_links_in_new_tab(config.general.external_links_in_new_tab);
+    set_heading_anchors(config.general.heading_anchors);
+    set_embed_hosts(config.general.embed_hosts.clone());
+    set_note_loader(load_note);
+    set_max_transclusion_depth(config.general.max_transclusion_depth);
     if build_all {
         let now = Instant::now();
-        if PathBuf::from("./public").exists() {
-            fs::remove_dir_all("./public").await.unwrap();
-        }
         let builder = Builder::new();
-        builder.sweep(&location).await;
-        builder.compile_all().await;
+        if let Err(e) = builder.build(&location, Path::new("./public")).await {
+            eprintln!(
+                "Could not build static site, ./public was left as it was: {}",
+                e
+            );
+            exit(1);
+        }
         println!("Built static site in: {}ms", now.elapsed().as_millis());
     } else {
+        if let Err(e) = prepare_media_location(&config.general.media_location) {
+            eprintln!(
+                "Could not use {} as the media location for uploads: {}",
+                config.general.media_location, e
+            );
+            exit(1);
+        }
+        if let Err(e) = config.general.tls() {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        if let Err(e) = config.general.socket_address() {
+            eprintln!("{}", e);
+            exit(1);
+        }
         let job_queue = Arc::new(JobQueue::default());
         if config.sync.use_git {
             sync(
@@ -68,22 +169,102 @@ async fn main() {
             )
             .await;
         }
-        let now = Instant::now();
-        // TODO: Don't clone so much...
-        let spec_loc = location.clone();
         let loc = Arc::new(location);
-        let (links, _) = tokio::join!(
-            build_links(loc.clone()),
-            spawn_blocking(move || build_search_index(spec_loc.as_str()))
-        );
-        println!("<indexing took: {:?}>", now.elapsed());
-        let links = Arc::new(Mutex::new(links));
+        let links = GlobalBacklinks::default();
         let queue = job_queue.clone();
-        tokio::spawn(process_tasks(queue, loc.clone(), links.clone()));
+        if config.general.lazy_index {
+            tokio::spawn(index_then_process_tasks(queue, loc.clone(), links.clone()));
+        } else {
+            build_indexes(
+                loc.clone(),
+                get_data_dir_location(),
+                links.clone(),
+                &INDEX_READY,
+            )
+            .await;
+            tokio::spawn(process_tasks(queue, loc.clone(), links.clone()));
+        }
         server(config.general, (links, job_queue.clone())).await
     }
 }
 
+/// Builds the indexes in the background before working through the task queue. Edits made in the
+/// meantime wait in the queue and are applied on top of the finished indexes, rather than the
+/// indexes overwriting them once they're done.
+async fn index_then_process_tasks(
+    queue: Arc<JobQueue>,
+    location: Arc<String>,
+    links: GlobalBacklinks,
+) {
+    build_indexes(
+        location.clone(),
+        get_data_dir_location(),
+        links.clone(),
+        &INDEX_READY,
+    )
+    .await;
+    process_tasks(queue, location, links).await;
+}
+
+/// Builds the links and the search index in `data_dir`, then sets `ready` so `/readyz` reports the
+/// wiki as ready.
+async fn build_indexes(
+    location: Arc<String>,
+    data_dir: PathBuf,
+    links: GlobalBacklinks,
+    ready: &Metric,
+) {
+    let now = Instant::now();
+    let search_location = location.clone();
+    let (rebuilt, _) = tokio::join!(
+        build_links(location),
+        spawn_blocking(move || build_search_index_in(&search_location, &data_dir))
+    );
+    *links.lock().await = rebuilt;
+    println!("<indexing took: {:?}>", now.elapsed());
+    ready.set(1);
+}
+
+/// Reads the note `![[title]]` refers to, so it can be rendered in place.
+fn load_note(title: &str) -> Option<Note> {
+    get_file_path(title)
+        .ok()
+        .and_then(|path| path_to_data_structure(&path).ok())
+}
+
+fn import_markdown(source: Option<&String>, dry_run: bool) {
+    let source = match source {
+        Some(source) if source != "--dry-run" => parse_location(source),
+        _ => {
+            eprintln!("usage: tendril --import-md <dir> [--dry-run]");
+            exit(1);
+        }
+    };
+    let config = read_config();
+    let location = normalize_wiki_location(&config.general.wiki_location);
+    let report = import_md(&source, &PathBuf::from(&location), dry_run);
+    for (path, reason) in report.skipped.iter() {
+        eprintln!("Skipped {:?}: {}", path, reason);
+    }
+    if dry_run {
+        for path in report.imported.iter() {
+            println!("Would create {:?}", path);
+        }
+        println!(
+            "<dry run: would import {} notes, skip {}>",
+            report.imported.len(),
+            report.skipped.len()
+        );
+        return;
+    }
+    println!(
+        "<imported {} notes, skipped {}>",
+        report.imported.len(),
+        report.skipped.len()
+    );
+    build_search_index(&location);
+}
+
 fn print_version() {
     println!("tendril-wiki v{}", env!("CARGO_PKG_VERSION"));
 }
@@ -97,17 +278,21 @@ fn print_help() {
         "Usage: tendril [options]
         Options:
         -i, --init                   Initialize config file and install
+        -c, --config <path>          Use the config file at <path> instead of the default one.
         -b, --build                  Build all pages as HTML and output to ./public
         -v, --version                Print version.
         -h, --help                   Show this message.
         -u, --update                 Update the installation by copying over any new files or updating config.toml.
+        -d, --doctor [--repair]      Check the installation for missing files, restoring them with --repair.
+        --import-md <dir> [--dry-run]
+                                     Import a folder of markdown notes with YAML frontmatter into the wiki.
+                                     With --dry-run, list the notes it would create without writing them.
 
 ",
     );
 }
 
-// TODO: Maybe later add in multi-config, multi-folder stuff
-// -c <path>, --config <path>   Use config at <path>
+// TODO: Maybe later add in multi-folder stuff
 //
 // Examples:
 
@@ -115,5 +300,106 @@ fn print_help() {
 //         $ tendril ~/work/wiki
 //   - Start wiki at location specified in config file
 //         $ tendril
-//   - Start wiki in current folder with a custom config file
-//         $ tendril . -c ./config.toml
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        fs,
+        sync::{Arc, Barrier},
+        thread,
+    };
+
+    use warp::http::StatusCode;
+    use www::handlers::readiness_of;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_ready_once_indexes_are_built() {
+        static READY: Metric = Metric::gauge("test_index_ready", "Whether the indexes are built");
+        let dir = "/tmp/tendril-test/bin-index/";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let note = Note {
+            header: std::collections::HashMap::from([(
+                String::from("title"),
+                String::from("Beans"),
+            )]),
+            content: String::from("Mulch them with [[Compost]]"),
+        };
+        fs::write(format!("{}Beans.txt", dir), String::from(note)).unwrap();
+
+        let readyz = readiness_of(&READY);
+        let response = warp::test::request().path("/readyz").reply(&readyz).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let links = GlobalBacklinks::default();
+        let location = Arc::new(String::from(dir));
+        build_indexes(location, PathBuf::from(dir), links.clone(), &READY).await;
+        let response = warp::test::request().path("/readyz").reply(&readyz).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(links.lock().await.get("Compost").unwrap()[0].title, "Beans");
+        let indexed = fs::read_dir(Path::new(dir).join("search-index/file_index")).unwrap();
+        assert_eq!(indexed.count(), 1);
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn takes_the_config_path_out_of_the_args() {
+        let mut build = args(&["-c", "./my.toml", "-b"]);
+        assert_eq!(
+            take_config_arg(&mut build),
+            Ok(Some(String::from("./my.toml")))
+        );
+        assert_eq!(build, args(&["-b"]));
+
+        let mut build = args(&["--build", "--config", "~/my.toml"]);
+        assert_eq!(
+            take_config_arg(&mut build),
+            Ok(Some(String::from("~/my.toml")))
+        );
+        assert_eq!(build, args(&["--build"]));
+
+        let mut serve = args(&["--config=/etc/tendril.toml"]);
+        assert_eq!(
+            take_config_arg(&mut serve),
+            Ok(Some(String::from("/etc/tendril.toml")))
+        );
+        assert!(serve.is_empty());
+
+        let mut journal = args(&["buy", "more", "compost"]);
+        assert_eq!(take_config_arg(&mut journal), Ok(None));
+        assert_eq!(journal, args(&["buy", "more", "compost"]));
+
+        let mut missing = args(&["-b", "-c"]);
+        assert!(take_config_arg(&mut missing).is_err());
+    }
+
+    #[test]
+    fn applies_configured_worker_threads() {
+        let runtime = build_runtime(2);
+        // Both workers have to be running tasks at once for the barrier to open
+        let barrier = Arc::new(Barrier::new(2));
+        let thread_ids = runtime.block_on(async {
+            let handles = (0..8)
+                .map(|_| {
+                    let barrier = barrier.clone();
+                    tokio::spawn(async move {
+                        barrier.wait();
+                        thread::current().id()
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut thread_ids = HashSet::new();
+            for handle in handles {
+                thread_ids.insert(handle.await.unwrap());
+            }
+            thread_ids
+        });
+        assert_eq!(thread_ids.len(), 2);
+    }
+}
@@ -1,25 +1,27 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
 
 use build::{build_links, delete_from_global_store, rename_in_global_store, update_global_store};
 use futures::{stream, StreamExt};
 use persistance::fs::{
+    config::read_config,
     move_archive, path_to_data_structure,
     utils::{archive_file_exists, get_file_path},
     write, write_archive,
 };
 use regex::Regex;
 use search_engine::{
-    delete_archived_file, delete_entry_from_update, patch_search_from_archive,
+    build_search_index, delete_archived_file, delete_entry_from_update, patch_search_from_archive,
     patch_search_from_update,
 };
 use task_runners::{
-    archive::{compress, extract},
-    cache::update_mru_cache,
+    archive::{compress, extract, Product},
+    cache::{rename_in_view_cache, update_mru_cache},
     messages::Message,
+    metrics::{ARCHIVE_FAILURES, ARCHIVE_SUCCESSES},
     verify::verify_data_installation,
-    JobQueue, Queue,
+    Job, JobQueue, Queue,
 };
-use tokio::time::sleep;
+use tokio::{sync::Semaphore, task::spawn_blocking, time::sleep};
 use wikitext::{processors::sanitize_html, GlobalBacklinks, PatchData};
 
 const NUM_JOBS: u32 = 50;
@@ -29,6 +31,9 @@ lazy_static! {
 }
 
 pub async fn process_tasks(queue: Arc<JobQueue>, location: Arc<String>, links: GlobalBacklinks) {
+    let fetches = Arc::new(Semaphore::new(
+        read_config().archive.concurrent_fetches.max(1),
+    ));
     loop {
         let jobs = match queue.pull(NUM_JOBS).await {
             Ok(jobs) => jobs,
@@ -37,89 +42,229 @@ pub async fn process_tasks(queue: Arc<JobQueue>, location: Arc<String>, links: G
                 panic!("Failed to pull jobs");
             }
         };
-        stream::iter(jobs)
-            .for_each_concurrent(NUM_JOBS as usize, |job| async {
-                match job.message {
-                    Message::Rebuild => {
-                        let mut links = links.lock().await;
-                        links.clear();
-                        links.extend(build_links(location.clone()).await);
-                    }
-                    Message::Patch { patch } => {
-                        let note = patch.clone().into();
+        let location = location.clone();
+        let links = links.clone();
+        dispatch(jobs, fetches.clone(), move |message| {
+            process_job(message, location.clone(), links.clone())
+        })
+        .await;
+        sleep(Duration::from_millis(10)).await;
+    }
+}
 
-                        update_global_store(&patch.title, &note, links.clone()).await;
-                        patch_search_from_update(&note);
+/// Runs local jobs to completion, while network bound jobs are handed off to their own tasks and
+/// only wait on each other, limited by `fetches`. Otherwise a batch of slow archive fetches would
+/// hold up saving notes until every page had downloaded.
+async fn dispatch<F, Fut>(jobs: Vec<Job>, fetches: Arc<Semaphore>, handler: F)
+where
+    F: Fn(Message) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (network, local): (Vec<Job>, Vec<Job>) = jobs
+        .into_iter()
+        .partition(|job| job.message.is_network_bound());
+    for job in network {
+        let fetches = fetches.clone();
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let _permit = fetches.acquire_owned().await;
+            handler(job.message).await;
+        });
+    }
+    stream::iter(local)
+        .for_each_concurrent(NUM_JOBS as usize, |job| handler(job.message))
+        .await;
+}
 
-                        if !patch.old_title.is_empty() && patch.old_title != patch.title {
-                            rename_in_global_store(&patch.title, &patch.old_title, links.clone())
-                                .await;
-                        }
-                        update_mru_cache(&patch.old_title, &patch.title).await;
-                    }
-                    Message::Delete { title } => {
-                        let path = get_file_path(&title).unwrap_or_else(|_| {
-                            panic!("Failed to find file for deletion: {}", title)
-                        });
-                        let note = path_to_data_structure(&path).unwrap();
-                        delete_from_global_store(&title, &note, links.clone()).await;
-                        delete_entry_from_update(&title).await;
-                        delete_archived_file(&title).await;
-                        persistance::fs::delete(&title).await.unwrap();
-                    }
-                    Message::Archive { url, title } => {
-                        let product = tokio::task::spawn_blocking(|| extract(url)).await.unwrap();
-                        let compressed = compress(&product.text);
-                        if !archive_file_exists(&title) {
-                            write_archive(compressed, &title).await;
-                            patch_search_from_archive((title, product.text)).await;
-                        }
-                    }
-                    Message::ArchiveMove {
-                        old_title,
-                        new_title,
-                    } => {
-                        move_archive(old_title, new_title).await;
-                    }
-                    Message::NewFromUrl { url, tags } => {
-                        let mut metadata = HashMap::new();
-                        metadata.insert(String::from("url"), url.clone());
-                        let product = tokio::task::spawn_blocking(move || extract(url))
-                            .await
-                            .unwrap();
-                        let note_title = TITLE_RGX.replace_all(&product.title, "").to_string();
-                        let sanitized_content = sanitize_html(&product.content);
-                        let compressed = compress(&product.text);
-                        write_archive(compressed, &note_title).await;
-                        patch_search_from_archive((note_title.clone(), product.text)).await;
-                        metadata.insert("content-type".into(), "html".into());
-                        let patch = PatchData {
-                            body: sanitized_content,
-                            tags,
-                            title: note_title.clone(),
-                            old_title: String::with_capacity(0),
-                            metadata,
-                        };
-                        write(&patch).await.unwrap();
-                        let note = patch.clone().into();
-                        update_global_store(&patch.title, &note, links.clone()).await;
-                        patch_search_from_update(&note);
-                        update_mru_cache(&patch.old_title, &patch.title).await;
-                    }
-                    Message::ArchiveBody { title, body } => {
-                        let compressed = compress(&body);
-                        write_archive(compressed, &title).await;
-                        patch_search_from_archive((title.clone(), body)).await;
-                    }
-                    Message::VerifyDataInstallation {
-                        dataset,
-                        install_location,
-                    } => {
-                        verify_data_installation(dataset, install_location).await;
+/// Fetches a page for archiving, counting how many fetches succeed and fail.
+async fn fetch(url: String) -> Option<Product> {
+    let fetched_url = url.clone();
+    match tokio::task::spawn_blocking(move || extract(fetched_url)).await {
+        Ok(Ok(product)) => {
+            ARCHIVE_SUCCESSES.inc();
+            Some(product)
+        }
+        Ok(Err(e)) => {
+            ARCHIVE_FAILURES.inc();
+            eprintln!("Could not archive {}", e);
+            None
+        }
+        Err(e) => {
+            ARCHIVE_FAILURES.inc();
+            eprintln!("Could not archive {}: {}", url, e);
+            None
+        }
+    }
+}
+
+async fn process_job(message: Message, location: Arc<String>, links: GlobalBacklinks) {
+    match message {
+        Message::Rebuild => {
+            let rebuilt = build_links(location.clone()).await;
+            *links.lock().await = rebuilt;
+        }
+        Message::Reindex => {
+            let search_location = location.clone();
+            let (rebuilt, _) = tokio::join!(
+                build_links(location.clone()),
+                spawn_blocking(move || build_search_index(&search_location))
+            );
+            *links.lock().await = rebuilt;
+        }
+        Message::Patch { patch } => {
+            let note = patch.clone().into();
+
+            update_global_store(&patch.title, &note, links.clone()).await;
+            patch_search_from_update(&note);
+
+            if !patch.old_title.is_empty() && patch.old_title != patch.title {
+                rename_in_global_store(&patch.title, &patch.old_title, links.clone(), false).await;
+                rename_in_view_cache(&patch.old_title, &patch.title).await;
+            }
+            update_mru_cache(&patch.old_title, &patch.title).await;
+        }
+        Message::Delete { title } => {
+            let path = get_file_path(&title)
+                .unwrap_or_else(|_| panic!("Failed to find file for deletion: {}", title));
+            let note = path_to_data_structure(&path).unwrap();
+            delete_from_global_store(&title, &note, links.clone()).await;
+            delete_entry_from_update(&title).await;
+            delete_archived_file(&title).await;
+            persistance::fs::delete(&title).await.unwrap();
+        }
+        Message::Archive { url, title } => {
+            let product = match fetch(url).await {
+                Some(product) => product,
+                None => return,
+            };
+            let compressed = compress(&product.text);
+            if !archive_file_exists(&title) {
+                write_archive(compressed, &title).await;
+                patch_search_from_archive((title, product.text)).await;
+            }
+        }
+        Message::ArchiveMove {
+            old_title,
+            new_title,
+        } => {
+            move_archive(old_title, new_title).await;
+        }
+        Message::NewFromUrl { url, tags } => {
+            let mut metadata = HashMap::new();
+            metadata.insert(String::from("url"), url.clone());
+            let product = match fetch(url).await {
+                Some(product) => product,
+                None => return,
+            };
+            let note_title = TITLE_RGX.replace_all(&product.title, "").to_string();
+            let sanitized_content = sanitize_html(&product.content);
+            let compressed = compress(&product.text);
+            write_archive(compressed, &note_title).await;
+            patch_search_from_archive((note_title.clone(), product.text)).await;
+            metadata.insert("content-type".into(), "html".into());
+            let patch = PatchData {
+                body: sanitized_content,
+                tags,
+                title: note_title.clone(),
+                old_title: String::with_capacity(0),
+                metadata,
+                dir: String::with_capacity(0),
+            };
+            write(&patch).await.unwrap();
+            let note = patch.clone().into();
+            update_global_store(&patch.title, &note, links.clone()).await;
+            patch_search_from_update(&note);
+            update_mru_cache(&patch.old_title, &patch.title).await;
+        }
+        Message::ArchiveBody { title, body } => {
+            let compressed = compress(&body);
+            write_archive(compressed, &title).await;
+            patch_search_from_archive((title.clone(), body)).await;
+        }
+        Message::VerifyDataInstallation {
+            dataset,
+            install_location,
+        } => {
+            verify_data_installation(dataset, install_location).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Counters {
+        in_flight: AtomicUsize,
+        peak: AtomicUsize,
+        fetched: AtomicUsize,
+        patched: AtomicUsize,
+    }
+
+    #[tokio::test]
+    async fn archive_fetches_do_not_hold_up_patches() {
+        let counters = Arc::new(Counters::default());
+        // Fetches wait here until the test lets them through, so none can finish before the patch
+        let gate = Arc::new(Semaphore::new(0));
+        let handler = {
+            let counters = counters.clone();
+            let gate = gate.clone();
+            move |message: Message| {
+                let counters = counters.clone();
+                let gate = gate.clone();
+                async move {
+                    if message.is_network_bound() {
+                        let in_flight = counters.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        counters.peak.fetch_max(in_flight, Ordering::SeqCst);
+                        drop(gate.acquire().await.unwrap());
+                        counters.in_flight.fetch_sub(1, Ordering::SeqCst);
+                        counters.fetched.fetch_add(1, Ordering::SeqCst);
+                    } else {
+                        counters.patched.fetch_add(1, Ordering::SeqCst);
                     }
                 }
+            }
+        };
+        let mut jobs: Vec<Job> = (0..8)
+            .map(|i| {
+                Message::Archive {
+                    url: format!("https://example.com/{}", i),
+                    title: format!("page {}", i),
+                }
+                .into()
             })
-            .await;
-        sleep(Duration::from_millis(10)).await;
+            .collect();
+        jobs.push(
+            Message::Patch {
+                patch: PatchData::from(HashMap::new()),
+            }
+            .into(),
+        );
+
+        dispatch(jobs, Arc::new(Semaphore::new(2)), handler).await;
+        assert_eq!(counters.patched.load(Ordering::SeqCst), 1);
+        assert_eq!(counters.fetched.load(Ordering::SeqCst), 0);
+
+        gate.add_permits(1);
+        timeout(Duration::from_secs(5), async {
+            while counters.fetched.load(Ordering::SeqCst) < 8 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("archive fetches never finished");
+        assert_eq!(counters.peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn counts_pages_that_cannot_be_fetched() {
+        let failures = ARCHIVE_FAILURES.get();
+        assert!(fetch(String::from("not a url")).await.is_none());
+        assert!(ARCHIVE_FAILURES.get() > failures);
     }
 }
@@ -0,0 +1,68 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Writes made by operations that change many notes at once, like importing a folder or relinking
+/// pages after a rename. On a dry run nothing is written, the files that would change are only
+/// recorded so they can be reviewed first.
+#[derive(Debug, Default)]
+pub struct BulkWrite {
+    dry_run: bool,
+    changed: Vec<PathBuf>,
+}
+
+impl BulkWrite {
+    pub fn new(dry_run: bool) -> Self {
+        BulkWrite {
+            dry_run,
+            changed: Vec::new(),
+        }
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Writes `contents` to `path`, or only records that it would on a dry run.
+    pub fn write(&mut self, path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+        if !self.dry_run {
+            fs::write(path, contents)?;
+        }
+        self.changed.push(path.to_path_buf());
+        Ok(())
+    }
+
+    /// The files written, or that would have been on a dry run, in the order they were written.
+    pub fn changed(&self) -> &[PathBuf] {
+        &self.changed
+    }
+
+    pub fn into_changed(self) -> Vec<PathBuf> {
+        self.changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_writes_without_making_them_on_a_dry_run() {
+        let dir = PathBuf::from("/tmp/tendril-test/bulk-write/");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("note.txt");
+
+        let mut dry_run = BulkWrite::new(true);
+        dry_run.write(&file, "planned").unwrap();
+        assert_eq!(dry_run.changed(), &[file.clone()]);
+        assert!(!file.exists());
+
+        let mut writes = BulkWrite::new(false);
+        writes.write(&file, "written").unwrap();
+        assert_eq!(writes.into_changed(), vec![file.clone()]);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "written");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
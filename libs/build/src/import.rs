@@ -0,0 +1,260 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use persistance::fs::path_to_string;
+
+use crate::bulk::BulkWrite;
+use wikitext::{normalize_title, parsers::Note, processors::tags::TagsArray};
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: Vec<PathBuf>,
+    /// `(markdown file, reason)` for each file that wasn't imported.
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+/// Copies every markdown file under `source` into `wiki_dir` as a tendril note. The original
+/// files are left untouched. With `dry_run`, nothing is written and the report lists the notes
+/// that would be created.
+pub fn import_md(source: &Path, wiki_dir: &Path, dry_run: bool) -> ImportReport {
+    let mut report = ImportReport::default();
+    import_md_dir(source, wiki_dir, &mut BulkWrite::new(dry_run), &mut report);
+    report
+}
+
+fn import_md_dir(dir: &Path, wiki_dir: &Path, writes: &mut BulkWrite, report: &mut ImportReport) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            report.skipped.push((dir.to_path_buf(), e.to_string()));
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            import_md_dir(&path, wiki_dir, writes, report);
+        } else if path.extension().map_or(false, |ext| ext == "md") {
+            match import_md_file(&path, wiki_dir, writes) {
+                Ok(imported) => report.imported.push(imported),
+                Err(reason) => report.skipped.push((path, reason)),
+            }
+        }
+    }
+}
+
+fn import_md_file(path: &Path, wiki_dir: &Path, writes: &mut BulkWrite) -> Result<PathBuf, String> {
+    let contents = path_to_string(path).map_err(|e| e.to_string())?;
+    let file_stem = path.file_stem().unwrap().to_string_lossy();
+    let note = md_to_note(&contents, &file_stem)?;
+    let title = note.header.get("title").unwrap();
+    let target = wiki_dir.join(format!("{}.txt", title.replace('/', "-")));
+    if target.exists() {
+        return Err(format!("{:?} already exists", target));
+    }
+    writes
+        .write(&target, std::convert::Into::<String>::into(note))
+        .map_err(|e| e.to_string())?;
+    Ok(target)
+}
+
+/// Converts a markdown file with optional YAML frontmatter into a note. Frontmatter lists are
+/// flattened into tendril's `[a,b]` form, `date` is kept as `created`, and the title falls back to
+/// the file name.
+fn md_to_note(contents: &str, file_stem: &str) -> Result<Note, String> {
+    let contents = contents
+        .trim_start_matches('\u{feff}')
+        .replace("\r\n", "\n");
+    let (mut header, body) = match contents.strip_prefix("---\n") {
+        Some(rest) => {
+            // Frontmatter with no fields closes immediately
+            let (frontmatter, body) = match rest.strip_prefix("---") {
+                Some(body) => ("", body),
+                None => rest
+                    .split_once("\n---")
+                    .ok_or_else(|| String::from("frontmatter is never closed"))?,
+            };
+            (
+                parse_frontmatter(frontmatter)?,
+                body.trim_start_matches('-'),
+            )
+        }
+        None => (HashMap::new(), contents.as_str()),
+    };
+    let title = match header.remove("title") {
+        Some(title) if !title.is_empty() => normalize_title(&title),
+        _ => normalize_title(file_stem),
+    };
+    header.insert("title".into(), title);
+    let tags = header
+        .remove("tags")
+        .or_else(|| header.remove("tag"))
+        .unwrap_or_default();
+    header.insert("tags".into(), TagsArray::new(&tags).write());
+    if let Some(date) = header.remove("date") {
+        header.entry("created".into()).or_insert(date);
+    }
+    let content = body
+        .trim()
+        .lines()
+        .map(normalize_md_heading)
+        .collect::<Vec<String>>()
+        .join("\n");
+    Ok(Note { header, content })
+}
+
+fn parse_frontmatter(frontmatter: &str) -> Result<HashMap<String, String>, String> {
+    let mut fields: Vec<(String, Vec<String>)> = Vec::new();
+    for line in frontmatter.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if let Some(item) = trimmed.strip_prefix("- ") {
+            match fields.last_mut() {
+                Some((_, values)) => values.push(unquote(item)),
+                None => return Err(format!("could not parse frontmatter line `{}`", line)),
+            }
+        } else if let Some((key, value)) = trimmed.split_once(':') {
+            let value = value.trim();
+            let values = if value.is_empty() {
+                Vec::new()
+            } else {
+                vec![unquote(value)]
+            };
+            fields.push((key.trim().to_lowercase(), values));
+        } else {
+            return Err(format!("could not parse frontmatter line `{}`", line));
+        }
+    }
+    Ok(fields
+        .into_iter()
+        .map(|(key, values)| {
+            let value = if values.len() > 1 {
+                format!("[{}]", values.join(","))
+            } else {
+                values.into_iter().next().unwrap_or_default()
+            };
+            (key, value)
+        })
+        .collect())
+}
+
+fn unquote(value: &str) -> String {
+    value
+        .trim()
+        .trim_matches('"')
+        .trim_matches('\'')
+        .to_string()
+}
+
+/// Markdown headings, `#` to `######` followed by a space, keep their level. An optional closing
+/// run of `#` is dropped. Anything else starting with `#`, like a `#tag`, is left alone.
+pub(crate) fn normalize_md_heading(line: &str) -> String {
+    let hashes = line.len() - line.trim_start_matches('#').len();
+    let text = &line[hashes..];
+    if !(1..=6).contains(&hashes) || !(text.is_empty() || text.starts_with([' ', '\t'])) {
+        return line.into();
+    }
+    let mut text = text.trim();
+    let unclosed = text.trim_end_matches('#');
+    if unclosed.is_empty() || unclosed.ends_with([' ', '\t']) {
+        text = unclosed.trim_end();
+    }
+    format!("{} {}", &line[..hashes], text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::references::{build_links, parse_entries};
+    use std::sync::Arc;
+
+    const TEST_DIR: &str = "/tmp/tendril-test/import/";
+
+    #[tokio::test]
+    async fn imports_markdown_folder() {
+        let _ = fs::remove_dir_all(TEST_DIR);
+        fs::create_dir_all(TEST_DIR).unwrap();
+        let report = import_md(Path::new("fixtures/markdown"), Path::new(TEST_DIR), false);
+        assert_eq!(report.imported.len(), 3);
+        assert_eq!(report.skipped.len(), 1);
+        assert!(report.skipped[0].0.ends_with("broken.md"));
+
+        let mut titles = parse_entries(PathBuf::from(TEST_DIR))
+            .await
+            .into_iter()
+            .map(|(title, _)| title)
+            .collect::<Vec<String>>();
+        titles.sort();
+        assert_eq!(titles, vec!["Garden", "Reading list", "octavia-butler"]);
+
+        let links = build_links(Arc::new(String::from(TEST_DIR))).await;
+        let linked_from = |page: &str| {
+            links
+                .get(page)
+                .unwrap()
+                .iter()
+                .map(|b| b.title.as_str())
+                .collect::<Vec<&str>>()
+        };
+        assert_eq!(linked_from("octavia-butler"), vec!["Reading list"]);
+        assert_eq!(linked_from("books"), vec!["Reading list"]);
+        assert_eq!(linked_from("outdoors"), vec!["Garden"]);
+
+        let reading_list = fs::read_to_string(format!("{}Reading list.txt", TEST_DIR)).unwrap();
+        let note: Note = reading_list.into();
+        assert_eq!(note.header.get("created").unwrap(), "2023-04-01");
+        assert_eq!(note.header.get("author").unwrap(), "Taylor");
+        assert!(note.content.starts_with("## Next up"));
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn keeps_heading_levels() {
+        let markdown =
+            "# Garden\n### Pests ###\n## Beds & <borders>\n####### Too deep\n#compost\n# C#";
+        let normalized = markdown
+            .lines()
+            .map(normalize_md_heading)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            normalized,
+            vec![
+                "# Garden",
+                "### Pests",
+                "## Beds & <borders>",
+                "####### Too deep",
+                "#compost",
+                "# C#",
+            ]
+        );
+    }
+
+    #[test]
+    fn dry_run_leaves_the_wiki_untouched() {
+        let dir = "/tmp/tendril-test/import-dry-run/";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let report = import_md(Path::new("fixtures/markdown"), Path::new(dir), true);
+        let mut would_import = report
+            .imported
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<String>>();
+        would_import.sort();
+        assert_eq!(
+            would_import,
+            vec!["Garden.txt", "Reading list.txt", "octavia-butler.txt"]
+        );
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -11,7 +11,7 @@ use persistance::fs::{
 use task_runners::hash_password;
 use wikitext::parsers::Note;
 
-use crate::{gen_config_interactive, ConfigOptions};
+use crate::{gen_config_interactive, import::normalize_md_heading, ConfigOptions};
 
 fn prep_files() {
     let data_dir = get_data_dir_location();
@@ -22,6 +22,7 @@ fn prep_files() {
     let template_dir = data_dir.join("templates");
     let archive_dir = data_dir.join("archive");
     let cache_file = data_dir.join("note_cache");
+    let view_cache_file = data_dir.join("view_cache");
     fs::create_dir_all(&archive_dir).unwrap();
     fs::create_dir_all(&static_dir).unwrap();
     fs::create_dir_all(&mods_dir).unwrap();
@@ -30,6 +31,9 @@ fn prep_files() {
     if !cache_file.exists() {
         fs::File::create(cache_file).unwrap();
     }
+    if !view_cache_file.exists() {
+        fs::File::create(view_cache_file).unwrap();
+    }
     let version = env!("CARGO_PKG_VERSION");
     for entry in fs::read_dir("./static").unwrap() {
         let entry = entry.unwrap();
@@ -78,6 +82,73 @@ pub fn install() {
 pub fn update() {
     prep_files();
     println!("<files updated>");
+    check_install(false);
+}
+
+/// Checks that the install has every template and static asset that ships with tendril,
+/// restoring any missing files from `./templates` and `./static` when `repair` is set.
+pub fn doctor(repair: bool) {
+    check_install(repair);
+}
+
+// Directories copied into the data directory by `prep_files`.
+const INSTALLED_DIRS: [&str; 2] = ["static", "templates"];
+
+fn check_install(repair: bool) {
+    let data_dir = get_data_dir_location();
+    let mut missing = Vec::new();
+    for dir in INSTALLED_DIRS {
+        let source_dir = PathBuf::from(".").join(dir);
+        if !source_dir.exists() {
+            eprintln!(
+                "Could not find {:?}, run this command from the directory tendril was unpacked or cloned into.",
+                source_dir
+            );
+            exit(1);
+        }
+        missing.extend(find_missing_files(&source_dir, &data_dir.join(dir)));
+    }
+    if missing.is_empty() {
+        println!("<install verified>");
+        return;
+    }
+    for (_, installed) in missing.iter() {
+        println!("missing: {}", installed.display());
+    }
+    if repair {
+        restore_missing_files(&missing);
+        println!("<restored {} files>", missing.len());
+    } else {
+        println!(
+            "<{} files missing, run `tendril --doctor --repair` to restore them>",
+            missing.len()
+        );
+    }
+}
+
+/// Returns `(source, installed)` pairs for each file under `source_dir` that has no counterpart
+/// under `install_dir`.
+fn find_missing_files(source_dir: &Path, install_dir: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let mut missing = Vec::new();
+    for entry in fs::read_dir(source_dir).unwrap() {
+        let entry = entry.unwrap();
+        let installed = install_dir.join(entry.file_name());
+        if entry.metadata().unwrap().is_dir() {
+            missing.extend(find_missing_files(&entry.path(), &installed));
+        } else if !installed.exists() {
+            missing.push((entry.path(), installed));
+        }
+    }
+    missing
+}
+
+fn restore_missing_files(missing: &[(PathBuf, PathBuf)]) {
+    for (source, installed) in missing {
+        if let Some(parent) = installed.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::copy(source, installed).unwrap();
+    }
 }
 
 pub fn migrate() {
@@ -143,12 +214,7 @@ fn process_migration_file(entry_path: PathBuf, target: PathBuf, data_dir: &Path)
                     }
                     return Some("\n".into());
                 }
-                if line.starts_with('#') {
-                    let cleaned_line = line.replace('#', "");
-                    let formatted = format!("# {}", cleaned_line);
-                    return Some(formatted);
-                }
-                Some(line.into())
+                Some(normalize_md_heading(line))
             })
             .collect::<Vec<String>>()
             .join("\n");
@@ -200,3 +266,41 @@ fn bootstrap_initial_files(options: ConfigOptions) {
         exit(0);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DIR: &str = "/tmp/tendril-test/install/";
+
+    #[test]
+    fn finds_and_restores_missing_install_files() {
+        let source = PathBuf::from(TEST_DIR).join("source");
+        let install = PathBuf::from(TEST_DIR).join("install");
+        let _ = fs::remove_dir_all(TEST_DIR);
+        fs::create_dir_all(source.join("mods")).unwrap();
+        fs::create_dir_all(&install).unwrap();
+        fs::write(source.join("style.css"), "body {}").unwrap();
+        fs::write(source.join("script.js"), "").unwrap();
+        fs::write(source.join("mods").join("editor.js"), "").unwrap();
+        fs::write(install.join("style.css"), "body {}").unwrap();
+
+        let mut missing = find_missing_files(&source, &install);
+        missing.sort();
+        let installed = missing
+            .iter()
+            .map(|(_, installed)| installed.to_owned())
+            .collect::<Vec<PathBuf>>();
+        assert_eq!(
+            installed,
+            vec![
+                install.join("mods").join("editor.js"),
+                install.join("script.js")
+            ]
+        );
+
+        restore_missing_files(&missing);
+        assert!(find_missing_files(&source, &install).is_empty());
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+}
@@ -1,9 +1,13 @@
+pub mod bulk;
 pub mod config;
+pub mod import;
 pub mod install;
 pub mod pages;
 pub mod references;
 
+pub use self::bulk::*;
 pub use self::config::*;
+pub use self::import::*;
 pub use self::install::*;
 pub use self::pages::*;
 pub use self::references::*;
@@ -3,20 +3,24 @@ use futures::{stream, StreamExt};
 use std::fmt::Write;
 
 use render::static_site_page::StaticSitePage;
+use urlencoding::encode;
 use wikitext::{
-    parsers::{ParsedPages, TemplattedPage},
+    parsers::{format_links, with_base_path, ParsedPages, TemplattedPage},
     GlobalBacklinks,
 };
 
 use persistance::fs::path_to_data_structure;
-use persistance::fs::utils::get_config_location;
+use persistance::fs::{config::read_config, utils::get_config_location};
 use render::Render;
 use tokio::sync::Mutex;
 use wikitext::processors::update_templatted_pages;
 
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
     fs::{self, read_dir},
+    future::Future,
+    hash::{Hash, Hasher},
+    io,
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -43,30 +47,43 @@ impl Builder {
             pages: Arc::new(Mutex::new(Vec::new())),
         }
     }
-    pub async fn compile_all(&self) {
+    /// Renders the site into `public`. Pages are rendered into a staging directory first and only
+    /// swapped into place once everything was written, so a failed or overlapping build never
+    /// leaves a half-populated site behind.
+    pub async fn build(&self, wiki_location: &str, public: &Path) -> io::Result<()> {
+        self.sweep(wiki_location).await;
+        publish(
+            public,
+            |out_dir| async move { self.compile_all(&out_dir).await },
+        )
+        .await
+    }
+
+    pub async fn compile_all(&self, out_dir: &Path) -> io::Result<()> {
         let pages = Arc::clone(&self.pages);
-        write_entries(&pages, &self.backlinks).await;
-        write_index_page(&pages).await;
         let mut config_dir = get_config_location().0;
         config_dir.push("userstyles.css");
-        fs::create_dir("public/static").unwrap();
-        fs::create_dir("public/config").unwrap();
-        fs::copy("./static/style.css", "./public/static/style.css").unwrap();
-        fs::copy("./static/mobile.css", "./public/static/mobile.css").unwrap();
+        fs::create_dir_all(out_dir.join("static"))?;
+        fs::create_dir_all(out_dir.join("config"))?;
+        fs::copy("./static/style.css", out_dir.join("static/style.css"))?;
+        fs::copy("./static/mobile.css", out_dir.join("static/mobile.css"))?;
         fs::copy(
             "./static/note-styles.css",
-            "./public/static/note-styles.css",
-        )
-        .unwrap();
+            out_dir.join("static/note-styles.css"),
+        )?;
         if config_dir.exists() {
-            fs::copy(config_dir, "./public/config/userstyles.css").unwrap();
+            fs::copy(config_dir, out_dir.join("config/userstyles.css"))?;
         }
+        let asset_hashes = if read_config().general.cache_bust_assets {
+            hash_assets(out_dir, PUBLISHED_ASSETS.as_slice())
+        } else {
+            Vec::with_capacity(0)
+        };
+        write_entries(out_dir, &pages, &self.backlinks, &asset_hashes).await;
+        write_index_page(out_dir, &pages, &asset_hashes).await
     }
 
     pub async fn sweep(&self, wiki_location: &str) {
-        if !Path::new("./public").exists() {
-            fs::create_dir_all("./public").unwrap();
-        }
         let links = Arc::clone(&self.backlinks);
         let pages = Arc::clone(&self.pages);
         parse_entries(PathBuf::from(wiki_location), links, pages).await;
@@ -80,7 +97,13 @@ impl Default for Builder {
 }
 
 async fn process_file(path: PathBuf, backlinks: &GlobalBacklinks, pages: ParsedPages) {
-    let note = path_to_data_structure(&path).unwrap();
+    let note = match path_to_data_structure(&path) {
+        Ok(note) => note,
+        Err(e) => {
+            eprintln!("Skipping {:?}: {}", path, e);
+            return;
+        }
+    };
     let structured = note.to_structured().as_owned();
     let mut backlinks = backlinks.lock().await;
     add_to_global_store(&structured.0, &structured.1, &mut backlinks).await;
@@ -98,31 +121,175 @@ async fn parse_entries(
     let pipeline = stream::iter(entries).for_each(|entry| async {
         let links = Arc::clone(&backlinks);
         let pages = Arc::clone(&rendered_pages);
-        let entry = entry.unwrap();
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                eprintln!("Could not read directory entry: {}", e);
+                return;
+            }
+        };
         let file_name = entry.file_name();
-        let file_name = file_name.to_str().unwrap();
-        if entry.file_type().unwrap().is_file() && file_name.ends_with(".txt") {
-            tokio::spawn(async move {
+        let file_name = file_name.to_string_lossy();
+        // Skip dotfiles and directories like `.git`
+        if file_name.starts_with('.') {
+            return;
+        }
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(e) => {
+                eprintln!("Skipping {:?}: {}", entry.path(), e);
+                return;
+            }
+        };
+        if file_type.is_file() && file_name.ends_with(".txt") {
+            let path = entry.path();
+            // A malformed note shouldn't take down the whole build
+            if let Err(e) = tokio::spawn(async move {
                 process_file(entry.path(), &links, pages).await;
             })
             .await
-            .unwrap();
-        } else if entry.file_type().unwrap().is_dir()
-            && !entry.path().to_str().unwrap().contains(".git")
-        {
+            {
+                eprintln!("Skipping {:?}: {}", path, e);
+            }
+        } else if file_type.is_dir() {
             parse_entries(entry.path(), links, pages).await;
         }
     });
     pipeline.await
 }
 
-async fn write_index_page(pages: &ParsedPages) {
+/// Runs `render` against a fresh staging directory next to `public`, then swaps the result into
+/// place. The staging directory is named after the process, so concurrent builds don't share one.
+async fn publish<F, Fut>(public: &Path, render: F) -> io::Result<()>
+where
+    F: FnOnce(PathBuf) -> Fut,
+    Fut: Future<Output = io::Result<()>>,
+{
+    let sibling = |label: &str| {
+        let name = public.file_name().unwrap_or_default().to_string_lossy();
+        public.with_file_name(format!(".{}-{}-{}", name, label, std::process::id()))
+    };
+    let staging = sibling("staging");
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+    if let Err(e) = render(staging.clone()).await {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+    let previous = sibling("previous");
+    if public.exists() {
+        fs::rename(public, &previous)?;
+    }
+    if let Err(e) = fs::rename(&staging, public) {
+        // Put the last good build back
+        if previous.exists() {
+            fs::rename(&previous, public)?;
+        }
+        return Err(e);
+    }
+    if previous.exists() {
+        fs::remove_dir_all(&previous)?;
+    }
+    Ok(())
+}
+
+// Assets copied into the output directory, relative to it.
+const PUBLISHED_ASSETS: [&str; 4] = [
+    "static/style.css",
+    "static/mobile.css",
+    "static/note-styles.css",
+    "config/userstyles.css",
+];
+
+/// `(asset path, content hash)` pairs for the assets in the output directory.
+type AssetHashes = Vec<(String, String)>;
+
+fn hash_assets(out_dir: &Path, assets: &[&str]) -> AssetHashes {
+    assets
+        .iter()
+        .filter_map(|asset| {
+            let contents = fs::read(out_dir.join(asset)).ok()?;
+            Some((asset.to_string(), hash_contents(&contents)))
+        })
+        .collect()
+}
+
+fn hash_contents(contents: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Appends a `?v=<hash>` query to asset references, so browsers fetch an asset again once its
+/// contents change instead of serving a stale copy from their cache.
+fn cache_bust(html: String, asset_hashes: &AssetHashes) -> String {
+    asset_hashes.iter().fold(html, |html, (asset, hash)| {
+        html.replace(
+            &format!("/{}\"", asset),
+            &format!("/{}?v={}\"", asset, hash),
+        )
+    })
+}
+
+/// Longest directory name written for a page, in bytes. Most filesystems cap a single path
+/// component at 255 bytes.
+const MAX_PAGE_DIR_LEN: usize = 200;
+
+/// The directory a page is written to. `/` can't appear in a directory name, and very long titles
+/// are cut short with a hash of the full title appended so they stay unique.
+fn page_dir_name(title: &str) -> String {
+    let name = title.replace('/', "-");
+    if name.len() <= MAX_PAGE_DIR_LEN {
+        return name;
+    }
+    let hash = hash_contents(title.as_bytes());
+    let mut end = MAX_PAGE_DIR_LEN - hash.len() - 1;
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}-{}", &name[..end], hash)
+}
+
+/// `(link, link to the page's directory)` pairs for pages whose directory isn't named after their
+/// title, so links to them can be pointed at where they were written.
+fn moved_links<'a>(titles: impl Iterator<Item = &'a str>) -> Vec<(String, String)> {
+    titles
+        .filter_map(|title| {
+            let dir = page_dir_name(title);
+            if dir == title {
+                return None;
+            }
+            Some((
+                format_links(title),
+                with_base_path(&format!("/{}", encode(&dir))),
+            ))
+        })
+        .collect()
+}
+
+fn relink(html: String, moved: &[(String, String)]) -> String {
+    moved.iter().fold(html, |html, (link, dir_link)| {
+        html.replace(
+            &format!("href=\"{}\"", link),
+            &format!("href=\"{}\"", dir_link),
+        )
+    })
+}
+
+async fn write_index_page(
+    out_dir: &Path,
+    pages: &ParsedPages,
+    asset_hashes: &AssetHashes,
+) -> io::Result<()> {
     let page_vals = pages.lock().await;
     let pages: String = page_vals.iter().fold(String::new(), |mut output, page| {
         let _ = write!(
             output,
             r#"<li><a href="{}">{}</a></li>"#,
-            page.title, page.title
+            encode(&page_dir_name(&page.title)),
+            page.title
         );
         output
     });
@@ -136,27 +303,197 @@ async fn write_index_page(pages: &ParsedPages) {
         tags: Vec::with_capacity(0),
         desc: String::from("list of all pages"),
         metadata: HashMap::with_capacity(0),
+        raw_md: String::with_capacity(0),
     };
-    let output = StaticSitePage::new(&page, None).render().await;
+    let output = cache_bust(
+        StaticSitePage::new(&page, None).render().await,
+        asset_hashes,
+    );
     // TODO: Figure out static site index
-    tokio::fs::write("public/index.html", output).await.unwrap();
+    tokio::fs::write(out_dir.join("index.html"), output).await
 }
 
-async fn write_entries(pages: &ParsedPages, backlinks: &GlobalBacklinks) {
+async fn write_entries(
+    out_dir: &Path,
+    pages: &ParsedPages,
+    backlinks: &GlobalBacklinks,
+    asset_hashes: &AssetHashes,
+) {
     let page_vals = pages.lock().await;
     let link_vals = backlinks.lock().await;
+    let moved = moved_links(page_vals.iter().map(|page| page.title.as_str()));
     for page in page_vals.iter() {
         let links = link_vals.get(&page.title);
-        let output = StaticSitePage::new(page, links).render().await;
-        let formatted_title = page.title.replace('/', "-");
-        let out_dir = format!("public/{}", formatted_title);
-        // TODO use path here instead of title? Since `/` in title can cause issues in fs::write
-        tokio::fs::create_dir(&out_dir)
+        let output = relink(
+            cache_bust(
+                StaticSitePage::new(page, links).render().await,
+                asset_hashes,
+            ),
+            &moved,
+        );
+        let page_dir = out_dir.join(page_dir_name(&page.title));
+        tokio::fs::create_dir(&page_dir)
             .await
-            .unwrap_or_else(|e| eprintln!("{:?}\nCould not create dir: {}", e, out_dir));
-        let out_file = format!("public/{}/index.html", formatted_title);
+            .unwrap_or_else(|e| eprintln!("{:?}\nCould not create dir: {:?}", e, page_dir));
+        let out_file = page_dir.join("index.html");
         tokio::fs::write(&out_file, output)
             .await
-            .unwrap_or_else(|e| eprintln!("{:?}\nCould not write file: {}", e, out_file));
+            .unwrap_or_else(|e| eprintln!("{:?}\nCould not write file: {:?}", e, out_file));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DIR: &str = "/tmp/tendril-test/sweep/";
+
+    #[tokio::test]
+    async fn sweep_skips_non_note_files() {
+        let _ = fs::remove_dir_all(TEST_DIR);
+        fs::create_dir_all(format!("{}.git/refs", TEST_DIR)).unwrap();
+        fs::create_dir_all(format!("{}nested", TEST_DIR)).unwrap();
+        for entry in fs::read_dir("../wikitext/fixtures").unwrap() {
+            let entry = entry.unwrap();
+            fs::copy(
+                entry.path(),
+                PathBuf::from(TEST_DIR).join(entry.file_name()),
+            )
+            .unwrap();
+        }
+        fs::write(format!("{}README.md", TEST_DIR), "# My wiki\n").unwrap();
+        fs::write(format!("{}.git/HEAD", TEST_DIR), "ref: refs/heads/main\n").unwrap();
+        fs::write(format!("{}.git/refs/notes.txt", TEST_DIR), "not a note").unwrap();
+        fs::write(format!("{}.hidden.txt", TEST_DIR), "not a note").unwrap();
+        fs::write(
+            format!("{}nested/nested note.txt", TEST_DIR),
+            "title: nested note\ntags: []\n\nnested body",
+        )
+        .unwrap();
+
+        let builder = Builder::new();
+        parse_entries(
+            PathBuf::from(TEST_DIR),
+            builder.backlinks.clone(),
+            builder.pages.clone(),
+        )
+        .await;
+        let pages = builder.pages.lock().await;
+        let titles = pages
+            .iter()
+            .map(|p| p.title.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(titles.len(), 4);
+        assert!(titles.contains(&"nested note"));
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[tokio::test]
+    async fn references_hashed_asset_urls() {
+        let out_dir = PathBuf::from("/tmp/tendril-test/cache-bust/");
+        let _ = fs::remove_dir_all(&out_dir);
+        for asset in PUBLISHED_ASSETS {
+            let path = out_dir.join(asset);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, format!("/* {} */", asset)).unwrap();
+        }
+        let asset_hashes = hash_assets(&out_dir, PUBLISHED_ASSETS.as_slice());
+        assert_eq!(asset_hashes.len(), PUBLISHED_ASSETS.len());
+        let page = TemplattedPage {
+            title: String::from("Garden"),
+            body: String::from("<p>Beans and squash</p>"),
+            tags: Vec::with_capacity(0),
+            desc: String::from("garden notes"),
+            metadata: HashMap::with_capacity(0),
+            raw_md: String::with_capacity(0),
+        };
+        let pages: ParsedPages = Arc::new(Mutex::new(vec![page]));
+        let backlinks: GlobalBacklinks = Arc::new(Mutex::new(BTreeMap::new()));
+
+        write_entries(&out_dir, &pages, &backlinks, &asset_hashes).await;
+        write_index_page(&out_dir, &pages, &asset_hashes)
+            .await
+            .unwrap();
+        for rendered in ["Garden/index.html", "index.html"] {
+            let html = fs::read_to_string(out_dir.join(rendered)).unwrap();
+            for (asset, hash) in asset_hashes.iter() {
+                assert!(
+                    html.contains(&format!(r#"href="/{}?v={}""#, asset, hash)),
+                    "{} doesn't reference {} by its hash",
+                    rendered,
+                    asset
+                );
+                assert!(!html.contains(&format!(r#"href="/{}""#, asset)));
+            }
+        }
+        assert_ne!(
+            hash_contents(b"body { color: red; }"),
+            hash_contents(b"body { color: blue; }")
+        );
+        fs::remove_dir_all(out_dir).unwrap();
+    }
+
+    #[test]
+    fn shortens_long_titles() {
+        let title = "a very long title ".repeat(17);
+        assert!(title.len() > 300);
+        let dir = page_dir_name(&title);
+        assert!(dir.len() <= MAX_PAGE_DIR_LEN);
+        assert_ne!(dir, page_dir_name(&format!("{}!", title)));
+        assert_eq!(page_dir_name("short/title"), "short-title");
+
+        let out_dir = PathBuf::from("/tmp/tendril-test/long-titles/");
+        let _ = fs::remove_dir_all(&out_dir);
+        fs::create_dir_all(out_dir.join(&dir)).unwrap();
+        fs::write(out_dir.join(&dir).join("index.html"), "long page").unwrap();
+
+        let html = format!(r#"<a href="{}">{}</a>"#, format_links(&title), title);
+        let moved = moved_links([title.as_str(), "short"].into_iter());
+        assert_eq!(moved.len(), 1);
+        let relinked = relink(html, &moved);
+        let href = relinked
+            .split('"')
+            .nth(1)
+            .unwrap()
+            .trim_start_matches('/')
+            .to_string();
+        let resolved = out_dir
+            .join(urlencoding::decode(&href).unwrap().as_ref())
+            .join("index.html");
+        assert_eq!(fs::read_to_string(resolved).unwrap(), "long page");
+        fs::remove_dir_all(out_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_build_keeps_previous_site() {
+        let root = PathBuf::from("/tmp/tendril-test/publish/");
+        let _ = fs::remove_dir_all(&root);
+        let public = root.join("public");
+        fs::create_dir_all(&public).unwrap();
+        fs::write(public.join("index.html"), "previous build").unwrap();
+
+        let failed = publish(&public, |out_dir| async move {
+            fs::write(out_dir.join("index.html"), "half built")?;
+            Err(io::Error::new(io::ErrorKind::Other, "render failed"))
+        })
+        .await;
+        assert!(failed.is_err());
+        assert_eq!(
+            fs::read_to_string(public.join("index.html")).unwrap(),
+            "previous build"
+        );
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+
+        publish(&public, |out_dir| async move {
+            fs::write(out_dir.join("index.html"), "next build")
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(public.join("index.html")).unwrap(),
+            "next build"
+        );
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+        fs::remove_dir_all(root).unwrap();
     }
 }
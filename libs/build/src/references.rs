@@ -1,34 +1,80 @@
-use std::{collections::BTreeMap, io, path::PathBuf, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::available_parallelism,
+};
 
-use async_recursion::async_recursion;
 use futures::{stream, StreamExt};
 use persistance::fs::{path_to_data_structure, utils::get_file_path};
-use tokio::fs::{self, read_dir};
-use wikitext::{parsers::Note, Backlinks, GlobalBacklinks};
-
-// TODO: Reduce these duplicated functions, think of a better abstraction
-#[async_recursion]
-pub async fn parse_entries(entrypoint: PathBuf) -> Vec<(String, Vec<String>)> {
-    let mut entries = read_dir(entrypoint).await.unwrap();
-    let mut result = Vec::new();
-    while let Some(entry) = entries.next_entry().await.unwrap() {
-        if entry.file_type().await.unwrap().is_file()
-            && entry.file_name().to_str().unwrap().ends_with(".txt")
-        {
-            let note = path_to_data_structure(&entry.path()).unwrap();
-            let structured = note.to_structured();
-            result.push(structured.as_owned());
-        } else if entry.file_type().await.unwrap().is_dir()
-            && !entry.path().to_str().unwrap().contains(".git")
-        {
-            let results = parse_entries(entry.path()).await;
-            result.extend(results);
+use tokio::{fs, task::spawn_blocking};
+use wikitext::{normalize_title, parsers::Note, Backlink, Backlinks, GlobalBacklinks};
+
+use crate::bulk::BulkWrite;
+
+/// The pages and tags a note links to, with the display text of aliased links.
+pub type OwnedOutlinks = Vec<(String, Option<String>)>;
+
+/// Collects the path of every note under `entrypoint`, skipping dotfiles and directories like
+/// `.git`.
+fn collect_note_paths(entrypoint: &Path, paths: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(entrypoint) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Could not read {:?}: {}", entrypoint, e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_name.starts_with('.') {
+            continue;
+        }
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_file() && file_name.ends_with(".txt") => {
+                paths.push(entry.path())
+            }
+            Ok(file_type) if file_type.is_dir() => collect_note_paths(&entry.path(), paths),
+            _ => {}
         }
     }
-    result
 }
 
-async fn create_global_store(notes: Vec<(String, Vec<String>)>) -> Backlinks {
+fn parse_note(path: &Path) -> Option<(String, OwnedOutlinks)> {
+    match path_to_data_structure(path) {
+        Ok(note) => Some(note.to_structured().as_owned()),
+        Err(e) => {
+            eprintln!("Skipping {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Reads and parses every note under `entrypoint` into `(title, links and tags)` pairs. Notes
+/// are parsed on the blocking pool, but results keep the order the notes were found in.
+pub async fn parse_entries(entrypoint: PathBuf) -> Vec<(String, OwnedOutlinks)> {
+    let mut paths = Vec::new();
+    collect_note_paths(&entrypoint, &mut paths);
+    let workers = available_parallelism().map(|n| n.get()).unwrap_or(4);
+    stream::iter(paths)
+        .map(|path| spawn_blocking(move || parse_note(&path)))
+        .buffered(workers)
+        .filter_map(|parsed| async move {
+            match parsed {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    eprintln!("Failed to parse note: {}", e);
+                    None
+                }
+            }
+        })
+        .collect()
+        .await
+}
+
+async fn create_global_store(notes: Vec<(String, OwnedOutlinks)>) -> Backlinks {
     let mut backlinks = BTreeMap::new();
     for note in notes {
         add_to_global_store(&note.0, &note.1, &mut backlinks).await;
@@ -44,56 +90,81 @@ async fn create_global_store(notes: Vec<(String, Vec<String>)>) -> Backlinks {
 /// We would take the vector consisting of the strings, `backlinks` and `networked though`, and
 /// iterate through each entry, placing it as a key in the BTreeMap. This makes it easy to query
 /// the map when we render a specific page since each value for that key will be the title of a
-/// page that has a link to the currently viewed entry.
+/// page that has a link to the currently viewed entry, along with the display text it used
+/// (`connect` above) when the link was aliased.
 ///
 pub async fn add_to_global_store<'a>(
     title: &'a str,
-    links_and_tags: &[String],
+    links_and_tags: &[(String, Option<String>)],
     backlinks: &mut Backlinks,
 ) {
-    for link in links_and_tags.iter() {
-        backlinks
-            .entry(link.to_string())
-            .or_default()
-            .push(title.to_string());
+    for (link, alias) in links_and_tags.iter() {
+        insert_sorted(
+            backlinks.entry(normalize_title(link)).or_default(),
+            Backlink {
+                title: title.to_string(),
+                alias: alias.clone(),
+            },
+        );
     }
 }
 
+/// Keeps the pages under each link or tag ordered by title, ignoring case, so listings don't
+/// depend on the order notes were scanned or edited in. A page linking more than once is listed
+/// once, with the display text of its first link.
+fn insert_sorted(pages: &mut Vec<Backlink>, page: Backlink) {
+    if pages.iter().any(|existing| existing.title == page.title) {
+        return;
+    }
+    let at = pages.partition_point(|existing| sort_key(existing) <= sort_key(&page));
+    pages.insert(at, page);
+}
+
+fn sort_key(page: &Backlink) -> (String, &str) {
+    (page.title.to_lowercase(), &page.title)
+}
+
 pub async fn build_links(wiki_location: Arc<String>) -> Backlinks {
     let entries = parse_entries(PathBuf::from(wiki_location.as_str())).await;
     create_global_store(entries).await
 }
 
+/// Brings the backlinks for a single edited note up to date without rebuilding the whole store:
+/// `current_title` is dropped from every entry, then added back to the ones the note still links
+/// to or is tagged with, so deleted links and changed display text don't linger.
 pub async fn update_global_store(current_title: &str, note: &Note, links: GlobalBacklinks) {
     let mut links = links.lock().await;
     let structured = note.to_structured();
-    for link in structured.links_and_tags.iter() {
-        match links.get_mut(*link) {
-            Some(exists) => {
-                if exists.contains(&String::from(current_title)) {
-                    continue;
-                } else {
-                    exists.push(current_title.into())
-                }
-            }
-            None => {
-                links.insert(link.to_string(), vec![current_title.into()]);
-            }
-        }
+    remove_backlinks_from(current_title, &mut links);
+    for (link, alias) in structured.links_and_tags.iter() {
+        insert_sorted(
+            links.entry(normalize_title(link)).or_default(),
+            Backlink {
+                title: current_title.into(),
+                alias: alias.map(|a| a.to_string()),
+            },
+        );
     }
 }
 
+fn remove_backlinks_from(title: &str, backlinks: &mut Backlinks) {
+    backlinks.retain(|_, linked_pages| {
+        linked_pages.retain(|page| page.title != title);
+        !linked_pages.is_empty()
+    });
+}
+
 pub async fn delete_from_global_store(title: &str, note: &Note, links: GlobalBacklinks) {
     let mut links = links.lock().await;
     let templatted = note.to_template();
     for link in templatted.outlinks {
         let link = link.to_string();
         if let Some(exists) = links.get(&link) {
-            if exists.contains(&title.to_string()) {
+            if exists.iter().any(|note| note.title == title) {
                 let filtered = exists
                     .iter()
-                    .filter(|&note| note != title)
-                    .map(|n| n.into())
+                    .filter(|&note| note.title != title)
+                    .cloned()
                     .collect();
                 links.insert(link, filtered);
             }
@@ -102,11 +173,11 @@ pub async fn delete_from_global_store(title: &str, note: &Note, links: GlobalBac
     for tag in templatted.page.tags {
         let tag = tag.to_string();
         if let Some(exists) = links.get(&tag) {
-            if exists.contains(&String::from(title)) {
+            if exists.iter().any(|note| note.title == title) {
                 let filtered = exists
                     .iter()
-                    .filter(|&note| note != title)
-                    .map(|n| n.to_owned())
+                    .filter(|&note| note.title != title)
+                    .cloned()
                     .collect();
                 links.insert(tag, filtered);
             }
@@ -115,40 +186,48 @@ pub async fn delete_from_global_store(title: &str, note: &Note, links: GlobalBac
     links.remove(title);
 }
 
+/// Points every note linking to `old_title` at `current_title` instead, and moves its backlinks
+/// over. Returns the notes that were rewritten. On a `dry_run` nothing is written and the
+/// backlinks are left alone, the returned notes are the ones that would be rewritten.
 pub async fn rename_in_global_store(
     current_title: &str,
     old_title: &str,
     backlinks: GlobalBacklinks,
-) {
+    dry_run: bool,
+) -> Vec<PathBuf> {
     let mut backlinks = backlinks.lock().await;
-    let linked_pages = backlinks.get(old_title);
-    if let Some(linked_pages) = linked_pages {
-        stream::iter(linked_pages)
-            .for_each(|page| async {
-                let location = get_file_path(page).unwrap();
-                match fs::read_to_string(&location).await {
-                    Ok(raw_page) => {
-                        let relinked_page = raw_page.replace(old_title, current_title);
-                        fs::write(location, relinked_page).await.unwrap();
+    let mut writes = BulkWrite::new(dry_run);
+    if let Some(linked_pages) = backlinks.get(old_title) {
+        for page in linked_pages {
+            let location = get_file_path(&page.title).unwrap();
+            match fs::read_to_string(&location).await {
+                Ok(raw_page) => {
+                    let relinked_page = raw_page.replace(old_title, current_title);
+                    if relinked_page != raw_page {
+                        writes.write(&location, relinked_page).unwrap();
                     }
-                    Err(e) => match e.kind() {
-                        io::ErrorKind::NotFound => {}
-                        _ => std::panic::panic_any(e),
-                    },
                 }
-            })
-            .await;
-        let pages = linked_pages.clone();
-        backlinks.insert(current_title.into(), pages);
-        backlinks.remove(old_title);
+                Err(e) => match e.kind() {
+                    io::ErrorKind::NotFound => {}
+                    _ => std::panic::panic_any(e),
+                },
+            }
+        }
+        if !writes.is_dry_run() {
+            let pages = linked_pages.clone();
+            backlinks.insert(current_title.into(), pages);
+            backlinks.remove(old_title);
+        }
     }
+    writes.into_changed()
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::collections::HashMap;
+    use std::sync::{Arc, OnceLock};
     use std::{env, fs};
-    use tokio::sync::Mutex;
+    use tokio::sync::{Mutex, MutexGuard};
 
     use persistance::fs::utils::get_file_path;
 
@@ -156,7 +235,11 @@ mod tests {
 
     const TEST_DIR: &str = "/tmp/tendril-test/references/";
 
-    fn init_temp_wiki(namespace: &str) {
+    /// The wiki directory is read once per process, so every test shares `TEST_DIR` and holds the
+    /// returned guard while using it.
+    async fn init_temp_wiki(namespace: &str) -> MutexGuard<'static, ()> {
+        static WIKI: OnceLock<Mutex<()>> = OnceLock::new();
+        let guard = WIKI.get_or_init(Default::default).lock().await;
         env::set_var("TENDRIL_WIKI_DIR", TEST_DIR);
         fs::create_dir_all(format!("{}{}", TEST_DIR, namespace)).unwrap();
         for entry in fs::read_dir("../wikitext/fixtures").unwrap() {
@@ -166,6 +249,7 @@ mod tests {
             dest.push(entry.file_name());
             fs::copy(&path, dest).unwrap();
         }
+        guard
     }
     fn cp_file(src: &str, dest: &str) {
         let mut src_path = PathBuf::from(TEST_DIR);
@@ -186,7 +270,7 @@ mod tests {
     // TODO: This is flaky
     #[ignore]
     async fn updates_note_succesfully() {
-        init_temp_wiki("update");
+        let _wiki = init_temp_wiki("update").await;
         let title = "Logical reality";
         let mut link_tree = BTreeMap::new();
         link_tree.insert(title.into(), vec!["wiki page".into()]);
@@ -196,29 +280,208 @@ mod tests {
         update_global_store(title, &note, links.clone()).await;
         let updated_links = links.lock().await;
         let entry = updated_links.get(title).unwrap();
-        assert_eq!(entry, &vec![String::from("wiki page")]);
+        assert_eq!(entry, &vec![Backlink::from("wiki page")]);
         teardown_temp_wiki("update");
     }
     #[tokio::test]
     async fn renames_note_succesfully() {
-        init_temp_wiki("rename");
+        let _wiki = init_temp_wiki("rename").await;
         let title = "Logical reality";
         let new_title = "reality building";
         cp_file(title, new_title);
         let mut link_tree = BTreeMap::new();
         link_tree.insert(title.into(), vec!["wiki page".into()]);
         let links: GlobalBacklinks = Arc::new(Mutex::new(link_tree));
-        rename_in_global_store(new_title, title, links.clone()).await;
+        rename_in_global_store(new_title, title, links.clone(), false).await;
         let updated_links = links.lock().await;
         let entry = updated_links.get(title);
         let renamed_entry = updated_links.get(new_title).unwrap();
         assert_eq!(entry, None);
-        assert_eq!(renamed_entry, &vec![String::from("wiki page")]);
+        assert_eq!(renamed_entry, &vec![Backlink::from("wiki page")]);
         teardown_temp_wiki("rename");
     }
     #[tokio::test]
+    async fn dry_run_renames_list_the_notes_they_would_relink() {
+        let _wiki = init_temp_wiki("rename-dry-run").await;
+        let linking = get_file_path("Garden plans").unwrap();
+        let unchanged = get_file_path("Seed swap").unwrap();
+        fs::write(
+            &linking,
+            "title: Garden plans\n---\nPlant [[Tomato beds]] first.",
+        )
+        .unwrap();
+        fs::write(&unchanged, "title: Seed swap\n---\nNo links here.").unwrap();
+        let mut link_tree = BTreeMap::new();
+        link_tree.insert(
+            "Tomato beds".into(),
+            vec![Backlink::from("Garden plans"), Backlink::from("Seed swap")],
+        );
+        let links: GlobalBacklinks = Arc::new(Mutex::new(link_tree));
+
+        let would_change =
+            rename_in_global_store("Raised beds", "Tomato beds", links.clone(), true).await;
+        assert_eq!(would_change, vec![linking.clone()]);
+        assert!(fs::read_to_string(&linking)
+            .unwrap()
+            .contains("[[Tomato beds]]"));
+        assert!(links.lock().await.contains_key("Tomato beds"));
+
+        let changed =
+            rename_in_global_store("Raised beds", "Tomato beds", links.clone(), false).await;
+        assert_eq!(changed, would_change);
+        assert!(fs::read_to_string(&linking)
+            .unwrap()
+            .contains("[[Raised beds]]"));
+        assert!(links.lock().await.contains_key("Raised beds"));
+        fs::remove_file(linking).unwrap();
+        fs::remove_file(unchanged).unwrap();
+    }
+    #[tokio::test]
+    async fn resolves_links_to_normalized_titles() {
+        let mut backlinks = BTreeMap::new();
+        add_to_global_store(
+            "linking page",
+            &[
+                (String::from("  My  Note "), None),
+                (String::from("My Note"), Some(String::from("second link"))),
+            ],
+            &mut backlinks,
+        )
+        .await;
+        assert_eq!(backlinks.len(), 1);
+        assert_eq!(
+            backlinks.get("My Note").unwrap(),
+            &vec![Backlink::from("linking page")]
+        );
+    }
+    #[tokio::test]
+    async fn parallel_links_match_sequential_links() {
+        let wiki_dir = format!("{}parallel/", TEST_DIR);
+        fs::create_dir_all(format!("{}nested", wiki_dir)).unwrap();
+        for entry in fs::read_dir("../wikitext/fixtures").unwrap() {
+            let entry = entry.unwrap();
+            fs::copy(
+                entry.path(),
+                PathBuf::from(&wiki_dir).join(entry.file_name()),
+            )
+            .unwrap();
+            fs::copy(
+                entry.path(),
+                PathBuf::from(&wiki_dir)
+                    .join("nested")
+                    .join(entry.file_name()),
+            )
+            .unwrap();
+        }
+        let mut paths = Vec::new();
+        collect_note_paths(Path::new(&wiki_dir), &mut paths);
+        let mut sequential = BTreeMap::new();
+        for path in paths {
+            let (title, links) = parse_note(&path).unwrap();
+            add_to_global_store(&title, &links, &mut sequential).await;
+        }
+        let parallel = build_links(Arc::new(wiki_dir.clone())).await;
+        assert!(!parallel.is_empty());
+        assert_eq!(parallel, sequential);
+        fs::remove_dir_all(wiki_dir).unwrap();
+    }
+    fn note_with_body(title: &str, body: &str) -> Note {
+        note_with_tags(title, "[]", body)
+    }
+    fn note_with_tags(title: &str, tags: &str, body: &str) -> Note {
+        let mut header = HashMap::new();
+        header.insert("title".into(), title.into());
+        header.insert("tags".into(), tags.into());
+        Note {
+            header,
+            content: body.into(),
+        }
+    }
+    #[tokio::test]
+    async fn sorts_pages_under_a_backlink() {
+        let mut backlinks = BTreeMap::new();
+        for title in ["zettel", "Bravo", "alpha", "Charlie"] {
+            add_to_global_store(title, &[(String::from("hub"), None)], &mut backlinks).await;
+        }
+        assert_eq!(
+            backlinks.get("hub").unwrap(),
+            &vec![
+                Backlink::from("alpha"),
+                Backlink::from("Bravo"),
+                Backlink::from("Charlie"),
+                Backlink::from("zettel"),
+            ]
+        );
+    }
+    #[tokio::test]
+    async fn sorts_pages_under_a_tag() {
+        let links: GlobalBacklinks = Arc::new(Mutex::new(BTreeMap::new()));
+        for title in ["soup", "Bread", "apple pie"] {
+            let note = note_with_tags(title, "[recipes]", "No links here.");
+            update_global_store(title, &note, links.clone()).await;
+        }
+        let links = links.lock().await;
+        assert_eq!(
+            links.get("recipes").unwrap(),
+            &vec![
+                Backlink::from("apple pie"),
+                Backlink::from("Bread"),
+                Backlink::from("soup"),
+            ]
+        );
+    }
+    #[tokio::test]
+    async fn removes_backlinks_for_deleted_links() {
+        let links: GlobalBacklinks = Arc::new(Mutex::new(BTreeMap::new()));
+        let note = note_with_body("source", "Links to [[first]] and [[second]].");
+        update_global_store("source", &note, links.clone()).await;
+        {
+            let links = links.lock().await;
+            assert_eq!(links.get("first").unwrap(), &vec![Backlink::from("source")]);
+            assert_eq!(
+                links.get("second").unwrap(),
+                &vec![Backlink::from("source")]
+            );
+        }
+        let note = note_with_body("source", "Only links to [[second]] now.");
+        update_global_store("source", &note, links.clone()).await;
+        let links = links.lock().await;
+        assert_eq!(links.get("first"), None);
+        assert_eq!(
+            links.get("second").unwrap(),
+            &vec![Backlink::from("source")]
+        );
+    }
+    #[tokio::test]
+    async fn records_display_text_of_aliased_links() {
+        let links: GlobalBacklinks = Arc::new(Mutex::new(BTreeMap::new()));
+        let note = note_with_body("source", "Ideas [[networked thought|connect]] here.");
+        update_global_store("source", &note, links.clone()).await;
+        let links = links.lock().await;
+        assert_eq!(
+            links.get("connect").unwrap(),
+            &vec![Backlink {
+                title: "source".into(),
+                alias: Some("networked thought".into()),
+            }]
+        );
+    }
+    #[tokio::test]
+    async fn keeps_other_notes_backlinks_on_update() {
+        let mut link_tree = BTreeMap::new();
+        link_tree.insert(
+            "first".into(),
+            vec![Backlink::from("source"), Backlink::from("other")],
+        );
+        let links: GlobalBacklinks = Arc::new(Mutex::new(link_tree));
+        let note = note_with_body("source", "No links here.");
+        update_global_store("source", &note, links.clone()).await;
+        let links = links.lock().await;
+        assert_eq!(links.get("first").unwrap(), &vec![Backlink::from("other")]);
+    }
+    #[tokio::test]
     async fn deletes_from_global_store() {
-        init_temp_wiki("delete");
+        let _wiki = init_temp_wiki("delete").await;
         let title = "Logical reality";
         let mut link_tree = BTreeMap::new();
         link_tree.insert(title.into(), vec!["wiki page".into()]);
@@ -231,4 +494,13 @@ mod tests {
         assert_eq!(entry, None);
         teardown_temp_wiki("delete");
     }
+    #[tokio::test]
+    async fn builds_links_for_an_empty_wiki() {
+        let empty = format!("{}empty", TEST_DIR);
+        fs::create_dir_all(&empty).unwrap();
+        assert!(parse_entries(PathBuf::from(&empty)).await.is_empty());
+        assert!(build_links(Arc::new(empty)).await.is_empty());
+        let missing = format!("{}missing", TEST_DIR);
+        assert!(build_links(Arc::new(missing)).await.is_empty());
+    }
 }
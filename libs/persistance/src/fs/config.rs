@@ -1,8 +1,14 @@
-use std::fs;
+use std::{
+    collections::HashMap,
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+};
 
 use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
 
-use super::utils::get_config_location;
+use super::utils::{expand_home, get_config_location};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Sync {
@@ -21,6 +27,132 @@ pub struct General {
     pub media_location: String,
     pub host: String,
     pub check_for_updates: bool,
+    #[serde(default)]
+    pub base_path: String,
+    #[serde(default)]
+    pub timezone: String,
+    #[serde(default)]
+    pub data_location: String,
+    #[serde(default = "enabled")]
+    pub cache_bust_assets: bool,
+    #[serde(default)]
+    pub worker_threads: usize,
+    #[serde(default)]
+    pub new_page_template: String,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Let visitors who aren't logged in read notes, without the editor or delete button.
+    #[serde(default)]
+    pub public_read: bool,
+    /// Note shown at `/` instead of the generated index, when set.
+    #[serde(default)]
+    pub home_note: String,
+    /// Open links to other sites in a new tab.
+    #[serde(default)]
+    pub external_links_in_new_tab: bool,
+    /// Build the links and search index in the background so the server starts right away.
+    #[serde(default)]
+    pub lazy_index: bool,
+    /// Strip trailing whitespace and end notes with a single newline when saving. Turn off to keep
+    /// note bodies byte for byte as they were submitted.
+    #[serde(default = "enabled")]
+    pub tidy_whitespace: bool,
+    /// How many months of daily notes `/journal` shows at a time.
+    #[serde(default = "default_journal_lookback_months")]
+    pub journal_lookback_months: u32,
+    /// Give headings an `id` so `/Page#some-section` links straight to them.
+    #[serde(default = "enabled")]
+    pub heading_anchors: bool,
+    /// Redirect `/Page/` to `/Page`.
+    #[serde(default = "enabled")]
+    pub redirect_trailing_slash: bool,
+    /// Sites whose links are embedded as players or viewers. Links to other sites stay plain links.
+    /// Every site tendril knows how to embed is allowed when this is left out.
+    #[serde(default)]
+    pub embed_hosts: Option<Vec<String>>,
+    /// How many notes deep `![[page]]` transclusions nest before the rest are left out.
+    #[serde(default = "default_max_transclusion_depth")]
+    pub max_transclusion_depth: usize,
+    /// Certificate to serve HTTPS with, along with `tls_key_path`.
+    #[serde(default)]
+    pub tls_cert_path: String,
+    /// Private key for `tls_cert_path`.
+    #[serde(default)]
+    pub tls_key_path: String,
+    /// Compress pages and API replies with gzip or brotli for clients that accept it.
+    #[serde(default = "enabled")]
+    pub compression: bool,
+    /// IP address the server listens on, e.g. `0.0.0.0` for every interface.
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+}
+
+fn default_bind_address() -> String {
+    Ipv4Addr::LOCALHOST.to_string()
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("bind_address {0:?} isn't an IP address, like 127.0.0.1 or ::1")]
+pub struct InvalidBindAddress(pub String);
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum TlsConfigError {
+    #[error(
+        "tls_cert_path is set but tls_key_path isn't, set both to serve HTTPS or neither for HTTP"
+    )]
+    MissingKey,
+    #[error(
+        "tls_key_path is set but tls_cert_path isn't, set both to serve HTTPS or neither for HTTP"
+    )]
+    MissingCert,
+}
+
+impl General {
+    /// Where the server listens: `bind_address` on `port`.
+    pub fn socket_address(&self) -> Result<SocketAddr, InvalidBindAddress> {
+        let address = self.bind_address.trim();
+        // Allow IPv6 addresses in brackets like in URLs, [::1]
+        let unbracketed = address.trim_start_matches('[').trim_end_matches(']');
+        unbracketed
+            .parse::<IpAddr>()
+            .map(|ip| SocketAddr::new(ip, self.port))
+            .map_err(|_| InvalidBindAddress(self.bind_address.clone()))
+    }
+
+    /// The certificate and key to serve HTTPS with, or `None` to serve plain HTTP. Setting only
+    /// one of them is an error rather than a quiet fallback to HTTP.
+    pub fn tls(&self) -> Result<Option<(PathBuf, PathBuf)>, TlsConfigError> {
+        match (self.tls_cert_path.trim(), self.tls_key_path.trim()) {
+            ("", "") => Ok(None),
+            (_, "") => Err(TlsConfigError::MissingKey),
+            ("", _) => Err(TlsConfigError::MissingCert),
+            (cert, key) => Ok(Some((
+                PathBuf::from(expand_home(cert)),
+                PathBuf::from(expand_home(key)),
+            ))),
+        }
+    }
+}
+
+/// `json` writes one JSON object per log line, for log aggregators.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn default_max_transclusion_depth() -> usize {
+    4
+}
+
+fn default_journal_lookback_months() -> u32 {
+    3
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -28,6 +160,132 @@ pub struct Config {
     pub general: General,
     pub sync: Sync,
     pub externals: Option<Externals>,
+    #[serde(default)]
+    pub search: Search,
+    #[serde(default)]
+    pub archive: Archive,
+    #[serde(default)]
+    pub session: Session,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SameSite {
+    Strict,
+    #[default]
+    Lax,
+    None,
+}
+
+/// Attributes for the login cookies. `secure` should only be turned off when serving over plain
+/// HTTP, like on a local network.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Session {
+    #[serde(default = "enabled")]
+    pub secure: bool,
+    #[serde(default)]
+    pub same_site: SameSite,
+    /// Seconds until the login expires.
+    #[serde(default = "default_session_max_age")]
+    pub max_age: u64,
+}
+
+fn default_session_max_age() -> u64 {
+    // A month
+    2629800
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            secure: true,
+            same_site: SameSite::default(),
+            max_age: default_session_max_age(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveCompression {
+    #[default]
+    Bzip2,
+    Gzip,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Archive {
+    #[serde(default)]
+    pub compression: ArchiveCompression,
+    #[serde(default = "default_compression_level")]
+    pub level: u32,
+    /// How many pages can be fetched for archiving at once. Fetches run apart from local jobs like
+    /// saving notes, so a burst of bookmarks doesn't hold those up.
+    #[serde(default = "default_concurrent_fetches")]
+    pub concurrent_fetches: usize,
+}
+
+fn default_compression_level() -> u32 {
+    9
+}
+
+fn default_concurrent_fetches() -> usize {
+    4
+}
+
+impl Default for Archive {
+    fn default() -> Self {
+        Archive {
+            compression: ArchiveCompression::default(),
+            level: default_compression_level(),
+            concurrent_fetches: default_concurrent_fetches(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Search {
+    /// Frontmatter fields, like `author`, whose values are indexed alongside note content.
+    #[serde(default)]
+    pub metadata_fields: Vec<String>,
+    /// How many results the search page shows before offering a "show more" link.
+    #[serde(default = "default_results_per_page")]
+    pub results_per_page: usize,
+    /// Words shorter than this many characters are left out of the index.
+    #[serde(default = "default_min_token_length")]
+    pub min_token_length: usize,
+    /// Index and search for the stems of words, so "running" also finds "run". Changing this
+    /// takes effect once the search index is rebuilt.
+    #[serde(default = "enabled")]
+    pub stemming: bool,
+    /// Words left out of the index, replacing the built-in English ones. An empty list keeps every
+    /// word.
+    #[serde(default)]
+    pub stop_words: Option<Vec<String>>,
+    /// How much a word counts in each field compared to the note body, e.g. `title = 3.0`. Fields
+    /// left out keep their built-in weight.
+    #[serde(default)]
+    pub field_weights: HashMap<String, f32>,
+}
+
+fn default_results_per_page() -> usize {
+    50
+}
+
+fn default_min_token_length() -> usize {
+    2
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Search {
+            metadata_fields: Vec::new(),
+            results_per_page: default_results_per_page(),
+            min_token_length: default_min_token_length(),
+            stemming: true,
+            stop_words: None,
+            field_weights: HashMap::new(),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -40,3 +298,77 @@ pub fn read_config() -> Config {
     let config: Config = toml::from_str(&fs::read_to_string(file).unwrap()).unwrap();
     config
 }
+
+/// Like `read_config`, but returns `None` instead of panicking when there's no readable config
+/// file, e.g. before tendril has been installed.
+pub fn try_read_config() -> Option<Config> {
+    let (_, file) = get_config_location();
+    let config = fs::read_to_string(file).ok()?;
+    toml::from_str(&config).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT_CONFIG: &str = include_str!("../../../../config/config.toml");
+
+    fn general(cert: &str, key: &str) -> General {
+        let mut config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        config.general.tls_cert_path = cert.into();
+        config.general.tls_key_path = key.into();
+        let config: Config = toml::from_str(&toml::to_string(&config).unwrap()).unwrap();
+        config.general
+    }
+
+    fn bind_to(address: &str) -> Result<SocketAddr, InvalidBindAddress> {
+        let config = DEFAULT_CONFIG.replace(
+            "bind_address = \"127.0.0.1\"",
+            &format!("bind_address = \"{}\"", address),
+        );
+        let config: Config = toml::from_str(&config).unwrap();
+        config.general.socket_address()
+    }
+
+    #[test]
+    fn reads_bind_addresses() {
+        assert_eq!(
+            bind_to("127.0.0.1"),
+            Ok(SocketAddr::from(([127, 0, 0, 1], 6683)))
+        );
+        assert_eq!(
+            bind_to("0.0.0.0"),
+            Ok(SocketAddr::from(([0, 0, 0, 0], 6683)))
+        );
+        let ipv6 = SocketAddr::new("::1".parse().unwrap(), 6683);
+        assert_eq!(bind_to("::1"), Ok(ipv6));
+        assert_eq!(bind_to("[::1]"), Ok(ipv6));
+        assert_eq!(
+            bind_to("localhost"),
+            Err(InvalidBindAddress(String::from("localhost")))
+        );
+        let config = DEFAULT_CONFIG.replace("bind_address = \"127.0.0.1\"\n", "");
+        let config: Config = toml::from_str(&config).unwrap();
+        assert_eq!(config.general.bind_address, "127.0.0.1");
+    }
+
+    #[test]
+    fn reads_tls_paths() {
+        assert_eq!(general("", "").tls(), Ok(None));
+        assert_eq!(
+            general("/etc/tendril/cert.pem", "/etc/tendril/key.pem").tls(),
+            Ok(Some((
+                PathBuf::from("/etc/tendril/cert.pem"),
+                PathBuf::from("/etc/tendril/key.pem")
+            )))
+        );
+        assert_eq!(
+            general("/etc/tendril/cert.pem", " ").tls(),
+            Err(TlsConfigError::MissingKey)
+        );
+        assert_eq!(
+            general("", "/etc/tendril/key.pem").tls(),
+            Err(TlsConfigError::MissingCert)
+        );
+    }
+}
@@ -1,4 +1,6 @@
+pub mod archive;
 pub mod config;
+pub mod store;
 pub mod utils;
 
 use std::fmt::Write as _;
@@ -7,10 +9,8 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use chrono::{DateTime, FixedOffset, Local};
-use directories::ProjectDirs;
+use chrono::{DateTime, FixedOffset, Local, Utc};
 use tokio::fs::{self, read_to_string};
-use tokio::task::spawn_blocking;
 use wikitext::parsers::{parse_meta, Note};
 use wikitext::PatchData;
 
@@ -21,9 +21,13 @@ use crate::fs::{
     utils::{get_file_path, normalize_wiki_location},
 };
 
+use crate::store::Store;
+
 use self::{
+    archive::decompress,
     config::Config,
-    utils::{get_archive_file_path, get_archive_location},
+    store::FsStore,
+    utils::{get_archive_file_path, get_archive_location, get_data_dir_location},
 };
 
 lazy_static::lazy_static! {
@@ -37,6 +41,7 @@ lazy_static::lazy_static! {
         }
     };
     pub(crate) static ref MEDIA_LOCATION: PathBuf = PathBuf::from(&normalize_wiki_location(&CONFIG.general.media_location));
+    static ref WIKI_STORE: FsStore = FsStore::new(WIKI_LOCATION.clone());
 }
 
 #[derive(Error, Debug)]
@@ -72,23 +77,33 @@ pub async fn write_media(filename: &str, bytes: &[u8]) -> Result<(), io::Error>
 
 pub async fn write(data: &PatchData) -> Result<(), WriteWikiError> {
     let current_title_on_disk = if data.old_title != data.title && !data.old_title.is_empty() {
-        data.old_title.clone()
+        data.old_location()
     } else {
         // wiki entires are stored by title + .md file ending
-        data.title.clone()
+        data.location()
     };
     let file_path = get_file_path(&current_title_on_disk).unwrap();
     let mut note_meta = Note::from(data);
-    let now = Local::now().format(DT_FORMAT).to_string();
+    if CONFIG.general.tidy_whitespace {
+        note_meta.content = tidy_whitespace(&note_meta.content);
+    }
+    if file_path.exists() {
+        if let Ok(existing) = read(current_title_on_disk.clone()).await {
+            merge_existing_frontmatter(&mut note_meta, existing);
+        }
+    }
+    let now = current_time();
     // In the case that we're creating a new file
     if !file_path.exists() && data.old_title.is_empty() {
-        note_meta.header.insert("created".into(), now.clone());
-        note_meta.header.insert("id".into(), now);
+        note_meta.header.remove("created");
+        note_meta
+            .header
+            .insert("id".into(), now.format(DT_FORMAT).to_string());
         note_meta
             .header
             .insert("content-type".into(), "wikitext".into());
-        let note: String = note_meta.into();
-        return match fs::write(file_path, note).await {
+        stamp_timestamps(&mut note_meta, &now);
+        return match WIKI_STORE.write(&current_title_on_disk, &note_meta).await {
             Ok(()) => Ok(()),
             Err(e) => {
                 eprintln!("Create new file err: {}", e);
@@ -96,18 +111,16 @@ pub async fn write(data: &PatchData) -> Result<(), WriteWikiError> {
             }
         };
     }
-    note_meta.header.insert("modified".into(), now.clone());
-
-    let created = note_meta.header.get("created");
-
     // HACK: Only for legacy notes
-    if created.is_none() {
-        note_meta.header.insert("created".into(), now.clone());
-        note_meta.header.insert("id".into(), now);
+    if note_meta.header.get("created").is_none() {
+        note_meta
+            .header
+            .insert("id".into(), now.format(DT_FORMAT).to_string());
         note_meta
             .header
             .insert("content-type".into(), "wikitext".into());
     }
+    stamp_timestamps(&mut note_meta, &now);
     if note_meta.header.get("id").is_none() {
         let created_time = note_meta.header.get("created").unwrap().to_owned();
         let parsed_created = match created_time.parse::<DateTime<FixedOffset>>() {
@@ -118,48 +131,19 @@ pub async fn write(data: &PatchData) -> Result<(), WriteWikiError> {
     }
     // END HACK
 
-    let final_note: String = note_meta.into();
     if data.old_title != data.title && !data.old_title.is_empty() {
-        let new_location = PathBuf::from(format!(
-            "{}{}.txt",
-            WIKI_LOCATION.to_str().unwrap(),
-            data.title
-        ));
-        if !PathBuf::from(format!(
-            "{}{}.txt",
-            WIKI_LOCATION.to_str().unwrap(),
-            data.old_title
-        ))
-        .exists()
+        match WIKI_STORE
+            .rename(&data.old_location(), &data.location(), &note_meta)
+            .await
         {
-            // This is the case where we've autofilled a default name on the web and are now
-            // renaming the note
-            match fs::write(new_location, final_note).await {
-                Ok(()) => Ok(()),
-                Err(e) => {
-                    eprintln!("write renamed file: {}", e);
-                    Err(WriteWikiError::WriteError(e))
-                }
-            }
-        } else {
-            // Rename the file to the new title
-            match fs::rename(&file_path, &new_location).await {
-                Ok(()) => match fs::write(new_location, final_note).await {
-                    Ok(()) => Ok(()),
-                    Err(e) => {
-                        eprintln!("write renamed file: {}", e);
-                        Err(WriteWikiError::WriteError(e))
-                    }
-                },
-
-                Err(e) => {
-                    eprintln!("could not perform rename action on file: {}", e);
-                    Err(WriteWikiError::WriteError(e))
-                }
+            Ok(()) => Ok(()),
+            Err(e) => {
+                eprintln!("could not rename note: {}", e);
+                Err(WriteWikiError::WriteError(e))
             }
         }
     } else {
-        match fs::write(file_path, final_note).await {
+        match WIKI_STORE.write(&current_title_on_disk, &note_meta).await {
             Ok(()) => Ok(()),
             Err(e) => {
                 eprintln!("Could not write file: {}", e);
@@ -169,39 +153,104 @@ pub async fn write(data: &PatchData) -> Result<(), WriteWikiError> {
     }
 }
 
-pub async fn delete(requested_file: &str) -> Result<(), io::Error> {
-    let file_path = get_file_path(requested_file).unwrap();
-    if !file_path.exists() {
-        return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Could not find requested file",
-        ));
+/// Strips trailing whitespace from every line and ends the body with exactly one newline, so
+/// editors that leave stray spaces behind don't show up in git diffs.
+fn tidy_whitespace(content: &str) -> String {
+    let mut tidied = content
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<&str>>()
+        .join("\n");
+    tidied.truncate(tidied.trim_end_matches('\n').len());
+    if !tidied.is_empty() {
+        tidied.push('\n');
     }
-    fs::remove_file(file_path).await?;
-    Ok(())
+    tidied
+}
+
+/// Sets `created` the first time a note is saved and bumps `updated` on every save.
+fn stamp_timestamps(note: &mut Note, now: &DateTime<FixedOffset>) {
+    let timestamp = now.to_rfc3339();
+    note.header
+        .entry("created".into())
+        .or_insert_with(|| timestamp.clone());
+    // Older versions wrote `modified`, which `updated` replaces.
+    note.header.remove("modified");
+    note.header.insert("updated".into(), timestamp);
+}
+
+/// The current time in the timezone set in the config, falling back to the system timezone.
+pub fn current_time() -> DateTime<FixedOffset> {
+    match parse_utc_offset(&CONFIG.general.timezone) {
+        Some(offset) => Utc::now().with_timezone(&offset),
+        None => Local::now().into(),
+    }
+}
+
+/// Parses a UTC offset such as `+02:00`, `-0530`, or `UTC`.
+fn parse_utc_offset(offset: &str) -> Option<FixedOffset> {
+    let offset = offset.trim();
+    if offset.eq_ignore_ascii_case("utc") || offset == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+    let sign = match offset.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = offset[1..].replace(':', "");
+    let (hours, minutes) = match digits.len() {
+        1 | 2 => (digits.as_str(), "0"),
+        4 => digits.split_at(2),
+        _ => return None,
+    };
+    let seconds = hours.parse::<i32>().ok()? * 3600 + minutes.parse::<i32>().ok()? * 60;
+    FixedOffset::east_opt(sign * seconds)
+}
+
+/// Carries over frontmatter keys from the note on disk that the edit form doesn't know about, so
+/// fields like `author` survive an edit. Values submitted with the edit always win.
+fn merge_existing_frontmatter(note: &mut Note, existing: Note) {
+    for (key, value) in existing.header {
+        note.header.entry(key).or_insert(value);
+    }
+}
+
+pub async fn delete(requested_file: &str) -> Result<(), io::Error> {
+    WIKI_STORE.delete(requested_file).await
 }
 
 pub async fn read(requested_file: String) -> Result<Note, ReadPageError> {
-    let file_path = get_file_path(&requested_file)?;
-    spawn_blocking(move || path_to_data_structure(&file_path))
-        .await
-        .unwrap()
+    WIKI_STORE.read(&requested_file).await
 }
 
+/// Titles of recently edited notes, newest first. Empty until the first note is saved.
 pub async fn read_note_cache() -> String {
-    let project_dir = ProjectDirs::from("", "", "tendril").unwrap();
-    let mut data_dir = project_dir.data_dir().to_owned();
+    let mut data_dir = get_data_dir_location();
     data_dir.push("note_cache");
-    read_to_string(&data_dir).await.unwrap()
+    read_to_string(&data_dir).await.unwrap_or_default()
 }
 
 pub async fn write_note_cache(cache: String) {
-    let project_dir = ProjectDirs::from("", "", "tendril").unwrap();
-    let mut data_dir = project_dir.data_dir().to_owned();
+    let mut data_dir = get_data_dir_location();
     data_dir.push("note_cache");
     fs::write(data_dir, cache).await.unwrap();
 }
 
+/// Titles of recently viewed notes, newest first. Older installs won't have the file until a
+/// page is viewed.
+pub async fn read_view_cache() -> String {
+    let mut data_dir = get_data_dir_location();
+    data_dir.push("view_cache");
+    read_to_string(&data_dir).await.unwrap_or_default()
+}
+
+pub async fn write_view_cache(cache: String) {
+    let mut data_dir = get_data_dir_location();
+    data_dir.push("view_cache");
+    fs::write(data_dir, cache).await.unwrap();
+}
+
 pub async fn create_journal_entry(entry: String) -> Result<PatchData, std::io::Error> {
     let now = Local::now();
     let daily_file = now.format("%Y-%m-%d").to_string();
@@ -236,6 +285,18 @@ pub async fn write_archive(compressed: Vec<u8>, title: &str) {
     fs::write(location, compressed).await.unwrap();
 }
 
+pub async fn read_archive(title: &str) -> Result<String, ReadPageError> {
+    read_archive_file(&get_archive_file_path(title)).await
+}
+
+async fn read_archive_file(location: &Path) -> Result<String, ReadPageError> {
+    match fs::read(location).await {
+        Ok(compressed) => decompress(&compressed).ok_or(ReadPageError::DeserializationError),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(ReadPageError::PageNotFoundError),
+        Err(_) => Err(ReadPageError::Unknown),
+    }
+}
+
 pub async fn move_archive(old_title: String, new_title: String) {
     let archive = get_archive_location();
     let old_location = archive.join(old_title);
@@ -245,8 +306,41 @@ pub async fn move_archive(old_title: String, new_title: String) {
 
 // TODO: this is really dependent on file system ops, won't be good if we change the storage
 // backend.
+/// Reads a note as UTF-8. Notes in other encodings, like ones imported from older tools, are
+/// decoded on a best effort basis instead of failing to load.
 pub fn path_to_string<P: AsRef<Path> + ?Sized>(path: &P) -> Result<String, std::io::Error> {
-    std::fs::read_to_string(path)
+    let bytes = std::fs::read(path)?;
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok(text),
+        Err(e) => {
+            let (text, encoding) = decode_legacy_text(e.as_bytes());
+            eprintln!(
+                "<{:?} is not UTF-8, read it as {}>",
+                path.as_ref(),
+                encoding
+            );
+            Ok(text)
+        }
+    }
+}
+
+/// UTF-16 is detected by its byte order mark. Anything else is read as Latin-1, which maps every
+/// byte to a character, so it can't fail.
+fn decode_legacy_text(bytes: &[u8]) -> (String, &'static str) {
+    match bytes {
+        [0xff, 0xfe, rest @ ..] => (decode_utf16(rest, u16::from_le_bytes), "UTF-16LE"),
+        [0xfe, 0xff, rest @ ..] => (decode_utf16(rest, u16::from_be_bytes), "UTF-16BE"),
+        _ => (bytes.iter().map(|&b| b as char).collect(), "Latin-1"),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> String {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|pair| to_unit([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
 }
 
 pub fn path_to_data_structure(path: &Path) -> Result<Note, ReadPageError> {
@@ -264,28 +358,26 @@ pub fn path_to_data_structure(path: &Path) -> Result<Note, ReadPageError> {
 }
 
 pub fn get_note_titles() -> Result<Vec<String>, io::Error> {
-    let entries = std::fs::read_dir(WIKI_LOCATION.clone())?;
-    let titles = entries
-        .filter_map(|entry| {
-            let entry = entry.unwrap();
-            if entry.file_type().unwrap().is_file()
-                && entry.file_name().to_str().unwrap().ends_with(".txt")
-            {
-                let fname = entry.file_name().to_str().unwrap().to_owned();
-                Some(fname.strip_suffix(".txt").unwrap().to_owned())
-            } else {
-                None
-            }
-        })
-        .collect();
-    Ok(titles)
+    WIKI_STORE.titles()
 }
 
 #[cfg(test)]
 mod tests {
     use crate::fs::utils::parse_location;
 
-    use std::{env, path::PathBuf};
+    use std::{collections::HashMap, env, path::PathBuf};
+
+    use wikitext::{parsers::Note, PatchData};
+
+    use chrono::{DateTime, Duration};
+
+    use super::{
+        archive::compress, config::Archive, merge_existing_frontmatter, parse_utc_offset,
+        path_to_data_structure, read_archive_file, stamp_timestamps, tidy_whitespace,
+        ReadPageError,
+    };
+
+    const ARCHIVE_TEST_DIR: &str = "/tmp/tendril-test/archive/";
 
     #[test]
     fn formats_wiki_location() {
@@ -297,4 +389,105 @@ mod tests {
             PathBuf::from("/user/test/wiki/")
         );
     }
+
+    #[test]
+    fn keeps_unknown_frontmatter_on_edit() {
+        let existing = Note::from(String::from(
+            "title: edited note\ntags: [test]\nauthor: someone\ncreated: 20220101120000\n\nold body",
+        ));
+        let patch = PatchData {
+            body: String::from("new body"),
+            tags: vec![String::from("test")],
+            title: String::from("edited note"),
+            old_title: String::from("edited note"),
+            metadata: HashMap::new(),
+            dir: String::new(),
+        };
+        let mut note = Note::from(&patch);
+        merge_existing_frontmatter(&mut note, existing);
+        assert_eq!(note.content, "new body");
+        assert_eq!(note.header.get("author").unwrap(), "someone");
+        assert_eq!(note.header.get("created").unwrap(), "20220101120000");
+        assert_eq!(note.header.get("tags").unwrap(), "[test]");
+    }
+
+    #[test]
+    fn tidies_whitespace_on_save() {
+        assert_eq!(
+            tidy_whitespace("first line  \r\n\tindented\t\nlast line "),
+            "first line\n\tindented\nlast line\n"
+        );
+        assert_eq!(tidy_whitespace("body\n\n\n"), "body\n");
+        assert_eq!(tidy_whitespace("body\n"), "body\n");
+        assert_eq!(tidy_whitespace("  \n"), "");
+    }
+
+    #[test]
+    fn stamps_created_once_and_updated_on_every_save() {
+        let offset = parse_utc_offset("+02:00").unwrap();
+        let first_save = DateTime::parse_from_rfc3339("2022-05-01T10:00:00+02:00").unwrap();
+        let mut note = Note::from(String::from("title: stamped\ntags: []\n\nbody"));
+        stamp_timestamps(&mut note, &first_save);
+        let created = note.header.get("created").unwrap().to_owned();
+        assert_eq!(created, "2022-05-01T10:00:00+02:00");
+        assert_eq!(note.header.get("updated").unwrap(), &created);
+
+        let second_save = (first_save + Duration::hours(3)).with_timezone(&offset);
+        stamp_timestamps(&mut note, &second_save);
+        assert_eq!(note.header.get("created").unwrap(), &created);
+        assert_eq!(
+            note.header.get("updated").unwrap(),
+            "2022-05-01T13:00:00+02:00"
+        );
+    }
+
+    #[test]
+    fn parses_configured_utc_offsets() {
+        assert_eq!(parse_utc_offset("+02:00").unwrap().local_minus_utc(), 7200);
+        assert_eq!(parse_utc_offset("-0530").unwrap().local_minus_utc(), -19800);
+        assert_eq!(parse_utc_offset("UTC").unwrap().local_minus_utc(), 0);
+        assert!(parse_utc_offset("").is_none());
+        assert!(parse_utc_offset("Europe/Helsinki").is_none());
+    }
+
+    #[tokio::test]
+    async fn reads_existing_archive() {
+        std::fs::create_dir_all(ARCHIVE_TEST_DIR).unwrap();
+        let location = PathBuf::from(ARCHIVE_TEST_DIR).join("Saved article");
+        std::fs::write(&location, compress("archived text", &Archive::default())).unwrap();
+        assert_eq!(read_archive_file(&location).await.unwrap(), "archived text");
+        std::fs::remove_file(location).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reports_missing_archive() {
+        let location = PathBuf::from(ARCHIVE_TEST_DIR).join("Never archived");
+        assert!(matches!(
+            read_archive_file(&location).await,
+            Err(ReadPageError::PageNotFoundError)
+        ));
+    }
+
+    #[test]
+    fn reads_non_utf8_notes() {
+        let dir = PathBuf::from("/tmp/tendril-test/encodings/");
+        std::fs::create_dir_all(&dir).unwrap();
+        let latin1 = dir.join("latin1.txt");
+        // "title: Café\ntags: []\n\nCrème brûlée" in Latin-1
+        let mut bytes = b"title: Caf\xe9\ntags: []\n\nCr\xe8me br\xfbl\xe9e".to_vec();
+        std::fs::write(&latin1, &bytes).unwrap();
+        let note = path_to_data_structure(&latin1).unwrap();
+        assert_eq!(note.header.get("title").unwrap(), "Café");
+        assert_eq!(note.content, "Crème brûlée");
+
+        let utf16 = dir.join("utf16.txt");
+        bytes = vec![0xff, 0xfe];
+        for unit in "title: Naïve\ntags: []\n\nbody".encode_utf16() {
+            bytes.extend(unit.to_le_bytes());
+        }
+        std::fs::write(&utf16, &bytes).unwrap();
+        let note = path_to_data_structure(&utf16).unwrap();
+        assert_eq!(note.header.get("title").unwrap(), "Naïve");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
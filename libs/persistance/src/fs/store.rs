@@ -0,0 +1,174 @@
+use std::{io, path::PathBuf};
+
+use async_trait::async_trait;
+use tokio::{fs, task::spawn_blocking};
+use wikitext::parsers::Note;
+
+use crate::store::Store;
+
+use super::{path_to_data_structure, ReadPageError};
+
+/// Keeps each note in a `.txt` file under `root`.
+#[derive(Debug, Clone)]
+pub struct FsStore {
+    root: PathBuf,
+}
+
+impl FsStore {
+    pub fn new(root: PathBuf) -> Self {
+        FsStore { root }
+    }
+
+    pub fn path_for(&self, location: &str) -> PathBuf {
+        let mut path = self.root.join(location);
+        path.set_extension("txt");
+        path
+    }
+
+    /// `list` without going through the async runtime, for callers that aren't async.
+    pub fn titles(&self) -> Result<Vec<String>, io::Error> {
+        let mut titles = Vec::new();
+        for entry in std::fs::read_dir(&self.root)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            if let Some(title) = entry.file_name().to_string_lossy().strip_suffix(".txt") {
+                titles.push(title.to_owned());
+            }
+        }
+        Ok(titles)
+    }
+}
+
+#[async_trait]
+impl Store for FsStore {
+    async fn read(&self, location: &str) -> Result<Note, ReadPageError> {
+        let path = self.path_for(location);
+        spawn_blocking(move || path_to_data_structure(&path))
+            .await
+            .map_err(|_| ReadPageError::Unknown)?
+    }
+
+    async fn write(&self, location: &str, note: &Note) -> Result<(), io::Error> {
+        let contents: String = note.into();
+        fs::write(self.path_for(location), contents).await
+    }
+
+    async fn rename(&self, from: &str, to: &str, note: &Note) -> Result<(), io::Error> {
+        let from = self.path_for(from);
+        if from.exists() {
+            fs::rename(&from, self.path_for(to)).await?;
+        }
+        self.write(to, note).await
+    }
+
+    async fn delete(&self, location: &str) -> Result<(), io::Error> {
+        let path = self.path_for(location);
+        if !path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Could not find requested file",
+            ));
+        }
+        fs::remove_file(path).await
+    }
+
+    async fn list(&self) -> Result<Vec<String>, io::Error> {
+        let store = self.clone();
+        spawn_blocking(move || store.titles())
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_store(namespace: &str) -> FsStore {
+        let root = PathBuf::from("/tmp/tendril-test/store/").join(namespace);
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("nested")).unwrap();
+        FsStore::new(root)
+    }
+
+    fn note(title: &str, body: &str) -> Note {
+        Note::from(format!("title: {}\ntags: [test]\n\n{}", title, body))
+    }
+
+    #[tokio::test]
+    async fn round_trips_notes() {
+        let store = test_store("round-trip");
+        store
+            .write(
+                "stored note",
+                &note("stored note", "first line\nsecond line"),
+            )
+            .await
+            .unwrap();
+        let read = store.read("stored note").await.unwrap();
+        assert_eq!(read.header.get("title").unwrap(), "stored note");
+        assert_eq!(read.header.get("tags").unwrap(), "[test]");
+        assert_eq!(read.content, "first line\nsecond line");
+
+        store
+            .write("stored note", &note("stored note", "replaced"))
+            .await
+            .unwrap();
+        assert_eq!(store.read("stored note").await.unwrap().content, "replaced");
+
+        store
+            .write("nested/deep note", &note("deep note", "nested"))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.read("nested/deep note").await.unwrap().content,
+            "nested"
+        );
+        assert_eq!(store.list().await.unwrap(), vec!["stored note"]);
+    }
+
+    #[tokio::test]
+    async fn renames_notes() {
+        let store = test_store("rename");
+        store
+            .write("old name", &note("old name", "body"))
+            .await
+            .unwrap();
+        store
+            .rename("old name", "nested/new name", &note("new name", "moved"))
+            .await
+            .unwrap();
+        assert!(!store.path_for("old name").exists());
+        assert_eq!(
+            store.read("nested/new name").await.unwrap().content,
+            "moved"
+        );
+
+        store
+            .rename("never saved", "named later", &note("named later", "fresh"))
+            .await
+            .unwrap();
+        assert_eq!(store.read("named later").await.unwrap().content, "fresh");
+    }
+
+    #[tokio::test]
+    async fn deletes_notes() {
+        let store = test_store("delete");
+        store
+            .write("short lived", &note("short lived", "body"))
+            .await
+            .unwrap();
+        store.delete("short lived").await.unwrap();
+        assert!(matches!(
+            store.read("short lived").await,
+            Err(ReadPageError::PageNotFoundError)
+        ));
+        assert!(store.list().await.unwrap().is_empty());
+        assert_eq!(
+            store.delete("short lived").await.unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
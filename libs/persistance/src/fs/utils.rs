@@ -1,24 +1,105 @@
 use directories::{ProjectDirs, UserDirs};
-use std::path::{PathBuf, MAIN_SEPARATOR};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf, MAIN_SEPARATOR},
+    sync::OnceLock,
+};
 
-use super::{ReadPageError, WIKI_LOCATION};
+use super::{config::try_read_config, ReadPageError, WIKI_LOCATION};
 
+lazy_static::lazy_static! {
+    static ref DATA_DIR_LOCATION: PathBuf = resolve_data_dir_location(
+        env::var("TENDRIL_DATA_DIR").ok(),
+        read_data_location_from_config(),
+    );
+}
+
+/// Where templates, static assets, the search index, and archives live. Defaults to the platform
+/// data directory, but can be overridden with the `TENDRIL_DATA_DIR` environment variable or the
+/// `data_location` config option, in that order of precedence.
 pub fn get_data_dir_location() -> PathBuf {
-    let project_dir = ProjectDirs::from("", "", "tendril").unwrap();
-    let data_dir = project_dir.data_dir();
-    data_dir.to_owned()
+    DATA_DIR_LOCATION.clone()
+}
+
+fn resolve_data_dir_location(
+    env_override: Option<String>,
+    config_override: Option<String>,
+) -> PathBuf {
+    match env_override
+        .into_iter()
+        .chain(config_override)
+        .find(|location| !location.trim().is_empty())
+    {
+        Some(location) => parse_location(&location),
+        None => {
+            let project_dir = ProjectDirs::from("", "", "tendril").unwrap();
+            project_dir.data_dir().to_owned()
+        }
+    }
+}
+
+// The config file doesn't exist yet while installing, so this can't go through `read_config`.
+fn read_data_location_from_config() -> Option<String> {
+    try_read_config().map(|config| config.general.data_location)
+}
+
+pub fn get_static_location() -> PathBuf {
+    static_location_in(&get_data_dir_location())
+}
+
+fn static_location_in(data_dir: &Path) -> PathBuf {
+    data_dir.join("static")
 }
 
 pub fn get_search_index_location() -> PathBuf {
-    let data_location = get_data_dir_location();
-    data_location.join("search-index")
+    search_index_location_in(&get_data_dir_location())
 }
+
+/// Where the search index lives in the data directory `data_dir`.
+pub fn search_index_location_in(data_dir: &Path) -> PathBuf {
+    data_dir.join("search-index")
+}
+
 pub fn get_search_file_index_location() -> PathBuf {
-    let data_location = get_data_dir_location();
-    data_location.join("search-index").join("file_index")
+    get_search_index_location().join("file_index")
+}
+
+/// The config file passed with `-c/--config`, used instead of the one in the config directory.
+static CONFIG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Reads the config from `path` instead of the default location. Has to be called before anything
+/// reads the config, and only once. Errors when there's no file at `path` rather than falling back
+/// to the default one, unless `init` is set since `--init` writes the config there.
+pub fn set_config_location(path: &str, init: bool) -> io::Result<()> {
+    let file = resolve_config_file(Path::new(&expand_home(path)), init)?;
+    CONFIG_FILE.set(file).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "the config location has already been set",
+        )
+    })
+}
+
+/// The absolute path of the config file at `path`, which only has to exist yet when not `init`.
+fn resolve_config_file(path: &Path, init: bool) -> io::Result<PathBuf> {
+    match fs::canonicalize(path) {
+        Ok(file) if file.is_file() => Ok(file),
+        Ok(file) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is not a file", file),
+        )),
+        Err(e) if init && e.kind() == io::ErrorKind::NotFound => Ok(env::current_dir()?.join(path)),
+        Err(e) => Err(e),
+    }
 }
 
+/// The directory holding the config, along with the config file itself. User styles live next to
+/// the config file.
 pub fn get_config_location() -> (PathBuf, PathBuf) {
+    if let Some(file) = CONFIG_FILE.get() {
+        let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+        return (dir, file.clone());
+    }
     let project_dir = ProjectDirs::from("", "", "tendril").unwrap();
     let config_dir = project_dir.config_dir();
     let mut config_path = PathBuf::from(config_dir);
@@ -40,18 +121,22 @@ pub fn get_file_path(requested_file: &str) -> Result<PathBuf, ReadPageError> {
     Ok(file_path)
 }
 
-pub fn parse_location(location: &str) -> PathBuf {
-    let mut loc: String;
+/// `location` with `~` replaced by the home directory.
+pub fn expand_home(location: &str) -> String {
     if location.contains('~') {
         if let Some(dirs) = UserDirs::new() {
             let home_dir: String = dirs.home_dir().to_string_lossy().into();
-            loc = location.replace('~', &home_dir);
+            location.replace('~', &home_dir)
         } else {
-            loc = location.replace('~', &std::env::var("HOME").unwrap());
+            location.replace('~', &std::env::var("HOME").unwrap())
         }
     } else {
-        loc = location.to_owned();
+        location.to_owned()
     }
+}
+
+pub fn parse_location(location: &str) -> PathBuf {
+    let mut loc = expand_home(location);
     if !loc.ends_with(MAIN_SEPARATOR) {
         loc.push(MAIN_SEPARATOR)
     }
@@ -67,14 +152,29 @@ pub fn normalize_wiki_location(wiki_location: &str) -> String {
     location.to_string_lossy().into()
 }
 
+/// Gets the media directory ready for uploads, creating it when it's missing and checking that files
+/// can be written to it, so a bad `media_location` is caught at startup instead of on upload.
+pub fn prepare_media_location(media_location: &str) -> io::Result<PathBuf> {
+    let location = parse_location(media_location);
+    fs::create_dir_all(&location)?;
+    let probe = location.join(".tendril-write-check");
+    fs::write(&probe, b"")?;
+    fs::remove_file(&probe)?;
+    Ok(location)
+}
+
 pub fn archive_file_exists(title: &str) -> bool {
     let location = get_archive_file_path(title);
     location.exists()
 }
 
 pub fn get_archive_location() -> PathBuf {
-    let stored_location = get_data_dir_location();
-    stored_location.join("archive")
+    archive_location_in(&get_data_dir_location())
+}
+
+/// Where archived pages live in the data directory `data_dir`.
+pub fn archive_location_in(data_dir: &Path) -> PathBuf {
+    data_dir.join("archive")
 }
 
 pub fn get_archive_file_path(title: &str) -> PathBuf {
@@ -88,3 +188,68 @@ pub fn get_todo_location() -> PathBuf {
     base_path.push("todo.txt");
     base_path
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_the_config_override_path() {
+        let dir = PathBuf::from("/tmp/tendril-test/config-override/");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("my.toml");
+        fs::write(&file, "").unwrap();
+        let missing = dir.join("missing.toml");
+
+        assert_eq!(resolve_config_file(&file, false).unwrap(), file);
+        assert_eq!(
+            resolve_config_file(&missing, false).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(
+            resolve_config_file(&dir, false).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(resolve_config_file(&missing, true).unwrap(), missing);
+        assert_eq!(
+            resolve_config_file(Path::new("missing.toml"), true).unwrap(),
+            env::current_dir().unwrap().join("missing.toml")
+        );
+    }
+
+    #[test]
+    fn honors_data_dir_override_for_static_location() {
+        let data_dir = resolve_data_dir_location(
+            Some(String::from("/tmp/tendril-data")),
+            Some(String::from("/tmp/from-config")),
+        );
+        assert_eq!(data_dir, PathBuf::from("/tmp/tendril-data/"));
+        assert_eq!(
+            static_location_in(&data_dir),
+            PathBuf::from("/tmp/tendril-data/static")
+        );
+
+        let data_dir =
+            resolve_data_dir_location(Some(String::new()), Some(String::from("/tmp/from-config")));
+        assert_eq!(
+            static_location_in(&data_dir),
+            PathBuf::from("/tmp/from-config/static")
+        );
+    }
+
+    #[test]
+    fn creates_missing_media_location() {
+        let root = PathBuf::from("/tmp/tendril-test/media/");
+        let _ = fs::remove_dir_all(&root);
+        let media = root.join("uploads/images");
+        let prepared = prepare_media_location(media.to_str().unwrap()).unwrap();
+        assert!(prepared.is_dir());
+        assert_eq!(fs::read_dir(&prepared).unwrap().count(), 0);
+
+        // A file where a parent directory should be can't be fixed by creating directories
+        fs::write(root.join("not-a-dir"), "").unwrap();
+        let blocked = root.join("not-a-dir/media");
+        assert!(prepare_media_location(blocked.to_str().unwrap()).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,23 @@
+use std::io;
+
+use async_trait::async_trait;
+use wikitext::parsers::Note;
+
+use crate::fs::ReadPageError;
+
+/// Where notes are kept. Notes are addressed by their location relative to the wiki root, without
+/// a file extension, like `garden` or `projects/garden`. The local filesystem is the only backend
+/// so far, see [`crate::fs::store::FsStore`].
+#[async_trait]
+pub trait Store: Send + Sync {
+    async fn read(&self, location: &str) -> Result<Note, ReadPageError>;
+    /// Creates the note, or replaces it if it already exists.
+    async fn write(&self, location: &str, note: &Note) -> Result<(), io::Error>;
+    /// Moves the note at `from` to `to` and saves it as `note`. When there's no note at `from`,
+    /// like a new note renamed before it was first saved, it's only written to `to`.
+    async fn rename(&self, from: &str, to: &str, note: &Note) -> Result<(), io::Error>;
+    /// Fails with `NotFound` when there's no note at `location`.
+    async fn delete(&self, location: &str) -> Result<(), io::Error>;
+    /// Locations of the notes at the top level of the wiki.
+    async fn list(&self) -> Result<Vec<String>, io::Error>;
+}
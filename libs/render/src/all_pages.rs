@@ -1,5 +1,7 @@
 use async_trait::async_trait;
 
+use wikitext::parsers::with_base_path;
+
 use crate::{get_template_file, render_includes, Render};
 
 type PageEntries<'a> = Vec<(&'a String, usize)>;
@@ -23,7 +25,7 @@ impl<'a> Render for PageList<'a> {
             .map(|(name, num_links)| {
                 format!(
                     "<tr><td><a href=\"{}\">{}</a></td><td style=\"text-align: center;\">{}</td></tr>",
-                    name,
+                    with_base_path(&format!("/{}", name)),
                     name,
                     num_links
                 )
@@ -0,0 +1,90 @@
+use crate::{get_template_file, render_includes, Render};
+use async_trait::async_trait;
+use wikitext::parsers::{escape_html, format_links};
+
+pub struct ArchivePage {
+    pub title: String,
+    /// The decompressed archive, which is the archived page's text rather than its HTML, or `None`
+    /// when the page was never archived.
+    pub archive: Option<String>,
+}
+
+impl ArchivePage {
+    pub fn new(title: String, archive: Option<String>) -> Self {
+        Self { title, archive }
+    }
+    fn render_archive(&self) -> String {
+        match &self.archive {
+            Some(archive) => archive
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| format!("<p>{}</p>", escape_html(line.trim())))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            None => String::from("<p>There's no archived copy of this page.</p>"),
+        }
+    }
+}
+
+#[async_trait]
+impl Render for ArchivePage {
+    async fn render(&self) -> String {
+        let mut ctx = get_template_file("archive_page").await.unwrap();
+        ctx = ctx
+            .replace("<%= title %>", &escape_html(&self.title))
+            .replace("<%= link %>", &format_links(&self.title))
+            .replace("<%= archive %>", &self.render_archive());
+        render_includes(ctx, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_archive_as_paragraphs() {
+        let page = ArchivePage::new(
+            String::from("Saved article"),
+            Some(String::from(
+                "First paragraph\n\n<script>alert(1)</script>\nSecond",
+            )),
+        );
+        assert_eq!(
+            page.render_archive(),
+            "<p>First paragraph</p>\n<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>\n<p>Second</p>"
+        );
+    }
+
+    #[test]
+    fn escapes_archived_markup() {
+        let page = ArchivePage::new(
+            String::from("Saved article"),
+            Some(String::from(
+                "<img src=x onerror=alert(1)>\n<iframe src=\"javascript:alert(1)\"></iframe>",
+            )),
+        );
+        assert_eq!(
+            page.render_archive(),
+            "<p>&lt;img src=x onerror=alert(1)&gt;</p>\n<p>&lt;iframe src=&quot;javascript:alert(1)&quot;&gt;&lt;/iframe&gt;</p>"
+        );
+    }
+
+    #[tokio::test]
+    async fn escapes_the_title() {
+        let page = ArchivePage::new(String::from("<script>alert(1)</script>"), None);
+        let html = page.render().await;
+        assert!(!html.contains("<script>alert(1)</script>"));
+        assert!(html.contains("<title>&lt;script&gt;alert(1)&lt;/script&gt; (archived)</title>"));
+        assert!(html.contains(r#"content="&lt;script&gt;alert(1)&lt;/script&gt; (archived)""#));
+    }
+
+    #[test]
+    fn explains_missing_archive() {
+        let page = ArchivePage::new(String::from("Never archived"), None);
+        assert_eq!(
+            page.render_archive(),
+            "<p>There's no archived copy of this page.</p>"
+        );
+    }
+}
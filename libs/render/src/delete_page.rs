@@ -0,0 +1,27 @@
+use crate::{get_template_file, render_includes, Render};
+use async_trait::async_trait;
+use wikitext::parsers::{escape_html, format_links};
+
+/// Asks before deleting a note. The form carries a one-time token, so only this page can delete it.
+pub struct DeletePage {
+    pub title: String,
+    pub token: String,
+}
+
+impl DeletePage {
+    pub fn new(title: String, token: String) -> Self {
+        Self { title, token }
+    }
+}
+
+#[async_trait]
+impl Render for DeletePage {
+    async fn render(&self) -> String {
+        let mut ctx = get_template_file("delete_page").await.unwrap();
+        ctx = ctx
+            .replace("<%= title %>", &escape_html(&self.title))
+            .replace("<%= link %>", &format_links(&self.title))
+            .replace("<%= token %>", &self.token);
+        render_includes(ctx, None).await
+    }
+}
@@ -0,0 +1,145 @@
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use std::fmt::Write as _;
+use wikitext::parsers::{escape_html, format_links, with_base_path, Note};
+
+use crate::{last_changed, Render};
+
+/// A recently changed note in the feed.
+pub struct FeedEntry {
+    pub title: String,
+    /// When the note was last changed, `None` for notes without a readable timestamp.
+    pub updated: Option<DateTime<FixedOffset>>,
+    /// The start of the note, as HTML.
+    pub summary: String,
+}
+
+impl FeedEntry {
+    pub fn new(title: String, note: &Note, summary: String) -> Self {
+        FeedEntry {
+            title,
+            updated: last_changed(note),
+            summary,
+        }
+    }
+}
+
+/// An Atom feed of recently changed notes, newest first.
+pub struct FeedPage {
+    pub user: String,
+    pub host: String,
+    pub entries: Vec<FeedEntry>,
+}
+
+impl FeedPage {
+    pub fn new(user: String, host: String, entries: Vec<FeedEntry>) -> Self {
+        FeedPage {
+            user,
+            host,
+            entries,
+        }
+    }
+
+    /// When the newest entry changed. Entries without a timestamp count as changed then too, since
+    /// Atom requires one.
+    fn updated(&self) -> DateTime<FixedOffset> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.updated)
+            .max()
+            .unwrap_or_else(|| Utc::now().into())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.host.trim_end_matches('/'), path)
+    }
+
+    fn render_entries(&self, updated: &DateTime<FixedOffset>) -> String {
+        let mut entries = String::new();
+        for entry in self.entries.iter() {
+            let link = escape_html(&self.url(&format_links(&entry.title)));
+            write!(
+                entries,
+                r#"<entry><title>{}</title><link href="{}"/><id>{}</id><updated>{}</updated><summary type="html">{}</summary></entry>"#,
+                escape_html(&entry.title),
+                link,
+                link,
+                format_date(entry.updated.as_ref().unwrap_or(updated)),
+                escape_html(&entry.summary),
+            )
+            .unwrap();
+        }
+        entries
+    }
+}
+
+/// Dates as RFC 3339, which Atom requires.
+fn format_date(date: &DateTime<FixedOffset>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+#[async_trait]
+impl Render for FeedPage {
+    async fn render(&self) -> String {
+        let updated = self.updated();
+        let home = escape_html(&self.url(&format_links("")));
+        let author = if self.user.is_empty() {
+            String::with_capacity(0)
+        } else {
+            format!("<author><name>{}</name></author>", escape_html(&self.user))
+        };
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?><feed xmlns="http://www.w3.org/2005/Atom"><title>Recent changes</title><link href="{}"/><link rel="self" href="{}"/><id>{}</id><updated>{}</updated>{}{}</feed>"#,
+            home,
+            escape_html(&self.url(&with_base_path("/feed.xml"))),
+            home,
+            format_date(&updated),
+            author,
+            self.render_entries(&updated),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, updated: Option<&str>) -> FeedEntry {
+        FeedEntry {
+            title: title.into(),
+            updated: updated.map(|date| DateTime::parse_from_rfc3339(date).unwrap()),
+            summary: String::from("turn the <mark>compost</mark> &amp; water"),
+        }
+    }
+
+    #[tokio::test]
+    async fn renders_atom_entries() {
+        let feed = FeedPage::new(
+            String::from("Ann & Bo"),
+            String::from("https://wiki.example.com/"),
+            vec![
+                entry("Salt & <Pepper>", Some("2022-03-04T10:20:30+02:00")),
+                entry("Compost", None),
+            ],
+        );
+        let xml = feed.render().await;
+        assert!(xml.starts_with(
+            r#"<?xml version="1.0" encoding="utf-8"?><feed xmlns="http://www.w3.org/2005/Atom">"#
+        ));
+        assert_eq!(xml.matches("<entry>").count(), 2);
+        assert!(xml.contains("<title>Salt &amp; &lt;Pepper&gt;</title>"));
+        assert!(
+            xml.contains(r#"<link href="https://wiki.example.com/Salt%20%26%20%3CPepper%3E"/>"#)
+        );
+        assert!(xml.contains("<author><name>Ann &amp; Bo</name></author>"));
+        assert!(xml.contains(
+            "<summary type=\"html\">turn the &lt;mark&gt;compost&lt;/mark&gt; &amp;amp; water</summary>"
+        ));
+        // The feed and undated entries take the newest entry's date
+        assert_eq!(
+            xml.matches("<updated>2022-03-04T10:20:30+02:00</updated>")
+                .count(),
+            3
+        );
+    }
+}
@@ -2,8 +2,8 @@ use crate::{
     get_template_file, render_includes, render_page_backlinks, render_page_metadata, Render,
 };
 use async_trait::async_trait;
-use persistance::fs::{config::read_config, ReadPageError};
-use wikitext::GlobalBacklinks;
+use persistance::fs::{config::read_config, get_note_titles, ReadPageError};
+use wikitext::{parsers::with_base_path, GlobalBacklinks};
 
 pub struct IndexPage {
     pub user: String,
@@ -32,6 +32,13 @@ impl IndexPage {
             String::with_capacity(0)
         }
     }
+    /// Points a brand-new wiki towards its first note.
+    fn render_empty_wiki(&self) -> String {
+        match get_note_titles() {
+            Ok(titles) if titles.is_empty() => empty_wiki_notice(),
+            _ => String::with_capacity(0),
+        }
+    }
     async fn render_today(&self) -> String {
         let mut content = get_template_file("content").await.unwrap();
         match persistance::fs::read(self.today.clone()).await {
@@ -82,6 +89,13 @@ impl IndexPage {
     }
 }
 
+fn empty_wiki_notice() -> String {
+    format!(
+        r#"<p class="empty-wiki">Your wiki is empty. <a href="{}">Create your first note</a>, or start today's entry below.</p>"#,
+        with_base_path("/new")
+    )
+}
+
 #[async_trait]
 impl Render for IndexPage {
     async fn render(&self) -> String {
@@ -92,9 +106,21 @@ impl Render for IndexPage {
             .replace("<%= user %>", &self.user)
             .replace("<%= host %>", &self.host)
             .replace("<%= nav %>", &nav)
+            .replace("<%= emptyWiki %>", &self.render_empty_wiki())
             .replace("<%= content %>", &self.render_today().await);
         render_includes(ctx, None)
             .await
             .replace("<%= title %>", &self.today)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offers_to_create_the_first_note() {
+        let notice = empty_wiki_notice();
+        assert!(notice.contains(r#"<a href="/new">Create your first note</a>"#));
+    }
+}
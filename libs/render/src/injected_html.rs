@@ -4,17 +4,28 @@ use wikitext::{parsers::TemplattedPage, processors::sanitize_html};
 
 use crate::{
     get_template_file, render_includes, render_page_backlinks, render_page_metadata,
-    PageRenderLinks, Render,
+    wiki_page::without_edit_controls, PageRenderLinks, Render,
 };
 
 pub struct InjectedHTML<'a> {
     page: &'a TemplattedPage,
     links: PageRenderLinks<'a>,
+    editable: bool,
 }
 
 impl<'a> InjectedHTML<'a> {
     pub fn new(page: &'a TemplattedPage, links: PageRenderLinks<'a>) -> Self {
-        Self { page, links }
+        Self {
+            page,
+            links,
+            editable: true,
+        }
+    }
+
+    /// Whoever is viewing the page can change it. When they can't, the editor is left out.
+    pub fn editable(mut self, editable: bool) -> Self {
+        self.editable = editable;
+        self
     }
 }
 
@@ -46,9 +57,14 @@ impl<'a> Render for InjectedHTML<'a> {
                 "<%= metadata %>",
                 &render_page_metadata(page.metadata.clone()),
             );
-        render_includes(ctx, Some(page))
+        let rendered = render_includes(ctx, Some(page))
             .await
             .replace("<%= nav %>", &nav)
-            .replace("<%= title %>", &page.title)
+            .replace("<%= title %>", &page.title);
+        if self.editable {
+            rendered
+        } else {
+            without_edit_controls(&rendered)
+        }
     }
 }
@@ -0,0 +1,156 @@
+use async_trait::async_trait;
+use chrono::{Months, NaiveDate};
+use wikitext::parsers::with_base_path;
+
+use crate::{get_template_file, render_includes, Render};
+
+/// Daily notes, newest first, a window of `months` at a time so the journal stays quick to load
+/// after years of entries. Page 0 covers the last `months` months, page 1 the ones before that,
+/// and so on.
+pub struct JournalPage {
+    entries: Vec<NaiveDate>,
+    page: usize,
+    has_older: bool,
+    since: NaiveDate,
+    until: NaiveDate,
+}
+
+impl JournalPage {
+    pub fn new(titles: &[String], today: NaiveDate, months: u32, page: usize) -> Self {
+        let months = months.max(1);
+        let months_back = |n: usize| {
+            u32::try_from(n)
+                .ok()
+                .and_then(|n| n.checked_mul(months))
+                .and_then(|back| today.checked_sub_months(Months::new(back)))
+                .unwrap_or(NaiveDate::MIN)
+        };
+        let until = months_back(page);
+        let since = months_back(page + 1);
+        let mut dates = titles
+            .iter()
+            .filter_map(|title| NaiveDate::parse_from_str(title, "%Y-%m-%d").ok())
+            .collect::<Vec<NaiveDate>>();
+        dates.sort_unstable_by(|a, b| b.cmp(a));
+        let has_older = dates.iter().any(|date| *date <= since);
+        let entries = dates
+            .into_iter()
+            .filter(|date| *date > since && *date <= until)
+            .collect();
+        Self {
+            entries,
+            page,
+            has_older,
+            since,
+            until,
+        }
+    }
+
+    fn render_entries(&self) -> String {
+        if self.entries.is_empty() {
+            return String::from("<li>No entries.</li>");
+        }
+        self.entries
+            .iter()
+            .map(|date| {
+                let title = date.format("%Y-%m-%d");
+                format!(
+                    r#"<li><a href="{}">{}</a></li>"#,
+                    with_base_path(&format!("/{}", title)),
+                    title
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn render_pagination(&self) -> String {
+        let link = |page: usize, text: &str| {
+            format!(
+                r#"<a href="{}">{}</a>"#,
+                with_base_path(&format!("/journal?page={}", page)),
+                text
+            )
+        };
+        let newer = if self.page > 0 {
+            link(self.page - 1, "&larr; Newer")
+        } else {
+            String::from("<span></span>")
+        };
+        let older = if self.has_older {
+            link(self.page + 1, "Older &rarr;")
+        } else {
+            String::from("<span></span>")
+        };
+        format!("{}{}", newer, older)
+    }
+}
+
+#[async_trait]
+impl Render for JournalPage {
+    async fn render(&self) -> String {
+        let ctx = get_template_file("journal").await.unwrap();
+        let nav = get_template_file("nav").await.unwrap();
+        let period = format!(
+            "{} to {}",
+            self.since
+                .succ_opt()
+                .unwrap_or(self.since)
+                .format("%Y-%m-%d"),
+            self.until.format("%Y-%m-%d")
+        );
+        let ctx = ctx
+            .replace("<%= period %>", &period)
+            .replace("<%= entries %>", &self.render_entries())
+            .replace("<%= pagination %>", &self.render_pagination());
+        render_includes(ctx, None).await.replace("<%= nav %>", &nav)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn titles() -> Vec<String> {
+        [
+            "2023-06-01",
+            "2023-05-20",
+            "2023-02-14",
+            "2022-11-30",
+            "Garden",
+            "2023-13-40",
+        ]
+        .iter()
+        .map(|t| t.to_string())
+        .collect()
+    }
+
+    #[test]
+    fn pages_through_older_entries() {
+        let today = NaiveDate::from_ymd_opt(2023, 6, 10).unwrap();
+        let recent = JournalPage::new(&titles(), today, 3, 0);
+        let rendered = recent.render_entries();
+        assert!(rendered.contains(">2023-06-01<"));
+        assert!(rendered.contains(">2023-05-20<"));
+        assert!(!rendered.contains("2023-02-14"));
+        assert!(!rendered.contains("2022-11-30"));
+        assert!(rendered.find("2023-06-01") < rendered.find("2023-05-20"));
+        assert_eq!(
+            recent.render_pagination(),
+            r#"<span></span><a href="/journal?page=1">Older &rarr;</a>"#
+        );
+
+        let older = JournalPage::new(&titles(), today, 3, 1);
+        let rendered = older.render_entries();
+        assert!(rendered.contains(">2023-02-14<"));
+        assert!(!rendered.contains("2023-05-20"));
+        assert!(older.render_pagination().contains("/journal?page=2"));
+
+        let oldest = JournalPage::new(&titles(), today, 3, 2);
+        assert!(oldest.render_entries().contains(">2022-11-30<"));
+        assert_eq!(
+            oldest.render_pagination(),
+            r#"<a href="/journal?page=1">&larr; Newer</a><span></span>"#
+        );
+    }
+}
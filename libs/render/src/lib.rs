@@ -4,23 +4,31 @@ use std::io;
 
 use chrono::{DateTime, FixedOffset};
 #[cfg(not(debug_assertions))]
-use directories::ProjectDirs;
+use persistance::fs::utils::get_data_dir_location;
 
 use async_trait::async_trait;
 use futures::{stream, StreamExt};
 use tokio::fs;
-use wikitext::parsers::{format_links, TemplattedPage};
+use wikitext::parsers::{
+    base_path, escape_html, format_links, with_base_path, Note, TemplattedPage,
+};
+use wikitext::{encode_path, Backlink};
 
 pub mod all_pages;
+pub mod archive_page;
 pub mod bookmark_page;
+pub mod delete_page;
 pub mod error_page;
+pub mod feed_page;
 pub mod file_upload_page;
 pub mod help_page;
 pub mod index_page;
 pub mod injected_html;
+pub mod journal_page;
 pub mod login_page;
 pub mod new_page;
 pub mod opensearch_page;
+pub mod recent_changes_page;
 pub mod search_results_page;
 pub mod static_site_page;
 pub mod styles_page;
@@ -33,7 +41,7 @@ pub enum CompileState {
     Dynamic,
 }
 
-pub type PageRenderLinks<'a> = Option<&'a Vec<String>>;
+pub type PageRenderLinks<'a> = Option<&'a Vec<Backlink>>;
 
 #[async_trait]
 pub trait Render {
@@ -52,14 +60,20 @@ pub fn parse_includes(include_str: &str) -> String {
 
 async fn process_included_file(file: String, page: Option<&TemplattedPage>) -> String {
     match file.as_ref() {
-        "search" => get_template_file("search").await.unwrap(),
+        "search" => {
+            let templatefile = get_template_file("search").await.unwrap();
+            let title_url = page
+                .map(|page| encode_path(&page.title))
+                .unwrap_or_default();
+            templatefile.replace("<%= title_url %>", &title_url)
+        }
         "styles" => get_template_file("styles").await.unwrap(),
         "meta" => {
             let templatefile = get_template_file("meta").await.unwrap();
             let page = page.unwrap();
             let icon_path = match &page.metadata.get("icon") {
-                Some(icon) => format!("/files/{}", icon),
-                None => String::from("static/favicon.ico"),
+                Some(icon) => with_base_path(&format!("/files/{}", icon)),
+                None => with_base_path("/static/favicon.ico"),
             };
             templatefile
                 .replace("<%= title %>", &page.title)
@@ -87,7 +101,7 @@ pub async fn render_includes(ctx: String, page: Option<&TemplattedPage>) -> Stri
 pub async fn get_template_file(requested_file: &str) -> Result<String, io::Error> {
     let file_path = get_template_location(requested_file);
     if let Ok(filestring) = fs::read_to_string(&file_path).await {
-        Ok(filestring)
+        Ok(filestring.replace("<%= base %>", base_path()))
     } else {
         eprintln!("Could not find {}", requested_file);
         Err(io::Error::new(
@@ -97,6 +111,14 @@ pub async fn get_template_file(requested_file: &str) -> Result<String, io::Error
     }
 }
 
+/// When a note was last changed, going by its frontmatter.
+pub(crate) fn last_changed(note: &Note) -> Option<DateTime<FixedOffset>> {
+    ["updated", "modified", "created"]
+        .iter()
+        .filter_map(|key| note.header.get(*key))
+        .find_map(|date| DateTime::parse_from_rfc3339(date.trim()).ok())
+}
+
 pub fn render_page_metadata(metadata: HashMap<String, String>) -> String {
     let mut metadata_html = String::new();
     if metadata.is_empty() {
@@ -107,7 +129,7 @@ pub fn render_page_metadata(metadata: HashMap<String, String>) -> String {
         // TODO: Add "created" date here as well
         // TODO: Modify dates to be compliant with DT parsing
         match key.as_str() {
-            "modified" | "created" => {
+            "modified" | "created" | "updated" => {
                 if let Ok(val) = value.parse::<DateTime<FixedOffset>>() {
                     let val = val.format("%Y-%m-%d %H:%M").to_string();
                     write!(metadata_html, "<dd>{}</dd>", val).unwrap();
@@ -142,20 +164,33 @@ pub fn render_page_metadata(metadata: HashMap<String, String>) -> String {
     metadata_html
 }
 
-
+/// Templates straight from the repo, so development builds and tests don't need them installed.
 #[cfg(debug_assertions)]
 fn get_template_location(requested_file: &str) -> String {
+    let templates = concat!(env!("CARGO_MANIFEST_DIR"), "/../../templates");
     if requested_file.contains('.') {
-        return format!("templates/{}", requested_file);
+        return format!("{}/{}", templates, requested_file);
     }
-    format!("templates/{}.html", requested_file)
+    format!("{}/{}.html", templates, requested_file)
 }
 
-pub fn render_page_backlinks(links: Vec<String>) -> String {
+pub fn render_page_backlinks(links: Vec<Backlink>) -> String {
     if !links.is_empty() {
         let backlinks_string = links
             .iter()
-            .map(|l| format!("<a href=\"{}\">{}</a>", format_links(l), l))
+            .map(|l| match &l.alias {
+                Some(alias) => format!(
+                    "<a href=\"{}\">{} <span class=\"backlink-alias\">as &ldquo;{}&rdquo;</span></a>",
+                    format_links(&l.title),
+                    escape_html(&l.title),
+                    escape_html(alias)
+                ),
+                None => format!(
+                    "<a href=\"{}\">{}</a>",
+                    format_links(&l.title),
+                    escape_html(&l.title)
+                ),
+            })
             .collect::<Vec<String>>()
             .join("\n");
         format!(
@@ -175,8 +210,7 @@ pub fn render_page_backlinks(links: Vec<String>) -> String {
 
 #[cfg(not(debug_assertions))]
 fn get_template_location(requested_file: &str) -> String {
-    let project_dir = ProjectDirs::from("", "", "tendril").unwrap();
-    let mut data_dir = project_dir.data_dir().to_owned();
+    let mut data_dir = get_data_dir_location();
 
     if requested_file.contains('.') {
         data_dir.push(format!("templates/{}", requested_file));
@@ -185,3 +219,34 @@ fn get_template_location(requested_file: &str) -> String {
     }
     data_dir.to_string_lossy().into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shows_display_text_of_aliased_backlinks() {
+        let rendered = render_page_backlinks(vec![
+            Backlink::from("direct note"),
+            Backlink {
+                title: "aliased note".into(),
+                alias: Some("networked thought".into()),
+            },
+        ]);
+        assert!(rendered.contains(r#"<a href="/direct%20note">direct note</a>"#));
+        assert!(rendered.contains(
+            r#"<a href="/aliased%20note">aliased note <span class="backlink-alias">as &ldquo;networked thought&rdquo;</span></a>"#
+        ));
+    }
+
+    #[test]
+    fn escapes_backlink_aliases() {
+        let rendered = render_page_backlinks(vec![Backlink {
+            title: "Q&A".into(),
+            alias: Some("<img src=x onerror=alert(1)>".into()),
+        }]);
+        assert!(rendered.contains(
+            r#"<a href="/Q%26A">Q&amp;A <span class="backlink-alias">as &ldquo;&lt;img src=x onerror=alert(1)&gt;&rdquo;</span></a>"#
+        ));
+    }
+}
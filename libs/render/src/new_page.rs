@@ -1,5 +1,7 @@
 use crate::{get_template_file, render_includes, Render};
 use async_trait::async_trait;
+use persistance::fs::config::read_config;
+use wikitext::parsers::to_html;
 
 pub struct NewPage<'a> {
     pub title: Option<String>,
@@ -42,6 +44,30 @@ impl<'a> NewPage<'a> {
             String::new()
         }
     }
+    fn get_body(&self) -> String {
+        use chrono::Local;
+        let date = Local::now().format("%Y-%m-%d").to_string();
+        let template = read_config().general.new_page_template;
+        render_new_page_body(&template, &self.get_note_title(), &date, &self.get_linkto())
+    }
+}
+
+/// The starting body for a new page: the configured template with `{{title}}` and `{{date}}`
+/// filled in, after a link back to the page it was created from, if any.
+fn render_new_page_body(template: &str, title: &str, date: &str, linkto: &str) -> String {
+    let linkto_block = format!(r#"<div class="text-block" tabindex="0">{}</div>"#, linkto);
+    if template.trim().is_empty() {
+        return linkto_block;
+    }
+    let expanded = template
+        .replace("{{title}}", title)
+        .replace("{{date}}", date);
+    let body = to_html(expanded.trim()).body;
+    if linkto.is_empty() {
+        body
+    } else {
+        linkto_block + &body
+    }
 }
 
 #[async_trait]
@@ -50,10 +76,7 @@ impl<'a> Render for NewPage<'a> {
         let mut ctx = get_template_file("new_page").await.unwrap();
         let mut content = get_template_file("content").await.unwrap();
         let nav = get_template_file("nav").await.unwrap();
-        let body = format!(
-            r#"<div class="text-block" tabindex="0">{}</div>"#,
-            self.get_linkto()
-        );
+        let body = self.get_body();
         content = content
             .replace("<%= body %>", &body)
             .replace("<%= metadata %>", "")
@@ -69,3 +92,28 @@ impl<'a> Render for NewPage<'a> {
             .replace("<%= title %>", &self.get_note_title())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_default_new_page_body() {
+        let body = render_new_page_body(
+            "# {{title}}\nCreated on {{date}}",
+            "Garden log",
+            "2023-04-01",
+            "",
+        );
+        assert_eq!(
+            body,
+            r#"<div data-indent="0" class="text-block"><h1 id="garden-log">Garden log</h1></div><div data-indent="0" class="text-block">Created on 2023-04-01</div>"#
+        );
+    }
+
+    #[test]
+    fn keeps_empty_body_without_a_template() {
+        let body = render_new_page_body("", "Garden log", "2023-04-01", "");
+        assert_eq!(body, r#"<div class="text-block" tabindex="0"></div>"#);
+    }
+}
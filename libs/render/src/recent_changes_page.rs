@@ -0,0 +1,95 @@
+use crate::{get_template_file, last_changed, render_includes, Render};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use std::fmt::Write as _;
+use wikitext::parsers::{escape_html, format_links, Note};
+
+/// A recently edited note.
+pub struct RecentChange {
+    pub title: String,
+    /// When the note was last changed, `None` for notes without a readable timestamp.
+    pub updated: Option<DateTime<FixedOffset>>,
+}
+
+impl RecentChange {
+    pub fn new(title: String, note: &Note) -> Self {
+        RecentChange {
+            title,
+            updated: last_changed(note),
+        }
+    }
+}
+
+/// The most recently edited notes, newest first.
+pub struct RecentChangesPage {
+    pub entries: Vec<RecentChange>,
+}
+
+impl RecentChangesPage {
+    pub fn new(entries: Vec<RecentChange>) -> Self {
+        Self { entries }
+    }
+    fn render_entries(&self) -> String {
+        if self.entries.is_empty() {
+            return String::from("<p>Nothing has been edited yet.</p>");
+        }
+        let mut entry_list = String::from("<ul class=\"recent-changes\">");
+        for entry in &self.entries {
+            write!(
+                entry_list,
+                "<li><a href=\"{}\">{}</a>",
+                format_links(&entry.title),
+                escape_html(&entry.title)
+            )
+            .unwrap();
+            if let Some(updated) = entry.updated {
+                write!(
+                    entry_list,
+                    " <time datetime=\"{}\">{}</time>",
+                    updated.to_rfc3339(),
+                    updated.format("%Y-%m-%d %H:%M")
+                )
+                .unwrap();
+            }
+            entry_list.push_str("</li>");
+        }
+        entry_list.push_str("</ul>");
+        entry_list
+    }
+}
+
+#[async_trait]
+impl Render for RecentChangesPage {
+    async fn render(&self) -> String {
+        let mut ctx = get_template_file("recent_changes").await.unwrap();
+        ctx = ctx.replace("<%= entries %>", &self.render_entries());
+        render_includes(ctx, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_recent_changes_with_timestamps() {
+        let page = RecentChangesPage::new(vec![
+            RecentChange {
+                title: String::from("Salt & Pepper"),
+                updated: DateTime::parse_from_rfc3339("2022-05-03T08:15:00+02:00").ok(),
+            },
+            RecentChange {
+                title: String::from("Beans"),
+                updated: None,
+            },
+        ]);
+        assert_eq!(
+            page.render_entries(),
+            "<ul class=\"recent-changes\"><li><a href=\"/Salt%20%26%20Pepper\">Salt &amp; Pepper</a> <time datetime=\"2022-05-03T08:15:00+02:00\">2022-05-03 08:15</time></li><li><a href=\"/Beans\">Beans</a></li></ul>"
+        );
+        assert_eq!(
+            RecentChangesPage::new(Vec::new()).render_entries(),
+            "<p>Nothing has been edited yet.</p>"
+        );
+    }
+}
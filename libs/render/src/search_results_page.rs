@@ -1,23 +1,51 @@
 use async_trait::async_trait;
 use std::{fmt::Write as _, time::Duration};
-use wikitext::parsers::format_links;
+use urlencoding::encode;
+use wikitext::parsers::{escape_html, format_links, with_base_path};
 
 use crate::{get_template_file, render_includes, Render};
 
-type SearchResult = Vec<String>;
+/// A page matching a search.
+pub struct SearchResult {
+    pub title: String,
+    /// How relevant the page is, higher is more relevant.
+    pub score: f32,
+    /// An excerpt of the page around what matched, as HTML.
+    pub snippet: String,
+}
 
 pub struct SearchResultsPage {
-    pub pages: SearchResult,
+    /// Matching pages, most relevant first.
+    pub pages: Vec<SearchResult>,
     pub num_results: usize,
     pub time: Duration,
+    pub term: String,
+    /// Only this many results are rendered, the rest are behind a "show more" link.
+    pub limit: usize,
+    /// How many more results the "show more" link adds.
+    pub page_size: usize,
+    /// Whether the results include words a typo or two away from the query.
+    pub fuzzy: bool,
 }
 
 impl SearchResultsPage {
-    pub fn new(pages: SearchResult, num_results: usize, time: Duration) -> Self {
+    pub fn new(
+        pages: Vec<SearchResult>,
+        num_results: usize,
+        time: Duration,
+        term: String,
+        limit: usize,
+        page_size: usize,
+        fuzzy: bool,
+    ) -> Self {
         SearchResultsPage {
             pages,
             num_results,
             time,
+            term,
+            limit,
+            page_size,
+            fuzzy,
         }
     }
     async fn render_pages(&self) -> String {
@@ -25,17 +53,33 @@ impl SearchResultsPage {
             return String::with_capacity(0);
         }
         let mut page_list = String::new();
-        for page in self.pages.iter() {
+        for page in self.pages.iter().take(self.limit) {
             write!(
                 page_list,
-                "<li><div class=\"result\"><h2><a href=\"{}\">{}</a></h2><button class=\"expand\">&#9660;</button></div></li>",
-                format_links(page),
-                page,
+                "<li><div class=\"result\" data-score=\"{:.3}\"><h2><a href=\"{}\">{}</a></h2><button class=\"expand\">&#9660;</button></div><p class=\"snippet\">{}</p></li>",
+                page.score,
+                format_links(&page.title),
+                escape_html(&page.title),
+                page.snippet,
             )
             .unwrap();
         }
         page_list
     }
+    fn render_show_more(&self) -> String {
+        if self.pages.len() <= self.limit {
+            return String::with_capacity(0);
+        }
+        format!(
+            r#"<a class="show-more" href="{}">Show more</a>"#,
+            with_base_path(&format!(
+                "/search?term={}&limit={}{}",
+                encode(&self.term),
+                self.limit + self.page_size,
+                if self.fuzzy { "&fuzzy=true" } else { "" }
+            ))
+        )
+    }
     fn render_result_header(&self) -> String {
         if self.pages.is_empty() {
             return String::from("<h3>No search results.</h3>");
@@ -58,7 +102,84 @@ impl Render for SearchResultsPage {
         let mut ctx = get_template_file("search_results").await.unwrap();
         ctx = ctx
             .replace("<%= pages %>", &self.render_pages().await)
-            .replace("<%= result_header %>", &self.render_result_header());
+            .replace("<%= result_header %>", &self.render_result_header())
+            .replace("<%= show_more %>", &self.render_show_more());
         render_includes(ctx, None).await.replace("<%= nav %>", &nav)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn renders_results_up_to_the_limit() {
+        let pages = (0..5)
+            .map(|i| SearchResult {
+                title: format!("result {}", i),
+                score: 5.0 - i as f32,
+                snippet: format!("the <mark>broad</mark> text of result {}", i),
+            })
+            .collect::<Vec<SearchResult>>();
+        let results = SearchResultsPage::new(
+            pages,
+            5,
+            Duration::from_millis(1),
+            String::from("broad query"),
+            2,
+            2,
+            false,
+        );
+        let rendered = results.render_pages().await;
+        assert!(rendered.contains(">result 0<"));
+        assert!(rendered.contains(r#"data-score="5.000""#));
+        assert!(
+            rendered.contains(r#"<p class="snippet">the <mark>broad</mark> text of result 0</p>"#)
+        );
+        assert!(rendered.contains(">result 1<"));
+        assert!(!rendered.contains(">result 2<"));
+        assert!(results
+            .render_result_header()
+            .contains("<strong>5</strong> results"));
+        assert_eq!(
+            results.render_show_more(),
+            r#"<a class="show-more" href="/search?term=broad%20query&limit=4">Show more</a>"#
+        );
+
+        let all_shown = SearchResultsPage {
+            limit: 5,
+            ..results
+        };
+        assert!(all_shown.render_pages().await.contains(">result 4<"));
+        assert!(all_shown.render_show_more().is_empty());
+
+        let fuzzy = SearchResultsPage {
+            fuzzy: true,
+            ..all_shown
+        };
+        assert_eq!(
+            SearchResultsPage { limit: 2, ..fuzzy }.render_show_more(),
+            r#"<a class="show-more" href="/search?term=broad%20query&limit=4&fuzzy=true">Show more</a>"#
+        );
+    }
+
+    #[tokio::test]
+    async fn escapes_result_titles() {
+        let results = SearchResultsPage::new(
+            vec![SearchResult {
+                title: String::from("<img src=x onerror=alert(1)>"),
+                score: 1.0,
+                snippet: String::from("a <mark>broken</mark> image"),
+            }],
+            1,
+            Duration::from_millis(1),
+            String::from("broken"),
+            10,
+            10,
+            false,
+        );
+        let rendered = results.render_pages().await;
+        assert!(!rendered.contains("<img"));
+        assert!(rendered.contains(">&lt;img src=x onerror=alert(1)&gt;</a>"));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{get_template_file, render_includes, Render};
 use async_trait::async_trait;
 use std::fmt::Write as _;
+use wikitext::parsers::with_base_path;
 
 pub struct UploadedFilesPage {
     pub entries: Vec<String>,
@@ -13,7 +14,13 @@ impl UploadedFilesPage {
     fn render_entries(&self) -> String {
         let mut entry_list = String::new();
         for entry in &self.entries {
-            write!(entry_list, "<a href=\"/files/{}\">{}</a>", entry, entry).unwrap();
+            write!(
+                entry_list,
+                "<a href=\"{}\">{}</a>",
+                with_base_path(&format!("/files/{}", entry)),
+                entry
+            )
+            .unwrap();
         }
         entry_list
     }
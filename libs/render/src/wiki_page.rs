@@ -1,3 +1,5 @@
+use std::fmt::Write as _;
+
 use async_trait::async_trait;
 
 use wikitext::parsers::TemplattedPage;
@@ -10,11 +12,73 @@ use crate::{
 pub struct WikiPage<'a> {
     page: &'a TemplattedPage,
     links: PageRenderLinks<'a>,
+    dir: &'a str,
+    editable: bool,
+}
+
+const EDIT_CONTROLS_START: &str = "<!-- edit controls -->";
+const EDIT_CONTROLS_END: &str = "<!-- /edit controls -->";
+
+/// Drops everything between the edit control markers in the templates, so read-only visitors
+/// don't get the editor or the delete button.
+pub(crate) fn without_edit_controls(html: &str) -> String {
+    let mut rendered = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(EDIT_CONTROLS_START) {
+        rendered.push_str(&rest[..start]);
+        rest = match rest[start..].find(EDIT_CONTROLS_END) {
+            Some(end) => &rest[start + end + EDIT_CONTROLS_END.len()..],
+            None => "",
+        };
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// `value` as a JSON string, safe to put in a `<script>`: `<` is escaped too, so a value containing
+/// `</script>` can't end the script early.
+fn script_string(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len() + 2);
+    encoded.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => encoded.push_str("\\\""),
+            '\\' => encoded.push_str("\\\\"),
+            '\n' => encoded.push_str("\\n"),
+            '\r' => encoded.push_str("\\r"),
+            '\t' => encoded.push_str("\\t"),
+            '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => {
+                write!(encoded, "\\u{:04x}", c as u32).unwrap()
+            }
+            c if c.is_control() => write!(encoded, "\\u{:04x}", c as u32).unwrap(),
+            c => encoded.push(c),
+        }
+    }
+    encoded.push('"');
+    encoded
 }
 
 impl<'a> WikiPage<'a> {
     pub fn new(page: &'a TemplattedPage, links: PageRenderLinks<'a>) -> Self {
-        Self { page, links }
+        Self {
+            page,
+            links,
+            dir: "",
+            editable: true,
+        }
+    }
+
+    /// Whoever is viewing the page can change it. When they can't, the editor and delete controls
+    /// are left out.
+    pub fn editable(mut self, editable: bool) -> Self {
+        self.editable = editable;
+        self
+    }
+
+    /// For notes nested in a subdirectory of the wiki, so edits are saved back there.
+    pub fn in_dir(mut self, dir: &'a str) -> Self {
+        self.dir = dir;
+        self
     }
 
     fn render_body(&self) -> String {
@@ -56,8 +120,48 @@ impl<'a> Render for WikiPage<'a> {
                 "<%= metadata %>",
                 &render_page_metadata(page.metadata.clone()),
             );
-        render_includes(ctx, Some(page))
+        let rendered = render_includes(ctx, Some(page))
             .await
-            .replace("<%= title %>", &page.title)
+            .replace("<%= title_json %>", &script_string(&page.title))
+            .replace("<%= dir_json %>", &script_string(self.dir))
+            .replace("<%= title %>", &page.title);
+        if self.editable {
+            rendered
+        } else {
+            without_edit_controls(&rendered)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_script_strings() {
+        assert_eq!(script_string("projects"), r#""projects""#);
+        assert_eq!(
+            script_string(r#"a"; alert(1); "</script><script>"#),
+            r#""a\"; alert(1); \u003c/script\u003e\u003cscript\u003e""#
+        );
+        assert_eq!(script_string("back\\slash\nline"), r#""back\\slash\nline""#);
+    }
+
+    #[test]
+    fn leaves_out_edit_controls_when_read_only() {
+        let page = [
+            include_str!("../../../templates/search.html"),
+            include_str!("../../../templates/main.html"),
+            include_str!("../../../templates/raw_html.html"),
+        ]
+        .join("\n");
+        assert!(page.contains("static/editor.js"));
+        assert!(page.contains("delete-form"));
+
+        let read_only = without_edit_controls(&page);
+        assert!(!read_only.contains("static/editor.js"));
+        assert!(!read_only.contains("delete-form"));
+        assert!(read_only.contains("static/dialogs.js"));
+        assert!(read_only.contains("view all pages"));
     }
 }
@@ -0,0 +1,160 @@
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime};
+use thiserror::Error;
+use wikitext::parsers::Note;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum QueryErr {
+    #[error("Could not understand the date in `{0}`. Use a date like 2023-04-01, today, yesterday, or a relative date like 7d, 2w, or 1y.")]
+    InvalidDate(String),
+}
+
+/// Limits search results by the date a note was created, from `after:`, `before:`, and `created:`
+/// words in a query.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum DateFilter {
+    After(NaiveDate),
+    Before(NaiveDate),
+    On(NaiveDate),
+}
+
+const DATE_FIELDS: [&str; 3] = ["after", "before", "created"];
+
+pub(crate) fn is_date_filter(word: &str) -> bool {
+    word.split_once(':').map_or(false, |(field, _)| {
+        DATE_FIELDS.contains(&field.to_lowercase().as_str())
+    })
+}
+
+impl DateFilter {
+    /// Returns `None` for words that aren't date filters, so they can be searched for as usual.
+    pub(crate) fn parse(word: &str, today: NaiveDate) -> Option<Result<DateFilter, QueryErr>> {
+        let (field, expr) = word.split_once(':')?;
+        let filter: fn(NaiveDate) -> DateFilter = match field.to_lowercase().as_str() {
+            "after" => DateFilter::After,
+            "before" => DateFilter::Before,
+            "created" => DateFilter::On,
+            _ => return None,
+        };
+        Some(
+            parse_date(expr, today)
+                .map(filter)
+                .ok_or_else(|| QueryErr::InvalidDate(word.into())),
+        )
+    }
+
+    pub(crate) fn matches(&self, created: NaiveDate) -> bool {
+        match self {
+            DateFilter::After(date) => created >= *date,
+            DateFilter::Before(date) => created < *date,
+            DateFilter::On(date) => created == *date,
+        }
+    }
+}
+
+/// Absolute dates, `today`, `yesterday`, or a number of days, weeks, or years (of 365 days) ago.
+fn parse_date(expr: &str, today: NaiveDate) -> Option<NaiveDate> {
+    match expr.to_lowercase().as_str() {
+        "today" => return Some(today),
+        "yesterday" => return today.pred_opt(),
+        _ => {}
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(expr, "%Y-%m-%d") {
+        return Some(date);
+    }
+    let unit = expr.chars().last()?;
+    let amount = i64::from(expr[..expr.len() - unit.len_utf8()].parse::<u32>().ok()?);
+    let days = match unit.to_ascii_lowercase() {
+        'd' => amount,
+        'w' => amount * 7,
+        'y' => amount * 365,
+        _ => return None,
+    };
+    today.checked_sub_signed(Duration::days(days))
+}
+
+/// The day a note was created in the configured timezone. Older notes might only have a date, or
+/// the compact timestamp used for ids.
+pub(crate) fn note_created(note: &Note, offset: &FixedOffset) -> Option<NaiveDate> {
+    let created = note
+        .header
+        .get("created")
+        .or_else(|| note.header.get("id"))?;
+    if let Ok(created) = DateTime::parse_from_rfc3339(created) {
+        return Some(created.with_timezone(offset).date_naive());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(created, "%Y-%m-%d") {
+        return Some(date);
+    }
+    NaiveDateTime::parse_from_str(created, "%Y%m%d%H%M%S")
+        .ok()
+        .map(|created| created.date())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_created_days_ago(days: i64, today: NaiveDate) -> Note {
+        let created = today - Duration::days(days);
+        Note::from(format!(
+            "title: {} days old\ntags: []\ncreated: {}T12:00:00+00:00\n\nbody",
+            days, created
+        ))
+    }
+
+    #[test]
+    fn filters_by_relative_dates() {
+        let today = NaiveDate::from_ymd_opt(2023, 4, 20).unwrap();
+        let utc = FixedOffset::east_opt(0).unwrap();
+        let after = DateFilter::parse("after:7d", today).unwrap().unwrap();
+        let old = note_created(&note_created_days_ago(8, today), &utc).unwrap();
+        let recent = note_created(&note_created_days_ago(3, today), &utc).unwrap();
+        assert!(!after.matches(old));
+        assert!(after.matches(recent));
+
+        let created_today = DateFilter::parse("created:today", today).unwrap().unwrap();
+        assert!(
+            created_today.matches(note_created(&note_created_days_ago(0, today), &utc).unwrap())
+        );
+        assert!(!created_today.matches(recent));
+        assert_eq!(
+            DateFilter::parse("before:2w", today),
+            Some(Ok(DateFilter::Before(
+                NaiveDate::from_ymd_opt(2023, 4, 6).unwrap()
+            )))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        let today = NaiveDate::from_ymd_opt(2023, 4, 20).unwrap();
+        assert_eq!(
+            DateFilter::parse("after:soon", today),
+            Some(Err(QueryErr::InvalidDate("after:soon".into())))
+        );
+        assert_eq!(
+            DateFilter::parse("after:7x", today),
+            Some(Err(QueryErr::InvalidDate("after:7x".into())))
+        );
+        assert_eq!(DateFilter::parse("author:jane", today), None);
+    }
+
+    #[test]
+    fn reads_created_dates_in_configured_timezone() {
+        let note = Note::from(String::from(
+            "title: late night\ntags: []\ncreated: 2023-04-19T23:30:00+00:00\n\nbody",
+        ));
+        let tokyo = FixedOffset::east_opt(9 * 3600).unwrap();
+        assert_eq!(
+            note_created(&note, &tokyo),
+            NaiveDate::from_ymd_opt(2023, 4, 20)
+        );
+        let legacy = Note::from(String::from(
+            "title: legacy\ntags: []\nid: 20220101120000\n\nbody",
+        ));
+        assert_eq!(
+            note_created(&legacy, &tokyo),
+            NaiveDate::from_ymd_opt(2022, 1, 1)
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use std::{collections::HashSet, fs::read_dir, path::Path};
+
+use crate::indexer::is_metadata_term;
+
+/// Every term in the search index at `index`, i.e. the names of the files in it.
+pub(crate) fn indexed_terms(index: &Path) -> HashSet<String> {
+    let entries = match read_dir(index) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Could not read the search index: {}", e);
+            return HashSet::new();
+        }
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map_or(false, |kind| kind.is_file()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|term| !is_metadata_term(term))
+        .collect()
+}
+
+/// How many edits a token can be away from a term and still match it. Short words get one so a
+/// three letter query doesn't match half the index.
+fn max_distance(token: &str) -> usize {
+    (token.chars().count() / 3).clamp(1, 2)
+}
+
+/// The terms in `known_terms` within a few edits of `token`, closest first.
+pub(crate) fn close_terms(token: &str, known_terms: &HashSet<String>) -> Vec<String> {
+    let max = max_distance(token);
+    let mut close = known_terms
+        .iter()
+        .filter_map(|term| {
+            let distance = levenshtein(token, term, max)?;
+            Some((distance, term))
+        })
+        .collect::<Vec<(usize, &String)>>();
+    close.sort();
+    close.into_iter().map(|(_, term)| term.clone()).collect()
+}
+
+/// The edit distance between `a` and `b`, or `None` once it's more than `max`.
+fn levenshtein(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a = a.chars().collect::<Vec<char>>();
+    let b = b.chars().collect::<Vec<char>>();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        if current.iter().min().map_or(false, |closest| *closest > max) {
+            return None;
+        }
+        previous = current;
+    }
+    Some(previous[b.len()]).filter(|distance| *distance <= max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_edit_distance() {
+        assert_eq!(levenshtein("tendril", "tendril", 2), Some(0));
+        assert_eq!(levenshtein("tendirl", "tendril", 2), Some(2));
+        assert_eq!(levenshtein("tendrl", "tendril", 2), Some(1));
+        assert_eq!(levenshtein("kitten", "sitting", 3), Some(3));
+        assert_eq!(levenshtein("kitten", "sitting", 2), None);
+        assert_eq!(levenshtein("garden", "gardenia", 1), None);
+    }
+
+    #[test]
+    fn finds_close_terms() {
+        let known_terms = HashSet::from(["garden".into(), "harden".into(), "gardens".into()]);
+        assert_eq!(
+            close_terms("gardenn", &known_terms),
+            vec!["garden", "gardens"]
+        );
+        assert_eq!(close_terms("gardn", &known_terms), vec!["garden"]);
+        let short_terms = HashSet::from(["cat".into(), "cot".into(), "dog".into()]);
+        assert_eq!(close_terms("cut", &short_terms), vec!["cat", "cot"]);
+    }
+}
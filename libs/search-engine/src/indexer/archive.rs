@@ -1,4 +1,4 @@
-use compression::prelude::*;
+use persistance::fs::{archive::decompress, utils::get_archive_file_path};
 use std::{
     collections::HashMap,
     fs::{read, read_dir},
@@ -7,39 +7,52 @@ use std::{
 
 use crate::Tokens;
 
-use super::{tokenize_document, Proccessor};
+use super::{tokenize_document, DocTokenCount, Proccessor};
 
+/// Archived copies of bookmarked pages, indexed under the title of the note they were saved for.
 #[derive(Default, Debug)]
 pub(crate) struct Archive {
     pub(crate) tokens: Tokens,
     pub(crate) file_index: HashMap<String, Vec<String>>,
 }
 
+/// Tokenizes an archived page the same way whether it's indexed in bulk or patched in later.
+pub(crate) fn tokenize_archive(title: &str, text: &str) -> DocTokenCount {
+    tokenize_document([title, text].join("\n"))
+}
+
+/// The tokens of the archived copy of `title`, if it has one.
+pub(crate) fn archived_tokens(title: &str) -> Option<DocTokenCount> {
+    let compressed = read(get_archive_file_path(title)).ok()?;
+    let text = decompress(&compressed)?;
+    Some(tokenize_archive(title, &text))
+}
+
 impl Proccessor for Archive {
     fn load(&mut self, location: &Path) {
-        let entries = read_dir(location).unwrap();
+        // A brand-new install might not have anything here yet
+        let entries = match read_dir(location) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Could not read archives in {:?}: {}", location, e);
+                return;
+            }
+        };
         let mut tokens: Tokens = HashMap::new();
         let mut term_index: HashMap<String, Vec<String>> = HashMap::new();
-        entries.for_each(|entry| {
-            let entry = entry.unwrap();
+        entries.filter_map(|entry| entry.ok()).for_each(|entry| {
             if let Some(fname) = entry.file_name().to_str() {
                 if fname.ends_with("pdf") {
                     return;
                 }
-                let content = read(entry.path()).unwrap();
-                let decompressed = content
-                    .iter()
-                    .cloned()
-                    .decode(&mut BZip2Decoder::new())
-                    .collect::<Result<Vec<_>, _>>()
-                    .unwrap();
-                let text_content = String::from_utf8(decompressed).unwrap_or_else(|_| {
-                    panic!(
-                        "Unable to convert compressed text to utf8 string, {}",
-                        fname
-                    );
-                });
-                let doc_token_counter = tokenize_document(text_content);
+                let text_content = match read(entry.path()).ok().and_then(|c| decompress(&c)) {
+                    Some(text_content) => text_content,
+                    None => {
+                        eprintln!("Could not index the archive of {}", fname);
+                        return;
+                    }
+                };
+                let doc_token_counter = tokenize_archive(fname, &text_content);
                 for (term, score) in doc_token_counter.iter() {
                     tokens
                         .entry(term.to_owned())
@@ -1,4 +1,6 @@
-use std::{path::Path, collections::HashMap};
+use std::{collections::HashMap, path::Path};
+
+use wikitext::parsers::Note;
 
 use crate::tokenizer::tokenize;
 
@@ -10,21 +12,102 @@ pub(crate) trait Proccessor {
 }
 pub type DocTokenCount = HashMap<String, f32>;
 
-pub fn tokenize_document(content: String) -> DocTokenCount {
-    let mut token_counter: DocTokenCount = HashMap::new();
+/// How much more a token found in an indexed metadata field counts than one in the note body.
+const METADATA_WEIGHT: f32 = 2.0;
+const TITLE_WEIGHT: f32 = 3.0;
+const TAGS_WEIGHT: f32 = 2.0;
+// Punctuation is stripped from tokens, so this can't collide with a regular term
+const METADATA_SEPARATOR: &str = "__";
+
+/// The index term for `token` appearing in the metadata `field`, used for `field:value` queries.
+pub(crate) fn metadata_term(field: &str, token: &str) -> String {
+    format!("{}{}{}", field.to_lowercase(), METADATA_SEPARATOR, token)
+}
+
+pub(crate) fn is_metadata_term(term: &str) -> bool {
+    term.contains(METADATA_SEPARATOR)
+}
+
+fn count_tokens(content: &str, weight: f32, token_counter: &mut DocTokenCount) -> f32 {
     let mut total_tokens = 0.0;
     for line in content.lines() {
         let raw_tokens = tokenize(line);
-        total_tokens += raw_tokens.len() as f32;
+        total_tokens += raw_tokens.len() as f32 * weight;
         for token in raw_tokens {
             token_counter
                 .entry(token)
-                .and_modify(|v| *v += 1.0)
-                .or_insert(1.0);
+                .and_modify(|v| *v += weight)
+                .or_insert(weight);
         }
     }
+    total_tokens
+}
+
+fn normalize(mut token_counter: DocTokenCount, total_tokens: f32) -> DocTokenCount {
     for (_, val) in token_counter.iter_mut() {
         *val /= total_tokens;
     }
     token_counter
 }
+
+/// Combines the tokens of two sources for the same document, keeping the higher score of a term
+/// found in both.
+pub(crate) fn merge_counts(mut counts: DocTokenCount, other: DocTokenCount) -> DocTokenCount {
+    for (term, score) in other {
+        counts
+            .entry(term)
+            .and_modify(|v| *v = v.max(score))
+            .or_insert(score);
+    }
+    counts
+}
+
+pub fn tokenize_document(content: String) -> DocTokenCount {
+    let mut token_counter: DocTokenCount = HashMap::new();
+    let total_tokens = count_tokens(&content, 1.0, &mut token_counter);
+    normalize(token_counter, total_tokens)
+}
+
+/// The weight of tokens in `field`, from `weights` when it's configured there.
+fn field_weight(field: &str, weights: &HashMap<String, f32>) -> f32 {
+    match weights.get(field) {
+        Some(weight) => *weight,
+        None => match field {
+            "title" => TITLE_WEIGHT,
+            "tags" => TAGS_WEIGHT,
+            _ => METADATA_WEIGHT,
+        },
+    }
+}
+
+/// Tokenizes a note's content, title, and tags, along with the values of any of the given
+/// metadata `fields` it has. Title, tag, and metadata values are weighted higher than the body,
+/// per `weights`, and metadata values are also indexed under their field so they can be searched
+/// with `field:value`.
+pub fn tokenize_note_meta(
+    note: &Note,
+    fields: &[String],
+    weights: &HashMap<String, f32>,
+) -> DocTokenCount {
+    let mut token_counter: DocTokenCount = HashMap::new();
+    let mut total_tokens = count_tokens(&note.content, 1.0, &mut token_counter);
+    for key in ["title", "tags"] {
+        if let Some(value) = note.header.get(key) {
+            let weight = field_weight(key, weights);
+            total_tokens += count_tokens(value, weight, &mut token_counter);
+        }
+    }
+    for field in fields {
+        if let Some(value) = note.header.get(field) {
+            let weight = field_weight(field, weights);
+            total_tokens += count_tokens(value, weight, &mut token_counter);
+            for token in tokenize(value) {
+                token_counter
+                    .entry(metadata_term(field, &token))
+                    .and_modify(|v| *v += weight)
+                    .or_insert(weight);
+            }
+        }
+    }
+    normalize(token_counter, total_tokens)
+}
@@ -1,13 +1,18 @@
-use super::{Proccessor, tokenize_document};
-use crate::Tokens;
-use persistance::fs::path_to_string;
+use super::{tokenize_note_meta, Proccessor};
+use crate::{Tokens, FIELD_WEIGHTS, METADATA_FIELDS};
+use persistance::fs::path_to_data_structure;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::read_dir, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::read_dir,
+    path::Path,
+};
+use wikitext::normalize_title;
 
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub(crate) struct Notebook {
     pub(crate) tokens: Tokens,
-    // filename, Vec<search_terms>
+    // title, Vec<search_terms>
     pub(crate) file_index: HashMap<String, Vec<String>>,
 }
 
@@ -15,21 +20,45 @@ impl Proccessor for Notebook {
     fn load(&mut self, location: &Path) {
         let mut tokens: Tokens = HashMap::new();
         let mut term_index: HashMap<String, Vec<String>> = HashMap::new();
-        let entries = read_dir(location).unwrap();
-        entries.for_each(|entry| {
-            let entry = entry.unwrap();
+        // A brand-new install might not have anything here yet
+        let entries = match read_dir(location) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Could not read notes in {:?}: {}", location, e);
+                return;
+            }
+        };
+        // Sorted so the same file wins every time two of them turn out to be the same note
+        let mut entries = entries.filter_map(|entry| entry.ok()).collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.file_name());
+        let mut indexed = HashSet::new();
+        entries.iter().for_each(|entry| {
             if let Some(fname) = entry.file_name().to_str() {
                 if fname.ends_with(".txt") {
                     let title = fname.strip_suffix(".txt").unwrap();
-                    let content = path_to_string(&entry.path()).unwrap();
-                    let doc_token_counter = tokenize_document(content);
+                    if !indexed.insert(normalize_title(title)) {
+                        eprintln!(
+                            "Skipping {}, it's the same note as one already indexed",
+                            fname
+                        );
+                        return;
+                    }
+                    let note = match path_to_data_structure(&entry.path()) {
+                        Ok(note) => note,
+                        Err(e) => {
+                            eprintln!("Could not index {}: {}", fname, e);
+                            return;
+                        }
+                    };
+                    let doc_token_counter =
+                        tokenize_note_meta(&note, &METADATA_FIELDS, &FIELD_WEIGHTS);
                     for (term, score) in doc_token_counter.iter() {
                         tokens
                             .entry(term.to_owned())
                             .and_modify(|v| v.push((title.to_string(), *score)))
                             .or_insert(vec![(title.to_string(), *score)]);
                         term_index
-                            .entry(fname.to_owned())
+                            .entry(title.to_owned())
                             .and_modify(|v| v.push(term.clone()))
                             .or_insert(vec![term.clone()]);
                     }
@@ -1,14 +1,27 @@
-use indexer::{notebook::Notebook, tokenize_document};
-use persistance::fs::utils::{
-    get_archive_location, get_search_file_index_location, get_search_index_location,
+use indexer::{
+    archive::{archived_tokens, tokenize_archive},
+    merge_counts,
+    notebook::Notebook,
+    tokenize_note_meta,
 };
-use searcher::search;
+use persistance::fs::{
+    config::try_read_config,
+    path_to_data_structure,
+    utils::{
+        archive_location_in, get_archive_location, get_data_dir_location, get_file_path,
+        get_search_file_index_location, get_search_index_location, search_index_location_in,
+    },
+};
+use searcher::{search, SearchSource};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     fs::{create_dir, read, write},
+    io::{self, ErrorKind},
     path::{Path, PathBuf},
     process::exit,
+    thread::sleep,
+    time::Duration,
     usize,
 };
 use thiserror::Error;
@@ -18,14 +31,41 @@ use tokio::fs::remove_file;
 
 use crate::indexer::{archive::Archive, Proccessor};
 
+mod date_filter;
+mod fuzzy;
 mod indexer;
+mod query;
 mod searcher;
+mod snippet;
+
+pub use date_filter::QueryErr;
+pub use snippet::snippet;
 mod tokenizer;
 
 type SearchTerm = String;
 type DocTitle = String;
 type NormalizedFrequency = f32;
 pub type Tokens = HashMap<SearchTerm, Vec<(DocTitle, NormalizedFrequency)>>;
+/// A document matching a search, and how relevant it is. Higher scores are more relevant.
+pub type SearchHit = (DocTitle, f32);
+
+lazy_static::lazy_static! {
+    pub(crate) static ref METADATA_FIELDS: Vec<String> = try_read_config()
+        .map(|config| config.search.metadata_fields)
+        .unwrap_or_default();
+    pub(crate) static ref FIELD_WEIGHTS: HashMap<String, f32> = try_read_config()
+        .map(|config| config.search.field_weights)
+        .unwrap_or_default();
+    pub(crate) static ref STEMMING: bool = try_read_config()
+        .map(|config| config.search.stemming)
+        .unwrap_or(true);
+    pub(crate) static ref CONFIGURED_STOP_WORDS: Option<Vec<String>> =
+        try_read_config().and_then(|config| config.search.stop_words);
+    pub(crate) static ref MIN_TOKEN_LENGTH: usize = try_read_config()
+        .map(|config| config.search)
+        .unwrap_or_default()
+        .min_token_length;
+}
 
 #[derive(Error, Debug)]
 pub enum SearchIndexErr {
@@ -44,36 +84,60 @@ pub(crate) struct Doc {
 }
 
 pub fn build_search_index(location: &str) {
-    let loc = get_search_index_location();
+    build_search_index_in(location, &get_data_dir_location());
+}
+
+/// Indexes the notes in `location` and the archives in the data directory `data_dir`, writing the
+/// index to `data_dir`.
+pub fn build_search_index_in(location: &str, data_dir: &Path) {
+    let loc = search_index_location_in(data_dir);
     if !loc.exists() {
         create_dir(&loc).unwrap();
-        create_dir(&get_search_file_index_location()).unwrap();
+        create_dir(loc.join("file_index")).unwrap();
     }
-    let archive_location = get_archive_location();
+    let archive_location = archive_location_in(data_dir);
     let mut n = Notebook::default();
     let mut a = Archive::default();
     println!("<indexing notes>");
     n.load(&PathBuf::from(location));
     a.load(&archive_location);
-    for (key, value) in a.tokens.iter() {
-        if let Some(exists) = n.tokens.get_mut(key) {
-            exists.extend(value.to_owned());
-        } else {
-            n.tokens.insert(key.to_owned(), value.to_owned());
+    merge_archive(&mut n, a);
+    write_search_index(&loc, &n.tokens, vec![n.file_index]);
+}
+
+/// Adds archived pages to the notebook's index. A note and the archive of the page it was saved
+/// from share a title, so they're one document with the higher score of any term in both.
+fn merge_archive(notebook: &mut Notebook, archive: Archive) {
+    for (term, docs) in archive.tokens {
+        let entries = notebook.tokens.entry(term).or_default();
+        for (title, score) in docs {
+            match entries.iter_mut().find(|(doc, _)| *doc == title) {
+                Some(entry) => entry.1 = entry.1.max(score),
+                None => entries.push((title, score)),
+            }
+        }
+    }
+    for (title, terms) in archive.file_index {
+        let indexed = notebook.file_index.entry(title).or_default();
+        for term in terms {
+            if !indexed.contains(&term) {
+                indexed.push(term);
+            }
         }
     }
-    write_search_index(&n.tokens, vec![n.file_index, a.file_index]);
 }
 
-pub async fn semantic_search(term: &str) -> Vec<String> {
-    search(term).await
+/// Documents matching `term`, most relevant first.
+pub async fn semantic_search(term: &str, fuzzy: bool) -> Result<Vec<SearchHit>, QueryErr> {
+    search(&SearchSource::default(), term, fuzzy).await
 }
 
+/// Writes an index built from scratch to `loc`, with the terms of each document under `file_index`.
 pub(crate) fn write_search_index(
+    loc: &Path,
     search_idx: &Tokens,
     term_indicies: Vec<HashMap<DocTitle, Vec<SearchTerm>>>,
 ) {
-    let loc = get_search_index_location();
     for (key, value) in search_idx.iter() {
         let bytes = bincode::serialize(value).unwrap();
         let file_loc = loc.join(key);
@@ -86,7 +150,7 @@ pub(crate) fn write_search_index(
         }
     }
     // write the term_index for easy deletion
-    let term_index_loc = get_search_file_index_location();
+    let term_index_loc = loc.join("file_index");
     for index in term_indicies.iter() {
         for (file, terms) in index.iter() {
             let bytes = bincode::serialize(terms).unwrap();
@@ -119,20 +183,61 @@ fn read_file_term_index(location: &Path) -> Result<Vec<String>, SearchIndexErr>
 fn write_file_term_index(location: &Path, content: Vec<String>) -> Result<(), SearchIndexErr> {
     let serialized_terms = bincode::serialize(&content);
     match serialized_terms {
-        Ok(terms) => {
-            write(location, terms).unwrap();
-            Ok(())
-        }
+        Ok(terms) => match write_with_retry(location, &terms, write_bytes) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(SearchIndexErr::WriteErr(format!(
+                "Could not write {:?}\n  {}",
+                location, e
+            ))),
+        },
         Err(e) => Err(SearchIndexErr::DeserErr(e)),
     }
 }
 
+const WRITE_ATTEMPTS: u32 = 3;
+const WRITE_BACKOFF: Duration = Duration::from_millis(50);
+
+fn write_bytes(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    write(path, bytes)
+}
+
+/// Writes `bytes` to `path` with `writer`, retrying with a linear backoff when the error could
+/// clear up on its own. Missing directories or permissions won't, so those fail immediately.
+fn write_with_retry<W>(path: &Path, bytes: &[u8], mut writer: W) -> io::Result<()>
+where
+    W: FnMut(&Path, &[u8]) -> io::Result<()>,
+{
+    let mut attempt = 1;
+    loop {
+        match writer(path, bytes) {
+            Ok(()) => return Ok(()),
+            Err(e)
+                if attempt < WRITE_ATTEMPTS
+                    && !matches!(e.kind(), ErrorKind::NotFound | ErrorKind::PermissionDenied) =>
+            {
+                eprintln!(
+                    "Could not write {:?} (attempt {}/{}), retrying: {}",
+                    path, attempt, WRITE_ATTEMPTS, e
+                );
+                sleep(WRITE_BACKOFF * attempt);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 pub(crate) fn read_search_index(
     filename: &str,
 ) -> Result<Vec<(DocTitle, NormalizedFrequency)>, SearchIndexErr> {
-    let index_location = get_search_index_location();
-    let read_loc = index_location.join(filename);
-    match read(read_loc) {
+    read_postings(&get_search_index_location().join(filename))
+}
+
+/// The documents in the index file at `path`, along with how often its term is in them.
+pub(crate) fn read_postings(
+    path: &Path,
+) -> Result<Vec<(DocTitle, NormalizedFrequency)>, SearchIndexErr> {
+    match read(path) {
         Ok(content) => {
             let deserialized_freqs = bincode::deserialize(&content);
             match deserialized_freqs {
@@ -145,11 +250,15 @@ pub(crate) fn read_search_index(
 }
 
 pub fn patch_search_from_update(note: &Note) {
-    let mut content = note.content.clone();
-    let title = note.header.get("title").unwrap();
-    content.push('\n');
-    content.push_str(title);
-    let doc_token_count = tokenize_document(content);
+    // The index is keyed by title, so there's nothing to patch without one
+    let Some(title) = note.header.get("title") else {
+        eprintln!("Not updating the search index for a note without a title");
+        return;
+    };
+    let mut doc_token_count = tokenize_note_meta(note, &METADATA_FIELDS, &FIELD_WEIGHTS);
+    if let Some(archived) = archived_tokens(title) {
+        doc_token_count = merge_counts(doc_token_count, archived);
+    }
     patch(doc_token_count, title.to_owned());
 }
 
@@ -171,11 +280,11 @@ pub fn patch(doc_token_count: HashMap<String, f32>, title: String) {
             if !found {
                 tokens.push((title.clone(), *score));
             }
-            write_search_entry(term, &tokens).unwrap();
+            warn_on_write_err(write_search_entry(term, &tokens));
         } else {
             let tokens = vec![(title.to_owned(), *score)];
             // The term we've parsed doesn't yet exist.
-            write_search_entry(term, &tokens).unwrap();
+            warn_on_write_err(write_search_entry(term, &tokens));
         }
     }
     for term in term_index_doc.iter() {
@@ -185,19 +294,34 @@ pub fn patch(doc_token_count: HashMap<String, f32>, title: String) {
 
         let tokens = read_search_index(term).unwrap();
         let tokens = tokens.into_iter().filter(|t| t.0 != *title).collect();
-        write_search_entry(term, &tokens).unwrap();
+        warn_on_write_err(write_search_entry(term, &tokens));
+    }
+    warn_on_write_err(write_file_term_index(&index_loc, file_terms));
+}
+
+fn warn_on_write_err(result: Result<(), SearchIndexErr>) {
+    match result {
+        Ok(()) => {}
+        Err(SearchIndexErr::WriteErr(e)) => eprintln!("Search index is out of date: {}", e),
+        Err(e) => eprintln!("Search index is out of date: {}", e),
     }
-    write_file_term_index(&index_loc, file_terms).unwrap();
 }
 
 type Title = String;
 type Content = String;
 type ArchivePatch = (Title, Content);
 
+/// Patches the index with an archived page. When there's a note with the same title, its tokens
+/// are kept alongside the archive's rather than replaced by them.
 pub async fn patch_search_from_archive(archive_patch: ArchivePatch) {
-    let content = [archive_patch.0.clone(), archive_patch.1].join("\n");
-    let doc_token_count = tokenize_document(content);
-    patch(doc_token_count, archive_patch.0);
+    let (title, content) = archive_patch;
+    let mut doc_token_count = tokenize_archive(&title, &content);
+    let note = get_file_path(&title).map(|path| path_to_data_structure(&path));
+    if let Ok(Ok(note)) = note {
+        let note_tokens = tokenize_note_meta(&note, &METADATA_FIELDS, &FIELD_WEIGHTS);
+        doc_token_count = merge_counts(note_tokens, doc_token_count);
+    }
+    patch(doc_token_count, title);
 }
 
 fn write_search_entry(
@@ -207,7 +331,7 @@ fn write_search_entry(
     let bytes = bincode::serialize(content);
     let path = get_search_index_location();
     match bytes {
-        Ok(b) => match write(path.join(entry), b) {
+        Ok(b) => match write_with_retry(&path.join(entry), &b, write_bytes) {
             Ok(()) => Ok(()),
             Err(e) => Err(SearchIndexErr::WriteErr(format!(
                 "Could not write {}\n  {}",
@@ -226,10 +350,9 @@ pub async fn delete_entry_from_update(entry: &str) {
         let contents = read_search_index(e).unwrap();
         let filtered_contents = contents
             .into_iter()
-            .filter(|c| c.0 == entry)
+            .filter(|c| c.0 != entry)
             .collect::<Vec<(String, f32)>>();
-        let bytes = bincode::serialize(&filtered_contents).unwrap();
-        write(e, bytes).unwrap();
+        warn_on_write_err(write_search_entry(e, &filtered_contents));
     }
 }
 
@@ -242,3 +365,211 @@ pub async fn delete_archived_file(entry: &str) {
             .expect("Could not delete archive file");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use persistance::fs::{archive::compress, config::Archive as ArchiveConfig};
+    use std::{cell::Cell, fs};
+
+    use super::*;
+
+    const TEST_DIR: &str = "/tmp/tendril-test/search/";
+
+    async fn found(source: &SearchSource, query: &str) -> Vec<String> {
+        let hits = search(source, query, false).await.unwrap();
+        hits.into_iter().map(|(title, _)| title).collect()
+    }
+
+    /// How `word` is indexed, which depends on whether stemming is on.
+    fn term(word: &str) -> String {
+        tokenizer::tokenize(word).remove(0)
+    }
+
+    #[test]
+    fn indexes_notes_of_only_stop_words() {
+        let note = Note {
+            header: HashMap::from([("title".into(), "The".into()), ("tags".into(), "[]".into())]),
+            content: "and of the it".into(),
+        };
+        let doc_token_count = tokenize_note_meta(&note, &[], &HashMap::new());
+        assert!(doc_token_count.is_empty());
+    }
+
+    #[test]
+    fn stems_words_in_notes_and_queries() {
+        let note = Note {
+            header: HashMap::from([("title".into(), "Training log".into())]),
+            content: "running every morning".into(),
+        };
+        let doc_token_count = tokenize_note_meta(&note, &[], &HashMap::new());
+        let query = query::Query::parse("run");
+        assert_eq!(query.tokens(), vec!["run"]);
+        assert!(doc_token_count.contains_key(&query.tokens()[0]));
+        assert_eq!(
+            tokenizer::tokenize("runs running run"),
+            ["run", "run", "run"]
+        );
+    }
+
+    #[test]
+    fn retries_transient_write_failures() {
+        let attempts = Cell::new(0);
+        let result = write_with_retry(Path::new("search-term"), b"tokens", |_, bytes| {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() == 1 {
+                Err(io::Error::new(ErrorKind::Interrupted, "interrupted"))
+            } else {
+                assert_eq!(bytes, b"tokens");
+                Ok(())
+            }
+        });
+        assert!(result.is_ok());
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[test]
+    fn gives_up_after_repeated_write_failures() {
+        let attempts = Cell::new(0);
+        let result = write_with_retry(Path::new("search-term"), b"tokens", |_, _| {
+            attempts.set(attempts.get() + 1);
+            Err(io::Error::new(ErrorKind::Other, "disk busy"))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), WRITE_ATTEMPTS);
+    }
+
+    #[test]
+    fn does_not_retry_missing_directories() {
+        let attempts = Cell::new(0);
+        let result = write_with_retry(Path::new("search-term"), b"tokens", |_, _| {
+            attempts.set(attempts.get() + 1);
+            Err(io::Error::new(ErrorKind::NotFound, "no such directory"))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn finds_notes_by_metadata() {
+        let _ = fs::remove_dir_all(TEST_DIR);
+        let index = Path::new(TEST_DIR).join("search-index");
+        fs::create_dir_all(index.join("file_index")).unwrap();
+        let source = SearchSource::new(index.clone(), Path::new(TEST_DIR).join("wiki"));
+        let mut header = HashMap::new();
+        header.insert("title".into(), "Reading list".into());
+        header.insert("tags".into(), "[]".into());
+        header.insert("author".into(), "Octavia Butler".into());
+        let note = Note {
+            header,
+            content: "Books to read next".into(),
+        };
+        let doc_token_count = tokenize_note_meta(&note, &[String::from("author")], &HashMap::new());
+        assert!(doc_token_count.get("octavia") > doc_token_count.get(&term("books")));
+        let mut tokens: Tokens = HashMap::new();
+        for (term, score) in doc_token_count {
+            tokens.insert(term, vec![(String::from("Reading list"), score)]);
+        }
+        write_search_index(&index, &tokens, vec![]);
+        assert_eq!(found(&source, "octavia").await, vec!["Reading list"]);
+        assert_eq!(found(&source, "author:Octavia").await, vec!["Reading list"]);
+        assert!(found(&source, "author:books").await.is_empty());
+        fs::remove_dir_all(TEST_DIR).unwrap();
+    }
+
+    #[test]
+    fn weights_titles_and_tags_above_the_body() {
+        let mut header = HashMap::new();
+        header.insert("title".into(), "Sourdough".into());
+        header.insert("tags".into(), "[baking]".into());
+        let note = Note {
+            header,
+            content: "sourdough baking starter".into(),
+        };
+        let doc_token_count = tokenize_note_meta(&note, &[], &HashMap::new());
+        // title 3 + body 1, tags 2 + body 1, body 1
+        assert_eq!(doc_token_count[&term("sourdough")], 4.0 / 8.0);
+        assert_eq!(doc_token_count[&term("baking")], 3.0 / 8.0);
+        assert_eq!(doc_token_count[&term("starter")], 1.0 / 8.0);
+
+        let weights = HashMap::from([(String::from("title"), 1.0)]);
+        let doc_token_count = tokenize_note_meta(&note, &[], &weights);
+        assert_eq!(doc_token_count[&term("sourdough")], 2.0 / 6.0);
+    }
+
+    #[test]
+    fn indexes_notes_without_a_title() {
+        let note = Note {
+            header: HashMap::new(),
+            content: "an untitled thought".into(),
+        };
+        let doc_token_count = tokenize_note_meta(&note, &METADATA_FIELDS, &FIELD_WEIGHTS);
+        assert!(doc_token_count.contains_key(&term("untitled")));
+        // Nothing to key the update by, so this leaves the index alone instead of panicking
+        patch_search_from_update(&note);
+    }
+
+    #[test]
+    fn indexes_archived_pages() {
+        let dir = PathBuf::from("/tmp/tendril-test/search-archives/");
+        let archive_dir = dir.join("archive");
+        fs::create_dir_all(&archive_dir).unwrap();
+        fs::write(
+            dir.join("Airships.txt"),
+            "title: Airships\ntags: []\n\nairships I want to read about",
+        )
+        .unwrap();
+        let compressed = compress(
+            "The zeppelin was the most famous of the rigid airships.",
+            &ArchiveConfig::default(),
+        );
+        fs::write(archive_dir.join("Airships"), compressed).unwrap();
+        fs::write(archive_dir.join("Unreadable"), "not compressed").unwrap();
+
+        let mut notebook = Notebook::default();
+        notebook.load(&dir);
+        let mut archive = Archive::default();
+        archive.load(&archive_dir);
+        merge_archive(&mut notebook, archive);
+        let docs = &notebook.tokens[&term("zeppelin")];
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].0, "Airships");
+        // The note and its archive are one document
+        assert_eq!(notebook.tokens[&term("airships")].len(), 1);
+        assert_eq!(notebook.file_index.len(), 1);
+        assert!(notebook.file_index["Airships"].contains(&term("zeppelin")));
+        assert!(notebook.file_index["Airships"].contains(&term("read")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn indexes_an_empty_wiki() {
+        let empty = PathBuf::from("/tmp/tendril-test/search-empty/");
+        fs::create_dir_all(&empty).unwrap();
+        let mut notebook = Notebook::default();
+        notebook.load(&empty);
+        assert!(notebook.tokens.is_empty());
+        let mut archive = Archive::default();
+        archive.load(&empty.join("archive"));
+        assert!(archive.tokens.is_empty());
+    }
+
+    #[test]
+    fn indexes_duplicate_titles_once() {
+        let dir = PathBuf::from("/tmp/tendril-test/search-duplicates/");
+        fs::create_dir_all(&dir).unwrap();
+        for fname in ["Garden Notes.txt", "Garden  Notes.txt"] {
+            fs::write(
+                dir.join(fname),
+                "title: Garden Notes\ntags: []\n\ntomatoes and basil",
+            )
+            .unwrap();
+        }
+        let mut notebook = Notebook::default();
+        notebook.load(&dir);
+        assert_eq!(notebook.file_index.len(), 1);
+        let docs = &notebook.tokens[&term("tomatoes")];
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].0, "Garden  Notes");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+
+use crate::{searcher::tokenize_query, tokenizer::tokenize, SearchHit};
+
+/// A search query split into its parts: `"quoted phrases"` whose words have to appear one after
+/// another, `+words` every result has to contain, `-words` no result can contain, and the rest.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Query {
+    /// The query without operators, for comparing against titles when ranking.
+    pub(crate) text: String,
+    pub(crate) terms: Vec<String>,
+    pub(crate) required: Vec<String>,
+    pub(crate) excluded: Vec<String>,
+    pub(crate) phrases: Vec<Vec<String>>,
+}
+
+/// The words of `text` as they're indexed, line by line so words on separate lines stay apart.
+fn tokenize_text(text: &str) -> Vec<String> {
+    text.lines().flat_map(tokenize).collect()
+}
+
+impl Query {
+    /// Parses `query`. Quotes that don't pair up are ignored, so the words are searched for as
+    /// usual.
+    pub(crate) fn parse(query: &str) -> Query {
+        let mut parsed = Query::default();
+        let mut text = Vec::new();
+        let mut words = Vec::new();
+        if query.matches('"').count() % 2 == 0 {
+            for (index, part) in query.split('"').enumerate() {
+                if index % 2 == 0 {
+                    words.extend(part.split_whitespace());
+                    continue;
+                }
+                let phrase = tokenize_text(part);
+                if !phrase.is_empty() {
+                    text.push(part.trim());
+                    parsed.phrases.push(phrase);
+                }
+            }
+        } else {
+            words.extend(query.split_whitespace().map(|word| word.trim_matches('"')));
+        }
+        for word in words {
+            if let Some(required) = word.strip_prefix('+').filter(|word| !word.is_empty()) {
+                text.push(required);
+                parsed.required.extend(tokenize_query(required));
+            } else if let Some(excluded) = word.strip_prefix('-').filter(|word| !word.is_empty()) {
+                parsed.excluded.extend(tokenize_query(excluded));
+            } else {
+                text.push(word);
+                parsed.terms.extend(tokenize_query(word));
+            }
+        }
+        parsed.text = text.join(" ");
+        parsed
+    }
+
+    /// The tokens to look up in the index. Excluded words only narrow down what these find, so a
+    /// query of nothing but exclusions has none.
+    pub(crate) fn tokens(&self) -> Vec<String> {
+        self.terms
+            .iter()
+            .chain(&self.required)
+            .chain(self.phrases.iter().flatten())
+            .cloned()
+            .collect()
+    }
+
+    /// Keeps the `results` that have every required word and phrase and none of the excluded
+    /// words. `docs_with` gives the documents containing a token and `text_of` a document's text,
+    /// which is only read when there are phrases to look for.
+    pub(crate) fn filter<D, T>(
+        &self,
+        results: Vec<SearchHit>,
+        docs_with: D,
+        text_of: T,
+    ) -> Vec<SearchHit>
+    where
+        D: Fn(&str) -> HashSet<String>,
+        T: Fn(&str) -> Option<String>,
+    {
+        let required = self
+            .required
+            .iter()
+            .map(|token| docs_with(token))
+            .collect::<Vec<HashSet<String>>>();
+        let excluded = self
+            .excluded
+            .iter()
+            .flat_map(|token| docs_with(token))
+            .collect::<HashSet<String>>();
+        results
+            .into_iter()
+            .filter(|(doc, _)| required.iter().all(|docs| docs.contains(doc)))
+            .filter(|(doc, _)| !excluded.contains(doc))
+            .filter(|(doc, _)| {
+                if self.phrases.is_empty() {
+                    return true;
+                }
+                let tokens = match text_of(doc) {
+                    Some(text) => tokenize_text(&text),
+                    None => return false,
+                };
+                self.phrases
+                    .iter()
+                    .all(|phrase| tokens.windows(phrase.len()).any(|words| words == phrase))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::Path};
+
+    use wikitext::parsers::Note;
+
+    use super::*;
+    use crate::searcher::{index_notes, search_terms, SearchSource};
+
+    fn docs(dir: &str) -> SearchSource {
+        let notes = [
+            (
+                "Tomatoes",
+                "heirloom tomatoes need full sun and deep watering",
+            ),
+            ("Basil", "basil grows well next to tomatoes in full shade"),
+            ("Peppers", "peppers like sun, water them deep and often"),
+        ]
+        .into_iter()
+        .map(|(title, content)| Note {
+            header: HashMap::from([("title".into(), title.into())]),
+            content: content.into(),
+        })
+        .collect();
+        index_notes(Path::new(dir), notes)
+    }
+
+    async fn search(source: &SearchSource, query: &str) -> Vec<String> {
+        let mut found = search_terms(source, query, false)
+            .await
+            .into_iter()
+            .map(|(title, _)| title)
+            .collect::<Vec<String>>();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn parses_operators() {
+        let query = Query::parse(r#"+sun "deep watering" -shade tomatoes"#);
+        assert_eq!(query.required, vec!["sun"]);
+        assert_eq!(query.excluded, vec!["shade"]);
+        assert_eq!(query.terms, tokenize("tomatoes"));
+        assert_eq!(query.phrases, vec![tokenize("deep watering")]);
+        assert_eq!(query.text, "deep watering sun tomatoes");
+        assert_eq!(query.tokens(), tokenize("tomatoes sun deep watering"));
+    }
+
+    #[tokio::test]
+    async fn matches_phrases() {
+        let source = docs("/tmp/tendril-test/search-query/phrases/");
+        let found = search(&source, "full sun").await;
+        assert_eq!(found, vec!["Basil", "Peppers", "Tomatoes"]);
+        assert_eq!(search(&source, r#""full sun""#).await, vec!["Tomatoes"]);
+        assert!(search(&source, r#""sun full""#).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn requires_plus_terms() {
+        let source = docs("/tmp/tendril-test/search-query/required/");
+        let found = search(&source, "sun tomatoes").await;
+        assert_eq!(found, vec!["Basil", "Peppers", "Tomatoes"]);
+        let found = search(&source, "sun +tomatoes").await;
+        assert_eq!(found, vec!["Basil", "Tomatoes"]);
+        assert_eq!(search(&source, "+sun +tomatoes").await, vec!["Tomatoes"]);
+    }
+
+    #[tokio::test]
+    async fn excludes_minus_terms() {
+        let source = docs("/tmp/tendril-test/search-query/excluded/");
+        assert_eq!(search(&source, "tomatoes -shade").await, vec!["Tomatoes"]);
+        assert!(search(&source, "sun -peppers -heirloom").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn returns_nothing_for_only_exclusions() {
+        let query = Query::parse("-shade -peppers");
+        assert!(query.tokens().is_empty());
+        let source = docs("/tmp/tendril-test/search-query/only-excluded/");
+        assert!(search(&source, "-shade").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ignores_unbalanced_quotes() {
+        let query = Query::parse(r#""full sun"#);
+        assert!(query.phrases.is_empty());
+        assert_eq!(query.terms, vec!["full", "sun"]);
+        let source = docs("/tmp/tendril-test/search-query/unbalanced/");
+        let found = search(&source, r#""full sun"#).await;
+        assert_eq!(found, vec!["Basil", "Peppers", "Tomatoes"]);
+    }
+}
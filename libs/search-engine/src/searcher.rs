@@ -1,30 +1,214 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::read_dir,
+    path::PathBuf,
+};
 
-use crate::{read_search_index, tokenizer::tokenize, SearchIndexErr};
+use chrono::{FixedOffset, NaiveDate};
+use persistance::fs::{
+    current_time, path_to_data_structure,
+    store::FsStore,
+    utils::{get_search_index_location, get_wiki_location},
+};
 
-fn tokenize_query(query: &str) -> Vec<String> {
-    tokenize(query)
+use crate::{
+    date_filter::{is_date_filter, note_created, DateFilter, QueryErr},
+    fuzzy::{close_terms, indexed_terms},
+    indexer::{is_metadata_term, metadata_term},
+    query::Query,
+    read_postings,
+    tokenizer::tokenize,
+    DocTitle, NormalizedFrequency, SearchHit, SearchIndexErr, STEMMING,
+};
+
+/// What a search reads: the search index, and the notes themselves for matching phrases and
+/// creation dates.
+pub(crate) struct SearchSource {
+    index: PathBuf,
+    notes: FsStore,
 }
 
-pub(crate) async fn search(query: &str) -> Vec<String> {
-    let tokens = tokenize_query(query);
-
-    let mut results = Vec::<(String, f32)>::new();
-    let mut document_appearences: HashMap<String, usize> = HashMap::new();
-    tokens.iter().for_each(|key| {
-        let variations = variations_of_word(key);
-        for variation in variations {
-            match read_search_index(&variation) {
-                Ok(entries) => {
-                    for entry in entries {
-                        if let Some(count) = document_appearences.get_mut(&entry.0) {
-                            *count += 1;
-                            continue;
-                        }
-                        document_appearences.insert(entry.0.to_string(), 1);
-                        results.push(entry)
-                    }
-                }
+impl Default for SearchSource {
+    fn default() -> Self {
+        SearchSource::new(get_search_index_location(), get_wiki_location())
+    }
+}
+
+impl SearchSource {
+    pub(crate) fn new(index: PathBuf, wiki: PathBuf) -> Self {
+        SearchSource {
+            index,
+            notes: FsStore::new(wiki),
+        }
+    }
+
+    fn postings(&self, term: &str) -> Result<Vec<(DocTitle, NormalizedFrequency)>, SearchIndexErr> {
+        read_postings(&self.index.join(term))
+    }
+
+    /// How many documents are in the index, going by the term lists kept for each of them.
+    fn doc_count(&self) -> usize {
+        read_dir(self.index.join("file_index")).map_or(0, |entries| entries.count())
+    }
+
+    /// Every document containing `token` in any of its forms.
+    fn docs_with(&self, token: &str) -> HashSet<String> {
+        variations_of_word(token)
+            .iter()
+            .filter_map(|variation| self.postings(variation).ok())
+            .flatten()
+            .map(|(doc, _)| doc)
+            .collect()
+    }
+
+    /// The title and content of a note, for matching phrases against. Archived pages aren't notes,
+    /// so they never match a phrase.
+    fn note_text(&self, title: &str) -> Option<String> {
+        let note = path_to_data_structure(&self.notes.path_for(title)).ok()?;
+        Some(format!("{}\n{}", title, note.content))
+    }
+
+    fn created_within(&self, title: &str, filters: &[DateFilter], offset: &FixedOffset) -> bool {
+        let note = match path_to_data_structure(&self.notes.path_for(title)) {
+            Ok(note) => note,
+            _ => return false,
+        };
+        match note_created(&note, offset) {
+            Some(created) => filters.iter().all(|filter| filter.matches(created)),
+            None => false,
+        }
+    }
+}
+
+/// Writes `notes` to a wiki in `dir`, along with a search index of them, for tests to search.
+#[cfg(test)]
+pub(crate) fn index_notes(
+    dir: &std::path::Path,
+    notes: Vec<wikitext::parsers::Note>,
+) -> SearchSource {
+    let _ = std::fs::remove_dir_all(dir);
+    let source = SearchSource::new(dir.join("search-index"), dir.join("wiki"));
+    std::fs::create_dir_all(source.index.join("file_index")).unwrap();
+    std::fs::create_dir_all(dir.join("wiki")).unwrap();
+    let mut tokens: crate::Tokens = HashMap::new();
+    for note in notes {
+        let title = note.header["title"].clone();
+        for (term, score) in crate::indexer::tokenize_note_meta(&note, &[], &HashMap::new()) {
+            tokens.entry(term).or_default().push((title.clone(), score));
+        }
+        let text: String = note.into();
+        std::fs::write(source.notes.path_for(&title), text).unwrap();
+    }
+    crate::write_search_index(&source.index, &tokens, vec![]);
+    source
+}
+
+/// Tokenizes a query, turning `field:value` words into terms that only match the value in that
+/// metadata field.
+pub(crate) fn tokenize_query(query: &str) -> Vec<String> {
+    query
+        .split_whitespace()
+        .flat_map(|word| match word.split_once(':') {
+            Some((field, value))
+                if !field.is_empty()
+                    && field.chars().all(char::is_alphanumeric)
+                    && !value.is_empty()
+                    && !value.starts_with("//") =>
+            {
+                tokenize(value)
+                    .iter()
+                    .map(|token| metadata_term(field, token))
+                    .collect::<Vec<String>>()
+            }
+            _ => tokenize(word),
+        })
+        .collect()
+}
+
+/// Splits `after:`, `before:`, and `created:` filters out of a query, returning the filters and
+/// the rest of the query.
+fn split_date_filters(
+    query: &str,
+    today: NaiveDate,
+) -> Result<(Vec<DateFilter>, String), QueryErr> {
+    let mut filters = Vec::new();
+    let mut words = Vec::new();
+    for word in query.split_whitespace() {
+        match DateFilter::parse(word, today) {
+            Some(filter) => filters.push(filter?),
+            None => words.push(word),
+        }
+    }
+    Ok((filters, words.join(" ")))
+}
+
+/// Searches the index for `query`. With `fuzzy`, words that aren't in the index also match
+/// indexed words a typo or two away.
+pub(crate) async fn search(
+    source: &SearchSource,
+    query: &str,
+    fuzzy: bool,
+) -> Result<Vec<SearchHit>, QueryErr> {
+    if !query.split_whitespace().any(is_date_filter) {
+        return Ok(search_terms(source, query, fuzzy).await);
+    }
+    let now = current_time();
+    let (filters, query) = split_date_filters(query, now.date_naive())?;
+    let results = if query.is_empty() && !filters.is_empty() {
+        // Only filtering by date, so every note is a candidate and none is more relevant
+        source
+            .notes
+            .titles()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|title| (title, 0.0))
+            .collect()
+    } else {
+        search_terms(source, &query, fuzzy).await
+    };
+    if filters.is_empty() {
+        return Ok(results);
+    }
+    Ok(results
+        .into_iter()
+        .filter(|(title, _)| source.created_within(title, &filters, now.offset()))
+        .collect())
+}
+
+/// Adds the indexed terms close to each token that isn't in the index in any form.
+fn with_close_terms(tokens: Vec<String>, known_terms: &HashSet<String>) -> Vec<String> {
+    let mut expanded = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let indexed = variations_of_word(&token)
+            .iter()
+            .any(|variation| known_terms.contains(variation));
+        if !indexed && !is_metadata_term(&token) {
+            expanded.extend(close_terms(&token, known_terms));
+        }
+        expanded.push(token);
+    }
+    expanded
+}
+
+pub(crate) async fn search_terms(
+    source: &SearchSource,
+    query: &str,
+    fuzzy: bool,
+) -> Vec<SearchHit> {
+    let query = Query::parse(query);
+    let mut tokens = query.tokens();
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+    if fuzzy {
+        tokens = with_close_terms(tokens, &indexed_terms(&source.index));
+    }
+
+    let mut postings = Vec::new();
+    for key in tokens.iter() {
+        for variation in variations_of_word(key) {
+            match source.postings(&variation) {
+                Ok(entries) => postings.push(entries),
                 Err(e) => match e {
                     SearchIndexErr::NotExistErr => {
                         continue;
@@ -38,9 +222,14 @@ pub(crate) async fn search(query: &str) -> Vec<String> {
                 },
             }
         }
-    });
+    }
     // TODO: Maybe some sort of proximity ranking?
-    rank_docs(&document_appearences, results, query)
+    let ranked = rank_docs(&postings, source.doc_count(), &query.text);
+    query.filter(
+        ranked,
+        |token| source.docs_with(token),
+        |title| source.note_text(title),
+    )
 }
 
 /// use term frequency-inverse document frequency to rank the search results.
@@ -48,41 +237,45 @@ pub(crate) async fn search(query: &str) -> Vec<String> {
 /// query
 /// We use the inverse document frequency smooth weight (log(N / 1 + nt) + 1)
 ///
+/// `postings` has the documents for each term looked up, along with the term's frequency in
+/// them, and `doc_count` is N. Documents with the query in their title get a boost on top.
+///
 /// ### What is a document in this context?
 ///
 /// A document is a `Doc` data structure which can be derived from multiple sources (though at the
 /// moment it is only derived from wiki notes).
-fn rank_docs(
-    doc_frequency: &HashMap<String, usize>,
-    mut results: Vec<(String, f32)>,
-    query: &str,
-) -> Vec<String> {
-    results.sort_by(|a, b| {
-        let mut processed_a = a.1 * *doc_frequency.get(&a.0).unwrap() as f32;
-        let mut title = a.0.to_lowercase();
-        let query_lc = query.to_lowercase();
-        if title.contains(&query_lc) {
-            if title == query_lc {
-                processed_a *= 5.0;
-            } else {
-                processed_a *= 2.5;
-            }
+fn rank_docs(postings: &[Vec<SearchHit>], doc_count: usize, query: &str) -> Vec<SearchHit> {
+    let largest_posting = postings.iter().map(Vec::len).max().unwrap_or(0);
+    let total_docs = doc_count.max(largest_posting) as f32;
+    let mut scores: HashMap<&str, f32> = HashMap::new();
+    for docs in postings {
+        let idf = (total_docs / (1.0 + docs.len() as f32)).ln() + 1.0;
+        for (title, frequency) in docs {
+            *scores.entry(title).or_default() += frequency * idf;
         }
-        title = b.0.to_lowercase();
-        let mut processed_b = b.1 * *doc_frequency.get(&b.0).unwrap() as f32;
-        if title.contains(&query_lc) {
-            if title == query_lc {
-                processed_b *= 5.0;
-            } else {
-                processed_b *= 2.5;
+    }
+    let query_lc = query.to_lowercase();
+    let mut results = scores
+        .into_iter()
+        .map(|(title, mut score)| {
+            let title_lc = title.to_lowercase();
+            if title_lc == query_lc {
+                score *= 5.0;
+            } else if title_lc.contains(&query_lc) {
+                score *= 2.5;
             }
-        }
-        processed_b.partial_cmp(&processed_a).unwrap()
-    });
-    results.iter().map(|r| r.0.to_owned()).collect()
+            (title.to_owned(), score)
+        })
+        .collect::<Vec<SearchHit>>();
+    results.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    results
 }
 
 fn variations_of_word(key: &str) -> Vec<String> {
+    // Stemmed words are already indexed under the same stem as their other forms
+    if is_metadata_term(key) || *STEMMING {
+        return vec![key.into()];
+    }
     let word_stem = stem::get(key).unwrap();
     let mut variations = Vec::with_capacity(19);
     // Very very hacky lemmatization
@@ -117,3 +310,74 @@ const WORD_ENDINGS: [&str; 17] = [
 //     }
 //     line
 // }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use wikitext::parsers::Note;
+
+    const FUZZY_DIR: &str = "/tmp/tendril-test/search-fuzzy/";
+
+    fn small_index() -> SearchSource {
+        let notes = [
+            ("Tendril", "a personal wiki for notes and bookmarks"),
+            ("Gardening", "planting tomatoes and basil"),
+        ]
+        .into_iter()
+        .map(|(title, content)| Note {
+            header: HashMap::from([("title".into(), title.into())]),
+            content: content.into(),
+        })
+        .collect();
+        index_notes(Path::new(FUZZY_DIR), notes)
+    }
+
+    async fn found(source: &SearchSource, query: &str, fuzzy: bool) -> Vec<String> {
+        let hits = search(source, query, fuzzy).await.unwrap();
+        hits.into_iter().map(|(title, _)| title).collect()
+    }
+
+    #[test]
+    fn ranks_frequent_matches_first() {
+        let postings = vec![vec![
+            (String::from("Mentioned once"), 0.02),
+            (String::from("All about compost"), 0.3),
+        ]];
+        let ranked = rank_docs(&postings, 10, "soil");
+        assert_eq!(ranked[0].0, "All about compost");
+        assert_eq!(ranked[1].0, "Mentioned once");
+        assert!(ranked[0].1 > ranked[1].1);
+
+        // A rare term counts for more than one most documents have, so the document with the rare
+        // term wins even though its terms are less frequent
+        let postings = vec![
+            vec![(String::from("Common"), 0.2), (String::from("Rare"), 0.15)],
+            vec![(String::from("Rare"), 0.15)],
+            (0..9)
+                .map(|i| (format!("Other {}", i), 0.2))
+                .chain([(String::from("Common"), 0.2)])
+                .collect(),
+        ];
+        let ranked = rank_docs(&postings, 10, "query");
+        assert_eq!(ranked[0].0, "Rare");
+    }
+
+    #[tokio::test]
+    async fn fuzzy_search_finds_typos() {
+        let source = small_index();
+        for query in ["tendirl", "bookmakrs", "gardenig"] {
+            assert!(found(&source, query, false).await.is_empty(), "{}", query);
+            assert_eq!(found(&source, query, true).await.len(), 1, "{}", query);
+        }
+        assert_eq!(found(&source, "tendirl", true).await, vec!["Tendril"]);
+        // Words already in the index aren't widened
+        let known_terms = indexed_terms(&source.index);
+        assert_eq!(
+            with_close_terms(tokenize_query("basil"), &known_terms),
+            vec!["basil"]
+        );
+        std::fs::remove_dir_all(FUZZY_DIR).unwrap();
+    }
+}
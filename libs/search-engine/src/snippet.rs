@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+
+use wikitext::parsers::escape_html;
+
+use crate::{indexer::is_metadata_term, query::Query, tokenizer::tokenize};
+
+/// About how many characters of content a snippet shows.
+const SNIPPET_LENGTH: usize = 200;
+
+/// An excerpt of `content` around the first word matching `query`, with each matching word in it
+/// wrapped in `<mark>`. Whitespace is collapsed, and the excerpt starts and ends on whole words,
+/// with an ellipsis where content was cut off. Without a match it's the start of the content.
+pub fn snippet(content: &str, query: &str) -> String {
+    let terms = Query::parse(query)
+        .tokens()
+        .into_iter()
+        .filter(|token| !is_metadata_term(token))
+        .collect::<HashSet<String>>();
+    let words = content.split_whitespace().collect::<Vec<&str>>();
+    let matches = words
+        .iter()
+        .map(|word| tokenize(word).iter().any(|token| terms.contains(token)))
+        .collect::<Vec<bool>>();
+    if words.is_empty() {
+        return String::new();
+    }
+    let center = matches.iter().position(|is_match| *is_match).unwrap_or(0);
+    let (first, last) = snippet_window(&words, center);
+
+    let mut excerpt = String::new();
+    if first > 0 {
+        excerpt.push_str("… ");
+    }
+    for index in first..=last {
+        if index > first {
+            excerpt.push(' ');
+        }
+        let word = escape_html(words[index]);
+        if matches[index] {
+            excerpt.push_str(&format!("<mark>{}</mark>", word));
+        } else {
+            excerpt.push_str(&word);
+        }
+    }
+    if last + 1 < words.len() {
+        excerpt.push_str(" …");
+    }
+    excerpt
+}
+
+/// The first and last index of the words that fit in a snippet centered on the word at `center`.
+fn snippet_window(words: &[&str], center: usize) -> (usize, usize) {
+    let length = |index: usize| words[index].chars().count() + 1;
+    let (mut first, mut last) = (center, center);
+    let (mut before, mut after) = (0, 0);
+    let mut used = length(center);
+    // Grow towards whichever side is shorter so the match stays in the middle, and towards the
+    // other one when a side runs out
+    loop {
+        let grow_before = match (first > 0, last + 1 < words.len()) {
+            (true, true) => before <= after,
+            (true, false) => true,
+            (false, true) => false,
+            (false, false) => break,
+        };
+        let index = if grow_before { first - 1 } else { last + 1 };
+        if used + length(index) > SNIPPET_LENGTH {
+            break;
+        }
+        used += length(index);
+        if grow_before {
+            first = index;
+            before += length(index);
+        } else {
+            last = index;
+            after += length(index);
+        }
+    }
+    (first, last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filler(word: &str, count: usize) -> String {
+        vec![word; count].join(" ")
+    }
+
+    #[test]
+    fn centers_snippets_on_the_first_match() {
+        let content = format!(
+            "{}\n\n  compost   the <kitchen> scraps {}",
+            filler("before", 40),
+            filler("after", 40)
+        );
+        let excerpt = snippet(&content, "compost");
+        assert!(excerpt.starts_with("… before"));
+        assert!(excerpt.ends_with("after …"));
+        assert!(excerpt.contains("before <mark>compost</mark> the &lt;kitchen&gt; scraps after"));
+        let (before, after) = excerpt.split_once("<mark>").unwrap();
+        assert!(before.len().abs_diff(after.len()) < 40);
+        assert!(excerpt.chars().count() < SNIPPET_LENGTH + 40);
+    }
+
+    #[test]
+    fn snippets_matches_at_the_start() {
+        let content = format!("Compost at home. {}", filler("words", 100));
+        let excerpt = snippet(&content, "compost");
+        assert!(excerpt.starts_with("<mark>Compost</mark> at home. words"));
+        assert!(excerpt.ends_with("words …"));
+        assert!(excerpt.chars().count() <= SNIPPET_LENGTH + 20);
+    }
+
+    #[test]
+    fn snippets_matches_at_the_end() {
+        let content = format!("{} last compost", filler("words", 100));
+        let excerpt = snippet(&content, "compost");
+        assert!(excerpt.starts_with("… words"));
+        assert!(excerpt.ends_with("last <mark>compost</mark>"));
+    }
+
+    #[test]
+    fn snippets_short_or_unmatched_content() {
+        assert_eq!(snippet("", "compost"), "");
+        assert_eq!(snippet("just a short note", "compost"), "just a short note");
+        assert_eq!(
+            snippet("compost, compost!", "compost"),
+            "<mark>compost,</mark> <mark>compost!</mark>"
+        );
+    }
+}
@@ -1,7 +1,9 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 
 use regex::Regex;
 
+use crate::{CONFIGURED_STOP_WORDS, MIN_TOKEN_LENGTH, STEMMING};
+
 const STOP_WORDS: [&str; 51] = [
     "a", "about", "an", "are", "and", "as", "at", "be", "but", "by", "co", "com", "do", "don't",
     "for", "from", "has", "have", "he", "his", "her", "hers", "http", "https", "i", "i'm", "in",
@@ -12,16 +14,48 @@ const STOP_WORDS: [&str; 51] = [
 lazy_static::lazy_static! {
     // |#|%|}|\*|<|>|_ might also need this.
     pub(crate) static ref PUNCT_RGX: Regex = Regex::new(r"[[[:punct:]]]").unwrap();
-    static ref STOP_WORD_MAP: HashMap<&'static str, bool> = {
-   let mut m = HashMap::with_capacity(51);
-    for word in STOP_WORDS {
-        m.insert(word, true);
-        }
-    m
-    };
+    static ref STOP_WORD_SET: HashSet<String> = stop_words(CONFIGURED_STOP_WORDS.as_deref());
 }
 
+/// The configured stop words, or the built-in English ones when none are configured. An empty
+/// list turns stop word filtering off.
+fn stop_words(configured: Option<&[String]>) -> HashSet<String> {
+    match configured {
+        Some(words) => words.iter().map(|word| word.to_lowercase()).collect(),
+        None => STOP_WORDS.iter().map(|word| word.to_string()).collect(),
+    }
+}
+
+/// The words in `slice` as they're indexed and searched for, stemmed unless that's turned off.
 pub(crate) fn tokenize(slice: &str) -> Vec<String> {
+    let tokens = tokenize_with_min_length(slice, *MIN_TOKEN_LENGTH);
+    if *STEMMING {
+        tokens.into_iter().map(stem_token).collect()
+    } else {
+        tokens
+    }
+}
+
+/// Reduces `token` to its stem, so "running", "runs", and "run" are all "run". The stemmer only
+/// knows English, so words with other characters are left as they are, and so is anything it
+/// can't stem.
+pub(crate) fn stem_token(token: String) -> String {
+    if !token.is_ascii() {
+        return token;
+    }
+    match stem::get(&token) {
+        Ok(stemmed) if !stemmed.is_empty() => stemmed,
+        _ => token,
+    }
+}
+
+fn tokenize_with_min_length(slice: &str, min_length: usize) -> Vec<String> {
+    split_words(slice, min_length, &STOP_WORD_SET)
+}
+
+/// Lowercased words in `slice`, without punctuation, `stop_words`, or words shorter than
+/// `min_length` characters.
+fn split_words(slice: &str, min_length: usize, stop_words: &HashSet<String>) -> Vec<String> {
     let punct_to_whitespace = PUNCT_RGX.replace_all(slice, " ");
     punct_to_whitespace
         .split(' ')
@@ -29,7 +63,9 @@ pub(crate) fn tokenize(slice: &str) -> Vec<String> {
             let word = w.to_lowercase();
             word.replace('\n', "")
         })
-        .filter(|w| STOP_WORD_MAP.get(w.as_str()).is_none() && !w.is_empty() && w.len() <= 80)
+        .filter(|w| {
+            !stop_words.contains(w) && w.chars().count() >= min_length.max(1) && w.len() <= 80
+        })
         .collect()
 }
 
@@ -51,7 +87,7 @@ mod tests {
             "bookmark",
             "idea",
         ];
-        let tokenized = tokenize(test_string);
+        let tokenized = tokenize_with_min_length(test_string, 2);
         assert_eq!(tokenized, tokens);
     }
 
@@ -59,7 +95,49 @@ mod tests {
     fn tokenizes_strings_with_links() {
         let test_string = "[[link|https://teukka.tech/luanvim.html]]";
         let tokens = ["link", "teukka", "tech", "luanvim", "html"];
-        let tokenized = tokenize(test_string);
+        let tokenized = tokenize_with_min_length(test_string, 2);
         assert_eq!(tokenized, tokens);
     }
+
+    #[test]
+    fn leaves_out_short_tokens() {
+        let test_string = "x marks 1 spot, 42 times";
+        assert_eq!(
+            tokenize_with_min_length(test_string, 2),
+            ["marks", "spot", "42", "times"]
+        );
+        assert_eq!(
+            tokenize_with_min_length(test_string, 0),
+            ["x", "marks", "1", "spot", "42", "times"]
+        );
+        assert_eq!(tokenize_with_min_length(test_string, 5), ["marks", "times"]);
+    }
+
+    #[test]
+    fn leaves_out_stop_words() {
+        let test_string = "The history of the garden and the people in it";
+        let defaults = stop_words(None);
+        assert_eq!(
+            split_words(test_string, 2, &defaults),
+            ["history", "garden", "people"]
+        );
+        let custom = stop_words(Some(&[String::from("Garden"), String::from("people")]));
+        assert_eq!(
+            split_words(test_string, 2, &custom),
+            ["the", "history", "of", "the", "and", "the", "in", "it"]
+        );
+        let none = stop_words(Some(&[]));
+        assert_eq!(split_words(test_string, 2, &none).len(), 10);
+        assert!(split_words("the and of", 2, &defaults).is_empty());
+    }
+
+    #[test]
+    fn stems_tokens() {
+        for word in ["running", "runs", "run"] {
+            assert_eq!(stem_token(word.into()), "run");
+        }
+        assert_eq!(stem_token("café".into()), "café");
+        assert_eq!(stem_token("über".into()), "über");
+        assert_eq!(stem_token("42".into()), "42");
+    }
 }
@@ -1,18 +1,22 @@
-use compression::prelude::*;
-use readability::extractor::{self, Product};
+use persistance::fs::{archive, config::read_config};
+use readability::extractor;
+pub use readability::extractor::Product;
+use thiserror::Error;
 
-pub fn extract(url: String) -> Product {
-    match extractor::scrape(&url) {
-        Ok(product) => product,
-        Err(e) => panic!("{}", e),
-    }
+#[derive(Error, Debug)]
+#[error("could not fetch {url}: {reason}")]
+pub struct ExtractError {
+    pub url: String,
+    pub reason: String,
+}
+
+pub fn extract(url: String) -> Result<Product, ExtractError> {
+    extractor::scrape(&url).map_err(|e| ExtractError {
+        reason: e.to_string(),
+        url,
+    })
 }
 
 pub fn compress(text: &str) -> Vec<u8> {
-    text.as_bytes()
-        .iter()
-        .cloned()
-        .encode(&mut BZip2Encoder::new(9), Action::Finish)
-        .collect::<Result<Vec<_>, _>>()
-        .unwrap()
+    archive::compress(text, &read_config().archive)
 }
@@ -1,4 +1,18 @@
-use persistance::fs::{read_note_cache, write_note_cache};
+use persistance::fs::{read_note_cache, read_view_cache, write_note_cache, write_view_cache};
+use tokio::sync::Mutex;
+
+/// Number of titles kept in the recently changed list.
+pub const NOTE_CACHE_SIZE: usize = 8;
+
+/// Number of titles kept in the recently viewed list.
+const VIEW_CACHE_SIZE: usize = 8;
+
+lazy_static! {
+    /// Views are recorded from request handlers while renames and deletes come through the task
+    /// queue, so each read-modify-write of the view cache holds this to keep them from clobbering
+    /// each other.
+    static ref VIEW_CACHE_LOCK: Mutex<()> = Mutex::new(());
+}
 
 pub async fn purge_mru_cache(title: &str) {
     let recent = read_note_cache().await;
@@ -9,16 +23,54 @@ pub async fn update_mru_cache(old_title: &str, current_title: &str) {
     let recent = read_note_cache().await;
     // Filter out the current title and the old title.
     // We don't need to separate based whether or not the not has been renamed since the
-    // array is only ever 8 entries long, this will be fast.
+    // array is only ever NOTE_CACHE_SIZE entries long, this will be fast.
     let filtered = filter_cache_file(&recent, current_title);
     let mut filtered = filter_cache_file(&filtered.join("\n"), old_title);
-    if filtered.len() >= 8 {
+    if filtered.len() >= NOTE_CACHE_SIZE {
         filtered.pop();
     }
     filtered.insert(0, current_title.into());
     write_filtered_cache_file(filtered).await;
 }
 
+pub async fn update_view_cache(title: &str) {
+    let _lock = VIEW_CACHE_LOCK.lock().await;
+    let viewed = read_view_cache().await;
+    write_view_cache(push_viewed(&viewed, title).join("\n")).await;
+}
+
+pub async fn purge_view_cache(title: &str) {
+    let _lock = VIEW_CACHE_LOCK.lock().await;
+    let viewed = read_view_cache().await;
+    write_view_cache(filter_cache_file(&viewed, title).join("\n")).await;
+}
+
+/// Keeps a renamed note in the same spot in the recently viewed list.
+pub async fn rename_in_view_cache(old_title: &str, current_title: &str) {
+    let _lock = VIEW_CACHE_LOCK.lock().await;
+    let viewed = read_view_cache().await;
+    let renamed = viewed
+        .lines()
+        .map(|line| {
+            if line == old_title {
+                current_title
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<&str>>();
+    write_view_cache(renamed.join("\n")).await;
+}
+
+/// Moves `title` to the front of the recently viewed list, dropping the oldest view once the list
+/// is full.
+fn push_viewed(viewed: &str, title: &str) -> Vec<String> {
+    let mut viewed = filter_cache_file(viewed, title);
+    viewed.insert(0, title.into());
+    viewed.truncate(VIEW_CACHE_SIZE);
+    viewed
+}
+
 async fn write_filtered_cache_file(filtered: Vec<String>) {
     let filtered = filtered.join("\n");
     write_note_cache(filtered).await;
@@ -31,3 +83,25 @@ fn filter_cache_file(recent: &str, title: &str) -> Vec<String> {
         .map(|l| l.to_owned())
         .collect::<Vec<String>>()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_views_newest_first() {
+        let viewed = ["Alpha", "Beta", "Gamma", "Beta"]
+            .iter()
+            .fold(Vec::new(), |viewed, title| {
+                push_viewed(&viewed.join("\n"), title)
+            });
+        assert_eq!(viewed, vec!["Beta", "Gamma", "Alpha"]);
+
+        let viewed = (0..VIEW_CACHE_SIZE + 2).fold(Vec::new(), |viewed, i| {
+            push_viewed(&viewed.join("\n"), &format!("Page {}", i))
+        });
+        assert_eq!(viewed.len(), VIEW_CACHE_SIZE);
+        assert_eq!(viewed[0], format!("Page {}", VIEW_CACHE_SIZE + 1));
+        assert_eq!(viewed[VIEW_CACHE_SIZE - 1], "Page 2");
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rand_core::{OsRng, RngCore};
+use thiserror::Error;
+
+/// How long the confirmation page can sit open before its token stops working.
+const DELETE_TOKEN_TTL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("This delete wasn't confirmed, or the confirmation expired. Open the delete page for the note and try again.")]
+pub struct InvalidDeleteToken;
+
+/// One-time tokens handed out by the delete confirmation page, so a note is only deleted from a
+/// page the wiki rendered and not from any request that happens to name its title.
+#[derive(Debug, Default)]
+pub struct DeleteTokens {
+    issued: HashMap<String, (String, Instant)>,
+}
+
+impl DeleteTokens {
+    pub fn issue(&mut self, title: &str, now: Instant) -> String {
+        self.issued
+            .retain(|_, (_, issued_at)| now.duration_since(*issued_at) < DELETE_TOKEN_TTL);
+        let mut bytes = [0u8; 16];
+        OsRng.fill_bytes(&mut bytes);
+        let token = bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        self.issued.insert(token.clone(), (title.into(), now));
+        token
+    }
+
+    /// Uses up `token`, which only confirms deleting the note it was issued for.
+    pub fn redeem(
+        &mut self,
+        title: &str,
+        token: &str,
+        now: Instant,
+    ) -> Result<(), InvalidDeleteToken> {
+        match self.issued.remove(token) {
+            Some((issued_for, issued_at))
+                if issued_for == title && now.duration_since(issued_at) < DELETE_TOKEN_TTL =>
+            {
+                Ok(())
+            }
+            _ => Err(InvalidDeleteToken),
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref DELETE_TOKENS: Mutex<DeleteTokens> = Mutex::new(DeleteTokens::default());
+}
+
+pub fn issue_delete_token(title: &str) -> String {
+    DELETE_TOKENS.lock().unwrap().issue(title, Instant::now())
+}
+
+pub fn redeem_delete_token(title: &str, token: &str) -> Result<(), InvalidDeleteToken> {
+    DELETE_TOKENS
+        .lock()
+        .unwrap()
+        .redeem(title, token, Instant::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_deletes_without_a_valid_token() {
+        let mut tokens = DeleteTokens::default();
+        let now = Instant::now();
+        assert_eq!(tokens.redeem("Garden", "", now), Err(InvalidDeleteToken));
+
+        let token = tokens.issue("Garden", now);
+        assert_eq!(
+            tokens.redeem("Other note", &token, now),
+            Err(InvalidDeleteToken)
+        );
+        // A token is gone once it's been tried, even for the wrong note
+        assert_eq!(
+            tokens.redeem("Garden", &token, now),
+            Err(InvalidDeleteToken)
+        );
+
+        let token = tokens.issue("Garden", now);
+        assert_eq!(tokens.redeem("Garden", &token, now), Ok(()));
+        assert_eq!(
+            tokens.redeem("Garden", &token, now),
+            Err(InvalidDeleteToken)
+        );
+
+        let token = tokens.issue("Garden", now);
+        let later = now + DELETE_TOKEN_TTL;
+        assert_eq!(
+            tokens.redeem("Garden", &token, later),
+            Err(InvalidDeleteToken)
+        );
+    }
+}
@@ -8,8 +8,11 @@ use ulid::Ulid;
 
 pub mod archive;
 pub mod cache;
+pub mod delete_tokens;
 pub mod messages;
+pub mod metrics;
 pub mod password;
+pub mod redirects;
 pub mod runners;
 pub mod sync;
 pub mod verify;
@@ -64,6 +67,7 @@ impl Queue for JobQueue {
         match self.jobs.lock() {
             Ok(mut jobs) => {
                 jobs.push(enqueued_job);
+                metrics::QUEUE_DEPTH.set(jobs.len() as u64);
                 Ok(())
             }
             Err(e) => {
@@ -75,13 +79,13 @@ impl Queue for JobQueue {
     async fn pull(&self, number_of_jobs: u32) -> Result<Vec<Job>, crate::TaskError> {
         match self.jobs.lock() {
             Ok(mut jobs) => {
-                if number_of_jobs as usize > jobs.len() {
-                    let job_queue = jobs.drain(..).collect::<Vec<Job>>();
-                    Ok(job_queue)
+                let job_queue = if number_of_jobs as usize > jobs.len() {
+                    jobs.drain(..).collect::<Vec<Job>>()
                 } else {
-                    let job_queue = jobs.drain(..number_of_jobs as usize).collect();
-                    Ok(job_queue)
-                }
+                    jobs.drain(..number_of_jobs as usize).collect()
+                };
+                metrics::QUEUE_DEPTH.set(jobs.len() as u64);
+                Ok(job_queue)
             }
             Err(e) => {
                 eprintln!("{}", e);
@@ -9,6 +9,9 @@ pub enum Message {
         patch: PatchData,
     },
     Rebuild,
+    /// Rebuilds the search index along with the links, e.g. after notes were edited outside the
+    /// wiki.
+    Reindex,
     Delete {
         title: String,
     },
@@ -33,3 +36,10 @@ pub enum Message {
         install_location: PathBuf,
     },
 }
+
+impl Message {
+    /// Jobs that fetch pages over the network, which can take far longer than local ones.
+    pub fn is_network_bound(&self) -> bool {
+        matches!(self, Message::Archive { .. } | Message::NewFromUrl { .. })
+    }
+}
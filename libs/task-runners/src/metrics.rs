@@ -0,0 +1,112 @@
+use std::{
+    fmt::Write as _,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// A single value exposed at `/metrics`, in the Prometheus text format.
+pub struct Metric {
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+    value: AtomicU64,
+}
+
+impl Metric {
+    const fn counter(name: &'static str, help: &'static str) -> Self {
+        Metric {
+            name,
+            help,
+            kind: "counter",
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub const fn gauge(name: &'static str, help: &'static str) -> Self {
+        Metric {
+            name,
+            help,
+            kind: "gauge",
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, value: u64) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+pub static PAGES_RENDERED: Metric =
+    Metric::counter("tendril_pages_rendered_total", "Wiki pages rendered.");
+pub static SEARCHES: Metric = Metric::counter("tendril_searches_total", "Searches performed.");
+pub static QUEUE_DEPTH: Metric = Metric::gauge(
+    "tendril_task_queue_depth",
+    "Jobs waiting in the task queue.",
+);
+pub static ARCHIVE_SUCCESSES: Metric = Metric::counter(
+    "tendril_archive_successes_total",
+    "Pages fetched and archived.",
+);
+pub static ARCHIVE_FAILURES: Metric = Metric::counter(
+    "tendril_archive_failures_total",
+    "Pages that could not be fetched for archiving.",
+);
+
+/// 1 once the links and search index have been built at startup, which `/readyz` reports on.
+pub static INDEX_READY: Metric = Metric::gauge(
+    "tendril_index_ready",
+    "Whether the links and search index have been built since starting.",
+);
+
+static METRICS: [&Metric; 6] = [
+    &PAGES_RENDERED,
+    &SEARCHES,
+    &QUEUE_DEPTH,
+    &ARCHIVE_SUCCESSES,
+    &ARCHIVE_FAILURES,
+    &INDEX_READY,
+];
+
+pub fn render_metrics() -> String {
+    render(&METRICS)
+}
+
+fn render(metrics: &[&Metric]) -> String {
+    metrics.iter().fold(String::new(), |mut output, metric| {
+        let _ = write!(
+            output,
+            "# HELP {name} {}\n# TYPE {name} {}\n{name} {}\n",
+            metric.help,
+            metric.kind,
+            metric.get(),
+            name = metric.name,
+        );
+        output
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_prometheus_text() {
+        let searches = Metric::counter("searches_total", "Searches performed.");
+        let depth = Metric::gauge("queue_depth", "Jobs waiting.");
+        searches.inc();
+        searches.inc();
+        depth.set(3);
+        assert_eq!(
+            render(&[&searches, &depth]),
+            "# HELP searches_total Searches performed.\n# TYPE searches_total counter\nsearches_total 2\n\
+             # HELP queue_depth Jobs waiting.\n# TYPE queue_depth gauge\nqueue_depth 3\n"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use thiserror::Error;
+use wikitext::parsers::Note;
+
+/// Notes with a `redirect` field in their frontmatter show the note it names instead.
+pub const REDIRECT_FIELD: &str = "redirect";
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("These pages redirect to each other in a loop: {}", .0.join(" → "))]
+pub struct RedirectLoop(pub Vec<String>);
+
+/// The pages visited while following redirects, so a page showing up twice can be caught before
+/// it loops forever.
+#[derive(Debug, Default)]
+pub struct RedirectChain {
+    visited: Vec<String>,
+}
+
+impl RedirectChain {
+    pub fn visit(&mut self, title: &str) -> Result<(), RedirectLoop> {
+        let seen = self.visited.iter().any(|visited| visited == title);
+        self.visited.push(title.into());
+        if seen {
+            return Err(RedirectLoop(self.visited.clone()));
+        }
+        Ok(())
+    }
+}
+
+pub fn redirect_target(note: &Note) -> Option<&str> {
+    note.header
+        .get(REDIRECT_FIELD)
+        .map(|target| target.trim())
+        .filter(|target| !target.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catches_pages_visited_twice() {
+        let mut chain = RedirectChain::default();
+        assert_eq!(chain.visit("A"), Ok(()));
+        assert_eq!(chain.visit("B"), Ok(()));
+        let redirect_loop = chain.visit("A").unwrap_err();
+        assert_eq!(
+            redirect_loop,
+            RedirectLoop(vec!["A".into(), "B".into(), "A".into()])
+        );
+        assert_eq!(
+            redirect_loop.to_string(),
+            "These pages redirect to each other in a loop: A → B → A"
+        );
+    }
+
+    #[test]
+    fn reads_redirect_targets() {
+        let note = Note::from(String::from("title: old\ntags: []\nredirect: New Page\n\n"));
+        assert_eq!(redirect_target(&note), Some("New Page"));
+        let note = Note::from(String::from("title: plain\ntags: []\n\nbody"));
+        assert_eq!(redirect_target(&note), None);
+    }
+}
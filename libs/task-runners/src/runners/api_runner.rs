@@ -1,12 +1,20 @@
 use std::{collections::HashMap, io, time::Instant};
 
 use bytes::Bytes;
-use persistance::fs::{read, utils::get_config_location, write_media};
-use render::{search_results_page::SearchResultsPage, Render};
-use search_engine::semantic_search;
+use persistance::fs::{
+    config::read_config, read, read_archive, utils::get_config_location, write_media,
+};
+use render::{
+    error_page::ErrorPage,
+    search_results_page::{SearchResult, SearchResultsPage},
+    Render,
+};
+use search_engine::{semantic_search, snippet, QueryErr};
 use thiserror::Error;
 use urlencoding::decode;
-use wikitext::parsers::Note;
+use wikitext::{parsers::Note, processors::sanitize_html};
+
+use crate::metrics::SEARCHES;
 
 pub struct APIRunner {}
 
@@ -41,18 +49,65 @@ impl APIRunner {
         write_media(&filename, bytes.as_ref()).await
     }
 
-    pub async fn note_search(term: String) -> String {
+    /// Every page matching `term`, most relevant first, with excerpts for the first `limit` of
+    /// them. With `fuzzy`, misspelled words also match what they're close to.
+    pub async fn search(
+        term: &str,
+        limit: usize,
+        fuzzy: bool,
+    ) -> Result<Vec<SearchResult>, QueryErr> {
+        SEARCHES.inc();
+        let found_pages = semantic_search(term, fuzzy).await?;
+        let mut pages = Vec::with_capacity(found_pages.len());
+        for (index, (title, score)) in found_pages.into_iter().enumerate() {
+            // Only the results on the page need an excerpt
+            let snippet = if index < limit {
+                Self::search_snippet(&title, term).await
+            } else {
+                String::with_capacity(0)
+            };
+            pages.push(SearchResult {
+                title,
+                score,
+                snippet,
+            });
+        }
+        Ok(pages)
+    }
+
+    /// Renders the first `limit` results, or a page's worth when no limit is given.
+    pub async fn note_search(term: String, limit: Option<usize>, fuzzy: bool) -> String {
         let now = Instant::now();
-        let found_pages = semantic_search(&term).await;
-        let num_results = found_pages.len();
-        let ctx = SearchResultsPage {
-            pages: found_pages,
-            num_results,
-            time: now.elapsed(),
+        let page_size = read_config().search.results_per_page;
+        let limit = limit.unwrap_or(page_size);
+        let pages = match Self::search(&term, limit, fuzzy).await {
+            Ok(pages) => pages,
+            Err(e) => return ErrorPage::new(sanitize_html(&e.to_string())).render().await,
         };
+        let num_results = pages.len();
+        let ctx = SearchResultsPage::new(
+            pages,
+            num_results,
+            now.elapsed(),
+            term,
+            limit,
+            page_size,
+            fuzzy,
+        );
         ctx.render().await
     }
 
+    /// An excerpt of the note or archived page `title` around what matched `term`.
+    async fn search_snippet(title: &str, term: &str) -> String {
+        match read(title.to_owned()).await {
+            Ok(note) => snippet(&note.content, term),
+            Err(_) => match read_archive(title).await {
+                Ok(archive) => snippet(&archive, term),
+                Err(_) => String::with_capacity(0),
+            },
+        }
+    }
+
     pub async fn update_styles(form_body: HashMap<String, String>) -> Result<(), io::Error> {
         let (path, _) = get_config_location();
         let style_location = path.join("userstyles.css");
@@ -4,7 +4,7 @@ use persistance::fs::write;
 use regex::Regex;
 use tokio::time::timeout;
 use urlencoding::encode;
-use wikitext::{processors::sanitize_html, PatchData};
+use wikitext::{parsers::with_base_path, processors::sanitize_html, PatchData};
 
 use crate::{archive::extract, messages::Message, Queue, QueueHandle};
 
@@ -14,7 +14,7 @@ impl BookmarkRunner {
     async fn new_from_url(url: String, tags: Vec<String>) -> Result<(String, PatchData), ()> {
         let mut metadata = HashMap::new();
         metadata.insert(String::from("url"), url.clone());
-        if let Ok(product) = tokio::task::spawn_blocking(move || extract(url)).await {
+        if let Ok(Ok(product)) = tokio::task::spawn_blocking(move || extract(url)).await {
             metadata.insert("content-type".into(), "html".into());
             let title = normalize_title(&product.title);
             let patch = PatchData {
@@ -23,6 +23,7 @@ impl BookmarkRunner {
                 title,
                 old_title: String::with_capacity(0),
                 metadata,
+                dir: String::with_capacity(0),
             };
             Ok((product.text, patch))
         } else {
@@ -63,11 +64,11 @@ impl BookmarkRunner {
                         })
                         .await
                         .unwrap();
-                    return format!("/{}", encode(&patch.title));
+                    return with_base_path(&format!("/{}", encode(&patch.title)));
                 }
                 Err(e) => {
                     eprintln!("  {}\n", e);
-                    return format!("/error?msg={}", encode(&format!("{:?}", e)));
+                    return with_base_path(&format!("/error?msg={}", encode(&format!("{:?}", e))));
                 }
             }
         } else {
@@ -79,7 +80,7 @@ impl BookmarkRunner {
                 .await
                 .unwrap();
         }
-        String::from("/bookmark")
+        with_base_path("/bookmark")
     }
 }
 
@@ -1,9 +1,15 @@
 use std::collections::HashMap;
 
-use persistance::fs::utils::get_config_location;
+use persistance::fs::{read, read_note_cache, utils::get_config_location};
 use render::{
-    error_page::ErrorPage, styles_page::StylesPage, uploaded_files_page::UploadedFilesPage, Render,
+    error_page::ErrorPage,
+    feed_page::{FeedEntry, FeedPage},
+    recent_changes_page::{RecentChange, RecentChangesPage},
+    styles_page::StylesPage,
+    uploaded_files_page::UploadedFilesPage,
+    Render,
 };
+use search_engine::snippet;
 use tokio::fs::{self, read_dir};
 
 pub struct StaticPageRunner {}
@@ -33,6 +39,37 @@ impl StaticPageRunner {
         let ctx = StylesPage { body };
         ctx.render().await
     }
+    /// An Atom feed of the notes in the recently changed list, which deleted notes are left out of.
+    pub async fn render_feed(user: String, host: String) -> String {
+        let recent = read_note_cache().await;
+        let mut entries = Vec::new();
+        for title in recent.lines().filter(|title| !title.trim().is_empty()) {
+            if let Ok(note) = read(title.to_string()).await {
+                let summary = snippet(&note.content, "");
+                entries.push(FeedEntry::new(title.to_string(), &note, summary));
+            }
+        }
+        FeedPage::new(user, host, entries).render().await
+    }
+    /// Up to `limit` of the notes in the recently changed list, newest first. Notes that have
+    /// since been deleted are left out.
+    pub async fn recent_changes(limit: usize) -> Vec<RecentChange> {
+        let recent = read_note_cache().await;
+        let mut entries = Vec::new();
+        for title in recent.lines().filter(|title| !title.trim().is_empty()) {
+            if entries.len() >= limit {
+                break;
+            }
+            if let Ok(note) = read(title.to_string()).await {
+                entries.push(RecentChange::new(title.to_string(), &note));
+            }
+        }
+        entries
+    }
+    pub async fn render_recent_changes(limit: usize) -> String {
+        let ctx = RecentChangesPage::new(Self::recent_changes(limit).await);
+        ctx.render().await
+    }
     pub async fn show_error(params: HashMap<String, String>) -> String {
         let msg = params
             .get("msg")
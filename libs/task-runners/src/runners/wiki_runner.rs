@@ -1,59 +1,162 @@
 use std::collections::HashMap;
 use std::fmt::Write as _;
 
-use persistance::fs::{create_journal_entry, read, write, ReadPageError, WriteWikiError};
-use render::{injected_html::InjectedHTML, new_page::NewPage, wiki_page::WikiPage, Render};
+use persistance::fs::{
+    create_journal_entry, get_note_titles, read, read_archive, utils::get_file_path, write,
+    ReadPageError, WriteWikiError,
+};
+use render::{
+    archive_page::ArchivePage, delete_page::DeletePage, error_page::ErrorPage,
+    injected_html::InjectedHTML, new_page::NewPage, wiki_page::WikiPage, PageRenderLinks, Render,
+};
 use urlencoding::decode;
-use wikitext::{parsers::Note, PatchData};
+use wikitext::{
+    normalize_title,
+    parsers::{escape_html, with_base_path, Note},
+    processors::sanitize_html,
+    PatchData,
+};
 
-use crate::{cache::purge_mru_cache, messages::Message, Queue, QueueHandle};
+use crate::{
+    cache::{purge_mru_cache, purge_view_cache, update_view_cache},
+    delete_tokens::{issue_delete_token, redeem_delete_token, InvalidDeleteToken},
+    messages::Message,
+    metrics::PAGES_RENDERED,
+    redirects::{redirect_target, RedirectChain, RedirectLoop},
+    Queue, QueueHandle,
+};
 
 pub struct WikiRunner {}
 
+/// Maps a requested page to the title it's stored under: the normalized title, or an existing
+/// note whose title only differs in case.
+fn resolve_title(requested: &str) -> String {
+    let title = normalize_title(requested);
+    if get_file_path(&title).map(|p| p.exists()).unwrap_or(false) {
+        return title;
+    }
+    get_note_titles()
+        .unwrap_or_default()
+        .into_iter()
+        .find(|existing| existing.to_lowercase() == title.to_lowercase())
+        .unwrap_or(title)
+}
+
 impl WikiRunner {
     pub async fn render_file(
         &self,
         path: String,
-        reflinks: Option<&Vec<String>>,
+        reflinks: PageRenderLinks<'_>,
         query_params: HashMap<String, String>,
+        editable: bool,
     ) -> String {
         let path = decode(&path).unwrap();
-        self.render_from_path(path.to_string(), reflinks, query_params)
+        let title = resolve_title(&path);
+        let exists = get_file_path(&title).map(|p| p.exists()).unwrap_or(false);
+        let page = self
+            .render_title(title.clone(), reflinks, query_params, editable)
+            .await
+            .unwrap();
+        if exists {
+            update_view_cache(&title).await;
+        }
+        page
+    }
+
+    /// The page a request for `path` should end up on when its note redirects elsewhere, following
+    /// redirects to redirect pages along the way.
+    pub async fn follow_redirects(&self, path: &str) -> Result<Option<String>, RedirectLoop> {
+        let mut chain = RedirectChain::default();
+        let requested = resolve_title(path);
+        let mut title = requested.clone();
+        chain.visit(&title)?;
+        while let Ok(note) = read(title.clone()).await {
+            match redirect_target(&note) {
+                Some(target) => {
+                    title = resolve_title(target);
+                    chain.visit(&title)?;
+                }
+                None => break,
+            }
+        }
+        Ok(Some(title).filter(|title| *title != requested))
+    }
+
+    pub async fn render_redirect_loop(&self, redirect_loop: RedirectLoop) -> String {
+        ErrorPage::new(sanitize_html(&redirect_loop.to_string()))
+            .render()
             .await
-            .unwrap()
     }
 
-    async fn note_to_html(&self, note: Note, links: Option<&Vec<String>>) -> String {
+    async fn note_to_html(
+        &self,
+        note: Note,
+        links: PageRenderLinks<'_>,
+        dir: &str,
+        editable: bool,
+    ) -> String {
+        PAGES_RENDERED.inc();
         let templatted = note.to_template();
         match note.header.get("content-type") {
             Some(content_type) => {
                 if content_type == "html" {
-                    return InjectedHTML::new(&templatted.page, links).render().await;
+                    return InjectedHTML::new(&templatted.page, links)
+                        .editable(editable)
+                        .render()
+                        .await;
                 }
-                WikiPage::new(&templatted.page, links).render().await
+                WikiPage::new(&templatted.page, links)
+                    .in_dir(dir)
+                    .editable(editable)
+                    .render()
+                    .await
+            }
+            None => {
+                WikiPage::new(&templatted.page, links)
+                    .in_dir(dir)
+                    .editable(editable)
+                    .render()
+                    .await
             }
-            None => WikiPage::new(&templatted.page, links).render().await,
         }
     }
 
+    /// The editor for a note that doesn't exist yet, or an error for visitors who can't create it.
+    async fn render_missing(
+        &self,
+        title: String,
+        linkto: Option<&String>,
+        editable: bool,
+    ) -> String {
+        if !editable {
+            return ErrorPage::new(format!("There's no note called {}.", escape_html(&title)))
+                .render()
+                .await;
+        }
+        let ctx = NewPage {
+            title: Some(title),
+            linkto,
+            action_params: None,
+        };
+        ctx.render().await
+    }
+
     pub async fn render_nested_file(
         &self,
         mut main_path: String,
         sub_path: String,
-        links: Option<&Vec<String>>,
+        links: PageRenderLinks<'_>,
+        editable: bool,
     ) -> Result<String, ReadPageError> {
         // I don't know why warp doesn't decode the sub path here...
         let sub_path_decoded = decode(&sub_path).unwrap();
+        let dir = main_path.clone();
         write!(main_path, "/{}", sub_path_decoded).unwrap();
         match read(main_path.clone()).await {
-            Ok(note) => Ok(self.note_to_html(note, links).await),
+            Ok(note) => Ok(self.note_to_html(note, links, &dir, editable).await),
             Err(ReadPageError::PageNotFoundError) => {
-                let ctx = NewPage {
-                    title: Some(urlencoding::decode(&sub_path).unwrap().into_owned()),
-                    linkto: None,
-                    action_params: None,
-                };
-                Ok(ctx.render().await)
+                let title = urlencoding::decode(&sub_path).unwrap().into_owned();
+                Ok(self.render_missing(title, None, editable).await)
             }
             e => {
                 eprint!("{:?}", e);
@@ -65,18 +168,29 @@ impl WikiRunner {
     pub async fn render_from_path(
         &self,
         path: String,
-        links: Option<&Vec<String>>,
+        links: PageRenderLinks<'_>,
+        query_params: HashMap<String, String>,
+        editable: bool,
+    ) -> Result<String, ReadPageError> {
+        self.render_title(resolve_title(&path), links, query_params, editable)
+            .await
+    }
+
+    /// Renders the note stored under `path`, which has already gone through `resolve_title`.
+    async fn render_title(
+        &self,
+        path: String,
+        links: PageRenderLinks<'_>,
         query_params: HashMap<String, String>,
+        editable: bool,
     ) -> Result<String, ReadPageError> {
         match read(path.clone()).await {
-            Ok(note) => Ok(self.note_to_html(note, links).await),
+            Ok(note) => Ok(self.note_to_html(note, links, "", editable).await),
             Err(ReadPageError::PageNotFoundError) => {
-                let ctx = NewPage {
-                    title: Some(urlencoding::decode(&path).unwrap().into_owned()),
-                    linkto: query_params.get("linkto"),
-                    action_params: None,
-                };
-                Ok(ctx.render().await)
+                let title = urlencoding::decode(&path).unwrap().into_owned();
+                Ok(self
+                    .render_missing(title, query_params.get("linkto"), editable)
+                    .await)
             }
             e => {
                 eprint!("{:?}", e);
@@ -84,6 +198,23 @@ impl WikiRunner {
             }
         }
     }
+    /// The note stored under `path`, for serving it as something other than a rendered page.
+    pub async fn read_note(&self, path: &str) -> Result<Note, ReadPageError> {
+        read(resolve_title(path)).await
+    }
+    pub async fn render_archive(&self, title: String) -> String {
+        let title = resolve_title(&title);
+        let archive = match read_archive(&title).await {
+            Ok(archive) => Some(archive),
+            Err(ReadPageError::PageNotFoundError) => None,
+            Err(e) => {
+                eprintln!("Could not read archive for {}: {}", title, e);
+                None
+            }
+        };
+        ArchivePage::new(title, archive).render().await
+    }
+
     pub async fn render_new(query_params: HashMap<String, String>) -> String {
         let ctx = NewPage {
             title: None,
@@ -93,7 +224,8 @@ impl WikiRunner {
         ctx.render().await
     }
 
-    pub async fn edit(body: PatchData, queue: QueueHandle) -> Result<(), WriteWikiError> {
+    pub async fn edit(mut body: PatchData, queue: QueueHandle) -> Result<(), WriteWikiError> {
+        body.title = normalize_title(&body.title);
         if body
             .tags
             .iter()
@@ -145,8 +277,23 @@ impl WikiRunner {
         }
     }
 
-    pub async fn delete(queue: QueueHandle, form_body: HashMap<String, String>) -> String {
-        let title = form_body.get("title").unwrap();
+    pub async fn render_delete(title: String) -> String {
+        let token = issue_delete_token(&title);
+        DeletePage::new(title, token).render().await
+    }
+
+    pub async fn render_delete_rejected(rejected: InvalidDeleteToken) -> String {
+        ErrorPage::new(rejected.to_string()).render().await
+    }
+
+    /// Deletes the note named in the form, as long as it carries a token from the confirmation page.
+    pub async fn delete(
+        queue: QueueHandle,
+        form_body: HashMap<String, String>,
+    ) -> Result<String, InvalidDeleteToken> {
+        let title = form_body.get("title").ok_or(InvalidDeleteToken)?;
+        let token = form_body.get("token").ok_or(InvalidDeleteToken)?;
+        redeem_delete_token(title, token)?;
         queue
             .push(Message::Delete {
                 title: title.into(),
@@ -155,6 +302,98 @@ impl WikiRunner {
             .unwrap();
 
         purge_mru_cache(title).await;
-        String::from("/")
+        purge_view_cache(title).await;
+        Ok(with_base_path("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use persistance::fs::read_view_cache;
+    use tokio::sync::{Mutex, MutexGuard};
+
+    use super::*;
+
+    /// The wiki and data directories are read once per process, so every test shares this one and
+    /// holds the returned guard while using it.
+    async fn init_test_wiki() -> (&'static str, MutexGuard<'static, ()>) {
+        const TEST_DIR: &str = "/tmp/tendril-test/wiki-runner/";
+        static WIKI: OnceLock<Mutex<()>> = OnceLock::new();
+        let guard = WIKI.get_or_init(Default::default).lock().await;
+        std::env::set_var("TENDRIL_WIKI_DIR", TEST_DIR);
+        std::env::set_var("TENDRIL_DATA_DIR", TEST_DIR);
+        std::fs::create_dir_all(TEST_DIR).unwrap();
+        (TEST_DIR, guard)
+    }
+
+    fn write_note(dir: &str, title: &str, content: &str) {
+        let note = Note {
+            header: HashMap::from([(String::from("title"), String::from(title))]),
+            content: String::from(content),
+        };
+        std::fs::write(format!("{}{}.txt", dir, title), String::from(note)).unwrap();
+    }
+
+    fn write_redirect(dir: &str, title: &str, target: &str) {
+        let note = Note {
+            header: HashMap::from([
+                (String::from("title"), String::from(title)),
+                (String::from("redirect"), String::from(target)),
+            ]),
+            content: String::new(),
+        };
+        std::fs::write(format!("{}{}.txt", dir, title), String::from(note)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn renders_notes_by_their_stored_title() {
+        let (dir, _wiki) = init_test_wiki().await;
+        write_note(dir, "Pole beans", "Give them something to climb");
+        let runner = WikiRunner {};
+
+        let page = runner
+            .render_file(String::from("pole%20BEANS"), None, HashMap::new(), true)
+            .await;
+        assert!(page.contains("Give them something to climb"));
+        assert!(read_view_cache()
+            .await
+            .lines()
+            .any(|viewed| viewed == "Pole beans"));
+
+        let page = runner
+            .render_file(String::from("Bush beans"), None, HashMap::new(), false)
+            .await;
+        assert!(page.contains("There's no note called Bush beans."));
+        assert!(!read_view_cache()
+            .await
+            .lines()
+            .any(|viewed| viewed == "Bush beans"));
+    }
+
+    #[tokio::test]
+    async fn follows_redirects_until_they_loop() {
+        let (dir, _wiki) = init_test_wiki().await;
+        write_redirect(dir, "Loop A", "Loop B");
+        write_redirect(dir, "Loop B", "Loop A");
+        write_redirect(dir, "Chain A", "Chain B");
+        write_redirect(dir, "Chain B", "chain c");
+        write_note(dir, "Chain C", "Where the chain ends");
+        let runner = WikiRunner {};
+
+        assert_eq!(
+            runner.follow_redirects("Loop A").await,
+            Err(RedirectLoop(vec![
+                String::from("Loop A"),
+                String::from("Loop B"),
+                String::from("Loop A"),
+            ]))
+        );
+        assert_eq!(
+            runner.follow_redirects("Chain A").await,
+            Ok(Some(String::from("Chain C")))
+        );
+        assert_eq!(runner.follow_redirects("Chain C").await, Ok(None));
     }
 }
@@ -1,16 +1,37 @@
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     sync::Arc,
 };
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use thiserror::Error;
 use tokio::sync::Mutex;
+use urlencoding::encode;
+
+use crate::parsers::with_base_path;
 
 pub mod parsers;
 pub mod processors;
 
 pub type GlobalBacklinks = Arc<Mutex<Backlinks>>;
-pub type Backlinks = BTreeMap<String, Vec<String>>;
+pub type Backlinks = BTreeMap<String, Vec<Backlink>>;
+
+/// A note linking to (or tagged with) a page. When the link was written as `[[display|page]]`,
+/// `alias` holds the display text the linking note used.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct Backlink {
+    pub title: String,
+    pub alias: Option<String>,
+}
+
+impl From<&str> for Backlink {
+    fn from(title: &str) -> Self {
+        Backlink {
+            title: title.into(),
+            alias: None,
+        }
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PatchData {
@@ -19,6 +40,103 @@ pub struct PatchData {
     pub title: String,
     pub old_title: String,
     pub metadata: HashMap<String, String>,
+    /// Subdirectory of the wiki the note lives in, empty for notes at the top level.
+    #[serde(default, deserialize_with = "deserialize_dir")]
+    pub dir: String,
+}
+
+/// A note directory that would point outside the wiki.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("{0:?} isn't a directory inside the wiki")]
+pub struct InvalidDir(pub String);
+
+/// Checks that `dir` is a path relative to the wiki root that stays inside it, so a note can't be
+/// written anywhere else with an absolute path or `..`. A trailing slash is dropped.
+pub fn parse_dir(dir: &str) -> Result<String, InvalidDir> {
+    // `C:\notes` is absolute on Windows
+    let absolute = dir.starts_with(['/', '\\']) || dir.chars().nth(1) == Some(':');
+    let escapes = dir.split(['/', '\\']).any(|segment| segment.trim() == "..");
+    if absolute || escapes {
+        return Err(InvalidDir(dir.to_string()));
+    }
+    Ok(dir.trim_end_matches('/').to_string())
+}
+
+fn deserialize_dir<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let dir = String::deserialize(deserializer)?;
+    parse_dir(&dir).map_err(serde::de::Error::custom)
+}
+
+impl PatchData {
+    /// Where the note is saved, relative to the wiki root and without the file extension.
+    pub fn location(&self) -> String {
+        self.in_dir(&self.title)
+    }
+
+    /// Where the note was before this edit, which differs from `location` when it's renamed.
+    pub fn old_location(&self) -> String {
+        self.in_dir(&self.old_title)
+    }
+
+    /// The page for the note, nested notes are served under their directory.
+    pub fn url(&self) -> String {
+        with_base_path(&format!("/{}", encode_path(&self.location())))
+    }
+
+    fn in_dir(&self, title: &str) -> String {
+        let dir = self.dir.trim_matches('/');
+        if dir.is_empty() {
+            title.to_string()
+        } else {
+            format!("{}/{}", dir, title)
+        }
+    }
+}
+
+/// Percent-encodes each `/`-separated segment of `path` on its own, so nested notes keep their
+/// directory in URLs.
+pub fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| encode(segment).into_owned())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// Collapses runs of whitespace and strips surrounding whitespace and trailing `,`, `;`, and `:`,
+/// so `"  My  Note "` and `"My Note"` refer to the same page. Trailing periods are kept since
+/// shortened bookmark titles end with an ellipsis.
+pub fn normalize_title(title: &str) -> String {
+    title
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .trim_end_matches([',', ';', ':'])
+        .trim_end()
+        .to_string()
+}
+
+/// Notes tagged with every one of `tags`, sorted by title. Tags share the backlinks with links, so
+/// a note linking to a tag's page counts as carrying it. An unknown tag matches nothing.
+pub fn tagged_with_all(backlinks: &Backlinks, tags: &[&str]) -> Vec<String> {
+    let mut tagged = tags.iter().map(|tag| {
+        backlinks
+            .get(&normalize_title(tag))
+            .map(|notes| {
+                notes
+                    .iter()
+                    .map(|note| note.title.clone())
+                    .collect::<BTreeSet<String>>()
+            })
+            .unwrap_or_default()
+    });
+    let first = tagged.next().unwrap_or_default();
+    tagged
+        .fold(first, |all, notes| &all & &notes)
+        .into_iter()
+        .collect()
 }
 
 impl From<HashMap<String, String>> for PatchData {
@@ -28,9 +146,10 @@ impl From<HashMap<String, String>> for PatchData {
         let mut tags: Vec<String> = Vec::new();
         let mut body: String = String::new();
         let mut metadata: HashMap<String, String> = HashMap::new();
+        let mut dir: String = String::new();
         for key in form_body.keys() {
             match key.as_str() {
-                "title" => title = form_body.get(key).unwrap().trim().to_owned(),
+                "title" => title = normalize_title(form_body.get(key).unwrap()),
                 "old_title" => {
                     if let Some(old_title_from_form) = form_body.get(key) {
                         old_title = old_title_from_form.to_owned()
@@ -45,6 +164,9 @@ impl From<HashMap<String, String>> for PatchData {
                         .collect()
                 }
                 "body" => body = form_body.get(key).unwrap().to_owned(),
+                // There's no way to turn the form down from here, so a directory outside the wiki
+                // is left out rather than written to
+                "dir" => dir = parse_dir(form_body.get(key).unwrap()).unwrap_or_default(),
                 "metadata" => {
                     let stringified_meta = form_body.get(key).unwrap().to_owned();
                     if !stringified_meta.is_empty() {
@@ -67,6 +189,98 @@ impl From<HashMap<String, String>> for PatchData {
             title,
             old_title,
             metadata,
+            dir,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::de::value::StrDeserializer;
+
+    use super::*;
+
+    #[test]
+    fn normalizes_titles() {
+        assert_eq!(normalize_title("  My  Note "), "My Note");
+        assert_eq!(normalize_title("My\tNote"), "My Note");
+        assert_eq!(normalize_title("My Note, "), "My Note");
+        assert_eq!(normalize_title("My Note:"), "My Note");
+        assert_eq!(
+            normalize_title("a really long bookmark title..."),
+            "a really long bookmark title..."
+        );
+        assert_eq!(normalize_title("My Note"), "My Note");
+    }
+
+    #[test]
+    fn rejects_directories_outside_the_wiki() {
+        assert_eq!(parse_dir(""), Ok(String::new()));
+        assert_eq!(
+            parse_dir("projects/garden/"),
+            Ok(String::from("projects/garden"))
+        );
+        assert_eq!(parse_dir("notes..old"), Ok(String::from("notes..old")));
+        for dir in [
+            "..",
+            "../secrets",
+            "projects/../../etc",
+            "/etc",
+            "\\server",
+            "C:\\notes",
+        ] {
+            assert_eq!(parse_dir(dir), Err(InvalidDir(dir.to_string())), "{}", dir);
         }
+
+        let submitted = StrDeserializer::<serde::de::value::Error>::new("../../etc");
+        assert!(deserialize_dir(submitted).is_err());
+        let submitted = StrDeserializer::<serde::de::value::Error>::new("projects");
+        assert_eq!(deserialize_dir(submitted), Ok(String::from("projects")));
+        let patch = PatchData::from(HashMap::from([
+            (String::from("title"), String::from("garden")),
+            (String::from("dir"), String::from("../outside")),
+        ]));
+        assert_eq!(patch.location(), "garden");
+    }
+
+    #[test]
+    fn nested_notes_keep_their_directory() {
+        let mut patch = PatchData::from(HashMap::from([
+            (String::from("title"), String::from("garden plans")),
+            (String::from("old_title"), String::from("garden")),
+            (String::from("dir"), String::from("projects")),
+            (String::from("body"), String::from("tomatoes")),
+        ]));
+        assert_eq!(patch.location(), "projects/garden plans");
+        assert_eq!(patch.old_location(), "projects/garden");
+        assert_eq!(patch.url(), "/projects/garden%20plans");
+
+        patch.dir = String::new();
+        assert_eq!(patch.location(), "garden plans");
+        assert_eq!(patch.url(), "/garden%20plans");
+    }
+
+    #[test]
+    fn intersects_tags() {
+        let backlinks = Backlinks::from([
+            (
+                String::from("rust"),
+                vec![Backlink::from("borrowck"), Backlink::from("tokio")],
+            ),
+            (
+                String::from("async"),
+                vec![Backlink::from("tokio"), Backlink::from("javascript")],
+            ),
+        ]);
+        assert_eq!(
+            tagged_with_all(&backlinks, &["rust", "async"]),
+            vec!["tokio"]
+        );
+        assert_eq!(
+            tagged_with_all(&backlinks, &["rust"]),
+            vec!["borrowck", "tokio"]
+        );
+        assert!(tagged_with_all(&backlinks, &["rust", "missing"]).is_empty());
+        assert!(tagged_with_all(&backlinks, &[]).is_empty());
     }
 }
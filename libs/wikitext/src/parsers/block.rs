@@ -8,38 +8,307 @@ pub enum ParseError {
 
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) enum BlockElement<'a> {
-    Heading(&'a str),
+    Heading {
+        level: u8,
+        text: &'a str,
+    },
     PageLink(&'a str),
+    Transclude(&'a str),
     Quote(Vec<BlockElement<'a>>),
+    Paragraph(Vec<BlockElement<'a>>),
     EmptySpace(&'a str),
     Text(&'a str),
     HyperLink(&'a str),
     IndentationLevel(u32),
+    MarkdownLink {
+        text: &'a str,
+        url: &'a str,
+    },
+    Bold(Vec<BlockElement<'a>>),
+    Italic(Vec<BlockElement<'a>>),
+    Highlight(Vec<BlockElement<'a>>),
+    CodeBlock {
+        lang: Option<String>,
+        body: String,
+    },
+    Image {
+        alt: &'a str,
+        src: &'a str,
+    },
+    List {
+        ordered: bool,
+        items: Vec<Vec<BlockElement<'a>>>,
+    },
+    TaskCheckbox(bool),
+    Table {
+        alignments: Vec<Alignment>,
+        header: Vec<Vec<BlockElement<'a>>>,
+        rows: Vec<Vec<Vec<BlockElement<'a>>>>,
+    },
+    Rule,
+    InlineMath(&'a str),
+    BlockMath(&'a str),
+}
+
+/// How a table column is aligned, set by the colons in its delimiter row.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Alignment {
+    Default,
+    Left,
+    Center,
+    Right,
 }
 
 type BlockResult<'a> = Result<(BlockElement<'a>, usize), ParseError>;
 type SliceWithIndex<'a> = Result<(&'a str, usize), ParseError>;
 
+/// A heading from `#` (level 1) to `######` (level 6). Longer runs of `#` are level 6 too.
 fn parse_heading(slice: &str) -> BlockResult {
-    let mut iter = slice.char_indices().peekable();
-    // Advance iterator to skip # character
-    iter.next();
-    let mut end = 1usize;
-    while let Some(&(_, token)) = iter.peek() {
-        match token {
-            ' ' | '\t' => {
-                iter.next();
-                end += 1;
+    let hashes = slice.len() - slice.trim_start_matches('#').len();
+    let text = slice[hashes..].trim_start_matches([' ', '\t']);
+    Ok((
+        BlockElement::Heading {
+            level: hashes.min(6) as u8,
+            text,
+        },
+        slice.len(),
+    ))
+}
+
+/// A fenced code block, from the opening fence up to and including the closing one. Nothing inside
+/// is parsed as wikitext.
+fn parse_code_block(slice: &str) -> BlockResult {
+    let (opening, rest) = slice.split_once('\n').unwrap_or((slice, ""));
+    let lang = opening.trim_start_matches('`').trim();
+    let body = match rest.rsplit_once('\n') {
+        Some((body, closing)) if closing.trim() == CODE_FENCE => body,
+        None if rest.trim() == CODE_FENCE => "",
+        _ => rest,
+    };
+    Ok((
+        BlockElement::CodeBlock {
+            lang: (!lang.is_empty()).then(|| lang.to_string()),
+            body: body.replace("\r\n", "\n"),
+        },
+        slice.len(),
+    ))
+}
+
+const CODE_FENCE: &str = "```";
+
+/// Consecutive lines that belong together in one block.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Run {
+    List,
+    Table,
+    Quote,
+}
+
+impl Run {
+    fn of(line: &str) -> Option<Run> {
+        if list_marker(line).is_some() {
+            Some(Run::List)
+        } else if line.trim_start().starts_with('|') {
+            Some(Run::Table)
+        } else if line.starts_with('>') {
+            Some(Run::Quote)
+        } else {
+            None
+        }
+    }
+
+    fn push<'a>(self, blocks: &mut Vec<&'a str>, lines: &'a str) {
+        let lines = lines.trim_end_matches(['\n', '\r']);
+        match self {
+            Run::Table => push_table_rows(blocks, lines),
+            Run::List | Run::Quote => blocks.push(lines),
+        }
+    }
+}
+
+/// The lines of `text`, except that a fenced code block stays together as a single block so its
+/// contents can't be mistaken for headings or links, and so do consecutive list items, quoted
+/// lines, and the rows of a table. An unclosed fence runs to the end of the text.
+pub(crate) fn split_blocks(text: &str) -> Vec<&str> {
+    let mut blocks = Vec::new();
+    let mut fence_start = None;
+    let mut run_start: Option<(Run, usize)> = None;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let line = line.trim_end_matches(['\n', '\r']);
+        let run = fence_start.is_none().then(|| Run::of(line)).flatten();
+        if let Some((current, run_start_at)) = run_start {
+            if run == Some(current) {
+                continue;
             }
-            _ => {
-                break;
+            current.push(&mut blocks, &text[run_start_at..start]);
+            run_start = None;
+        }
+        if let Some(run) = run {
+            run_start = Some((run, start));
+            continue;
+        }
+        match fence_start {
+            Some(fence_start_at) => {
+                if line.trim_end() == CODE_FENCE {
+                    blocks.push(&text[fence_start_at..start + line.len()]);
+                    fence_start = None;
+                }
             }
+            None if line.starts_with(CODE_FENCE) => fence_start = Some(start),
+            None => blocks.push(line),
         }
     }
-    Ok((
-        BlockElement::Heading(window(slice, end, slice.len())),
-        slice.len(),
-    ))
+    if let Some(start) = fence_start {
+        blocks.push(text[start..].trim_end_matches(['\n', '\r']));
+    }
+    if let Some((run, start)) = run_start {
+        run.push(&mut blocks, &text[start..]);
+    }
+    blocks
+}
+
+/// Lines starting with `|` are one block when they make up a table, and stay separate lines
+/// otherwise.
+fn push_table_rows<'a>(blocks: &mut Vec<&'a str>, rows: &'a str) {
+    if is_table(rows) {
+        blocks.push(rows);
+    } else {
+        blocks.extend(rows.lines());
+    }
+}
+
+/// A header row followed by a delimiter row like `|---|:---:|` with as many columns.
+fn is_table(block: &str) -> bool {
+    let mut lines = block.lines();
+    match (lines.next(), lines.next()) {
+        (Some(header), Some(delimiter)) => {
+            let delimiters = split_cells(delimiter);
+            delimiters.len() == split_cells(header).len()
+                && delimiters
+                    .iter()
+                    .all(|cell| column_alignment(cell).is_some())
+        }
+        _ => false,
+    }
+}
+
+/// The cells of a table row, split on `|` except inside `[[page|alias]]` links or when escaped as
+/// `\|`.
+fn split_cells(row: &str) -> Vec<&str> {
+    let row = row.trim();
+    let row = row.strip_prefix('|').unwrap_or(row);
+    let row = match row.strip_suffix('|') {
+        Some(inner) if !inner.ends_with('\\') => inner,
+        _ => row,
+    };
+    let mut cells = Vec::new();
+    let mut cell_start = 0;
+    let mut link_depth = 0;
+    let mut escaped = false;
+    for (index, c) in row.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' if row[index..].starts_with("[[") => link_depth += 1,
+            ']' if link_depth > 0 && row[index..].starts_with("]]") => link_depth -= 1,
+            '|' if link_depth == 0 => {
+                cells.push(row[cell_start..index].trim());
+                cell_start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    cells.push(row[cell_start..].trim());
+    cells
+}
+
+/// The alignment a delimiter cell like `:---:` sets, or `None` if it isn't a delimiter.
+fn column_alignment(cell: &str) -> Option<Alignment> {
+    let left = cell.starts_with(':');
+    let right = cell.len() > 1 && cell.ends_with(':');
+    let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+    if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+        return None;
+    }
+    Some(match (left, right) {
+        (true, true) => Alignment::Center,
+        (true, false) => Alignment::Left,
+        (false, true) => Alignment::Right,
+        (false, false) => Alignment::Default,
+    })
+}
+
+/// A table, with each cell parsed like any other text. Rows are cut or padded to the header's
+/// number of columns.
+fn parse_table(block: &str) -> BlockElement {
+    let mut lines = block.lines();
+    let header = split_cells(lines.next().unwrap_or_default());
+    let alignments = split_cells(lines.next().unwrap_or_default())
+        .into_iter()
+        .map(|cell| column_alignment(cell).unwrap_or(Alignment::Default))
+        .collect::<Vec<Alignment>>();
+    let rows = lines
+        .map(|line| {
+            let mut cells = split_cells(line);
+            cells.resize(header.len(), "");
+            cells.into_iter().map(iterate_slice).collect()
+        })
+        .collect();
+    BlockElement::Table {
+        alignments,
+        header: header.into_iter().map(iterate_slice).collect(),
+        rows,
+    }
+}
+
+/// The length of the `- `, `* `, or `1. ` marker starting a list item, and whether the list is
+/// numbered.
+fn list_marker(line: &str) -> Option<(usize, bool)> {
+    if line.starts_with("- ") || line.starts_with("* ") {
+        return Some((2, false));
+    }
+    let digits = line.bytes().take_while(u8::is_ascii_digit).count();
+    (digits > 0 && line[digits..].starts_with(". ")).then_some((digits + 2, true))
+}
+
+/// Consecutive list items. Switching between bullets and numbers starts a new list.
+fn parse_list(block: &str) -> Vec<BlockElement> {
+    let mut lists = Vec::new();
+    for line in block.lines() {
+        let Some((marker_len, ordered)) = list_marker(line) else {
+            continue;
+        };
+        let item = parse_list_item(&line[marker_len..]);
+        match lists.last_mut() {
+            Some(BlockElement::List {
+                ordered: current,
+                items,
+            }) if *current == ordered => items.push(item),
+            _ => lists.push(BlockElement::List {
+                ordered,
+                items: vec![item],
+            }),
+        }
+    }
+    lists
+}
+
+/// A list item, which can start with `[ ]` or `[x]` to make it a task.
+fn parse_list_item(item: &str) -> Vec<BlockElement> {
+    let task = ["[ ]", "[x]", "[X]"]
+        .iter()
+        .find(|checkbox| item == **checkbox || item.starts_with(&format!("{} ", checkbox)));
+    match task {
+        Some(checkbox) => {
+            let mut elements = vec![BlockElement::TaskCheckbox(*checkbox != "[ ]")];
+            elements.extend(iterate_slice(&item[checkbox.len()..]));
+            elements
+        }
+        None => iterate_slice(item),
+    }
 }
 
 fn parse_empty_space(slice: &str) -> BlockResult {
@@ -69,45 +338,185 @@ fn parse_indentation(slice: &str) -> BlockResult {
 }
 
 fn parse_link(slice: &str) -> BlockResult {
-    if slice.starts_with("[[") {
-        let mut iter = slice.char_indices().peekable();
-        // Skip first two indicies
-        iter.next();
-        iter.next();
-        let mut idx = 0;
-        while let Some(&(index, token)) = iter.peek() {
-            idx = index;
-            match token {
-                ']' => {
-                    break;
-                }
-                _ => {
-                    iter.next();
-                }
-            }
-        }
-        return Ok((BlockElement::PageLink(window(slice, 2, idx)), idx + 1));
+    if let Some(link) = slice.strip_prefix("[[") {
+        // The iterator steps over chars, not bytes, so count the chars up to the closing brackets
+        return match link.find(']') {
+            Some(close) => Ok((
+                BlockElement::PageLink(&link[..close]),
+                slice[..close + 2].chars().count() + 1,
+            )),
+            None => parse_text(slice),
+        };
+    }
+    if let Some((text, url, end)) = markdown_link(slice) {
+        return Ok((
+            BlockElement::MarkdownLink { text, url },
+            slice[..end].chars().count() - 1,
+        ));
     }
     Ok((BlockElement::Text(window(slice, 0, 1)), 0))
 }
+
+/// The text, url, and length of a `[text](url)` link at the start of `slice`.
+fn markdown_link(slice: &str) -> Option<(&str, &str, usize)> {
+    let rest = slice.strip_prefix('[')?;
+    let close = rest.find(']')?;
+    let url_start = rest[close + 1..].strip_prefix('(')?;
+    let url = &url_start[..url_start.find(')')?];
+    if close == 0 || url.trim().is_empty() || url.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((
+        &rest[..close],
+        url,
+        "[".len() + close + "](".len() + url.len() + ")".len(),
+    ))
+}
+
+/// `**bold**`, `__bold__`, `*italic*`, or `_italic_`, with the text between the markers parsed
+/// like any other. Markers that aren't closed, or that only wrap whitespace, are plain text.
+fn parse_emphasis(slice: &str) -> BlockResult {
+    let marker = if slice.starts_with("**") || slice.starts_with("__") {
+        &slice[..2]
+    } else {
+        &slice[..1]
+    };
+    let emphasized = slice[marker.len()..].find(marker).and_then(|close| {
+        let inner = &slice[marker.len()..marker.len() + close];
+        let padded = inner.starts_with(char::is_whitespace) || inner.ends_with(char::is_whitespace);
+        (!inner.is_empty() && !padded).then(|| (inner, marker.len() * 2 + close))
+    });
+    match emphasized {
+        Some((inner, end)) => {
+            let elements = iterate_slice(inner);
+            let element = if marker.len() == 2 {
+                BlockElement::Bold(elements)
+            } else {
+                BlockElement::Italic(elements)
+            };
+            Ok((element, slice[..end].chars().count() - 1))
+        }
+        None => parse_text(slice),
+    }
+}
+
+/// `==highlighted==` text, parsed like any other. A lone `=`, or a `==` that isn't closed, is plain
+/// text.
+fn parse_highlight(slice: &str) -> BlockResult {
+    let highlighted = slice.strip_prefix("==").and_then(|rest| {
+        let close = rest.find("==")?;
+        let inner = &rest[..close];
+        let padded = inner.starts_with(char::is_whitespace) || inner.ends_with(char::is_whitespace);
+        (!inner.is_empty() && !padded).then_some((inner, "====".len() + close))
+    });
+    match highlighted {
+        Some((inner, end)) => Ok((
+            BlockElement::Highlight(iterate_slice(inner)),
+            slice[..end].chars().count() - 1,
+        )),
+        None => parse_text(slice),
+    }
+}
+
+/// An image embed like `![alt text](picture.png)`, or another note embedded with `![[page]]`.
+/// Anything else starting with `!` is plain text.
+fn parse_image(slice: &str) -> BlockResult {
+    if let Some(link) = slice.strip_prefix("![[") {
+        if let Some(close) = link.find("]]").filter(|close| *close > 0) {
+            return Ok((
+                BlockElement::Transclude(&link[..close]),
+                slice[.."![[".len() + close + "]]".len()].chars().count() - 1,
+            ));
+        }
+    }
+    let image = slice.strip_prefix("![").and_then(|rest| {
+        let (alt, rest) = rest.split_once("](")?;
+        let (src, _) = rest.split_once(')')?;
+        let end = "![".len() + alt.len() + "](".len() + src.len();
+        (!src.trim().is_empty()).then(|| (alt, src.trim(), end))
+    });
+    match image {
+        Some((alt, src, end)) => Ok((
+            BlockElement::Image { alt, src },
+            slice[..end].chars().count(),
+        )),
+        None => parse_text(slice),
+    }
+}
+
+/// TeX between `$` or `$$` markers, kept as written for KaTeX to render in the browser. Like
+/// Pandoc, the TeX can't start or end with whitespace and a closing `$` can't be followed by a
+/// digit, so prices like "costs $5 and $6" stay plain text.
+fn parse_math(slice: &str) -> BlockResult {
+    let marker = if slice.starts_with("$$") { "$$" } else { "$" };
+    let math = slice[marker.len()..].find(marker).and_then(|close| {
+        let tex = &slice[marker.len()..marker.len() + close];
+        let end = marker.len() * 2 + close;
+        let padded = tex.starts_with(char::is_whitespace) || tex.ends_with(char::is_whitespace);
+        let before_digit = slice[end..].starts_with(|c: char| c.is_ascii_digit());
+        (!tex.is_empty() && !padded && !before_digit).then_some((tex, end))
+    });
+    match math {
+        Some((tex, end)) => {
+            let element = if marker.len() == 2 {
+                BlockElement::BlockMath(tex)
+            } else {
+                BlockElement::InlineMath(tex)
+            };
+            Ok((element, slice[..end].chars().count() - 1))
+        }
+        None => parse_text(slice),
+    }
+}
+
+/// A quote runs to the end of its last consecutive `>` line, so whatever follows it is parsed on
+/// its own. A quote can itself start with `>` to quote a quote.
 fn parse_quote(slice: &str) -> BlockResult {
+    let mut lines = Vec::new();
+    let mut end = 0;
+    for line in slice.split_inclusive('\n') {
+        if !line.starts_with('>') {
+            break;
+        }
+        end += line.len();
+        lines.push(unquote(line));
+    }
+    let quoted = &slice[..end];
+    Ok((
+        BlockElement::Quote(parse_quoted_lines(&lines)),
+        quoted.strip_suffix('\n').unwrap_or(quoted).chars().count(),
+    ))
+}
+
+fn unquote(line: &str) -> &str {
+    line.trim_end_matches(['\n', '\r'])[1..].trim_start_matches([' ', '\t'])
+}
+
+/// A single quoted line is parsed as it is. Several become a paragraph each, with runs of lines
+/// quoted again collected into one nested quote.
+fn parse_quoted_lines<'a>(lines: &[&'a str]) -> Vec<BlockElement<'a>> {
+    if let [line] = lines {
+        return iterate_slice(line);
+    }
     let mut elements = Vec::new();
-    let mut iter = slice.char_indices().peekable();
-    // Advance iterator to skip > character
-    iter.next();
-    while let Some(&(index, token)) = iter.peek() {
-        match token {
-            ' ' | '\t' => {
-                iter.next();
-            }
-            _ => {
-                elements = iterate_slice(slice.get(index..slice.len()).unwrap());
-                break;
+    let mut index = 0;
+    while index < lines.len() {
+        let nested = lines[index..]
+            .iter()
+            .take_while(|line| line.starts_with('>'))
+            .map(|line| unquote(line))
+            .collect::<Vec<&str>>();
+        if nested.is_empty() {
+            if !lines[index].is_empty() {
+                elements.push(BlockElement::Paragraph(iterate_slice(lines[index])));
             }
+            index += 1;
+        } else {
+            index += nested.len();
+            elements.push(BlockElement::Quote(parse_quoted_lines(&nested)));
         }
     }
-
-    Ok((BlockElement::Quote(elements), slice.len()))
+    elements
 }
 
 fn parse_text(slice: &str) -> BlockResult {
@@ -129,7 +538,25 @@ fn parse_text(slice: &str) -> BlockResult {
     }
 }
 
+/// A line of three or more `-`, `*`, or `_` and nothing else.
+fn is_rule(line: &str) -> bool {
+    let line = line.trim();
+    line.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|rule| line.chars().all(|c| c == *rule))
+}
+
 pub(crate) fn parse_block(block: &str) -> Vec<BlockElement> {
+    if list_marker(block).is_some() {
+        return parse_list(block);
+    }
+    if is_rule(block) {
+        return vec![BlockElement::Rule];
+    }
+    if is_table(block) {
+        return vec![parse_table(block)];
+    }
     iterate_slice(block)
 }
 
@@ -147,6 +574,11 @@ fn iterate_slice(input: &str) -> Vec<BlockElement> {
                 }
             }
             '[' => parse_link,
+            '*' | '_' => parse_emphasis,
+            '!' => parse_image,
+            '$' => parse_math,
+            '=' => parse_highlight,
+            '`' if index == 0 && input.starts_with(CODE_FENCE) => parse_code_block,
             ' ' => parse_empty_space,
             '\t' => {
                 if index == 0 {
@@ -156,7 +588,7 @@ fn iterate_slice(input: &str) -> Vec<BlockElement> {
                 }
             }
             '>' => {
-                if index == 0 {
+                if index == 0 || input[..index].ends_with('\n') {
                     parse_quote
                 } else {
                     parse_text
@@ -250,6 +682,122 @@ fn until_empty_space(slice: &str) -> SliceWithIndex {
 mod tests {
     use super::*;
 
+    #[test]
+    fn parses_fenced_code_blocks() {
+        let text = "before\n```rust\n# not a heading\nlet x = [[not a link]];\n```\nafter";
+        let blocks = split_blocks(text);
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(
+            parse_block(blocks[1]),
+            vec![BlockElement::CodeBlock {
+                lang: Some(String::from("rust")),
+                body: String::from("# not a heading\nlet x = [[not a link]];"),
+            }]
+        );
+        assert_eq!(blocks[2], "after");
+
+        let unclosed = split_blocks("```\nstill code\n# here");
+        assert_eq!(
+            parse_block(unclosed[0]),
+            vec![BlockElement::CodeBlock {
+                lang: None,
+                body: String::from("still code\n# here"),
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_image_embeds() {
+        let block = parse_block("look ![a red barn](https://example.com/barn.png) here");
+        assert_eq!(block.len(), 5);
+        assert_eq!(
+            block[2],
+            BlockElement::Image {
+                alt: "a red barn",
+                src: "https://example.com/barn.png"
+            }
+        );
+        assert_eq!(block[4], BlockElement::Text("here"));
+
+        let block = parse_block("![](/files/diagram.png)");
+        assert_eq!(
+            block,
+            vec![BlockElement::Image {
+                alt: "",
+                src: "/files/diagram.png"
+            }]
+        );
+
+        let block = parse_block("see ![[Other Page]] too");
+        assert_eq!(block[2], BlockElement::Transclude("Other Page"));
+        assert_eq!(block[4], BlockElement::Text("too"));
+
+        let block = parse_block("wow ! really! ![not closed");
+        assert_eq!(block[2], BlockElement::Text("!"));
+        assert_eq!(block[4], BlockElement::Text("really!"));
+        assert_eq!(block[6], BlockElement::Text("![not"));
+    }
+
+    #[test]
+    fn parses_lists() {
+        let text = "intro\n- first [[Some Page]]\n* second\n1. one\n22. two\n\n- after a gap";
+        let blocks = split_blocks(text);
+        assert_eq!(blocks.len(), 4);
+        let lists = parse_block(blocks[1]);
+        assert_eq!(lists.len(), 2);
+        match &lists[0] {
+            BlockElement::List { ordered, items } => {
+                assert!(!ordered);
+                assert_eq!(items.len(), 2);
+                assert_eq!(items[0][2], BlockElement::PageLink("Some Page"));
+            }
+            other => panic!("expected a list, got {:?}", other),
+        }
+        match &lists[1] {
+            BlockElement::List { ordered, items } => {
+                assert!(ordered);
+                assert_eq!(items.len(), 2);
+                assert_eq!(items[1], vec![BlockElement::Text("two")]);
+            }
+            other => panic!("expected a list, got {:?}", other),
+        }
+        assert_eq!(blocks[2], "");
+        assert_eq!(
+            parse_block(blocks[3]),
+            vec![BlockElement::List {
+                ordered: false,
+                items: vec![vec![
+                    BlockElement::Text("after"),
+                    BlockElement::EmptySpace(" "),
+                    BlockElement::Text("a"),
+                    BlockElement::EmptySpace(" "),
+                    BlockElement::Text("gap"),
+                ]],
+            }]
+        );
+        assert_eq!(parse_block("-not a list")[0], BlockElement::Text("-not"));
+        assert_eq!(parse_block("1.5 cups")[0], BlockElement::Text("1.5"));
+    }
+
+    #[test]
+    fn parses_task_lists() {
+        let lists = parse_block("- [ ] water [[Tomatoes]]\n- [x] weed\n- [link](Garden)");
+        let BlockElement::List { items, .. } = &lists[0] else {
+            panic!("expected a list, got {:?}", lists);
+        };
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0][0], BlockElement::TaskCheckbox(false));
+        assert_eq!(items[0][4], BlockElement::PageLink("Tomatoes"));
+        assert_eq!(items[1][0], BlockElement::TaskCheckbox(true));
+        assert_eq!(
+            items[2][0],
+            BlockElement::MarkdownLink {
+                text: "link",
+                url: "Garden"
+            }
+        );
+    }
+
     #[test]
     fn parses_block_headers() {
         let test_string = "# hello";
@@ -257,11 +805,35 @@ mod tests {
         assert_eq!(block.len(), 1);
     }
 
+    #[test]
+    fn parses_heading_levels() {
+        let mut block = parse_block("### three");
+        assert_eq!(block.len(), 1);
+        assert_eq!(
+            block[0],
+            BlockElement::Heading {
+                level: 3,
+                text: "three"
+            }
+        );
+        block = parse_block("####### seven");
+        assert_eq!(
+            block[0],
+            BlockElement::Heading {
+                level: 6,
+                text: "seven"
+            }
+        );
+    }
+
     #[test]
     fn parses_block_headers_no_space() {
         let test_string = "#hello";
         let block = parse_block(test_string);
-        let matching_block = BlockElement::Heading("hello");
+        let matching_block = BlockElement::Heading {
+            level: 1,
+            text: "hello",
+        };
         assert_eq!(block.len(), 1);
         assert_eq!(block[0], matching_block);
     }
@@ -270,7 +842,10 @@ mod tests {
     fn parses_multi_word_headers() {
         let test_string = "#hello world";
         let block = parse_block(test_string);
-        let matching_block = BlockElement::Heading("hello world");
+        let matching_block = BlockElement::Heading {
+            level: 1,
+            text: "hello world",
+        };
         assert_eq!(block.len(), 1);
         assert_eq!(block[0], matching_block);
     }
@@ -330,6 +905,38 @@ mod tests {
         assert_eq!(block[2], matching_block);
     }
 
+    #[test]
+    fn parses_markdown_links() {
+        let test_string = "[Rust](https://rust-lang.org) and [[Some Page]]";
+        let block = parse_block(test_string);
+        assert_eq!(block.len(), 5);
+        assert_eq!(
+            block[0],
+            BlockElement::MarkdownLink {
+                text: "Rust",
+                url: "https://rust-lang.org"
+            }
+        );
+        assert_eq!(block[2], BlockElement::Text("and"));
+        assert_eq!(block[4], BlockElement::PageLink("Some Page"));
+
+        let block = parse_block("[the docs](https://docs.rs).");
+        assert_eq!(
+            block,
+            vec![
+                BlockElement::MarkdownLink {
+                    text: "the docs",
+                    url: "https://docs.rs"
+                },
+                BlockElement::Text("."),
+            ]
+        );
+
+        let block = parse_block("[not a link] (really)");
+        assert_eq!(block[0], BlockElement::Text("["));
+        assert_eq!(block[1], BlockElement::Text("not"));
+    }
+
     #[test]
     fn parses_block_links_in_sentences() {
         let test_string = "parsing [[another link]]";
@@ -348,6 +955,21 @@ mod tests {
         assert_eq!(block[2], matching_block);
     }
 
+    #[test]
+    fn parses_text_after_aliased_links() {
+        let test_string = "see [[the café|Café Notes]] for más details";
+        let block = parse_block(test_string);
+        assert_eq!(block.len(), 9);
+        assert_eq!(block[2], BlockElement::PageLink("the café|Café Notes"));
+        assert_eq!(block[3], BlockElement::EmptySpace(" "));
+        assert_eq!(block[4], BlockElement::Text("for"));
+        assert_eq!(block[6], BlockElement::Text("más"));
+        assert_eq!(block[8], BlockElement::Text("details"));
+
+        let block = parse_block("[[never closed");
+        assert_eq!(block[0], BlockElement::Text("[[never"));
+    }
+
     #[test]
     fn parses_more_complex_sentences_with_links() {
         let test_string = "[[another page]] asdf do the things.";
@@ -451,6 +1073,214 @@ mod tests {
         assert_eq!(block[0], matching_block);
     }
 
+    #[test]
+    fn parses_emphasis() {
+        let mut test_string = "some **bold** and _italic words_";
+        let mut block = parse_block(test_string);
+        assert_eq!(block.len(), 7);
+        let mut matching_block = BlockElement::Bold(vec![BlockElement::Text("bold")]);
+        assert_eq!(block[2], matching_block);
+        matching_block = BlockElement::Italic(vec![
+            BlockElement::Text("italic"),
+            BlockElement::EmptySpace(" "),
+            BlockElement::Text("words"),
+        ]);
+        assert_eq!(block[6], matching_block);
+
+        test_string = "**bold with [[a link]]**";
+        block = parse_block(test_string);
+        assert_eq!(block.len(), 1);
+        matching_block = BlockElement::Bold(vec![
+            BlockElement::Text("bold"),
+            BlockElement::EmptySpace(" "),
+            BlockElement::Text("with"),
+            BlockElement::EmptySpace(" "),
+            BlockElement::PageLink("a link"),
+        ]);
+        assert_eq!(block[0], matching_block);
+
+        test_string = "5 * 3 **never closed";
+        block = parse_block(test_string);
+        assert_eq!(block.len(), 9);
+        assert_eq!(block[2], BlockElement::Text("*"));
+        assert_eq!(block[6], BlockElement::Text("**never"));
+    }
+
+    #[test]
+    fn parses_tables() {
+        let text = "before\n| Plant | Notes |\n| --- | --- |\n| [[Tomatoes|tomato]] | **water** daily |\n| Basil |\nafter";
+        let blocks = split_blocks(text);
+        assert_eq!(blocks.len(), 3);
+        let BlockElement::Table {
+            alignments,
+            header,
+            rows,
+        } = &parse_block(blocks[1])[0]
+        else {
+            panic!("expected a table, got {:?}", parse_block(blocks[1]));
+        };
+        assert_eq!(alignments, &vec![Alignment::Default, Alignment::Default]);
+        assert_eq!(header[0], vec![BlockElement::Text("Plant")]);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0][0], vec![BlockElement::PageLink("Tomatoes|tomato")]);
+        assert_eq!(
+            rows[0][1][0],
+            BlockElement::Bold(vec![BlockElement::Text("water")])
+        );
+        assert_eq!(rows[1][1], vec![]);
+
+        let not_a_table = split_blocks("| just a pipe\n| and another");
+        assert_eq!(not_a_table, vec!["| just a pipe", "| and another"]);
+    }
+
+    #[test]
+    fn parses_table_alignment() {
+        let table = parse_block("| a | b | c | d |\n|---|:---|:--:|--:|\n| 1 | 2 | 3 | 4 |");
+        let BlockElement::Table { alignments, .. } = &table[0] else {
+            panic!("expected a table, got {:?}", table);
+        };
+        assert_eq!(
+            alignments,
+            &vec![
+                Alignment::Default,
+                Alignment::Left,
+                Alignment::Center,
+                Alignment::Right
+            ]
+        );
+        assert!(!is_table("| a | b |\n|---|"));
+        assert!(!is_table("| a |\n| text |"));
+    }
+
+    #[test]
+    fn parses_horizontal_rules() {
+        for rule in ["---", "***", "___", "-----", "*** "] {
+            assert_eq!(parse_block(rule), vec![BlockElement::Rule], "{}", rule);
+        }
+        assert_eq!(parse_block("--"), vec![BlockElement::Text("--")]);
+        assert_eq!(parse_block("-*-")[0], BlockElement::Text("-*-"));
+        assert_ne!(parse_block("--- more"), vec![BlockElement::Rule]);
+    }
+
+    #[test]
+    fn parses_highlights() {
+        let block = parse_block("a ==key point== here");
+        assert_eq!(block.len(), 5);
+        assert_eq!(
+            block[2],
+            BlockElement::Highlight(vec![
+                BlockElement::Text("key"),
+                BlockElement::EmptySpace(" "),
+                BlockElement::Text("point"),
+            ])
+        );
+        assert_eq!(block[4], BlockElement::Text("here"));
+
+        let block = parse_block("==see [[Some Page]]==");
+        assert_eq!(
+            block,
+            vec![BlockElement::Highlight(vec![
+                BlockElement::Text("see"),
+                BlockElement::EmptySpace(" "),
+                BlockElement::PageLink("Some Page"),
+            ])]
+        );
+
+        let block = parse_block("x = 1 and ==never closed");
+        assert_eq!(block[2], BlockElement::Text("="));
+        assert_eq!(block[8], BlockElement::Text("==never"));
+    }
+
+    #[test]
+    fn parses_math() {
+        let block = parse_block("where $e^{i\\pi} + 1 = 0$ holds");
+        assert_eq!(block.len(), 5);
+        assert_eq!(block[2], BlockElement::InlineMath("e^{i\\pi} + 1 = 0"));
+        assert_eq!(block[4], BlockElement::Text("holds"));
+
+        let block = parse_block("$$\\int_0^1 x\\,dx$$");
+        assert_eq!(block, vec![BlockElement::BlockMath("\\int_0^1 x\\,dx")]);
+
+        let block = parse_block("costs $5 and $6");
+        assert_eq!(block.len(), 5);
+        assert_eq!(block[0], BlockElement::Text("costs"));
+        assert_eq!(block[2], BlockElement::Text("$5"));
+        assert_eq!(block[4], BlockElement::Text("$6"));
+
+        let block = parse_block("a $ sign and $x$");
+        assert_eq!(block[2], BlockElement::Text("$"));
+        assert_eq!(block[8], BlockElement::InlineMath("x"));
+    }
+
+    #[test]
+    fn parses_content_after_quotes() {
+        let block = parse_block("> quoted\r\nafter");
+        assert_eq!(
+            block,
+            vec![
+                BlockElement::Quote(vec![BlockElement::Text("quoted")]),
+                BlockElement::Text("after"),
+            ]
+        );
+
+        let block = parse_block(">> nested\n> outer\nplain text");
+        assert_eq!(block.len(), 4);
+        assert_eq!(
+            block[0],
+            BlockElement::Quote(vec![
+                BlockElement::Quote(vec![BlockElement::Text("nested")]),
+                BlockElement::Paragraph(vec![BlockElement::Text("outer")]),
+            ])
+        );
+        assert_eq!(block[1], BlockElement::Text("plain"));
+    }
+
+    #[test]
+    fn parses_multi_line_quotes() {
+        let text = "> first\n> second [[link]]\n>\n> third\nafter";
+        let blocks = split_blocks(text);
+        assert_eq!(
+            blocks,
+            vec!["> first\n> second [[link]]\n>\n> third", "after"]
+        );
+        assert_eq!(
+            parse_block(blocks[0]),
+            vec![BlockElement::Quote(vec![
+                BlockElement::Paragraph(vec![BlockElement::Text("first")]),
+                BlockElement::Paragraph(vec![
+                    BlockElement::Text("second"),
+                    BlockElement::EmptySpace(" "),
+                    BlockElement::PageLink("link"),
+                ]),
+                BlockElement::Paragraph(vec![BlockElement::Text("third")]),
+            ])]
+        );
+    }
+
+    #[test]
+    fn parses_nested_quotes() {
+        let block = parse_block("> outer\n>> inner\n> > more inner\n> outer again");
+        assert_eq!(
+            block,
+            vec![BlockElement::Quote(vec![
+                BlockElement::Paragraph(vec![BlockElement::Text("outer")]),
+                BlockElement::Quote(vec![
+                    BlockElement::Paragraph(vec![BlockElement::Text("inner")]),
+                    BlockElement::Paragraph(vec![
+                        BlockElement::Text("more"),
+                        BlockElement::EmptySpace(" "),
+                        BlockElement::Text("inner"),
+                    ]),
+                ]),
+                BlockElement::Paragraph(vec![
+                    BlockElement::Text("outer"),
+                    BlockElement::EmptySpace(" "),
+                    BlockElement::Text("again"),
+                ]),
+            ])]
+        );
+    }
+
     #[test]
     fn parses_indentation_levels() {
         let mut test_string = "\ttesting examples";
@@ -1,52 +1,117 @@
+use std::collections::HashMap;
 use std::fmt::Write as _;
+use std::sync::OnceLock;
 use urlencoding::encode;
 
-use super::block::BlockElement;
+use super::{
+    block::{Alignment, BlockElement},
+    split_link_alias,
+};
+use crate::{
+    normalize_title,
+    processors::{transclusion::transclude, TocEntry},
+};
 
 impl BlockElement<'_> {
     pub fn collapse_to(&self, target: &mut String) {
+        self.collapse_with_ids(target, &mut HeadingIds::default())
+    }
+
+    /// Like `collapse_to`, but keeps heading ids unique across everything rendered with `ids`.
+    pub(crate) fn collapse_with_ids(&self, target: &mut String, ids: &mut HeadingIds) {
         match self {
-            BlockElement::Heading(content) => {
-                write!(target, "<h2>{}</h2>", content).unwrap();
+            BlockElement::Heading { level, text } if heading_anchors() => {
+                write!(
+                    target,
+                    r#"<h{} id="{}">{}</h{}>"#,
+                    level,
+                    ids.heading(*level, text),
+                    escape_html(text),
+                    level
+                )
+                .unwrap();
+            }
+            BlockElement::Heading { level, text } => {
+                write!(target, "<h{}>{}</h{}>", level, escape_html(text), level).unwrap();
             }
             BlockElement::PageLink(content) => {
                 let aliases = content.split('|').collect::<Vec<&str>>();
                 if aliases.len() > 1 {
+                    let href = format_links(aliases[1]);
                     write!(
                         target,
-                        r#"<a href="{}">{}</a>"#,
-                        format_links(aliases[1]),
-                        aliases[0]
+                        r#"<a href="{}"{}>{}</a>"#,
+                        escape_html(&href),
+                        link_attributes(&href, external_links_in_new_tab()),
+                        escape_html(aliases[0])
                     )
                     .unwrap();
                 } else {
+                    let href = format_links(aliases[0]);
                     write!(
                         target,
-                        r#"<a href="{}">{}</a>"#,
-                        format_links(aliases[0]),
-                        aliases[0]
+                        r#"<a href="{}"{}>{}</a>"#,
+                        escape_html(&href),
+                        link_attributes(&href, external_links_in_new_tab()),
+                        escape_html(aliases[0])
                     )
                     .unwrap();
                 }
             }
+            BlockElement::Transclude(link) => {
+                write_to_string(target, transclude(split_link_alias(link).0));
+            }
             BlockElement::Quote(content) => {
                 write!(target, "<blockquote>").unwrap();
                 for part in content {
-                    part.collapse_to(target);
+                    part.collapse_with_ids(target, ids);
                 }
                 write!(target, "</blockquote>").unwrap();
             }
+            BlockElement::Paragraph(content) => {
+                write!(target, "<p>").unwrap();
+                for part in content {
+                    part.collapse_with_ids(target, ids);
+                }
+                write!(target, "</p>").unwrap();
+            }
+            BlockElement::MarkdownLink { text, url } => {
+                let href = format_links(url);
+                write!(
+                    target,
+                    r#"<a href="{}"{}>{}</a>"#,
+                    escape_html(&href),
+                    link_attributes(&href, external_links_in_new_tab()),
+                    escape_html(text)
+                )
+                .unwrap();
+            }
+            BlockElement::Bold(content) => {
+                write!(target, "<strong>").unwrap();
+                for part in content {
+                    part.collapse_with_ids(target, ids);
+                }
+                write!(target, "</strong>").unwrap();
+            }
+            BlockElement::Italic(content) => {
+                write!(target, "<em>").unwrap();
+                for part in content {
+                    part.collapse_with_ids(target, ids);
+                }
+                write!(target, "</em>").unwrap();
+            }
+            BlockElement::Highlight(content) => {
+                write!(target, "<mark>").unwrap();
+                for part in content {
+                    part.collapse_with_ids(target, ids);
+                }
+                write!(target, "</mark>").unwrap();
+            }
             BlockElement::EmptySpace(content) | BlockElement::Text(content) => {
-                write_to_string(target, content.replace('<', "&lt;").replace('>', "&gt;"));
+                write_to_string(target, escape_html(content));
             }
             BlockElement::HyperLink(content) => {
-                if content.contains("youtube.com") || content.contains("youtu.be") {
-                    write_to_string(target, transform_youtube_url(content));
-                } else if content.contains("codesandbox.io") {
-                    write_to_string(target, transform_cs_url(content));
-                } else if content.contains("codepen.io") {
-                    write_to_string(target, transform_cp_url(content));
-                } else if content.ends_with(".mp3")
+                if content.ends_with(".mp3")
                     || content.ends_with(".ogg")
                     || content.ends_with(".flac")
                 {
@@ -57,21 +122,129 @@ impl BlockElement<'_> {
                     || content.ends_with(".webp")
                 {
                     write_to_string(target, transform_image_url(content));
-                } else if content.contains("vimeo.com") {
-                    write_to_string(target, transform_vimeo_url(content));
-                } else if content.contains("spotify.com") {
-                    write_to_string(target, transform_spotify_url(content));
+                } else if let Some(embed) = embed_frame(content, embed_hosts()) {
+                    write_to_string(target, embed);
                 } else {
-                    write_to_string(target, format!(r#"<a href="{}">{}</a>"#, content, content));
+                    write_to_string(target, plain_link(content));
                 }
             }
             BlockElement::IndentationLevel(_) => {
                 // noop
             }
+            BlockElement::Image { src, .. } if !embeddable_src(src) => {
+                write_to_string(target, escape_html(src));
+            }
+            BlockElement::Image { src, .. } if is_pdf(src) => {
+                let src = image_src(src);
+                match embed_frame(&src, embed_hosts()) {
+                    Some(embed) => write_to_string(target, embed),
+                    None => write_to_string(target, plain_link(&src)),
+                }
+            }
+            BlockElement::Image { alt, src } => {
+                write!(
+                    target,
+                    r#"<img src="{}" alt="{}" loading="lazy">"#,
+                    escape_html(&image_src(src)),
+                    escape_html(alt)
+                )
+                .unwrap();
+            }
+            BlockElement::List { ordered, items } => {
+                let tag = if *ordered { "ol" } else { "ul" };
+                write!(target, "<{}>", tag).unwrap();
+                for item in items {
+                    write!(target, "<li>").unwrap();
+                    for part in item {
+                        part.collapse_with_ids(target, ids);
+                    }
+                    write!(target, "</li>").unwrap();
+                }
+                write!(target, "</{}>", tag).unwrap();
+            }
+            BlockElement::Table {
+                alignments,
+                header,
+                rows,
+            } => {
+                write!(target, "<table><thead><tr>").unwrap();
+                for (cell, alignment) in header.iter().zip(alignments) {
+                    write!(target, "<th{}>", alignment_attribute(*alignment)).unwrap();
+                    for part in cell {
+                        part.collapse_with_ids(target, ids);
+                    }
+                    write!(target, "</th>").unwrap();
+                }
+                write!(target, "</tr></thead><tbody>").unwrap();
+                for row in rows {
+                    write!(target, "<tr>").unwrap();
+                    for (cell, alignment) in row.iter().zip(alignments) {
+                        write!(target, "<td{}>", alignment_attribute(*alignment)).unwrap();
+                        for part in cell {
+                            part.collapse_with_ids(target, ids);
+                        }
+                        write!(target, "</td>").unwrap();
+                    }
+                    write!(target, "</tr>").unwrap();
+                }
+                write!(target, "</tbody></table>").unwrap();
+            }
+            BlockElement::TaskCheckbox(checked) => {
+                let checked = if *checked { " checked" } else { "" };
+                write!(target, r#"<input type="checkbox" disabled{}>"#, checked).unwrap();
+            }
+            BlockElement::Rule => {
+                write!(target, "<hr>").unwrap();
+            }
+            BlockElement::InlineMath(tex) => {
+                write!(
+                    target,
+                    r#"<span class="math inline">{}</span>"#,
+                    escape_html(tex)
+                )
+                .unwrap();
+            }
+            BlockElement::BlockMath(tex) => {
+                write!(
+                    target,
+                    r#"<div class="math block">{}</div>"#,
+                    escape_html(tex)
+                )
+                .unwrap();
+            }
+            BlockElement::CodeBlock { lang, body } => {
+                let class = lang
+                    .as_ref()
+                    .map(|lang| format!(r#" class="language-{}""#, escape_html(lang)))
+                    .unwrap_or_default();
+                write!(
+                    target,
+                    "<pre><code{}>{}</code></pre>",
+                    class,
+                    escape_html(body)
+                )
+                .unwrap();
+            }
         }
     }
 }
 
+fn alignment_attribute(alignment: Alignment) -> &'static str {
+    match alignment {
+        Alignment::Default => "",
+        Alignment::Left => r#" align="left""#,
+        Alignment::Center => r#" align="center""#,
+        Alignment::Right => r#" align="right""#,
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 fn write_to_string(target: &mut String, incl: String) {
     write!(target, "{}", incl).unwrap();
 }
@@ -84,14 +257,224 @@ pub fn transform_image_url(text: &str) -> String {
     format!(r#"<img src={} />"#, text)
 }
 
+/// Whether `src` can be shown in an `<img>` or an iframe: a remote http(s) link, a `files:` upload,
+/// or a path on the wiki. Other schemes, like `javascript:`, never are.
+fn embeddable_src(src: &str) -> bool {
+    let src = src.trim_start_matches(|c: char| c.is_whitespace() || c.is_control());
+    if src.starts_with("//") {
+        return false;
+    }
+    let before_path = src.split(['/', '?', '#']).next().unwrap_or_default();
+    match before_path.split_once(':') {
+        Some((scheme, _)) => ["http", "https", "files"]
+            .iter()
+            .any(|allowed| scheme.eq_ignore_ascii_case(allowed)),
+        None => true,
+    }
+}
+
+/// Remote images and `files:` uploads are linked like any other link, absolute paths like
+/// `/files/diagram.png` are served under the base path, and relative paths are left alone.
+fn image_src(src: &str) -> String {
+    if src.starts_with("http://") || src.starts_with("https://") || src.starts_with("files:") {
+        format_links(src)
+    } else {
+        with_base_path(src)
+    }
+}
+
+static BASE_PATH: OnceLock<String> = OnceLock::new();
+static EXTERNAL_LINKS_IN_NEW_TAB: OnceLock<bool> = OnceLock::new();
+static HEADING_ANCHORS: OnceLock<bool> = OnceLock::new();
+static EMBED_HOSTS: OnceLock<Option<Vec<String>>> = OnceLock::new();
+
+/// Gives headings an `id` so sections can be linked to. Only the first call has any effect.
+pub fn set_heading_anchors(anchors: bool) {
+    let _ = HEADING_ANCHORS.set(anchors);
+}
+
+fn heading_anchors() -> bool {
+    HEADING_ANCHORS.get().copied().unwrap_or(true)
+}
+
+/// The heading ids used so far on a page, so two sections with the same title get `intro` and
+/// `intro-1` instead of clashing.
+#[derive(Debug, Default)]
+pub(crate) struct HeadingIds {
+    seen: HashMap<String, usize>,
+    /// Every heading given an id, in page order.
+    pub(crate) headings: Vec<TocEntry>,
+}
+
+impl HeadingIds {
+    /// The id for a heading, remembered for the table of contents.
+    pub(crate) fn heading(&mut self, level: u8, text: &str) -> String {
+        let id = self.next(text);
+        self.headings.push(TocEntry {
+            level,
+            id: id.clone(),
+            text: text.to_string(),
+        });
+        id
+    }
+
+    pub(crate) fn next(&mut self, heading: &str) -> String {
+        let slug = slugify(heading);
+        let count = self.seen.entry(slug.clone()).or_insert(0);
+        let id = if *count == 0 {
+            slug
+        } else {
+            format!("{}-{}", slug, count)
+        };
+        *count += 1;
+        id
+    }
+}
+
+/// Lowercases `text`, turns whitespace into hyphens, and drops punctuation, so "Why Rust?"
+/// becomes `why-rust`.
+pub fn slugify(text: &str) -> String {
+    let slug = text
+        .split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<String>>()
+        .join("-");
+    if slug.is_empty() {
+        String::from("section")
+    } else {
+        slug
+    }
+}
+
+/// Opens links to other sites in a new tab. Only the first call has any effect.
+pub fn set_external_links_in_new_tab(new_tab: bool) {
+    let _ = EXTERNAL_LINKS_IN_NEW_TAB.set(new_tab);
+}
+
+fn external_links_in_new_tab() -> bool {
+    EXTERNAL_LINKS_IN_NEW_TAB.get().copied().unwrap_or(false)
+}
+
+/// Extra anchor attributes for `href`. Links to other sites don't get a handle on the wiki's window
+/// or see where the visitor came from, while wiki pages open in place as usual.
+fn link_attributes(href: &str, new_tab: bool) -> &'static str {
+    let external = href.starts_with("http://") || href.starts_with("https://");
+    match (external, new_tab) {
+        (false, _) => "",
+        (true, false) => r#" rel="noopener noreferrer""#,
+        (true, true) => r#" rel="noopener noreferrer" target="_blank""#,
+    }
+}
+
+/// Only embeds links to these sites, or their subdomains. Without a list every site that can be
+/// embedded is. Only the first call has any effect.
+pub fn set_embed_hosts(hosts: Option<Vec<String>>) {
+    let _ = EMBED_HOSTS.set(hosts);
+}
+
+fn embed_hosts() -> Option<&'static [String]> {
+    EMBED_HOSTS.get().and_then(|hosts| hosts.as_deref())
+}
+
+/// Whether `link` can be embedded. Files served by the wiki always can, other sites only when
+/// they're in `allowed_hosts`.
+fn embed_allowed(link: &str, allowed_hosts: Option<&[String]>) -> bool {
+    let remote = link.starts_with("http://") || link.starts_with("https://");
+    let (true, Some(allowed_hosts)) = (remote, allowed_hosts) else {
+        return true;
+    };
+    let host = link_host(link);
+    allowed_hosts.iter().any(|allowed| {
+        let allowed = allowed.trim().trim_start_matches("www.");
+        host == allowed
+            || host
+                .strip_suffix(allowed)
+                .map_or(false, |subdomain| subdomain.ends_with('.'))
+    })
+}
+
+/// The player or viewer for a link to a site that can be embedded, when it's allowed to be.
+fn embed_frame(link: &str, allowed_hosts: Option<&[String]>) -> Option<String> {
+    if !embed_allowed(link, allowed_hosts) {
+        return None;
+    }
+    if link.contains("youtube.com") || link.contains("youtu.be") {
+        Some(transform_youtube_url(link))
+    } else if link.contains("codesandbox.io") {
+        Some(transform_cs_url(link))
+    } else if link.contains("codepen.io") {
+        Some(transform_cp_url(link))
+    } else if is_pdf(link) {
+        Some(transform_pdf_url(link))
+    } else if link.contains("vimeo.com") {
+        Some(transform_vimeo_url(link))
+    } else if link.contains("spotify.com") {
+        Some(transform_spotify_url(link))
+    } else if link_host(link) == "soundcloud.com" {
+        Some(transform_soundcloud_url(link))
+    } else {
+        transform_bandcamp_url(link).or_else(|| transform_twitter_url(link))
+    }
+}
+
+fn plain_link(link: &str) -> String {
+    format!(
+        r#"<a href="{}"{}>{}</a>"#,
+        link,
+        link_attributes(link, external_links_in_new_tab()),
+        link
+    )
+}
+
+/// Sets the path prefix that generated links and redirects are served under, e.g. `/wiki` when
+/// running behind a reverse proxy. Only the first call has any effect.
+pub fn set_base_path(base_path: &str) {
+    let _ = BASE_PATH.set(normalize_base_path(base_path));
+}
+
+/// The configured path prefix without a trailing slash, or an empty string when serving from `/`.
+pub fn base_path() -> &'static str {
+    BASE_PATH.get().map(|b| b.as_str()).unwrap_or("")
+}
+
+/// Prefixes an absolute path (`/some/page`) with the configured base path.
+pub fn with_base_path(path: &str) -> String {
+    prefix_base_path(base_path(), path)
+}
+
+fn normalize_base_path(base_path: &str) -> String {
+    let trimmed = base_path.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        return String::with_capacity(0);
+    }
+    format!("/{}", trimmed)
+}
+
+/// Prefixes an absolute path with `base_path`, which should come from `base_path()`. Relative paths
+/// are left as they are.
+pub fn prefix_base_path(base_path: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        format!("{}{}", base_path, path)
+    } else {
+        path.to_string()
+    }
+}
+
 pub fn format_links(link: &str) -> String {
     let proto_prefixes = link.split(':').collect::<Vec<&str>>();
     match proto_prefixes[0] {
         "http" | "https" => link.to_string(),
-        "files" => {
-            format!("/files/{}", encode(link.strip_prefix("files:").unwrap()))
-        }
-        _ => format!("/{}", encode(link)), // HACK: deal with warp decoding this later
+        "files" => with_base_path(&format!(
+            "/files/{}",
+            encode(link.strip_prefix("files:").unwrap())
+        )),
+        _ => with_base_path(&format!("/{}", encode(&normalize_title(link)))), // HACK: deal with warp decoding this later
     }
 }
 
@@ -101,6 +484,8 @@ const CS_FMT_STRING: &str = r#"<iframe frameborder="0" title="Code Sandbox" allo
     camera; encrypted-media; geolocation; gyroscope; hid; microphone; midi; payment; usb; vr;
     xr-spatial-tracking" sandbox="allow-forms allow-modals allow-popups allow-presentation
     allow-same-origin allow-scripts""#;
+const TWEET_FMT_STRING: &str = r#"<iframe title="Tweet" frameborder="0" scrolling="no" loading="lazy" width="550" height="600""#;
+const PDF_FMT_STRING: &str = r#"<iframe title="PDF viewer" class="pdf-embed" frameborder="0" loading="lazy" width="100%" height="600""#;
 const CP_FMT_STRING: &str = r#"<iframe frameborder="0" title="CodePen" scrolling="no" allowtransparency="true" allowfullscreen="true" loading="lazy""#;
 
 pub(crate) fn transform_cs_url(link: &str) -> String {
@@ -153,6 +538,72 @@ pub(crate) fn transform_vimeo_url(text: &str) -> String {
     format!(r#"{} src="{}"></iframe>"#, MEDIA_FMT_STRING, text)
 }
 
+/// Whether a link points at a PDF, ignoring any query string or fragment.
+fn is_pdf(link: &str) -> bool {
+    link.split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+        .ends_with(".pdf")
+}
+
+/// Shows a PDF in the browser's own viewer, with a link to download it for browsers that can't.
+/// It's an iframe since `sanitize_html` strips `<object>` and `<embed>`.
+pub(crate) fn transform_pdf_url(link: &str) -> String {
+    let link = escape_html(link);
+    format!(
+        r#"{} src="{}"></iframe><a href="{}" download>Download PDF</a>"#,
+        PDF_FMT_STRING, link, link
+    )
+}
+
+/// The host of a link, without any `www.` or `mobile.` prefix.
+fn link_host(link: &str) -> &str {
+    let without_scheme = link.split_once("://").map_or(link, |(_, rest)| rest);
+    let host = without_scheme
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default();
+    host.trim_start_matches("www.")
+        .trim_start_matches("mobile.")
+}
+
+/// Embeds a single tweet. Links to profiles or anything else on Twitter stay plain links.
+pub(crate) fn transform_twitter_url(link: &str) -> Option<String> {
+    if !matches!(link_host(link), "twitter.com" | "x.com") {
+        return None;
+    }
+    let (_, after_status) = link.split_once("/status/")?;
+    let id = after_status
+        .split(['/', '?', '#'])
+        .next()
+        .filter(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))?;
+    Some(format!(
+        r#"{} src="https://platform.twitter.com/embed/Tweet.html?id={}"></iframe>"#,
+        TWEET_FMT_STRING, id
+    ))
+}
+
+pub(crate) fn transform_soundcloud_url(link: &str) -> String {
+    format!(
+        r#"{} src="https://w.soundcloud.com/player/?url={}"></iframe>"#,
+        MEDIA_FMT_STRING,
+        encode(link)
+    )
+}
+
+/// Bandcamp players need the album or track id, which only its embed links have, so links to the
+/// release page itself stay plain links.
+pub(crate) fn transform_bandcamp_url(link: &str) -> Option<String> {
+    let host = link_host(link);
+    if !(host == "bandcamp.com" || host.ends_with(".bandcamp.com"))
+        || !link.contains("/EmbeddedPlayer/")
+    {
+        return None;
+    }
+    Some(format!(r#"{} src="{}"></iframe>"#, MEDIA_FMT_STRING, link))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -162,6 +613,201 @@ mod tests {
         assert_eq!(String::from("https://example.com"), format_links(http_link));
         let wiki_page = "My Cool Page";
         assert_eq!(String::from("/My%20Cool%20Page"), format_links(wiki_page));
+        let messy_wiki_page = "  My  Cool Page ";
+        assert_eq!(
+            String::from("/My%20Cool%20Page"),
+            format_links(messy_wiki_page)
+        );
+    }
+
+    #[test]
+    fn marks_external_links() {
+        assert_eq!(
+            link_attributes(&format_links("https://example.com"), false),
+            r#" rel="noopener noreferrer""#
+        );
+        assert_eq!(
+            link_attributes("http://example.com", true),
+            r#" rel="noopener noreferrer" target="_blank""#
+        );
+        assert_eq!(link_attributes(&format_links("My Cool Page"), true), "");
+        assert_eq!(link_attributes(&format_links("files:a.png"), true), "");
+
+        let mut rendered = String::new();
+        BlockElement::HyperLink("https://example.com").collapse_to(&mut rendered);
+        BlockElement::PageLink("My Cool Page").collapse_to(&mut rendered);
+        assert_eq!(
+            rendered,
+            r#"<a href="https://example.com" rel="noopener noreferrer">https://example.com</a><a href="/My%20Cool%20Page">My Cool Page</a>"#
+        );
+    }
+
+    #[test]
+    fn renders_markdown_links() {
+        let mut rendered = String::new();
+        BlockElement::MarkdownLink {
+            text: "Rust",
+            url: "https://rust-lang.org",
+        }
+        .collapse_to(&mut rendered);
+        BlockElement::MarkdownLink {
+            text: "my garden",
+            url: "Garden",
+        }
+        .collapse_to(&mut rendered);
+        assert_eq!(
+            rendered,
+            r#"<a href="https://rust-lang.org" rel="noopener noreferrer">Rust</a><a href="/Garden">my garden</a>"#
+        );
+    }
+
+    #[test]
+    fn renders_image_embeds() {
+        let mut rendered = String::new();
+        BlockElement::Image {
+            alt: "a \"red\" barn",
+            src: "https://example.com/barn.png",
+        }
+        .collapse_to(&mut rendered);
+        assert_eq!(
+            rendered,
+            r#"<img src="https://example.com/barn.png" alt="a &quot;red&quot; barn" loading="lazy">"#
+        );
+        assert_eq!(image_src("/files/diagram.png"), "/files/diagram.png");
+        assert_eq!(image_src("files:my diagram.png"), "/files/my%20diagram.png");
+        assert_eq!(image_src("diagram.png"), "diagram.png");
+    }
+
+    #[test]
+    fn slugifies_headings() {
+        assert_eq!(slugify("My Section"), "my-section");
+        assert_eq!(
+            slugify("  Why Rust?  (2023 edition) "),
+            "why-rust-2023-edition"
+        );
+        assert_eq!(slugify("Café & crème"), "café-crème");
+        assert_eq!(slugify("?!"), "section");
+    }
+
+    #[test]
+    fn disambiguates_duplicate_headings() {
+        let mut ids = HeadingIds::default();
+        assert_eq!(ids.next("Notes"), "notes");
+        assert_eq!(ids.next("Ideas"), "ideas");
+        assert_eq!(ids.next("Notes"), "notes-1");
+        assert_eq!(ids.next("notes!"), "notes-2");
+
+        let mut rendered = String::new();
+        BlockElement::Heading {
+            level: 2,
+            text: "My Section",
+        }
+        .collapse_to(&mut rendered);
+        assert_eq!(rendered, r#"<h2 id="my-section">My Section</h2>"#);
+    }
+
+    #[test]
+    fn escapes_headings_and_page_links() {
+        let mut rendered = String::new();
+        BlockElement::Heading {
+            level: 3,
+            text: "<b>Fish & chips</b>",
+        }
+        .collapse_to(&mut rendered);
+        assert_eq!(
+            rendered,
+            r#"<h3 id="bfish-chipsb">&lt;b&gt;Fish &amp; chips&lt;/b&gt;</h3>"#
+        );
+
+        let mut rendered = String::new();
+        BlockElement::PageLink("<script>alert(1)</script>|Recipes").collapse_to(&mut rendered);
+        BlockElement::PageLink("Salt & Pepper").collapse_to(&mut rendered);
+        assert_eq!(
+            rendered,
+            r#"<a href="/Recipes">&lt;script&gt;alert(1)&lt;/script&gt;</a><a href="/Salt%20%26%20Pepper">Salt &amp; Pepper</a>"#
+        );
+    }
+
+    #[test]
+    fn embeds_pdfs() {
+        let mut rendered = String::new();
+        BlockElement::HyperLink("https://example.com/paper.PDF?download=1")
+            .collapse_to(&mut rendered);
+        assert_eq!(
+            rendered,
+            r#"<iframe title="PDF viewer" class="pdf-embed" frameborder="0" loading="lazy" width="100%" height="600" src="https://example.com/paper.PDF?download=1"></iframe><a href="https://example.com/paper.PDF?download=1" download>Download PDF</a>"#
+        );
+
+        let mut rendered = String::new();
+        BlockElement::Image {
+            alt: "taxes",
+            src: "files:tax return.pdf",
+        }
+        .collapse_to(&mut rendered);
+        assert!(rendered.contains(r#"src="/files/tax%20return.pdf"></iframe>"#));
+        assert!(rendered.contains(r#"<a href="/files/tax%20return.pdf" download>"#));
+
+        assert!(!is_pdf("https://example.com/pdfs"));
+    }
+
+    #[test]
+    fn only_embeds_web_and_wiki_sources() {
+        let mut rendered = String::new();
+        BlockElement::Image {
+            alt: "x",
+            src: "javascript:alert(1)//a.pdf",
+        }
+        .collapse_to(&mut rendered);
+        BlockElement::Image {
+            alt: "x",
+            src: "JavaScript:alert(1)//a.png",
+        }
+        .collapse_to(&mut rendered);
+        assert_eq!(
+            rendered,
+            "javascript:alert(1)//a.pdfJavaScript:alert(1)//a.png"
+        );
+        assert!(!embeddable_src("data:text/html,<script>alert(1)</script>"));
+        assert!(!embeddable_src("//example.com/a.pdf"));
+        assert!(embeddable_src("https://example.com/a.pdf"));
+        assert!(embeddable_src("files:a.pdf"));
+        assert!(embeddable_src("/files/a.pdf"));
+        assert!(embeddable_src("docs/a:b.pdf"));
+    }
+
+    #[test]
+    fn only_embeds_allowed_hosts() {
+        let allowed = [String::from("vimeo.com"), String::from("www.example.com")];
+        let youtube = "https://www.youtube.com/watch?v=giEnkiRHJ9Y";
+        assert!(embed_frame(youtube, Some(&allowed)).is_none());
+        assert!(embed_frame(youtube, None).is_some());
+        assert_eq!(
+            embed_frame("https://player.vimeo.com/video/76979871", Some(&allowed)),
+            Some(transform_vimeo_url(
+                "https://player.vimeo.com/video/76979871"
+            ))
+        );
+        assert!(embed_frame("https://docs.example.com/paper.pdf", Some(&allowed)).is_some());
+        assert!(embed_frame("https://notexample.com/paper.pdf", Some(&allowed)).is_none());
+        // Files served by the wiki itself aren't from another site
+        assert!(embed_frame("/files/paper.pdf", Some(&[])).is_some());
+    }
+
+    #[test]
+    fn prefixes_links_with_base_path() {
+        let base = normalize_base_path("wiki/");
+        assert_eq!(base, "/wiki");
+        assert_eq!(normalize_base_path("/"), "");
+        assert_eq!(
+            prefix_base_path(&base, "/My%20Cool%20Page"),
+            "/wiki/My%20Cool%20Page"
+        );
+        assert_eq!(prefix_base_path(&base, "/"), "/wiki/");
+        assert_eq!(
+            prefix_base_path(&base, "https://example.com"),
+            "https://example.com"
+        );
+        assert_eq!(prefix_base_path("", "/files/a.png"), "/files/a.png");
     }
 
     #[test]
@@ -172,6 +818,18 @@ mod tests {
         assert_eq!(*final_string, transformed_string);
     }
 
+    #[test]
+    fn renders_only_the_embed_for_media_links() {
+        let mut rendered = String::new();
+        BlockElement::HyperLink("https://youtube.com/watch?v=giEnkiRHJ9Y")
+            .collapse_to(&mut rendered);
+        assert_eq!(
+            rendered,
+            transform_youtube_url("https://youtube.com/watch?v=giEnkiRHJ9Y")
+        );
+        assert!(!rendered.contains("<a "));
+    }
+
     #[test]
     fn transforms_vimeo_urls_to_embedable() {
         let link = "https://vimeo.com/665036978#t=20s";
@@ -194,4 +852,51 @@ mod tests {
         let transformed_string = transform_cp_url(link);
         assert_eq!(*final_string, transformed_string);
     }
+
+    #[test]
+    fn transforms_tweet_urls_to_embedable() {
+        let final_string = r#"<iframe title="Tweet" frameborder="0" scrolling="no" loading="lazy" width="550" height="600" src="https://platform.twitter.com/embed/Tweet.html?id=1445078208190291968"></iframe>"#;
+        for link in [
+            "https://twitter.com/rustlang/status/1445078208190291968",
+            "https://x.com/rustlang/status/1445078208190291968?s=20",
+            "https://mobile.twitter.com/rustlang/status/1445078208190291968/photo/1",
+        ] {
+            assert_eq!(transform_twitter_url(link).as_deref(), Some(final_string));
+        }
+        assert_eq!(transform_twitter_url("https://twitter.com/rustlang"), None);
+        assert_eq!(
+            transform_twitter_url("https://dropbox.com/status/1445078208190291968"),
+            None
+        );
+
+        let mut rendered = String::new();
+        BlockElement::HyperLink("https://x.com/rustlang").collapse_to(&mut rendered);
+        assert_eq!(
+            rendered,
+            r#"<a href="https://x.com/rustlang" rel="noopener noreferrer">https://x.com/rustlang</a>"#
+        );
+    }
+
+    #[test]
+    fn transforms_soundcloud_urls_to_embedable() {
+        let link = "https://soundcloud.com/forss/flickermood";
+        let final_string = r#"<iframe title="Video player" frameborder="0" allow="autoplay;" allowfullscreen src="https://w.soundcloud.com/player/?url=https%3A%2F%2Fsoundcloud.com%2Fforss%2Fflickermood"></iframe>"#;
+        assert_eq!(transform_soundcloud_url(link), final_string);
+
+        let mut rendered = String::new();
+        BlockElement::HyperLink(link).collapse_to(&mut rendered);
+        assert_eq!(rendered, final_string);
+    }
+
+    #[test]
+    fn transforms_bandcamp_urls_to_embedable() {
+        let link =
+            "https://bandcamp.com/EmbeddedPlayer/album=1536701931/size=large/tracklist=false/";
+        let final_string = r#"<iframe title="Video player" frameborder="0" allow="autoplay;" allowfullscreen src="https://bandcamp.com/EmbeddedPlayer/album=1536701931/size=large/tracklist=false/"></iframe>"#;
+        assert_eq!(transform_bandcamp_url(link).as_deref(), Some(final_string));
+        assert_eq!(
+            transform_bandcamp_url("https://artist.bandcamp.com/album/some-album"),
+            None
+        );
+    }
 }
@@ -3,10 +3,13 @@ use std::fmt::Write as _;
 
 use serde::{Deserialize, Serialize};
 
-use crate::processors::tags::{tag_string_from_vec, TagsArray};
+use crate::processors::{
+    tags::{tag_string_from_vec, TagsArray},
+    transclusion::rendering,
+};
 use crate::PatchData;
 
-use super::{get_outlinks, to_html, Html, ParsedTemplate, TemplattedPage};
+use super::{get_aliased_outlinks, html::render_html, Html, ParsedTemplate, TemplattedPage};
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 enum MetaParserState {
@@ -20,29 +23,41 @@ pub struct Note {
     pub content: String,
 }
 
+/// A page this note links to, or a tag, along with the display text if the link was aliased.
+pub type Outlink<'a> = (&'a str, Option<&'a str>);
+
 #[derive(Debug, Default, Clone)]
 pub struct StructuredNote<'a> {
     pub title: &'a str,
-    pub links_and_tags: Vec<&'a str>,
+    pub links_and_tags: Vec<Outlink<'a>>,
 }
 
 impl StructuredNote<'_> {
-    pub fn as_owned(&self) -> (String, Vec<String>) {
+    pub fn as_owned(&self) -> (String, Vec<(String, Option<String>)>) {
         (
             self.title.to_string(),
-            self.links_and_tags.iter().map(|l| l.to_string()).collect(),
+            self.links_and_tags
+                .iter()
+                .map(|(link, alias)| (link.to_string(), alias.map(|a| a.to_string())))
+                .collect(),
         )
     }
 }
 
 impl Note {
+    /// Tags sorted case-insensitively, so they render in the same order on every page.
     fn parse_tags(&self) -> Vec<&str> {
         match self.header.get("tags") {
             None => Vec::with_capacity(0),
-            Some(raw_tags) => TagsArray::new(raw_tags).values,
+            Some(raw_tags) => {
+                let mut tags = TagsArray::new(raw_tags).values;
+                tags.sort_by_cached_key(|t| t.to_lowercase());
+                tags
+            }
         }
     }
     pub fn to_template(&self) -> ParsedTemplate {
+        let title = self.header.get("title").unwrap();
         let content_type = if let Some(content_type) = self.header.get("content-type") {
             content_type.as_str()
         } else {
@@ -54,9 +69,12 @@ impl Note {
                 outlinks: Vec::with_capacity(0),
             }
         } else {
-            to_html(&self.content)
+            let toc = self
+                .header
+                .get("toc")
+                .map_or(false, |toc| toc.trim() == "true");
+            rendering(title, || render_html(&self.content, toc))
         };
-        let title = self.header.get("title").unwrap();
         let tags = self.parse_tags();
         let mut rendered_metadata = self.header.to_owned();
         // We're already showing this, so no need to dump it in the table...
@@ -80,6 +98,7 @@ impl Note {
             body: html.body,
             metadata: rendered_metadata,
             desc,
+            raw_md: self.content.clone(),
         };
         ParsedTemplate {
             outlinks: html.outlinks.into_iter().map(|t| t.to_string()).collect(),
@@ -87,8 +106,8 @@ impl Note {
         }
     }
     pub fn to_structured(&self) -> StructuredNote {
-        let mut links = get_outlinks(&self.content);
-        links.extend(self.parse_tags());
+        let mut links = get_aliased_outlinks(&self.content);
+        links.extend(self.parse_tags().into_iter().map(|tag| (tag, None)));
         StructuredNote {
             title: self.header.get("title").unwrap(),
             links_and_tags: links,
@@ -145,6 +164,7 @@ impl Into<PatchData> for Note {
             title,
             old_title,
             metadata: self.header,
+            dir: String::with_capacity(0),
         }
     }
 }
@@ -231,3 +251,42 @@ pub fn parse_meta<'a>(lines: impl Iterator<Item = &'a str>, debug_marker: &str)
     }
     notemeta
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_keep_raw_note_content() {
+        let raw = "title: raw content\ntags: [test]\n\n# Heading\nSome [[linked page]] text";
+        let note = Note::from(String::from(raw));
+        let templatted = note.to_template();
+        assert_eq!(
+            templatted.page.raw_md,
+            "# Heading\nSome [[linked page]] text"
+        );
+        assert_eq!(templatted.page.raw_md, note.content);
+    }
+
+    #[test]
+    fn sorts_tags_alphabetically() {
+        let note = Note::from(String::from(
+            "title: sorted tags\ntags: [zettelkasten, Rust, async, Books]\n\nbody",
+        ));
+        let templatted = note.to_template();
+        assert_eq!(
+            templatted.page.tags,
+            vec!["async", "Books", "Rust", "zettelkasten"]
+        );
+        let structured = note.to_structured();
+        assert_eq!(
+            structured.links_and_tags,
+            vec![
+                ("async", None),
+                ("Books", None),
+                ("Rust", None),
+                ("zettelkasten", None)
+            ]
+        );
+    }
+}
@@ -1,4 +1,8 @@
-use super::block::{parse_block, BlockElement};
+use super::{
+    block::{parse_block, split_blocks, BlockElement},
+    formatters::HeadingIds,
+};
+use crate::processors::table_of_contents;
 
 pub struct Html<'a> {
     pub outlinks: Vec<&'a str>,
@@ -29,6 +33,12 @@ impl Block {
 }
 
 pub fn to_html(text: &str) -> Html {
+    render_html(text, false)
+}
+
+/// Renders `text`, adding a table of contents where a `[[toc]]` line is, or at the top when `toc`
+/// is set and there's no marker.
+pub(crate) fn render_html(text: &str, toc: bool) -> Html {
     if text.is_empty() {
         let body = Block::new();
         return Html {
@@ -39,60 +49,103 @@ pub fn to_html(text: &str) -> Html {
     // let now = Instant::now();
     let mut outlinks = Vec::new();
     let mut page_blocks: Vec<Vec<BlockElement>> = Vec::new();
-    for line in text.lines() {
+    let mut heading_ids = HeadingIds::default();
+    let mut toc_marker = None;
+    for line in split_blocks(text) {
         let blocks = parse_block(line);
         page_blocks.push(blocks);
     }
-    let output = page_blocks
-        .iter()
-        .filter_map(|block| {
-            if block.is_empty() {
-                return None;
-            }
-            let mut final_block = Block::new();
-            for entity in block {
-                match entity {
-                    BlockElement::PageLink(outlink) => {
-                        let aliases = outlink.split('|').collect::<Vec<&str>>();
-                        if aliases.len() > 1 {
-                            outlinks.push(aliases[1]);
-                        } else {
-                            outlinks.push(aliases[0]);
-                        }
-                    }
-                    BlockElement::IndentationLevel(level) => {
-                        final_block.update_indentation(*level);
-                    }
-                    _ => {}
-                }
-                entity.collapse_to(&mut final_block.text);
+    let mut output = Vec::with_capacity(page_blocks.len());
+    for block in page_blocks.iter() {
+        if block.is_empty() {
+            continue;
+        }
+        if is_toc_marker(block) {
+            toc_marker.get_or_insert(output.len());
+            continue;
+        }
+        let mut final_block = Block::new();
+        for entity in block {
+            if let BlockElement::IndentationLevel(level) = entity {
+                final_block.update_indentation(*level);
             }
-
-            Some(final_block.close())
-        })
-        .collect::<Vec<String>>()
-        .join("");
+            collect_links(entity, &mut |link| outlinks.push(split_link_alias(link).0));
+            entity.collapse_with_ids(&mut final_block.text, &mut heading_ids);
+        }
+        output.push(final_block.close());
+    }
+    if let Some(at) = toc_marker.or(toc.then_some(0)) {
+        let nav = table_of_contents(&heading_ids.headings);
+        if !nav.is_empty() {
+            output.insert(at, nav);
+        }
+    }
 
     Html {
-        body: output,
+        body: output.join(""),
         outlinks,
     }
 }
 
+/// A line with nothing but `[[toc]]` on it.
+fn is_toc_marker(block: &[BlockElement]) -> bool {
+    matches!(block, [BlockElement::PageLink(link)] if link.eq_ignore_ascii_case("toc"))
+}
+
+/// Page links in `element`, including the ones inside emphasized text and list items.
+fn collect_links<'a>(element: &BlockElement<'a>, found: &mut impl FnMut(&'a str)) {
+    match element {
+        BlockElement::PageLink(link) | BlockElement::Transclude(link) => found(link),
+        BlockElement::Bold(parts)
+        | BlockElement::Italic(parts)
+        | BlockElement::Highlight(parts) => {
+            for part in parts {
+                collect_links(part, found);
+            }
+        }
+        BlockElement::List { items, .. } => {
+            for part in items.iter().flatten() {
+                collect_links(part, found);
+            }
+        }
+        BlockElement::Table { header, rows, .. } => {
+            for part in header.iter().chain(rows.iter().flatten()).flatten() {
+                collect_links(part, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Splits a `[[display text|page]]` link into the page it points to and, when it's aliased, the
+/// text shown in its place.
+pub fn split_link_alias(link: &str) -> (&str, Option<&str>) {
+    let aliases = link.split('|').collect::<Vec<&str>>();
+    if aliases.len() > 1 {
+        (aliases[1], Some(aliases[0]))
+    } else {
+        (aliases[0], None)
+    }
+}
+
 // TODO: Move this somewhere more logical...
 pub fn get_outlinks(text: &str) -> Vec<&str> {
+    get_aliased_outlinks(text)
+        .into_iter()
+        .map(|(link, _)| link)
+        .collect()
+}
+
+/// Like `get_outlinks`, but keeps the display text of aliased links alongside each page.
+pub fn get_aliased_outlinks(text: &str) -> Vec<(&str, Option<&str>)> {
     let mut outlinks = Vec::new();
-    for line in text.lines() {
+    for line in split_blocks(text) {
         let blocks = parse_block(line);
-        for block in blocks {
-            if let BlockElement::PageLink(link) = block {
-                let aliases = link.split('|').collect::<Vec<&str>>();
-                if aliases.len() > 1 {
-                    outlinks.push(aliases[1]);
-                } else {
-                    outlinks.push(aliases[0]);
-                }
-            }
+        if is_toc_marker(&blocks) {
+            continue;
+        }
+        for block in &blocks {
+            collect_links(block, &mut |link| outlinks.push(split_link_alias(link)));
         }
     }
     outlinks
@@ -115,10 +168,155 @@ mod tests {
         let test_string = "# Title\n[[Some Page]]. Another thing\n * Hi\n * List\n * Output";
         let test_html = Html {
             outlinks: vec!["Some Page"],
-            body: r#"<div data-indent="0" class="text-block"><h2>Title</h2></div><div data-indent="0" class="text-block"><a href="/Some%20Page">Some Page</a>. Another thing</div><div data-indent="0" class="text-block"> * Hi</div><div data-indent="0" class="text-block"> * List</div><div data-indent="0" class="text-block"> * Output</div>"#.into()
+            body: r#"<div data-indent="0" class="text-block"><h1 id="title">Title</h1></div><div data-indent="0" class="text-block"><a href="/Some%20Page">Some Page</a>. Another thing</div><div data-indent="0" class="text-block"> * Hi</div><div data-indent="0" class="text-block"> * List</div><div data-indent="0" class="text-block"> * Output</div>"#.into()
         };
         let parsed = to_html(test_string);
         assert_eq!(parsed.outlinks, test_html.outlinks);
         assert_eq!(parsed.body, test_html.body);
     }
+    #[test]
+    fn renders_emphasis() {
+        let test_string = "**see [[Some Page]]** and *this*";
+        let parsed = to_html(test_string);
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block"><strong>see <a href="/Some%20Page">Some Page</a></strong> and <em>this</em></div>"#
+        );
+        assert_eq!(parsed.outlinks, vec!["Some Page"]);
+        assert_eq!(get_outlinks(test_string), vec!["Some Page"]);
+    }
+    #[test]
+    fn renders_fenced_code_without_parsing_it() {
+        let test_string =
+            "```rust\n# not a heading\nif a < b { [[Not a link]] }\n```\n[[Some Page]]";
+        let parsed = to_html(test_string);
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block"><pre><code class="language-rust"># not a heading
+if a &lt; b { [[Not a link]] }</code></pre></div><div data-indent="0" class="text-block"><a href="/Some%20Page">Some Page</a></div>"#
+        );
+        assert_eq!(parsed.outlinks, vec!["Some Page"]);
+        assert_eq!(get_outlinks(test_string), vec!["Some Page"]);
+    }
+    #[test]
+    fn renders_lists() {
+        let test_string = "- [[Some Page]]\n- two\n1. one";
+        let parsed = to_html(test_string);
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block"><ul><li><a href="/Some%20Page">Some Page</a></li><li>two</li></ul><ol><li>one</li></ol></div>"#
+        );
+        assert_eq!(parsed.outlinks, vec!["Some Page"]);
+        assert_eq!(get_outlinks(test_string), vec!["Some Page"]);
+    }
+    #[test]
+    fn renders_markdown_inside_list_items() {
+        let test_string = "- a **bold [[Some Page]]** step\n- _lightly_";
+        let parsed = to_html(test_string);
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block"><ul><li>a <strong>bold <a href="/Some%20Page">Some Page</a></strong> step</li><li><em>lightly</em></li></ul></div>"#
+        );
+        assert_eq!(parsed.outlinks, vec!["Some Page"]);
+    }
+    #[test]
+    fn renders_task_checkboxes() {
+        let parsed = to_html("- [ ] water\n- [x] weed");
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block"><ul><li><input type="checkbox" disabled> water</li><li><input type="checkbox" disabled checked> weed</li></ul></div>"#
+        );
+    }
+    #[test]
+    fn renders_multi_line_quotes() {
+        let parsed = to_html("> one\n>> two\n> three");
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block"><blockquote><p>one</p><blockquote>two</blockquote><p>three</p></blockquote></div>"#
+        );
+    }
+    #[test]
+    fn renders_tables() {
+        let test_string = "| Plant | Water |\n|:---|---:|\n| [[Tomatoes]] | _daily_ |";
+        let parsed = to_html(test_string);
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block"><table><thead><tr><th align="left">Plant</th><th align="right">Water</th></tr></thead><tbody><tr><td align="left"><a href="/Tomatoes">Tomatoes</a></td><td align="right"><em>daily</em></td></tr></tbody></table></div>"#
+        );
+        assert_eq!(parsed.outlinks, vec!["Tomatoes"]);
+        assert_eq!(get_outlinks(test_string), vec!["Tomatoes"]);
+    }
+    #[test]
+    fn renders_a_table_of_contents() {
+        let test_string = "[[toc]]\n# Garden\n## Tomatoes\nsee [[Pests]]\n# Harvest";
+        let parsed = to_html(test_string);
+        assert!(parsed.body.starts_with(
+            r##"<nav class="toc"><ul><li><a href="#garden">Garden</a><ul><li><a href="#tomatoes">Tomatoes</a></li></ul></li><li><a href="#harvest">Harvest</a></li></ul></nav><div data-indent="0" class="text-block"><h1 id="garden">"##
+        ));
+        assert!(!parsed.body.contains("toc</a>"));
+        assert_eq!(parsed.outlinks, vec!["Pests"]);
+        assert_eq!(get_outlinks(test_string), vec!["Pests"]);
+
+        let parsed = render_html("intro\n# Garden", true);
+        assert!(parsed.body.starts_with(r#"<nav class="toc">"#));
+        let parsed = to_html("intro\n[[TOC]]\nno headings here");
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block">intro</div><div data-indent="0" class="text-block">no headings here</div>"#
+        );
+    }
+    #[test]
+    fn gives_headings_unique_ids() {
+        let parsed = to_html("## Notes\n# Notes\n### Notes");
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block"><h2 id="notes">Notes</h2></div><div data-indent="0" class="text-block"><h1 id="notes-1">Notes</h1></div><div data-indent="0" class="text-block"><h3 id="notes-2">Notes</h3></div>"#
+        );
+    }
+    #[test]
+    fn escapes_plain_text() {
+        let parsed = to_html(r#"if a < b && c > d say "hi" to [[Some Page]]"#);
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block">if a &lt; b &amp;&amp; c &gt; d say &quot;hi&quot; to <a href="/Some%20Page">Some Page</a></div>"#
+        );
+        let parsed = to_html("<script>alert(1)</script>");
+        assert!(!parsed.body.contains("<script>"));
+    }
+    #[test]
+    fn renders_horizontal_rules() {
+        let parsed = to_html("above\n---\n***\n___\nbelow --");
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block">above</div><div data-indent="0" class="text-block"><hr></div><div data-indent="0" class="text-block"><hr></div><div data-indent="0" class="text-block"><hr></div><div data-indent="0" class="text-block">below --</div>"#
+        );
+    }
+    #[test]
+    fn renders_highlights() {
+        let test_string = "==look at [[Some Page]]== and x = 1";
+        let parsed = to_html(test_string);
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block"><mark>look at <a href="/Some%20Page">Some Page</a></mark> and x = 1</div>"#
+        );
+        assert_eq!(parsed.outlinks, vec!["Some Page"]);
+        assert_eq!(get_outlinks(test_string), vec!["Some Page"]);
+    }
+    #[test]
+    fn renders_math_for_katex() {
+        let parsed = to_html("if $a < b$ then $$\\sum_i x_i$$ but $5 and $6 stay");
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block">if <span class="math inline">a &lt; b</span> then <div class="math block">\sum_i x_i</div> but $5 and $6 stay</div>"#
+        );
+    }
+    #[test]
+    fn keeps_display_text_of_aliased_links() {
+        let test_string = "See [[networked thought|connect]] and [[Some Page]]";
+        assert_eq!(
+            get_aliased_outlinks(test_string),
+            vec![("connect", Some("networked thought")), ("Some Page", None)]
+        );
+        assert_eq!(get_outlinks(test_string), vec!["connect", "Some Page"]);
+    }
 }
@@ -9,6 +9,8 @@ pub struct TemplattedPage {
     pub tags: Vec<String>,
     pub desc: String,
     pub metadata: HashMap<String, String>,
+    /// The unrendered note body, as stored on disk.
+    pub raw_md: String,
 }
 
 pub struct ParsedTemplate {
@@ -1,12 +1,63 @@
-use crate::parsers::{ParsedPages, TemplattedPage};
+use std::fmt::Write as _;
+
+use crate::parsers::{escape_html, ParsedPages, TemplattedPage};
 
 pub mod tags;
+pub mod transclusion;
 
 pub async fn update_templatted_pages(page: TemplattedPage, pages: ParsedPages) {
     let mut tempatted_pages = pages.lock().await;
     tempatted_pages.push(page);
 }
 
+/// A heading on the page, as listed in its table of contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TocEntry {
+    pub level: u8,
+    pub id: String,
+    pub text: String,
+}
+
+/// A `<nav class="toc">` linking to every heading, with deeper headings nested under the closest
+/// shallower one before them. Headings that skip a level, like an h3 right under an h1, share a
+/// list with the h2s that come after them. Empty when there are no headings.
+pub fn table_of_contents(headings: &[TocEntry]) -> String {
+    let Some(first) = headings.first() else {
+        return String::with_capacity(0);
+    };
+    let mut toc = String::from(r#"<nav class="toc"><ul>"#);
+    let mut open_levels = vec![first.level];
+    for (index, heading) in headings.iter().enumerate() {
+        if index > 0 {
+            if heading.level > *open_levels.last().unwrap() {
+                toc.push_str("<ul>");
+                open_levels.push(heading.level);
+            } else {
+                toc.push_str("</li>");
+                while open_levels.len() > 1 && heading.level <= open_levels[open_levels.len() - 2] {
+                    open_levels.pop();
+                    toc.push_str("</ul></li>");
+                }
+                let open = open_levels.last_mut().unwrap();
+                *open = (*open).min(heading.level);
+            }
+        }
+        write!(
+            toc,
+            r##"<li><a href="#{}">{}</a>"##,
+            escape_html(&heading.id),
+            escape_html(&heading.text)
+        )
+        .unwrap();
+    }
+    toc.push_str("</li>");
+    for _ in 1..open_levels.len() {
+        toc.push_str("</ul></li>");
+    }
+    toc.push_str("</ul></nav>");
+    toc
+}
+
 const FORBIDDEN_TAGS: [&str; 5] = ["noscript", "script", "object", "embed", "link"];
 
 pub fn sanitize_html(html: &str) -> String {
@@ -27,6 +78,59 @@ pub fn sanitize_html(html: &str) -> String {
 mod tests {
     use super::*;
 
+    fn heading(level: u8, text: &str) -> TocEntry {
+        TocEntry {
+            level,
+            id: text.to_lowercase(),
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn nests_table_of_contents_by_level() {
+        assert_eq!(table_of_contents(&[]), "");
+        let toc = table_of_contents(&[
+            heading(1, "Garden"),
+            heading(2, "Tomatoes"),
+            heading(3, "Pests"),
+            heading(1, "Harvest"),
+        ]);
+        assert_eq!(
+            toc,
+            r##"<nav class="toc"><ul><li><a href="#garden">Garden</a><ul><li><a href="#tomatoes">Tomatoes</a><ul><li><a href="#pests">Pests</a></li></ul></li></ul></li><li><a href="#harvest">Harvest</a></li></ul></nav>"##
+        );
+    }
+
+    #[test]
+    fn nests_headings_that_skip_a_level() {
+        let toc = table_of_contents(&[
+            heading(1, "Garden"),
+            heading(3, "Pests"),
+            heading(2, "Tomatoes"),
+            heading(1, "Harvest"),
+        ]);
+        assert_eq!(
+            toc,
+            r##"<nav class="toc"><ul><li><a href="#garden">Garden</a><ul><li><a href="#pests">Pests</a></li><li><a href="#tomatoes">Tomatoes</a></li></ul></li><li><a href="#harvest">Harvest</a></li></ul></nav>"##
+        );
+        let toc = table_of_contents(&[heading(2, "Tomatoes"), heading(1, "Harvest")]);
+        assert_eq!(
+            toc,
+            r##"<nav class="toc"><ul><li><a href="#tomatoes">Tomatoes</a></li><li><a href="#harvest">Harvest</a></li></ul></nav>"##
+        );
+    }
+
+    #[test]
+    fn escapes_heading_text() {
+        let toc = table_of_contents(&[TocEntry {
+            level: 1,
+            id: String::from("script"),
+            text: String::from("<script>alert(1)</script>"),
+        }]);
+        assert!(toc.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!toc.contains("<script>"));
+    }
+
     #[test]
     fn sanitizes_html() {
         for tag in FORBIDDEN_TAGS {
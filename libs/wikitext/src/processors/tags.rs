@@ -3,23 +3,25 @@ pub struct TagsArray<'a> {
 }
 
 impl<'a> TagsArray<'a> {
+    /// Accepts `[rust, async web]`, `rust, async web`, `rust async web`, and a single `rust`.
+    /// Tags are only split on whitespace when there are no brackets or commas, empty entries are
+    /// dropped, and duplicates are collapsed keeping the first occurrence.
     pub fn new(tag_str: &'a str) -> Self {
-        if tag_str.find('[').is_some() {
-            let split_tags = tag_str
-                .strip_prefix('[')
-                .unwrap()
-                .strip_suffix(']')
-                .unwrap()
-                .split(',')
-                .filter(|s| !s.is_empty() && s != &" ") // maybe use filter_map here?
-                .map(|s| s.trim())
-                .collect();
-            TagsArray { values: split_tags }
+        let trimmed = tag_str.trim();
+        let is_list = trimmed.starts_with('[');
+        let inner = trimmed.trim_start_matches('[').trim_end_matches(']');
+        let split_tags: Vec<&str> = if is_list || inner.contains(',') {
+            inner.split(',').collect()
         } else {
-            TagsArray {
-                values: tag_str.split(' ').filter(|s| !s.is_empty()).collect(),
+            inner.split_whitespace().collect()
+        };
+        let mut values: Vec<&str> = Vec::with_capacity(split_tags.len());
+        for tag in split_tags.into_iter().map(|s| s.trim()) {
+            if !tag.is_empty() && !values.contains(&tag) {
+                values.push(tag);
             }
         }
+        TagsArray { values }
     }
     pub fn write(&self) -> String {
         let mut tag_string = self.values.join(",");
@@ -87,4 +89,38 @@ mod tests {
             String::from("[Tools Article,project-management]")
         );
     }
+
+    #[test]
+    fn parse_comma_separated_tags_without_brackets() {
+        assert_eq!(
+            TagsArray::new("rust, async web").values,
+            vec!["rust", "async web"]
+        );
+    }
+
+    #[test]
+    fn parse_single_tag() {
+        assert_eq!(TagsArray::new("rust").values, vec!["rust"]);
+        assert_eq!(TagsArray::new("[rust]").values, vec!["rust"]);
+    }
+
+    #[test]
+    fn collapses_duplicate_tags() {
+        assert_eq!(
+            TagsArray::new("[rust, async, rust]").values,
+            vec!["rust", "async"]
+        );
+        assert_eq!(TagsArray::new("rust  web rust").values, vec!["rust", "web"]);
+    }
+
+    #[test]
+    fn ignores_empty_tags() {
+        assert_eq!(
+            TagsArray::new("[rust, , async,,  web ]").values,
+            vec!["rust", "async", "web"]
+        );
+        assert!(TagsArray::new("[]").is_empty());
+        assert!(TagsArray::new("  ").is_empty());
+        assert_eq!(TagsArray::new("[ rust, web").values, vec!["rust", "web"]);
+    }
 }
@@ -0,0 +1,184 @@
+use std::{cell::RefCell, sync::OnceLock};
+
+use crate::{
+    normalize_title,
+    parsers::{escape_html, format_links, Note},
+};
+
+/// How deeply transclusions nest when no depth is configured.
+pub const DEFAULT_MAX_TRANSCLUSION_DEPTH: usize = 4;
+
+static NOTE_LOADER: OnceLock<fn(&str) -> Option<Note>> = OnceLock::new();
+static MAX_TRANSCLUSION_DEPTH: OnceLock<usize> = OnceLock::new();
+
+thread_local! {
+    /// Titles of the notes being rendered on this thread, outermost first.
+    static RENDERING: RefCell<Vec<String>> = RefCell::new(Vec::new());
+}
+
+/// Sets how `![[page]]` finds the note to embed. Until this is called, transclusions render as
+/// links. Only the first call has any effect.
+pub fn set_note_loader(loader: fn(&str) -> Option<Note>) {
+    let _ = NOTE_LOADER.set(loader);
+}
+
+/// Sets how many notes deep transclusions nest before the rest are left out. Only the first call
+/// has any effect.
+pub fn set_max_transclusion_depth(depth: usize) {
+    let _ = MAX_TRANSCLUSION_DEPTH.set(depth);
+}
+
+fn max_transclusion_depth() -> usize {
+    MAX_TRANSCLUSION_DEPTH
+        .get()
+        .copied()
+        .unwrap_or(DEFAULT_MAX_TRANSCLUSION_DEPTH)
+}
+
+/// Marks a note as being rendered until it's dropped, so the title comes off the stack even when
+/// rendering panics.
+struct Rendering;
+
+impl Rendering {
+    fn start(title: &str) -> Self {
+        RENDERING.with(|stack| stack.borrow_mut().push(normalize_title(title)));
+        Rendering
+    }
+}
+
+impl Drop for Rendering {
+    fn drop(&mut self) {
+        RENDERING.with(|stack| stack.borrow_mut().pop());
+    }
+}
+
+/// Runs `render` with `title` marked as being rendered, so the note can't transclude itself.
+pub(crate) fn rendering<T>(title: &str, render: impl FnOnce() -> T) -> T {
+    let _rendering = Rendering::start(title);
+    render()
+}
+
+/// The rendered body of `title` wrapped in a section. Missing notes, and notes that are already
+/// being rendered further up, become a plain link so pages transcluding each other can't loop.
+/// Past the maximum depth, a placeholder says the note was left out.
+pub(crate) fn transclude(title: &str) -> String {
+    let title = normalize_title(title);
+    let (looping, depth) = RENDERING.with(|stack| {
+        let stack = stack.borrow();
+        (stack.contains(&title), stack.len())
+    });
+    let link = || {
+        format!(
+            r#"<a href="{}">{}</a>"#,
+            escape_html(&format_links(&title)),
+            escape_html(&title)
+        )
+    };
+    if looping {
+        return link();
+    }
+    let max_depth = max_transclusion_depth();
+    if depth >= max_depth {
+        return format!(
+            r#"<span class="transclusion-too-deep">{} not shown, transclusions are nested more than {} deep</span>"#,
+            link(),
+            max_depth
+        );
+    }
+    match NOTE_LOADER.get().and_then(|load| load(&title)) {
+        Some(note) => format!(
+            r#"<section class="transclusion" data-title="{}">{}</section>"#,
+            escape_html(&title),
+            note.to_template().page.body
+        ),
+        None => link(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::parsers::to_html;
+
+    use super::*;
+
+    fn note(title: &str, content: &str) -> Option<Note> {
+        let mut header = HashMap::new();
+        header.insert("title".into(), title.into());
+        Some(Note {
+            header,
+            content: content.into(),
+        })
+    }
+
+    fn load_test_note(title: &str) -> Option<Note> {
+        match title {
+            "Recipe" => note(title, "Two **eggs**"),
+            "Mirror" => note(title, "Reflecting ![[Mirror]]"),
+            "Ping" => note(title, "ping ![[Pong]]"),
+            "Pong" => note(title, "pong ![[Ping]]"),
+            "Chain 1" => note(title, "1 ![[Chain 2]]"),
+            "Chain 2" => note(title, "2 ![[Chain 3]]"),
+            "Chain 3" => note(title, "3 ![[Chain 4]]"),
+            "Chain 4" => note(title, "4 ![[Chain 5]]"),
+            "Chain 5" => note(title, "5 ![[Chain 6]]"),
+            "Chain 6" => note(title, "6"),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn transcludes_other_notes() {
+        set_note_loader(load_test_note);
+        assert_eq!(
+            to_html("Breakfast: ![[Recipe]]").body,
+            r#"<div data-indent="0" class="text-block">Breakfast: <section class="transclusion" data-title="Recipe"><div data-indent="0" class="text-block">Two <strong>eggs</strong></div></section></div>"#
+        );
+        assert_eq!(
+            to_html("![[Nowhere]]").body,
+            r#"<div data-indent="0" class="text-block"><a href="/Nowhere">Nowhere</a></div>"#
+        );
+    }
+
+    #[test]
+    fn stops_transcluding_notes_already_being_rendered() {
+        set_note_loader(load_test_note);
+        let mirror = note("Mirror", "Reflecting ![[Mirror]]").unwrap();
+        assert_eq!(
+            mirror.to_template().page.body,
+            r#"<div data-indent="0" class="text-block">Reflecting <a href="/Mirror">Mirror</a></div>"#
+        );
+        let ping = to_html("![[Ping]]").body;
+        assert_eq!(ping.matches(r#"class="transclusion""#).count(), 2);
+        assert!(ping
+            .ends_with(r#"pong <a href="/Ping">Ping</a></div></section></div></section></div>"#));
+    }
+
+    #[test]
+    fn stops_transcluding_past_the_max_depth() {
+        set_note_loader(load_test_note);
+        let chain = note("Chain 1", "1 ![[Chain 2]]").unwrap();
+        let body = chain.to_template().page.body;
+        // Chain 1 is being rendered, so only three more fit within the default depth of four
+        assert_eq!(
+            body.matches(r#"class="transclusion""#).count(),
+            DEFAULT_MAX_TRANSCLUSION_DEPTH - 1
+        );
+        assert!(body.contains(r#"data-title="Chain 4""#));
+        assert!(!body.contains(r#"data-title="Chain 5""#));
+        assert!(body.contains(
+            r#"4 <span class="transclusion-too-deep"><a href="/Chain%205">Chain 5</a> not shown, transclusions are nested more than 4 deep</span>"#
+        ));
+        assert!(!body.contains("Chain 6"));
+    }
+
+    #[test]
+    fn unmarks_notes_when_rendering_panics() {
+        let panicked = std::panic::catch_unwind(|| rendering("Broken", || panic!("bad note")));
+        assert!(panicked.is_err());
+        RENDERING.with(|stack| assert!(stack.borrow().is_empty()));
+        assert_eq!(rendering("Fine", || 42), 42);
+        RENDERING.with(|stack| assert!(stack.borrow().is_empty()));
+    }
+}
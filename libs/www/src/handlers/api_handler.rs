@@ -1,29 +1,401 @@
-use crate::services::{create_jwt, MONTH};
+use crate::services::{create_jwt, session_cookie};
 use bytes::BufMut;
 use futures::TryStreamExt;
-use persistance::fs::{get_note_titles, read_note_cache};
-use std::collections::HashMap;
-use task_runners::runners::api_runner::{APIRunner, FileError};
-use urlencoding::encode;
+use persistance::fs::{
+    config::{read_config, Session},
+    get_note_titles, read_note_cache, read_view_cache,
+    utils::get_file_path,
+    ReadPageError,
+};
+use render::search_results_page::SearchResult;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use task_runners::{
+    messages::Message,
+    metrics::{render_metrics, Metric, INDEX_READY},
+    runners::{
+        api_runner::{APIRunner, FileError},
+        wiki_runner::WikiRunner,
+    },
+    Queue, QueueHandle,
+};
+use urlencoding::{decode, encode};
 use warp::{
     filters::BoxedFilter,
-    http::{header, HeaderValue, Response},
+    http::{header, Response},
     hyper::{StatusCode, Uri},
     multipart::{self, Part},
-    Filter, Reply,
+    path::Tail,
+    Filter, Rejection, Reply,
+};
+use wikitext::{
+    normalize_title, parse_dir,
+    parsers::{with_base_path, Note},
+    processors::tags::TagsArray,
+    Backlink, Backlinks, GlobalBacklinks, PatchData,
 };
 
+use crate::RefHubParts;
+
 use super::{
-    filters::{with_auth, AuthError},
+    filters::{revoke_jwt, with_auth, with_links, with_queue, AuthError},
     MAX_BODY_SIZE,
 };
 
-pub struct APIRouter {}
+pub struct APIRouter {
+    parts: RefHubParts,
+}
+
+/// The notes linking to (or tagged with) `title`, in the order shown under the page.
+async fn page_backlinks(title: &str, links: GlobalBacklinks) -> Vec<Backlink> {
+    let links = links.lock().await;
+    let mut backlinks = links
+        .get(&normalize_title(title))
+        .cloned()
+        .unwrap_or_default();
+    backlinks.sort_unstable();
+    backlinks.dedup();
+    backlinks
+}
+
+/// The link graph as `{ nodes: [{ id }], edges: [{ source, target }] }`. Every note in `titles`
+/// is a node, even without any links, as is every page or tag something links to. Edges don't
+/// have a direction, so two notes linking to each other share one.
+fn link_graph(links: &Backlinks, titles: Vec<String>) -> serde_json::Value {
+    let mut nodes = titles.into_iter().collect::<BTreeSet<String>>();
+    let mut edges = BTreeSet::new();
+    for (target, backlinks) in links.iter() {
+        nodes.insert(target.clone());
+        for backlink in backlinks.iter().filter(|link| &link.title != target) {
+            nodes.insert(backlink.title.clone());
+            let edge = if backlink.title < *target {
+                (backlink.title.clone(), target.clone())
+            } else {
+                (target.clone(), backlink.title.clone())
+            };
+            edges.insert(edge);
+        }
+    }
+    serde_json::json!({
+        "nodes": nodes
+            .iter()
+            .map(|id| serde_json::json!({ "id": id }))
+            .collect::<Vec<serde_json::Value>>(),
+        "edges": edges
+            .iter()
+            .map(|(source, target)| serde_json::json!({ "source": source, "target": target }))
+            .collect::<Vec<serde_json::Value>>(),
+    })
+}
+
+/// Sends the browser back to `root` with the session cookies when `token` was issued, or answers
+/// why logging in failed.
+fn login_reply(
+    token: Result<String, AuthError>,
+    session: &Session,
+    root: &str,
+) -> Response<&'static str> {
+    match token {
+        Ok(token) => Response::builder()
+            .status(StatusCode::MOVED_PERMANENTLY)
+            .header(header::LOCATION, root)
+            .header(
+                header::SET_COOKIE,
+                session_cookie("token", &token, true, session.max_age, session, root),
+            )
+            .header(
+                header::SET_COOKIE,
+                session_cookie("login", "true", false, session.max_age, session, root),
+            )
+            .body("ok")
+            .unwrap(),
+        Err(e) => {
+            let (status, body) = if let AuthError::JWTDecodeError = e {
+                (StatusCode::BAD_REQUEST, "Could not process request")
+            } else {
+                (StatusCode::FORBIDDEN, "Invalid username or password")
+            };
+            Response::builder()
+                .status(status)
+                .header(header::LOCATION, root)
+                .body(body)
+                .unwrap()
+        }
+    }
+}
+
+/// Sends the browser back to `root` with both session cookies cleared.
+fn logout_reply(session: &Session, root: &str) -> Response<&'static str> {
+    Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header(header::LOCATION, root)
+        .header(
+            header::SET_COOKIE,
+            session_cookie("token", "", true, 0, session, root),
+        )
+        .header(
+            header::SET_COOKIE,
+            session_cookie("login", "", false, 0, session, root),
+        )
+        .body("ok")
+        .unwrap()
+}
+
+/// `POST /login`, where `sign_in` issues a token for the username and password in the form when
+/// they're right.
+fn login_form<F>(sign_in: F, session: fn() -> Session) -> BoxedFilter<(impl Reply,)>
+where
+    F: Fn(&str, &str) -> Result<String, AuthError> + Clone + Send + Sync + 'static,
+{
+    warp::post()
+        .and(warp::path("login"))
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::form())
+        .map(move |form_body: HashMap<String, String>| {
+            let username = form_body.get("username").map_or("", |u| u.as_str());
+            let pwd = form_body.get("password").map_or("", |p| p.as_str());
+            login_reply(sign_in(username, pwd), &session(), &with_base_path("/"))
+        })
+        .boxed()
+}
+
+/// `POST /logout`. Logging out is only a `POST` so other sites can't log you out with a link. The
+/// session token is revoked as well as cleared, so a copy of it stops working too.
+fn logout_form(session: fn() -> Session) -> BoxedFilter<(impl Reply,)> {
+    warp::post()
+        .and(warp::path("logout"))
+        .and(warp::path::end())
+        .and(warp::filters::cookie::optional("token"))
+        .map(move |token: Option<String>| {
+            if let Some(token) = token.filter(|token| !token.is_empty()) {
+                revoke_jwt(&token);
+            }
+            logout_reply(&session(), &with_base_path("/"))
+        })
+        .boxed()
+}
+
+/// `POST /api/reindex` queues a rebuild of the links, tags, and search index, answering as soon as
+/// it's queued rather than once it's done.
+fn queue_reindex(queue: QueueHandle) -> BoxedFilter<(impl Reply,)> {
+    warp::post()
+        .and(warp::path!("api" / "reindex"))
+        .and(with_queue(queue))
+        .then(|queue: QueueHandle| async move {
+            match queue.push(Message::Reindex).await {
+                Ok(()) => warp::reply::with_status("queued", StatusCode::ACCEPTED),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    warp::reply::with_status(
+                        "internal server error",
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    )
+                }
+            }
+        })
+        .boxed()
+}
+
+/// `GET /readyz` answers 503 until the links and search index are built, so a load balancer can
+/// hold off on sending traffic while `lazy_index` builds them in the background.
+pub fn readiness() -> BoxedFilter<(impl Reply,)> {
+    readiness_of(&INDEX_READY)
+}
+
+/// `GET /readyz`, answering from the `ready` gauge rather than `INDEX_READY`.
+pub fn readiness_of(ready: &'static Metric) -> BoxedFilter<(impl Reply,)> {
+    warp::get()
+        .and(warp::path("readyz"))
+        .and(warp::path::end())
+        .map(move || {
+            if ready.get() == 1 {
+                warp::reply::with_status("ready", StatusCode::OK)
+            } else {
+                warp::reply::with_status("indexing", StatusCode::SERVICE_UNAVAILABLE)
+            }
+        })
+        .boxed()
+}
+
+/// Search results as a JSON array of `{ title, score, snippet }`, most relevant first.
+fn search_results_json(results: &[SearchResult]) -> serde_json::Value {
+    results
+        .iter()
+        .map(|result| {
+            serde_json::json!({
+                "title": result.title,
+                "score": result.score,
+                "snippet": result.snippet,
+            })
+        })
+        .collect()
+}
+
+/// `GET /api/search?q=...` runs the same search as the search page and answers with the results
+/// as JSON. `limit` and `fuzzy` work like they do there, and an empty query finds nothing.
+fn search_json() -> BoxedFilter<(impl Reply,)> {
+    warp::get()
+        .and(warp::path!("api" / "search"))
+        .and(warp::query::<HashMap<String, String>>())
+        .then(|query_params: HashMap<String, String>| async move {
+            let term = query_params.get("q").map(|q| q.trim()).unwrap_or_default();
+            if term.is_empty() {
+                return warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!([])),
+                    StatusCode::OK,
+                );
+            }
+            let limit = query_params
+                .get("limit")
+                .and_then(|limit| limit.parse::<usize>().ok())
+                .unwrap_or_else(|| read_config().search.results_per_page);
+            let fuzzy = query_params
+                .get("fuzzy")
+                .map_or(false, |fuzzy| fuzzy == "true");
+            match APIRunner::search(term, limit, fuzzy).await {
+                Ok(mut results) => {
+                    results.truncate(limit);
+                    warp::reply::with_status(
+                        warp::reply::json(&search_results_json(&results)),
+                        StatusCode::OK,
+                    )
+                }
+                Err(e) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
+                    StatusCode::BAD_REQUEST,
+                ),
+            }
+        })
+        .boxed()
+}
+
+/// A note as `GET /api/pages/<title>` answers with it: the raw markdown in `content`, the tags as
+/// a list, and the rest of the frontmatter in `metadata`.
+fn page_json(note: &Note) -> serde_json::Value {
+    let mut metadata = note.header.clone();
+    let title = metadata.remove("title").unwrap_or_default();
+    let tags = metadata.remove("tags").unwrap_or_default();
+    serde_json::json!({
+        "title": title,
+        "tags": TagsArray::new(&tags).values,
+        "metadata": metadata,
+        "content": note.content,
+    })
+}
+
+/// The body of `PUT /api/pages/<title>`, shaped like what `GET` answers with. The title comes
+/// from the path, so renaming still goes through the editor.
+#[derive(Debug, Deserialize)]
+struct PageBody {
+    content: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+impl PageBody {
+    /// The edit saving this as `title` in `dir`, which creates the note unless it `exists`.
+    fn into_patch(self, dir: String, title: String, exists: bool) -> PatchData {
+        let mut metadata = self.metadata;
+        metadata.remove("title");
+        metadata.remove("tags");
+        PatchData {
+            body: self.content,
+            tags: self.tags,
+            old_title: if exists { title.clone() } else { String::new() },
+            title,
+            metadata,
+            dir,
+        }
+    }
+}
+
+fn json_error(message: &str, status: StatusCode) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "error": message })),
+        status,
+    )
+}
+
+fn decode_title(title: String) -> String {
+    let title = decode(&title)
+        .map(|title| title.into_owned())
+        .unwrap_or(title);
+    normalize_title(&title)
+}
+
+/// `/api/pages/<title>`, where the title is the rest of the path so notes in subdirectories are at
+/// `/api/pages/<dir>/<title>`.
+fn page_location() -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::path("api")
+        .and(warp::path("pages"))
+        .and(warp::path::tail())
+        .map(|tail: Tail| decode_title(tail.as_str().to_string()))
+}
+
+/// `GET /api/pages/<title>` answers with the note as JSON, see `page_json`.
+fn read_page_json() -> BoxedFilter<(impl Reply,)> {
+    warp::get()
+        .and(page_location())
+        .then(|title: String| async move {
+            let runner = WikiRunner {};
+            match runner.read_note(&title).await {
+                Ok(note) => {
+                    warp::reply::with_status(warp::reply::json(&page_json(&note)), StatusCode::OK)
+                }
+                Err(ReadPageError::PageNotFoundError) => {
+                    json_error("page not found", StatusCode::NOT_FOUND)
+                }
+                Err(e) => {
+                    eprintln!("Could not read {}: {}", title, e);
+                    json_error(&e.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            }
+        })
+        .boxed()
+}
+
+/// `PUT /api/pages/<title>` saves the note the same way the editor does, so links, tags, and the
+/// search index are patched too. Answers 201 when the note is new and 200 when it's updated.
+fn write_page_json(queue: QueueHandle) -> BoxedFilter<(impl Reply,)> {
+    warp::put()
+        .and(page_location())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json())
+        .and(with_queue(queue))
+        .then(
+            |location: String, body: PageBody, queue: QueueHandle| async move {
+                let (dir, title) = location.rsplit_once('/').unwrap_or(("", &location));
+                let dir = match parse_dir(dir) {
+                    Ok(dir) => dir,
+                    Err(e) => return json_error(&e.to_string(), StatusCode::BAD_REQUEST),
+                };
+                let title = normalize_title(title);
+                if title.is_empty() {
+                    return json_error("title cannot be empty", StatusCode::BAD_REQUEST);
+                }
+                let exists = get_file_path(&location).map_or(false, |path| path.exists());
+                let patch = body.into_patch(dir, title.clone(), exists);
+                match WikiRunner::edit(patch, queue).await {
+                    Ok(()) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({ "title": title })),
+                        if exists {
+                            StatusCode::OK
+                        } else {
+                            StatusCode::CREATED
+                        },
+                    ),
+                    Err(e) => json_error(&e.to_string(), StatusCode::INTERNAL_SERVER_ERROR),
+                }
+            },
+        )
+        .boxed()
+}
 
-#[allow(clippy::new_without_default)]
 impl APIRouter {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(parts: RefHubParts) -> Self {
+        Self { parts }
     }
     pub fn routes(&self) -> BoxedFilter<(impl Reply,)> {
         self.login()
@@ -33,9 +405,18 @@ impl APIRouter {
             .or(self.files())
             .or(self.titles())
             .or(self.mru())
+            .or(self.recently_viewed())
+            .or(self.backlinks())
+            .or(self.graph())
+            .or(self.read_page())
+            .or(self.write_page())
+            .or(self.reindex())
+            .or(self.json_search())
             .or(self.json_page())
             .or(self.search_from_qs())
             .or(self.version())
+            .or(self.metrics())
+            .or(self.readyz())
             .boxed()
     }
     fn json_page(&self) -> BoxedFilter<(impl Reply,)> {
@@ -54,6 +435,13 @@ impl APIRouter {
             .with(warp::cors().allow_any_origin())
             .boxed()
     }
+    fn json_search(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(search_json())
+            .with(warp::cors().allow_any_origin())
+            .boxed()
+    }
     fn titles(&self) -> BoxedFilter<(impl Reply,)> {
         warp::get()
             .and(with_auth())
@@ -75,6 +463,62 @@ impl APIRouter {
             })
             .boxed()
     }
+    /// Recently viewed pages, newest first, for picking up where you left off.
+    fn recently_viewed(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(warp::path!("api" / "recent"))
+            .then(|| async move {
+                let viewed = read_view_cache().await;
+                let viewed = viewed.lines().collect::<Vec<&str>>();
+                warp::reply::json(&viewed)
+            })
+            .boxed()
+    }
+    fn backlinks(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(warp::path!("api" / "pages" / String / "backlinks"))
+            .and(with_links(self.parts.0.to_owned()))
+            .then(|title: String, links: GlobalBacklinks| async move {
+                let title = decode(&title)
+                    .map(|title| title.into_owned())
+                    .unwrap_or(title);
+                warp::reply::json(&page_backlinks(&title, links).await)
+            })
+            .boxed()
+    }
+    fn graph(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(warp::path!("api" / "graph"))
+            .and(with_links(self.parts.0.to_owned()))
+            .then(|links: GlobalBacklinks| async move {
+                let titles = get_note_titles().unwrap_or_default();
+                let links = links.lock().await;
+                warp::reply::json(&link_graph(&links, titles))
+            })
+            .boxed()
+    }
+    fn read_page(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(read_page_json())
+            .with(warp::cors().allow_any_origin())
+            .boxed()
+    }
+    fn write_page(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::put()
+            .and(with_auth())
+            .and(write_page_json(self.parts.1.to_owned()))
+            .boxed()
+    }
+    fn reindex(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::post()
+            .and(with_auth())
+            .and(queue_reindex(self.parts.1.to_owned()))
+            .boxed()
+    }
     fn version(&self) -> BoxedFilter<(impl Reply,)> {
         warp::get()
             .and(with_auth())
@@ -84,6 +528,22 @@ impl APIRouter {
             }))
             .boxed()
     }
+    /// Prometheus metrics. These are only counts, no note contents, so scrapers don't need to log
+    /// in.
+    fn metrics(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(warp::path("metrics"))
+            .and(warp::path::end())
+            .map(|| {
+                Response::builder()
+                    .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                    .body(render_metrics())
+            })
+            .boxed()
+    }
+    fn readyz(&self) -> BoxedFilter<(impl Reply,)> {
+        readiness()
+    }
     fn img(&self) -> BoxedFilter<(impl Reply,)> {
         warp::post()
             .and(with_auth())
@@ -133,10 +593,11 @@ impl APIRouter {
                     .await
                     .unwrap_or_default();
                 match APIRunner::file(filename, data).await {
-                    Ok(()) => warp::redirect(Uri::from_static("/")),
+                    Ok(()) => warp::redirect(with_base_path("/").parse::<Uri>().unwrap()),
                     Err(e) => {
                         eprintln!("{}", e);
-                        let redir_url = format!("/error?msg={}", encode(&format!("{:?}", e)));
+                        let redir_url =
+                            with_base_path(&format!("/error?msg={}", encode(&format!("{:?}", e))));
                         warp::redirect(redir_url.parse::<Uri>().unwrap())
                     }
                 }
@@ -144,64 +605,10 @@ impl APIRouter {
             .boxed()
     }
     fn login(&self) -> BoxedFilter<(impl Reply,)> {
-        warp::post()
-            .and(warp::path("login"))
-            .and(
-                warp::body::content_length_limit(MAX_BODY_SIZE)
-                    .and(warp::body::form())
-                    .then(|form_body: HashMap<String, String>| async move {
-                        let username = form_body.get("username").unwrap();
-                        let pwd = form_body.get("password").unwrap();
-                        match create_jwt(username, pwd) {
-                            Ok(token) => Ok(Response::builder()
-                                .status(StatusCode::MOVED_PERMANENTLY)
-                                .header(header::LOCATION, HeaderValue::from_static("/"))
-                                .header(
-                                    header::SET_COOKIE,
-                                    format!(
-                                        "token={}; Secure; HttpOnly; Max-Age={}; Path=/",
-                                        token, MONTH
-                                    ),
-                                )
-                                .header(
-                                    header::SET_COOKIE,
-                                    format!("login=true; Secure; Max-Age={}; Path=/", MONTH),
-                                )
-                                .body("ok")),
-                            Err(e) => {
-                                let status: StatusCode;
-                                let body: &str;
-                                if let AuthError::JWTDecodeError = e {
-                                    status = StatusCode::BAD_REQUEST;
-                                    body = "Could not process request";
-                                } else {
-                                    status = StatusCode::FORBIDDEN;
-                                    body = "Invalid username or password";
-                                }
-                                Ok(Response::builder()
-                                    .status(status)
-                                    .header(header::LOCATION, HeaderValue::from_static("/"))
-                                    .body(body))
-                            }
-                        }
-                    }),
-            )
-            .boxed()
+        login_form(create_jwt, || read_config().session)
     }
     fn logout(&self) -> BoxedFilter<(impl Reply,)> {
-        warp::get()
-            .and(warp::path("logout"))
-            .then(|| async {
-                Ok(Response::builder()
-                    .status(StatusCode::MOVED_PERMANENTLY)
-                    .header(header::LOCATION, HeaderValue::from_static("/"))
-                    .header(
-                        header::SET_COOKIE,
-                        "token=; Secure; HttpOnly; Max-Age=0; Path=/",
-                    )
-                    .body("ok"))
-            })
-            .boxed()
+        logout_form(|| read_config().session)
     }
     fn search_from_qs(&self) -> BoxedFilter<(impl Reply,)> {
         warp::path("search")
@@ -210,7 +617,13 @@ impl APIRouter {
             .and(warp::query::<HashMap<String, String>>())
             .then(|query_params: HashMap<String, String>| async move {
                 let term = query_params.get("term").unwrap();
-                let results_page = APIRunner::note_search(term.clone()).await;
+                let limit = query_params
+                    .get("limit")
+                    .and_then(|limit| limit.parse::<usize>().ok());
+                let fuzzy = query_params
+                    .get("fuzzy")
+                    .map_or(false, |fuzzy| fuzzy == "true");
+                let results_page = APIRunner::note_search(term.clone(), limit, fuzzy).await;
                 warp::reply::html(results_page)
             })
             .boxed()
@@ -221,11 +634,13 @@ impl APIRouter {
                 warp::body::content_length_limit(MAX_BODY_SIZE).and(warp::body::form().then(
                     |form_body| async {
                         match APIRunner::update_styles(form_body).await {
-                            Ok(()) => warp::redirect(Uri::from_static("/")),
+                            Ok(()) => warp::redirect(with_base_path("/").parse::<Uri>().unwrap()),
                             Err(e) => {
                                 eprintln!("{}", e);
-                                let redir_url =
-                                    format!("/error?msg={}", encode(&format!("{:?}", e)));
+                                let redir_url = with_base_path(&format!(
+                                    "/error?msg={}",
+                                    encode(&format!("{:?}", e))
+                                ));
                                 warp::redirect(redir_url.parse::<Uri>().unwrap())
                             }
                         }
@@ -235,3 +650,374 @@ impl APIRouter {
             .boxed()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use task_runners::{
+        hash_password,
+        metrics::{PAGES_RENDERED, SEARCHES},
+        JobQueue,
+    };
+
+    use crate::{handlers::verify_jwt, services::issue_jwt};
+
+    use super::*;
+
+    #[test]
+    fn logs_in_with_the_configured_password() {
+        let pass_hash = hash_password(b"hunter2");
+        let token = issue_jwt("tendril", "hunter2", "tendril", &pass_hash, 60).unwrap();
+        assert!(verify_jwt(&token, &pass_hash).is_ok());
+        assert!(verify_jwt(&token, &hash_password(b"hunter2")).is_err());
+
+        let session = Session::default();
+        let res = login_reply(Ok(token.clone()), &session, "/");
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        let cookies = res
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .map(|cookie| cookie.to_str().unwrap())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            cookies,
+            vec![
+                format!(
+                    "token={}; Max-Age={}; Path=/; Secure; HttpOnly; SameSite=Lax",
+                    token, session.max_age
+                ),
+                format!(
+                    "login=true; Max-Age={}; Path=/; Secure; SameSite=Lax",
+                    session.max_age
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_a_bad_password() {
+        let pass_hash = hash_password(b"hunter2");
+        for (username, password) in [("tendril", "hunter3"), ("someone", "hunter2")] {
+            let token = issue_jwt(username, password, "tendril", &pass_hash, 60);
+            assert!(matches!(token, Err(AuthError::BadCredentials)));
+            let res = login_reply(token, &Session::default(), "/");
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            assert!(res.headers().get(header::SET_COOKIE).is_none());
+        }
+    }
+
+    #[test]
+    fn logging_out_clears_the_session() {
+        let res = logout_reply(&Session::default(), "/wiki/");
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        assert_eq!(res.headers()[header::LOCATION], "/wiki/");
+        let cookies = res
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .map(|cookie| cookie.to_str().unwrap())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            cookies,
+            vec![
+                "token=; Max-Age=0; Path=/wiki/; Secure; HttpOnly; SameSite=Lax",
+                "login=; Max-Age=0; Path=/wiki/; Secure; SameSite=Lax",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn logs_in_and_out_over_http() {
+        let pass_hash = hash_password(b"hunter2");
+        let secret = pass_hash.clone();
+        let sign_in = move |username: &str, password: &str| {
+            issue_jwt(username, password, "tendril", &secret, 60)
+        };
+        let login = login_form(sign_in, Session::default);
+        let mut token = String::new();
+        for (form, status, cookies) in [
+            (
+                "username=tendril&password=hunter2",
+                StatusCode::MOVED_PERMANENTLY,
+                2,
+            ),
+            (
+                "username=tendril&password=hunter3",
+                StatusCode::FORBIDDEN,
+                0,
+            ),
+        ] {
+            let res = warp::test::request()
+                .method("POST")
+                .path("/login")
+                .header("content-type", "application/x-www-form-urlencoded")
+                .body(form)
+                .reply(&login)
+                .await;
+            assert_eq!(res.status(), status, "{}", form);
+            assert_eq!(
+                res.headers().get_all(header::SET_COOKIE).iter().count(),
+                cookies,
+                "{}",
+                form
+            );
+            if let Some(cookie) = res.headers().get(header::SET_COOKIE) {
+                let cookie = cookie.to_str().unwrap().split(';').next().unwrap();
+                token = cookie.strip_prefix("token=").unwrap().to_owned();
+            }
+        }
+        assert!(verify_jwt(&token, &pass_hash).is_ok());
+
+        let logout = logout_form(Session::default);
+        let res = warp::test::request()
+            .method("POST")
+            .path("/logout")
+            .header("cookie", format!("token={}", token))
+            .reply(&logout)
+            .await;
+        assert!(matches!(
+            verify_jwt(&token, &pass_hash),
+            Err(AuthError::SessionRevoked)
+        ));
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        assert!(res
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .any(|cookie| cookie.to_str().unwrap().starts_with("token=; Max-Age=0")));
+        let res = warp::test::request()
+            .method("GET")
+            .path("/logout")
+            .reply(&logout)
+            .await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn exposes_metrics() {
+        PAGES_RENDERED.inc();
+        let searches = SEARCHES.get();
+        SEARCHES.inc();
+
+        let res = warp::test::request()
+            .path("/metrics")
+            .reply(&APIRouter::new((GlobalBacklinks::default(), Arc::default())).metrics())
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = String::from_utf8(res.body().to_vec()).unwrap();
+        for name in [
+            "tendril_pages_rendered_total",
+            "tendril_searches_total",
+            "tendril_task_queue_depth",
+            "tendril_archive_successes_total",
+            "tendril_archive_failures_total",
+        ] {
+            assert!(body.contains(&format!("# TYPE {} ", name)), "{}", name);
+        }
+        // Other tests search at the same time, so only the lower bound is known here.
+        let rendered_searches = body
+            .lines()
+            .find_map(|line| line.strip_prefix("tendril_searches_total "))
+            .unwrap();
+        assert!(rendered_searches.parse::<u64>().unwrap() > searches);
+    }
+
+    #[tokio::test]
+    async fn lists_backlinks_for_a_page() {
+        let links = GlobalBacklinks::default();
+        links.lock().await.insert(
+            String::from("Rust"),
+            vec![
+                Backlink::from("Tokio"),
+                Backlink {
+                    title: String::from("Borrowing"),
+                    alias: Some(String::from("the language")),
+                },
+                Backlink::from("Tokio"),
+            ],
+        );
+        let backlinks = page_backlinks("Rust ", links.clone()).await;
+        assert_eq!(
+            serde_json::to_value(&backlinks).unwrap(),
+            serde_json::json!([
+                { "title": "Borrowing", "alias": "the language" },
+                { "title": "Tokio", "alias": null },
+            ])
+        );
+        assert!(page_backlinks("Missing", links).await.is_empty());
+    }
+
+    #[test]
+    fn builds_the_link_graph() {
+        let links = Backlinks::from([
+            (
+                String::from("Rust"),
+                vec![Backlink::from("Tokio"), Backlink::from("Borrowing")],
+            ),
+            (
+                String::from("Tokio"),
+                vec![Backlink::from("Rust"), Backlink::from("Tokio")],
+            ),
+            (String::from("async"), vec![Backlink::from("Tokio")]),
+        ]);
+        let titles = ["Rust", "Tokio", "Borrowing", "Orphan"]
+            .map(String::from)
+            .to_vec();
+        let graph = link_graph(&links, titles);
+        let nodes = graph["nodes"].as_array().unwrap();
+        let edges = graph["edges"].as_array().unwrap();
+        assert_eq!(nodes.len(), 5);
+        assert!(nodes.contains(&serde_json::json!({ "id": "Orphan" })));
+        assert!(nodes.contains(&serde_json::json!({ "id": "async" })));
+        assert_eq!(edges.len(), 3);
+        assert_eq!(
+            edges[1],
+            serde_json::json!({ "source": "Rust", "target": "Tokio" })
+        );
+    }
+
+    #[tokio::test]
+    async fn searches_as_json() {
+        let results = [SearchResult {
+            title: String::from("Compost"),
+            score: 0.5,
+            snippet: String::from("turn the <mark>compost</mark> weekly"),
+        }];
+        assert_eq!(
+            search_results_json(&results),
+            serde_json::json!([{
+                "title": "Compost",
+                "score": 0.5,
+                "snippet": "turn the <mark>compost</mark> weekly",
+            }])
+        );
+
+        for path in ["/api/search?q=", "/api/search?q=%20%20", "/api/search"] {
+            let res = warp::test::request().path(path).reply(&search_json()).await;
+            assert_eq!(res.status(), StatusCode::OK, "{}", path);
+            assert_eq!(res.headers()[header::CONTENT_TYPE], "application/json");
+            assert_eq!(res.body(), "[]", "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trips_pages_as_json() {
+        let (_, _wiki) = crate::handlers::init_test_wiki().await;
+        let page = serde_json::json!({
+            "title": "Compost Bins",
+            "tags": ["garden", "soil"],
+            "metadata": { "source": "https://example.com/compost" },
+            "content": "Turn it weekly.\n- [[Worms]] help",
+        });
+        let body: PageBody = serde_json::from_value(page.clone()).unwrap();
+        let patch = body.into_patch(String::new(), String::from("Compost Bins"), false);
+        assert!(patch.old_title.is_empty());
+        let saved: String = Note::from(&patch).into();
+        std::fs::write(get_file_path("Compost Bins").unwrap(), saved).unwrap();
+
+        let res = warp::test::request()
+            .path("/api/pages/Compost%20Bins")
+            .reply(&read_page_json())
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let read: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(read, page);
+
+        let body: PageBody = serde_json::from_value(read).unwrap();
+        let patch = body.into_patch(String::new(), String::from("Compost Bins"), true);
+        assert_eq!(patch.old_title, "Compost Bins");
+        assert!(!patch.metadata.contains_key("title"));
+        assert!(!patch.metadata.contains_key("tags"));
+
+        let res = warp::test::request()
+            .path("/api/pages/Missing%20Page")
+            .reply(&read_page_json())
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(res.body()).unwrap(),
+            serde_json::json!({ "error": "page not found" })
+        );
+        std::fs::remove_file(get_file_path("Compost Bins").unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_page_writes_without_content() {
+        let queue = Arc::new(JobQueue::default());
+        let res = warp::test::request()
+            .method("PUT")
+            .path("/api/pages/Compost")
+            .json(&serde_json::json!({ "tags": ["garden"] }))
+            .reply(&write_page_json(queue.clone()))
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(queue.pull(10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn saves_pages_from_json() {
+        let (dir, _wiki) = crate::handlers::init_test_wiki().await;
+        std::fs::create_dir_all(format!("{}Projects", dir)).unwrap();
+        let _ = std::fs::remove_file(format!("{}Projects/Compost Heap.txt", dir));
+        let queue = Arc::new(JobQueue::default());
+        let res = warp::test::request()
+            .method("PUT")
+            .path("/api/pages/Projects/Compost%20Heap")
+            .json(&serde_json::json!({ "content": "Keep it damp", "tags": ["garden"] }))
+            .reply(&write_page_json(queue.clone()))
+            .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let jobs = queue.pull(10).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        match &jobs[0].message {
+            Message::Patch { patch } => {
+                assert_eq!(patch.title, "Compost Heap");
+                assert_eq!(patch.dir, "Projects");
+                assert_eq!(patch.body, "Keep it damp");
+                assert_eq!(patch.tags, vec![String::from("garden")]);
+                assert!(patch.old_title.is_empty());
+            }
+            message => panic!("expected a patch, got {:?}", message),
+        }
+
+        let res = warp::test::request()
+            .path("/api/pages/Projects/Compost%20Heap")
+            .reply(&read_page_json())
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let read: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(read["content"], "Keep it damp");
+
+        let res = warp::test::request()
+            .method("PUT")
+            .path("/api/pages/../Compost")
+            .json(&serde_json::json!({ "content": "Escaped" }))
+            .reply(&write_page_json(queue.clone()))
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(queue.pull(10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn queues_a_reindex() {
+        let queue = Arc::new(JobQueue::default());
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/reindex")
+            .reply(&queue_reindex(queue.clone()))
+            .await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let jobs = queue.pull(10).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert!(matches!(jobs[0].message, Message::Reindex));
+
+        let res = warp::test::request()
+            .path("/api/reindex")
+            .reply(&queue_reindex(queue.clone()))
+            .await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
@@ -1,12 +1,23 @@
-use std::{fmt::Display, sync::Arc};
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError},
+};
 
 use jsonwebtoken::{Algorithm, DecodingKey, Validation};
 use persistance::fs::config::read_config;
 use serde::{Deserialize, Serialize};
 use task_runners::JobQueue;
 use thiserror::Error;
-use warp::{hyper::StatusCode, reply::WithStatus, Filter, Rejection};
-use wikitext::GlobalBacklinks;
+use warp::{
+    filters::BoxedFilter,
+    http::{header, Response},
+    hyper::StatusCode,
+    path::FullPath,
+    reply::WithStatus,
+    Filter, Rejection, Reply,
+};
+use wikitext::{parsers::prefix_base_path, GlobalBacklinks};
 
 #[derive(Error, Debug)]
 pub enum AuthError {
@@ -22,6 +33,8 @@ pub enum AuthError {
     JWTDecodeError,
     #[error("could not create jwt")]
     JWTTokenCreationError,
+    #[error("session has been logged out")]
+    SessionRevoked,
 }
 
 impl warp::reject::Reject for AuthError {}
@@ -68,6 +81,119 @@ pub fn with_auth() -> impl Filter<Extract = (), Error = Rejection> + Clone {
         .boxed()
 }
 
+/// Whether the visitor can edit the page they're reading. Without a password everyone can. With
+/// `public_read`, visitors who aren't logged in can still read pages, just without the editor.
+/// Everyone else is asked to log in, like `with_auth`.
+pub fn with_edit_access() -> impl Filter<Extract = (bool,), Error = Rejection> + Clone {
+    warp::any()
+        .and(warp::filters::cookie::optional("token"))
+        .and_then(|token: Option<String>| async move {
+            let config = read_config();
+            edit_access(
+                token.as_deref(),
+                &config.general.pass,
+                config.general.public_read,
+            )
+            .map_err(warp::reject::custom)
+        })
+        .boxed()
+}
+
+fn edit_access(token: Option<&str>, pass: &str, public_read: bool) -> Result<bool, AuthError> {
+    if pass.is_empty() {
+        return Ok(true);
+    }
+    let verified = match token.filter(|token| !token.is_empty()) {
+        Some(token) => verify_jwt(token, pass),
+        None => Err(AuthError::AuthNotPresent),
+    };
+    match verified {
+        Ok(()) => Ok(true),
+        Err(_) if public_read => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Redirects `/Page/` to `/Page` under `base_path`, keeping the query string, so both resolve to
+/// the same page and nested routes never see an empty sub path. Rejects everything else so the
+/// other routes can handle it.
+pub fn without_trailing_slash(
+    enabled: bool,
+    base_path: &'static str,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and_then(move |path: FullPath, query: String| async move {
+            let trimmed = path.as_str().trim_end_matches('/');
+            if !enabled || trimmed.is_empty() || trimmed == path.as_str() {
+                return Err(warp::reject::not_found());
+            }
+            let mut location = prefix_base_path(base_path, trimmed);
+            if !query.is_empty() {
+                location.push('?');
+                location.push_str(&query);
+            }
+            Ok(Response::builder()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header(header::LOCATION, location)
+                .body(""))
+        })
+        .boxed()
+}
+
+/// What an `Accept` or `Accept-Encoding` header lists, with each entry's `q` weight, in the order
+/// they're listed. Entries refused with `q=0` are left out, and ones without a weight count as 1.
+pub(crate) fn accepted(header: &str) -> impl Iterator<Item = (&str, f32)> {
+    header.split(',').filter_map(|accepted| {
+        let mut parts = accepted.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let q = parts
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        Some((name, q)).filter(|(name, q)| !name.is_empty() && *q > 0.0)
+    })
+}
+
+/// Whether an `Accept-Encoding` header lists `encoding`, leaving out ones with `q=0`.
+fn accepts_encoding(accept_encoding: &str, encoding: &str) -> bool {
+    accepted(accept_encoding).any(|(name, _)| name.eq_ignore_ascii_case(encoding) || name == "*")
+}
+
+/// Matches when `enabled` and the client accepts `encoding`.
+fn with_encoding(
+    encoding: &'static str,
+    enabled: bool,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("accept-encoding")
+        .and_then(move |accept_encoding: Option<String>| async move {
+            match accept_encoding {
+                Some(accepted) if enabled && accepts_encoding(&accepted, encoding) => Ok(()),
+                _ => Err(warp::reject()),
+            }
+        })
+        .untuple_one()
+}
+
+/// Compresses what `routes` reply with, using brotli or gzip depending on what the client
+/// accepts. `warp::compression` compresses every reply it wraps, so this picks one for each
+/// request, and leaves replies alone when `enabled` is off. `routes` should recover from their own
+/// rejections, since a rejection falls through to trying them again uncompressed.
+pub fn with_compression<R>(routes: BoxedFilter<(R,)>, enabled: bool) -> BoxedFilter<(impl Reply,)>
+where
+    R: Reply + Send + 'static,
+{
+    with_encoding("br", enabled)
+        .and(routes.clone())
+        .with(warp::compression::brotli())
+        .or(with_encoding("gzip", enabled)
+            .and(routes.clone())
+            .with(warp::compression::gzip()))
+        .or(routes)
+        .boxed()
+}
+
 pub fn reply_on_result<'a, E>(result: Result<(), E>) -> WithStatus<&'a str>
 where
     E: Display + std::fmt::Debug,
@@ -92,14 +218,197 @@ pub async fn check_auth(token: Option<String>) -> AuthResult<()> {
     if token.is_empty() {
         return Err(warp::reject::custom(AuthError::AuthNotPresent));
     }
+    verify_jwt(&token, &config.general.pass).map_err(warp::reject::custom)
+}
+
+/// Tokens that were logged out before they expired. They're only kept in memory, so restarting the
+/// server lets them back in until they expire.
+static REVOKED_TOKENS: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+fn revoked_tokens() -> MutexGuard<'static, HashSet<String>> {
+    REVOKED_TOKENS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Ends the session `token` was issued for, so it's turned away even if it was copied before
+/// logging out.
+pub fn revoke_jwt(token: &str) {
+    revoked_tokens().insert(token.to_owned());
+}
+
+/// Checks that `token` was signed with `secret`, hasn't expired yet, and hasn't been logged out.
+pub fn verify_jwt(token: &str, secret: &str) -> Result<(), AuthError> {
+    if revoked_tokens().contains(token) {
+        return Err(AuthError::SessionRevoked);
+    }
     jsonwebtoken::decode::<Claims>(
-        &token,
-        &DecodingKey::from_secret(config.general.pass.as_bytes()),
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
         &Validation::new(Algorithm::HS512),
     )
+    .map(|_| ())
     .map_err(|e| {
         eprintln!("{}", e);
-        warp::reject::custom(AuthError::JWTDecodeError)
-    })?;
-    Ok(())
+        AuthError::JWTDecodeError
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn follow(path: &str) -> (StatusCode, String) {
+        let pages = warp::path!(String).map(|title: String| title);
+        let nested =
+            warp::path!(String / String).map(|dir: String, title: String| dir + "/" + &title);
+        let routes = without_trailing_slash(true, "").or(pages).or(nested);
+        let mut response = warp::test::request().path(path).reply(&routes).await;
+        if response.status() == StatusCode::MOVED_PERMANENTLY {
+            let location = response.headers()[header::LOCATION]
+                .to_str()
+                .unwrap()
+                .to_owned();
+            response = warp::test::request().path(&location).reply(&routes).await;
+        }
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        (response.status(), body)
+    }
+
+    #[tokio::test]
+    async fn redirects_trailing_slashes() {
+        assert_eq!(follow("/Page/").await, follow("/Page").await);
+        assert_eq!(
+            follow("/Page/").await,
+            (StatusCode::OK, String::from("Page"))
+        );
+        assert_eq!(
+            follow("/projects/garden/").await,
+            (StatusCode::OK, String::from("projects/garden"))
+        );
+
+        let routes = without_trailing_slash(true, "");
+        let response = warp::test::request()
+            .path("/Page/?linkto=Home")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.headers()[header::LOCATION], "/Page?linkto=Home");
+        let response = warp::test::request().path("/").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let disabled = without_trailing_slash(false, "");
+        let response = warp::test::request().path("/Page/").reply(&disabled).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn redirects_under_the_base_path() {
+        let routes = without_trailing_slash(true, "/wiki");
+        let response = warp::test::request()
+            .path("/projects/garden/?linkto=Home")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "/wiki/projects/garden?linkto=Home"
+        );
+    }
+
+    #[test]
+    fn grants_edit_access_to_logged_in_visitors() {
+        let claims = Claims {
+            exp: usize::MAX,
+            sub: String::from("tendril"),
+        };
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(Algorithm::HS512),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert!(edit_access(None, "", false).unwrap());
+        assert!(edit_access(Some(&token), "secret", false).unwrap());
+        assert!(!edit_access(None, "secret", true).unwrap());
+        assert!(!edit_access(Some(&token), "other secret", true).unwrap());
+        assert!(matches!(
+            edit_access(None, "secret", false),
+            Err(AuthError::AuthNotPresent)
+        ));
+        assert!(matches!(
+            edit_access(Some(&token), "other secret", false),
+            Err(AuthError::JWTDecodeError)
+        ));
+    }
+
+    #[test]
+    fn leaves_out_refused_entries() {
+        assert_eq!(
+            accepted("image/avif;q=0, image/webp;q=0.8, text/html").collect::<Vec<_>>(),
+            vec![("image/webp", 0.8), ("text/html", 1.0)]
+        );
+        assert_eq!(accepted("").count(), 0);
+    }
+
+    #[test]
+    fn rejects_expired_and_forged_tokens() {
+        let token = |exp: i64, secret: &[u8]| {
+            let claims = Claims {
+                exp: exp as usize,
+                sub: String::from("tendril"),
+            };
+            jsonwebtoken::encode(
+                &jsonwebtoken::Header::new(Algorithm::HS512),
+                &claims,
+                &jsonwebtoken::EncodingKey::from_secret(secret),
+            )
+            .unwrap()
+        };
+        let now = chrono::Utc::now().timestamp();
+        assert!(verify_jwt(&token(now + 3600, b"secret"), "secret").is_ok());
+        assert!(matches!(
+            verify_jwt(&token(now - 3600, b"secret"), "secret"),
+            Err(AuthError::JWTDecodeError)
+        ));
+        assert!(matches!(
+            verify_jwt(&token(now + 3600, b"forged"), "secret"),
+            Err(AuthError::JWTDecodeError)
+        ));
+    }
+
+    #[tokio::test]
+    async fn compresses_accepted_encodings() {
+        let page = warp::any().map(|| "compost ".repeat(200)).boxed();
+        let routes = with_compression(page.clone(), true);
+        for (accept_encoding, encoding) in [
+            ("gzip", Some("gzip")),
+            ("gzip, deflate, br", Some("br")),
+            ("br;q=0, gzip;q=0.8", Some("gzip")),
+            ("identity", None),
+        ] {
+            let response = warp::test::request()
+                .header("accept-encoding", accept_encoding)
+                .reply(&routes)
+                .await;
+            assert_eq!(
+                response
+                    .headers()
+                    .get(header::CONTENT_ENCODING)
+                    .map(|encoding| encoding.to_str().unwrap()),
+                encoding,
+                "{}",
+                accept_encoding
+            );
+        }
+        let response = warp::test::request().reply(&routes).await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(response.body().len(), "compost ".len() * 200);
+
+        let disabled = with_compression(page, false);
+        let response = warp::test::request()
+            .header("accept-encoding", "gzip")
+            .reply(&disabled)
+            .await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    }
 }
@@ -22,12 +22,34 @@ use warp::{http::StatusCode, Rejection, Reply};
 // 40MB file limit
 pub const MAX_BODY_SIZE: u64 = 40_000_000;
 
+/// Points the wiki and data directories at a scratch directory for tests that read or write notes,
+/// with the default config in it for saving notes. The locations are read once per process, so
+/// every test has to share the same one, and holds the returned guard so they take turns with it.
+#[cfg(test)]
+pub(crate) async fn init_test_wiki() -> (&'static str, tokio::sync::MutexGuard<'static, ()>) {
+    const TEST_DIR: &str = "/tmp/tendril-test/www/";
+    static CONFIG: std::sync::Once = std::sync::Once::new();
+    static WIKI: std::sync::OnceLock<tokio::sync::Mutex<()>> = std::sync::OnceLock::new();
+    let guard = WIKI.get_or_init(Default::default).lock().await;
+    std::env::set_var("TENDRIL_WIKI_DIR", TEST_DIR);
+    std::env::set_var("TENDRIL_DATA_DIR", TEST_DIR);
+    std::fs::create_dir_all(TEST_DIR).unwrap();
+    CONFIG.call_once(|| {
+        let config = format!("{}config.toml", TEST_DIR);
+        std::fs::write(&config, include_str!("../../../../config/config.toml")).unwrap();
+        persistance::fs::utils::set_config_location(&config, false).unwrap();
+    });
+    (TEST_DIR, guard)
+}
+
 pub async fn handle_rejection(err: Rejection) -> std::result::Result<impl Reply, Infallible> {
     let (code, message) = if err.is_not_found() {
         (StatusCode::NOT_FOUND, "Not Found".to_string())
     } else if let Some(e) = err.find::<AuthError>() {
         match e {
-            AuthError::AuthNotPresent => (StatusCode::UNAUTHORIZED, e.to_string()),
+            AuthError::AuthNotPresent | AuthError::SessionRevoked => {
+                (StatusCode::UNAUTHORIZED, e.to_string())
+            }
             AuthError::BadCredentials => (StatusCode::FORBIDDEN, e.to_string()),
             _ => (StatusCode::BAD_REQUEST, e.to_string()),
         }
@@ -1,10 +1,26 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    fs::Metadata,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use chrono::{DateTime, Utc};
 use persistance::fs::utils::get_config_location;
-use warp::{filters::BoxedFilter, Filter, Reply};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use warp::{
+    filters::BoxedFilter,
+    http::{header, HeaderValue, Response, StatusCode},
+    hyper::Body,
+    path::Tail,
+    Filter, Rejection, Reply,
+};
 
 use crate::get_static_dir;
 
+use super::filters::accepted;
+
 pub struct StaticFileRouter {
     media_location: Arc<String>,
 }
@@ -28,11 +44,538 @@ impl StaticFileRouter {
             .with(warp::cors().allow_any_origin())
             .boxed()
     }
+    /// Uploaded media. `warp::fs` answers `Range` requests with `206 Partial Content` and
+    /// advertises `Accept-Ranges: bytes`, so audio and video can seek without downloading the
+    /// whole file. Requests for several ranges at once get the whole file. Files carry an `ETag` and `Last-Modified` so browsers can revalidate them with
+    /// a `304 Not Modified` instead of downloading them again.
     fn files(&self) -> BoxedFilter<(impl Reply,)> {
         let media_location = self.media_location.clone();
         warp::path("files")
-            .and(warp::fs::dir(PathBuf::from(media_location.as_str())))
+            .and(
+                modern_image(media_location.clone()).or(warp::header::headers_cloned()
+                    .and(warp::fs::dir(PathBuf::from(media_location.as_str())))
+                    .then(
+                        |request: header::HeaderMap, file: warp::fs::File| async move {
+                            let negotiable = is_negotiable_image(file.path());
+                            let path = file.path().to_path_buf();
+                            let metadata = tokio::fs::metadata(&path).await.ok();
+                            let range = requested_range(
+                                request.get(header::RANGE).map(HeaderValue::as_bytes),
+                                metadata.as_ref().map_or(0, Metadata::len),
+                            );
+                            let mut res = file.into_response();
+                            match (range, metadata.as_ref()) {
+                                (RangeRequest::Unsatisfiable, Some(metadata)) => {
+                                    return range_not_satisfiable(metadata.len())
+                                }
+                                // `warp::fs` answers with the first of several ranges, but they're
+                                // served whole here
+                                (RangeRequest::Full, Some(metadata))
+                                    if res.status() == StatusCode::PARTIAL_CONTENT =>
+                                {
+                                    let mut full = Response::builder();
+                                    if let Some(content_type) =
+                                        res.headers().get(header::CONTENT_TYPE)
+                                    {
+                                        full = full.header(header::CONTENT_TYPE, content_type);
+                                    }
+                                    res = ranged_file(full, &path, metadata.len(), range).await;
+                                }
+                                _ => {}
+                            }
+                            if negotiable {
+                                res.headers_mut()
+                                    .insert(header::VARY, header::ACCEPT.as_str().parse().unwrap());
+                            }
+                            match metadata {
+                                Some(metadata) => revalidate(res, &metadata, &request),
+                                None => res,
+                            }
+                        },
+                    )),
+            )
             .with(warp::cors().allow_any_origin())
             .boxed()
     }
 }
+
+/// What to send back for a `Range` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RangeRequest {
+    /// The whole file, when there's no range or it's one that isn't supported, like several
+    /// ranges at once or a unit other than bytes.
+    Full,
+    /// The first and last byte, inclusive.
+    Partial(u64, u64),
+    /// A byte range that's malformed or outside the file, which is answered with a 416.
+    Unsatisfiable,
+}
+
+/// How to answer a request with `range` for a file of `len` bytes. Only single byte ranges are
+/// served partially.
+fn requested_range(range: Option<&[u8]>, len: u64) -> RangeRequest {
+    let range = match range {
+        Some(range) => String::from_utf8_lossy(range),
+        None => return RangeRequest::Full,
+    };
+    let ranges = match range.trim().strip_prefix("bytes=") {
+        Some(ranges) if !ranges.contains(',') => ranges,
+        _ => return RangeRequest::Full,
+    };
+    match byte_range(ranges, len) {
+        Some((start, end)) => RangeRequest::Partial(start, end),
+        None => RangeRequest::Unsatisfiable,
+    }
+}
+
+/// The first and last byte, inclusive, of a single `start-end` byte range for a file of `len`
+/// bytes. `None` when the range is malformed or outside the file.
+fn byte_range(range: &str, len: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // The last `suffix` bytes
+        ("", suffix) => {
+            let suffix = suffix.parse::<u64>().ok().filter(|suffix| *suffix > 0)?;
+            (len.saturating_sub(suffix), len.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, len.checked_sub(1)?),
+        (start, end) => {
+            let end = end.parse::<u64>().ok()?;
+            (start.parse().ok()?, end.min(len.checked_sub(1)?))
+        }
+    };
+    Some((start, end)).filter(|(start, end)| start <= end)
+}
+
+fn range_not_satisfiable(len: u64) -> warp::reply::Response {
+    Response::builder()
+        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+        .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// The file at `path`, `len` bytes long, as a reply to `range`. Only the requested bytes are read.
+async fn ranged_file(
+    mut res: warp::http::response::Builder,
+    path: &Path,
+    len: u64,
+    range: RangeRequest,
+) -> warp::reply::Response {
+    res = res.header(header::ACCEPT_RANGES, "bytes");
+    let (start, end) = match range {
+        RangeRequest::Full if len == 0 => return res.body(Body::empty()).unwrap(),
+        RangeRequest::Full => (0, len - 1),
+        RangeRequest::Partial(start, end) => {
+            res = res.status(StatusCode::PARTIAL_CONTENT).header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, len),
+            );
+            (start, end)
+        }
+        RangeRequest::Unsatisfiable => return range_not_satisfiable(len),
+    };
+    match read_slice(path, start, end - start + 1).await {
+        Ok(body) => res.body(Body::from(body)).unwrap(),
+        Err(e) => {
+            eprintln!("Could not read {:?}: {}", path, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn read_slice(path: &Path, start: u64, len: u64) -> std::io::Result<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut body = Vec::with_capacity(len as usize);
+    file.take(len).read_to_end(&mut body).await?;
+    Ok(body)
+}
+
+/// An `ETag` made of a file's size and modification time, `None` when the time isn't available.
+fn file_etag(metadata: &Metadata) -> Option<String> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!(
+        "\"{:x}-{:x}\"",
+        metadata.len(),
+        modified.as_nanos()
+    ))
+}
+
+/// Whether an `If-None-Match` header lists `etag`, ignoring weak `W/` prefixes.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// Whether the file has changed since the `If-Modified-Since` date. Dates that don't parse count
+/// as changed.
+fn modified_since(if_modified_since: &str, modified: SystemTime) -> bool {
+    match DateTime::parse_from_rfc2822(if_modified_since) {
+        // HTTP dates only have whole seconds
+        Ok(since) => DateTime::<Utc>::from(modified).timestamp() > since.timestamp(),
+        Err(_) => true,
+    }
+}
+
+/// Adds `ETag` and `Last-Modified` to a response for a file, swapping it for a `304 Not Modified`
+/// when the `request` headers show the client's copy is still current. `If-None-Match` wins over
+/// `If-Modified-Since` when both are sent. Headers whose values can't be worked out are left off.
+fn revalidate(
+    mut res: warp::reply::Response,
+    metadata: &Metadata,
+    request: &header::HeaderMap,
+) -> warp::reply::Response {
+    if res.status() != StatusCode::OK && res.status() != StatusCode::PARTIAL_CONTENT {
+        return res;
+    }
+    let etag = file_etag(metadata);
+    let modified = metadata.modified().ok();
+    if let Some(etag) = etag
+        .as_ref()
+        .and_then(|etag| HeaderValue::from_str(etag).ok())
+    {
+        res.headers_mut().insert(header::ETAG, etag);
+    }
+    if let Some(modified) = modified {
+        let last_modified = DateTime::<Utc>::from(modified)
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+        res.headers_mut().insert(
+            header::LAST_MODIFIED,
+            HeaderValue::from_str(&last_modified).unwrap(),
+        );
+    }
+    let header_str =
+        |name: header::HeaderName| request.get(name).and_then(|value| value.to_str().ok());
+    let unchanged = match (header_str(header::IF_NONE_MATCH), etag.as_deref()) {
+        (Some(if_none_match), Some(etag)) => etag_matches(if_none_match, etag),
+        (Some(_), None) => false,
+        (None, _) => match (header_str(header::IF_MODIFIED_SINCE), modified) {
+            (Some(since), Some(modified)) => !modified_since(since, modified),
+            _ => false,
+        },
+    };
+    if !unchanged {
+        return res;
+    }
+    let mut not_modified = Response::new(Body::empty());
+    *not_modified.status_mut() = StatusCode::NOT_MODIFIED;
+    for name in [header::ETAG, header::LAST_MODIFIED, header::VARY] {
+        if let Some(value) = res.headers().get(&name) {
+            not_modified.headers_mut().insert(name, value.clone());
+        }
+    }
+    not_modified
+}
+
+/// Formats to offer in place of an uploaded image, smallest first.
+const MODERN_IMAGE_FORMATS: [(&str, &str); 2] = [("avif", "image/avif"), ("webp", "image/webp")];
+
+fn is_negotiable_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| {
+            matches!(ext.to_lowercase().as_str(), "jpg" | "jpeg" | "png" | "gif")
+        })
+}
+
+/// Uploaded images can have `.avif` or `.webp` copies next to them, e.g. `photo.avif` beside
+/// `photo.jpg`. Clients whose `Accept` header lists those formats get the smaller copy; everyone
+/// else falls through to the original. Tendril doesn't make the copies itself, they're picked up
+/// when they've been put in the media folder, e.g. with `cwebp` or `avifenc`.
+fn modern_image(
+    media_location: Arc<String>,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path::tail())
+        .and(warp::header::optional::<String>("accept"))
+        .and(warp::header::headers_cloned())
+        .and_then(
+            move |tail: Tail, accept: Option<String>, request: header::HeaderMap| {
+                let media_location = media_location.clone();
+                async move {
+                    let requested = urlencoding::decode(tail.as_str())
+                        .map_err(|_| warp::reject::not_found())?
+                        .into_owned();
+                    let (variant, content_type) = modern_variant(
+                        Path::new(media_location.as_str()),
+                        &requested,
+                        accept.as_deref(),
+                    )
+                    .ok_or_else(warp::reject::not_found)?;
+                    let metadata = tokio::fs::metadata(&variant)
+                        .await
+                        .map_err(|_| warp::reject::not_found())?;
+                    let range = requested_range(
+                        request.get(header::RANGE).map(HeaderValue::as_bytes),
+                        metadata.len(),
+                    );
+                    let res = ranged_file(
+                        Response::builder()
+                            .header(header::CONTENT_TYPE, content_type)
+                            .header(header::VARY, header::ACCEPT.as_str()),
+                        &variant,
+                        metadata.len(),
+                        range,
+                    )
+                    .await;
+                    Ok::<_, Rejection>(revalidate(res, &metadata, &request))
+                }
+            },
+        )
+}
+
+/// The best existing copy of `requested` in a format the client accepts, if there is one.
+fn modern_variant(
+    media_location: &Path,
+    requested: &str,
+    accept: Option<&str>,
+) -> Option<(PathBuf, &'static str)> {
+    let requested = Path::new(requested);
+    let escapes = requested
+        .components()
+        .any(|part| !matches!(part, std::path::Component::Normal(_)));
+    if escapes || !is_negotiable_image(requested) {
+        return None;
+    }
+    let accept = accept?;
+    MODERN_IMAGE_FORMATS
+        .iter()
+        .filter(|(_, content_type)| {
+            accepted(accept).any(|(accepted, _)| accepted.eq_ignore_ascii_case(content_type))
+        })
+        .map(|(ext, content_type)| {
+            (
+                media_location.join(requested.with_extension(ext)),
+                *content_type,
+            )
+        })
+        .find(|(variant, _)| variant.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use warp::http::StatusCode;
+
+    use super::*;
+
+    const TEST_DIR: &str = "/tmp/tendril-test/static-files/";
+
+    fn init_media_dir(namespace: &str) -> StaticFileRouter {
+        let media_dir = format!("{}{}", TEST_DIR, namespace);
+        fs::create_dir_all(&media_dir).unwrap();
+        fs::write(format!("{}/clip.mp3", media_dir), b"0123456789").unwrap();
+        StaticFileRouter::new(Arc::new(media_dir))
+    }
+
+    #[tokio::test]
+    async fn serves_modern_image_formats_when_accepted() {
+        let router = init_media_dir("images");
+        let media_dir = format!("{}images", TEST_DIR);
+        fs::write(format!("{}/photo.jpg", media_dir), b"jpeg bytes").unwrap();
+        fs::write(format!("{}/photo.webp", media_dir), b"webp bytes").unwrap();
+
+        let res = warp::test::request()
+            .path("/files/photo.jpg")
+            .header("accept", "image/webp,image/*,*/*;q=0.8")
+            .reply(&router.files())
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-type"], "image/webp");
+        assert_eq!(res.headers()["vary"], "accept");
+        assert_eq!(res.body().as_ref(), b"webp bytes");
+
+        let res = warp::test::request()
+            .path("/files/photo.jpg")
+            .header("accept", "image/*")
+            .reply(&router.files())
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-type"], "image/jpeg");
+        assert_eq!(res.headers()["vary"], "accept");
+        assert_eq!(res.body().as_ref(), b"jpeg bytes");
+
+        let res = warp::test::request()
+            .path("/files/photo.jpg")
+            .header("accept", "image/webp;q=0, image/*")
+            .reply(&router.files())
+            .await;
+        assert_eq!(res.headers()["content-type"], "image/jpeg");
+        assert_eq!(res.body().as_ref(), b"jpeg bytes");
+    }
+
+    #[tokio::test]
+    async fn revalidates_unchanged_media() {
+        let router = init_media_dir("etag");
+        let res = warp::test::request()
+            .path("/files/clip.mp3")
+            .reply(&router.files())
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let etag = res.headers()["etag"].to_str().unwrap().to_owned();
+        let last_modified = res.headers()["last-modified"].to_str().unwrap().to_owned();
+
+        let res = warp::test::request()
+            .path("/files/clip.mp3")
+            .header("if-none-match", &etag)
+            .reply(&router.files())
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers()["etag"], etag.as_str());
+        assert!(res.body().is_empty());
+
+        let res = warp::test::request()
+            .path("/files/clip.mp3")
+            .header("if-modified-since", &last_modified)
+            .reply(&router.files())
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        let res = warp::test::request()
+            .path("/files/clip.mp3")
+            .header("if-none-match", "\"stale\"")
+            .reply(&router.files())
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body().as_ref(), b"0123456789");
+    }
+
+    #[test]
+    fn matches_etags() {
+        assert!(etag_matches("\"a\", \"b\"", "\"b\""));
+        assert!(etag_matches("W/\"b\"", "\"b\""));
+        assert!(etag_matches("*", "\"b\""));
+        assert!(!etag_matches("\"a\"", "\"b\""));
+    }
+
+    #[tokio::test]
+    async fn serves_byte_ranges_of_media() {
+        let router = init_media_dir("range");
+        let res = warp::test::request()
+            .path("/files/clip.mp3")
+            .header("range", "bytes=2-5")
+            .reply(&router.files())
+            .await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()["content-range"], "bytes 2-5/10");
+        assert_eq!(res.body().as_ref(), b"2345");
+    }
+
+    #[tokio::test]
+    async fn rejects_unsatisfiable_ranges() {
+        let router = init_media_dir("bad-range");
+        for range in [
+            HeaderValue::from_static("bytes=20-30"),
+            HeaderValue::from_static("bytes=5-2"),
+            HeaderValue::from_static("bytes=abc"),
+            HeaderValue::from_static("bytes=-0"),
+            HeaderValue::from_bytes(b"bytes=\xff-").unwrap(),
+        ] {
+            let res = warp::test::request()
+                .path("/files/clip.mp3")
+                .header("range", range.clone())
+                .reply(&router.files())
+                .await;
+            assert_eq!(
+                res.status(),
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "{:?}",
+                range
+            );
+            assert_eq!(res.headers()["content-range"], "bytes */10", "{:?}", range);
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_unsupported_ranges_whole() {
+        let router = init_media_dir("unsupported-range");
+        for range in ["bytes=0-1, 4-5", "items=0-1"] {
+            let res = warp::test::request()
+                .path("/files/clip.mp3")
+                .header("range", range)
+                .reply(&router.files())
+                .await;
+            assert_eq!(res.status(), StatusCode::OK, "{}", range);
+            assert!(res.headers().get("content-range").is_none(), "{}", range);
+            assert_eq!(res.body().as_ref(), b"0123456789", "{}", range);
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_byte_ranges_of_modern_images() {
+        let router = init_media_dir("image-range");
+        let media_dir = format!("{}image-range", TEST_DIR);
+        fs::write(format!("{}/photo.png", media_dir), b"png bytes").unwrap();
+        fs::write(format!("{}/photo.avif", media_dir), b"avif bytes").unwrap();
+        let request = || {
+            warp::test::request()
+                .path("/files/photo.png")
+                .header("accept", "image/avif")
+        };
+        let res = request()
+            .header("range", "bytes=-5")
+            .reply(&router.files())
+            .await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()["content-range"], "bytes 5-9/10");
+        assert_eq!(res.body().as_ref(), b"bytes");
+
+        let res = request()
+            .header("range", "bytes=10-")
+            .reply(&router.files())
+            .await;
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+        let res = request().reply(&router.files()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["accept-ranges"], "bytes");
+        assert_eq!(res.body().as_ref(), b"avif bytes");
+    }
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(byte_range("2-5", 10), Some((2, 5)));
+        assert_eq!(byte_range("2-", 10), Some((2, 9)));
+        assert_eq!(byte_range("-3", 10), Some((7, 9)));
+        assert_eq!(byte_range("-30", 10), Some((0, 9)));
+        assert_eq!(byte_range("8-30", 10), Some((8, 9)));
+        assert_eq!(byte_range("10-", 10), None);
+        assert_eq!(byte_range("0-0", 0), None);
+
+        let range = |range: &str| requested_range(Some(range.as_bytes()), 10);
+        assert_eq!(requested_range(None, 10), RangeRequest::Full);
+        assert_eq!(range("bytes=2-5"), RangeRequest::Partial(2, 5));
+        assert_eq!(range("bytes=0-1, 4-5"), RangeRequest::Full);
+        assert_eq!(range("items=0-1"), RangeRequest::Full);
+        assert_eq!(range("bytes=abc"), RangeRequest::Unsatisfiable);
+        assert_eq!(
+            requested_range(Some(b"bytes=\xff-"), 10),
+            RangeRequest::Unsatisfiable
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_only_the_requested_slice() {
+        init_media_dir("slice");
+        let path = PathBuf::from(format!("{}slice/clip.mp3", TEST_DIR));
+        assert_eq!(read_slice(&path, 3, 4).await.unwrap(), b"3456");
+        assert_eq!(read_slice(&path, 8, 10).await.unwrap(), b"89");
+    }
+
+    #[tokio::test]
+    async fn advertises_range_support_on_full_requests() {
+        let router = init_media_dir("full");
+        let res = warp::test::request()
+            .path("/files/clip.mp3")
+            .reply(&router.files())
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["accept-ranges"], "bytes");
+        assert_eq!(res.body().as_ref(), b"0123456789");
+    }
+}
@@ -1,12 +1,14 @@
-use persistance::fs::get_note_titles;
+use persistance::fs::{config::read_config, current_time, get_note_titles};
+use rand_core::{OsRng, RngCore};
 use render::{
     all_pages::PageList, file_upload_page::FileUploader, help_page::HelpPage,
-    index_page::IndexPage, opensearch_page::OpenSearchPage, Render,
+    index_page::IndexPage, journal_page::JournalPage, opensearch_page::OpenSearchPage, Render,
 };
 use std::{collections::HashMap, sync::Arc};
-use task_runners::runners::static_page_runner::StaticPageRunner;
-use warp::{filters::BoxedFilter, Filter, Reply};
-use wikitext::GlobalBacklinks;
+use task_runners::{cache::NOTE_CACHE_SIZE, runners::static_page_runner::StaticPageRunner};
+use urlencoding::{decode, encode};
+use warp::{filters::BoxedFilter, hyper::Uri, Filter, Rejection, Reply};
+use wikitext::{encode_path, parsers::with_base_path, tagged_with_all, GlobalBacklinks};
 
 use crate::handlers::filters::with_location;
 
@@ -16,20 +18,103 @@ pub struct StaticPageRouter {
     user: Arc<String>,
     media_location: Arc<String>,
     host: Arc<String>,
+    home: Arc<String>,
     links: GlobalBacklinks,
 }
 
+/// Sends `/` to the configured home note instead of the generated index, rejecting when no home
+/// note is set.
+fn home_redirect(
+    home: Arc<String>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path::end().and_then(move || {
+        let home = home.clone();
+        async move {
+            if home.is_empty() {
+                return Err(warp::reject::not_found());
+            }
+            let location = with_base_path(&format!("/{}", encode(&home)));
+            Ok(warp::redirect::temporary(location.parse::<Uri>().unwrap()))
+        }
+    })
+}
+
+/// A note picked at random from `titles`, or the new note page when there aren't any yet.
+fn random_page_location(titles: &[String]) -> String {
+    if titles.is_empty() {
+        return with_base_path("/new");
+    }
+    let index = OsRng.next_u64() as usize % titles.len();
+    with_base_path(&format!("/{}", encode_path(&titles[index])))
+}
+
+/// `GET /random` redirects to a random note, listed by `titles`.
+fn random_page<F>(titles: F) -> BoxedFilter<(impl Reply,)>
+where
+    F: Fn() -> Vec<String> + Clone + Send + Sync + 'static,
+{
+    warp::get()
+        .and(warp::path("random"))
+        .and(warp::path::end())
+        .map(move || {
+            let location = random_page_location(&titles());
+            warp::redirect::found(location.parse::<Uri>().unwrap())
+        })
+        .boxed()
+}
+
+/// How many notes `/recent` lists, `?limit=` or everything in the recently changed list. That list
+/// only keeps the last `NOTE_CACHE_SIZE` notes, so larger limits are capped to it.
+fn recent_changes_limit(query_params: &HashMap<String, String>) -> usize {
+    query_params
+        .get("limit")
+        .and_then(|limit| limit.parse::<usize>().ok())
+        .map_or(NOTE_CACHE_SIZE, |limit| limit.min(NOTE_CACHE_SIZE))
+}
+
+/// `GET /recent`, the notes in the recently changed list.
+fn recent_changes_page() -> BoxedFilter<(impl Reply,)> {
+    warp::get()
+        .and(warp::path("recent"))
+        .and(warp::path::end())
+        .and(warp::query::<HashMap<String, String>>())
+        .then(|query_params: HashMap<String, String>| async move {
+            let limit = recent_changes_limit(&query_params);
+            warp::reply::html(StaticPageRunner::render_recent_changes(limit).await)
+        })
+        .boxed()
+}
+
+/// `GET /feed.xml`, an Atom feed of the recently changed notes for feed readers.
+fn recent_changes_feed(user: Arc<String>, host: Arc<String>) -> BoxedFilter<(impl Reply,)> {
+    warp::get()
+        .and(warp::path("feed.xml"))
+        .and(warp::path::end())
+        .and(with_user(user.to_string()))
+        .and(with_host(host.to_string()))
+        .then(|user: String, host: String| async {
+            warp::reply::with_header(
+                StaticPageRunner::render_feed(user, host).await,
+                "Content-Type",
+                "application/atom+xml",
+            )
+        })
+        .boxed()
+}
+
 impl StaticPageRouter {
     pub fn new(
         user: Arc<String>,
         media_location: Arc<String>,
         host: Arc<String>,
+        home: Arc<String>,
         links: GlobalBacklinks,
     ) -> Self {
         Self {
             user,
             media_location,
             host,
+            home,
             links,
         }
     }
@@ -37,8 +122,13 @@ impl StaticPageRouter {
         self.file_list()
             .or(self.upload())
             .or(self.all_pages())
+            .or(self.tagged())
+            .or(self.journal())
             .or(self.help())
             .or(self.open_search())
+            .or(self.feed())
+            .or(self.random())
+            .or(self.recent_changes())
             .or(self.styles())
             .or(self.error())
             .boxed()
@@ -59,13 +149,15 @@ impl StaticPageRouter {
         let host = self.host.clone();
         warp::get()
             .and(with_auth())
-            .and(with_user(user.to_string()))
-            .and(with_host(host.to_string()))
-            .and(with_links(self.links.to_owned()))
-            .then(|user: String, host: String, links: GlobalBacklinks| async {
-                let idx_ctx = IndexPage::new(user, host, links);
-                warp::reply::html(idx_ctx.render().await)
-            })
+            .and(
+                home_redirect(self.home.clone()).or(with_user(user.to_string())
+                    .and(with_host(host.to_string()))
+                    .and(with_links(self.links.to_owned()))
+                    .then(|user: String, host: String, links: GlobalBacklinks| async {
+                        let idx_ctx = IndexPage::new(user, host, links);
+                        warp::reply::html(idx_ctx.render().await)
+                    })),
+            )
             .boxed()
     }
     fn all_pages(&self) -> BoxedFilter<(impl Reply,)> {
@@ -92,6 +184,48 @@ impl StaticPageRouter {
             })
             .boxed()
     }
+    /// Notes carrying every tag in the path, like `/tags/rust+async`.
+    fn tagged(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(warp::path!("tags" / String))
+            .and(with_links(self.links.to_owned()))
+            .then(|tags: String, links: GlobalBacklinks| async move {
+                let tags = decode(&tags)
+                    .map(|tags| tags.into_owned())
+                    .unwrap_or_else(|_| tags.clone());
+                let tags = tags.split('+').collect::<Vec<&str>>();
+                let links = links.lock().await;
+                let titles = tagged_with_all(&links, &tags);
+                let name_and_count = titles
+                    .iter()
+                    .map(|title| (title, links.get(title).map_or(0, Vec::len)))
+                    .collect();
+                let idx_ctx = PageList::new(name_and_count);
+                warp::reply::html(idx_ctx.render().await)
+            })
+            .boxed()
+    }
+    /// Daily notes from the last few months, with `?page=` going further back.
+    fn journal(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(warp::path("journal"))
+            .and(warp::path::end())
+            .and(warp::query::<HashMap<String, String>>())
+            .then(|params: HashMap<String, String>| async move {
+                let page = params
+                    .get("page")
+                    .and_then(|page| page.parse::<usize>().ok())
+                    .unwrap_or(0);
+                let months = read_config().general.journal_lookback_months;
+                let titles = get_note_titles().unwrap_or_default();
+                let today = current_time().date_naive();
+                let ctx = JournalPage::new(&titles, today, months, page);
+                warp::reply::html(ctx.render().await)
+            })
+            .boxed()
+    }
     fn open_search(&self) -> BoxedFilter<(impl Reply,)> {
         let user = self.user.clone();
         let host = self.host.clone();
@@ -110,6 +244,24 @@ impl StaticPageRouter {
             })
             .boxed()
     }
+    fn recent_changes(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(recent_changes_page())
+            .boxed()
+    }
+    fn random(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(random_page(|| get_note_titles().unwrap_or_default()))
+            .boxed()
+    }
+    fn feed(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(recent_changes_feed(self.user.clone(), self.host.clone()))
+            .boxed()
+    }
     fn upload(&self) -> BoxedFilter<(impl Reply,)> {
         warp::get()
             .and(with_auth())
@@ -151,3 +303,149 @@ impl StaticPageRouter {
             .boxed()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use task_runners::cache::update_mru_cache;
+    use warp::{http::header, hyper::StatusCode};
+    use wikitext::parsers::Note;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn redirects_to_the_home_note() {
+        let index = warp::any().map(|| "index");
+        let routes = home_redirect(Arc::new(String::from("My Dashboard"))).or(index);
+        let response = warp::test::request().path("/").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(response.headers()[header::LOCATION], "/My%20Dashboard");
+
+        let routes = home_redirect(Arc::new(String::new())).or(index);
+        let response = warp::test::request().path("/").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "index");
+    }
+
+    #[tokio::test]
+    async fn redirects_to_a_random_page() {
+        let titles = || {
+            ["Beans", "Salt & Pepper", "Projects/Compost"]
+                .map(String::from)
+                .to_vec()
+        };
+        let mut seen = Vec::new();
+        for _ in 0..50 {
+            let response = warp::test::request()
+                .path("/random")
+                .reply(&random_page(titles))
+                .await;
+            assert_eq!(response.status(), StatusCode::FOUND);
+            let location = response.headers()[header::LOCATION].to_str().unwrap();
+            assert!(
+                ["/Beans", "/Salt%20%26%20Pepper", "/Projects/Compost"].contains(&location),
+                "{}",
+                location
+            );
+            if !seen.contains(&location.to_string()) {
+                seen.push(location.to_string());
+            }
+        }
+        assert!(seen.len() > 1);
+
+        let response = warp::test::request()
+            .path("/random")
+            .reply(&random_page(Vec::new))
+            .await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers()[header::LOCATION], "/new");
+    }
+
+    #[tokio::test]
+    // The feed and the recent changes page share the recently changed list, so they're tested
+    // together rather than racing each other for it.
+    async fn lists_recent_changes() {
+        let (dir, _wiki) = crate::handlers::init_test_wiki().await;
+        std::fs::write(format!("{}note_cache", dir), "").unwrap();
+        for (title, updated) in [
+            ("Feed Beans", "2022-05-01T08:00:00+00:00"),
+            ("Feed Salt & Pepper", "2022-05-02T08:00:00+00:00"),
+            ("Feed Compost", "2022-05-03T08:00:00+00:00"),
+        ] {
+            let note = Note {
+                header: HashMap::from([
+                    (String::from("title"), String::from(title)),
+                    (String::from("updated"), String::from(updated)),
+                ]),
+                content: format!("All about {}", title),
+            };
+            let note: String = note.into();
+            std::fs::write(format!("{}{}.txt", dir, title), note).unwrap();
+        }
+        for title in [
+            "Feed Beans",
+            "Feed Salt & Pepper",
+            "Feed Beans",
+            "Feed Compost",
+            "Feed Deleted",
+        ] {
+            update_mru_cache(title, title).await;
+        }
+
+        let feed = recent_changes_feed(
+            Arc::new(String::from("tendril")),
+            Arc::new(String::from("http://localhost:6683")),
+        );
+        let response = warp::test::request().path("/feed.xml").reply(&feed).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/atom+xml"
+        );
+        let xml = String::from_utf8(response.body().to_vec()).unwrap();
+        assert_eq!(xml.matches("<entry>").count(), 3);
+        let titles = xml
+            .match_indices("<entry><title>")
+            .map(|(start, _)| &xml[start + 14..start + 14 + xml[start + 14..].find('<').unwrap()])
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            titles,
+            vec!["Feed Compost", "Feed Beans", "Feed Salt &amp; Pepper"]
+        );
+        assert!(xml.contains("<updated>2022-05-03T08:00:00+00:00</updated>"));
+        assert!(xml.contains(r#"<link href="http://localhost:6683/Feed%20Compost"/>"#));
+
+        let response = warp::test::request()
+            .path("/recent")
+            .reply(&recent_changes_page())
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let html = String::from_utf8(response.body().to_vec()).unwrap();
+        let list = &html[html.find(r#"<ul class="recent-changes">"#).unwrap()..];
+        let list = &list[..list.find("</ul>").unwrap()];
+        let titles = list
+            .match_indices("<li><a href=")
+            .map(|(start, _)| {
+                let entry = &list[start + 4..];
+                let title = &entry[entry.find('>').unwrap() + 1..];
+                &title[..title.find('<').unwrap()]
+            })
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            titles,
+            vec!["Feed Compost", "Feed Beans", "Feed Salt &amp; Pepper"]
+        );
+        assert!(list.contains(r#"<time datetime="2022-05-03T08:00:00+00:00">"#));
+
+        let response = warp::test::request()
+            .path("/recent?limit=2")
+            .reply(&recent_changes_page())
+            .await;
+        let html = String::from_utf8(response.body().to_vec()).unwrap();
+        let list = &html[html.find(r#"<ul class="recent-changes">"#).unwrap()..];
+        let list = &list[..list.find("</ul>").unwrap()];
+        assert_eq!(list.matches("<li>").count(), 2);
+
+        let params = HashMap::from([(String::from("limit"), String::from("50"))]);
+        assert_eq!(recent_changes_limit(&params), NOTE_CACHE_SIZE);
+    }
+}
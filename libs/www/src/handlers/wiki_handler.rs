@@ -1,14 +1,26 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Display, path::PathBuf};
 
+use build::rename_in_global_store;
+use persistance::fs::ReadPageError;
 use task_runners::{runners::wiki_runner::WikiRunner, QueueHandle};
-use urlencoding::decode;
-use warp::{filters::BoxedFilter, hyper::Uri, Filter, Reply};
-use wikitext::{GlobalBacklinks, PatchData};
+use urlencoding::{decode, encode};
+use warp::{
+    filters::{path::Tail, BoxedFilter},
+    http::{header, HeaderValue, StatusCode},
+    hyper::Uri,
+    reply::{html, Response},
+    Filter, Reply,
+};
+use wikitext::{
+    normalize_title,
+    parsers::{with_base_path, Note},
+    GlobalBacklinks, PatchData,
+};
 
 use crate::RefHubParts;
 
 use super::{
-    filters::{reply_on_result, with_auth, with_links, with_queue},
+    filters::{accepted, reply_on_result, with_auth, with_edit_access, with_links, with_queue},
     MAX_BODY_SIZE,
 };
 
@@ -21,7 +33,9 @@ impl WikiPageRouter {
         Self { parts }
     }
     pub fn routes(&self) -> BoxedFilter<(impl Reply,)> {
-        self.get_nested()
+        self.get_archive()
+            .or(self.confirm_delete())
+            .or(self.get_nested())
             .or(self.delete())
             .or(self.edit())
             .or(self.quick_add())
@@ -33,43 +47,18 @@ impl WikiPageRouter {
     fn get(&self) -> BoxedFilter<(impl Reply,)> {
         let (links, _) = &self.parts;
         warp::get()
-            .and(with_auth())
-            .and(warp::path::param())
-            .and(with_links(links.clone()))
-            .and(warp::query::<HashMap<String, String>>())
-            .then(
-                |path: String,
-                 reflinks: GlobalBacklinks,
-                 query_params: HashMap<String, String>| async move {
-                    let links = reflinks.lock().await;
-                    let path = decode(&path).unwrap();
-                    let path = path.to_string();
-                    let links = links.get(&path);
-                    let runner = WikiRunner {};
-                    let response = runner.render_file(path, links, query_params).await;
-                    warp::reply::html(response)
-                },
-            )
+            .and(note_page(links.clone(), with_edit_access().boxed()))
             .boxed()
     }
 
+    fn get_archive(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get().and(with_auth()).and(archive_page()).boxed()
+    }
+
     fn get_nested(&self) -> BoxedFilter<(impl Reply,)> {
         let (links, _) = &self.parts;
         warp::get()
-            .and(with_auth())
-            .and(warp::path!(String / String))
-            .and(with_links(links.to_owned()))
-            .then(
-                |main_path: String, sub_path: String, reflinks: GlobalBacklinks| async move {
-                    let runner = WikiRunner {};
-                    let main_path = decode(&main_path).unwrap().to_string();
-                    let sub_path = decode(&sub_path).unwrap().to_string();
-                    let links = reflinks.lock().await;
-                    let links = links.get(&*sub_path);
-                    let response = runner.render_nested_file(main_path, sub_path, links).await;
-                    warp::reply::html(response.unwrap())
-                },
-            )
+            .and(nested_page(links.to_owned(), with_edit_access().boxed()))
             .boxed()
     }
 
@@ -83,14 +72,28 @@ impl WikiPageRouter {
             .and(warp::body::form())
             .then(
                 |queue: QueueHandle, form_body: HashMap<String, String>| async {
-                    let response = WikiRunner::delete(queue, form_body).await;
-                    let response = response.parse::<Uri>().unwrap();
-                    warp::redirect(response)
+                    match WikiRunner::delete(queue, form_body).await {
+                        Ok(response) => {
+                            warp::redirect(response.parse::<Uri>().unwrap()).into_response()
+                        }
+                        Err(rejected) => warp::reply::with_status(
+                            html(WikiRunner::render_delete_rejected(rejected).await),
+                            StatusCode::FORBIDDEN,
+                        )
+                        .into_response(),
+                    }
                 },
             )
             .boxed()
     }
 
+    fn confirm_delete(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(delete_confirmation())
+            .boxed()
+    }
+
     fn new_page(&self) -> BoxedFilter<(impl Reply,)> {
         warp::get()
             .and(with_auth())
@@ -106,19 +109,10 @@ impl WikiPageRouter {
     }
 
     fn edit(&self) -> BoxedFilter<(impl Reply,)> {
-        let (_, queue) = &self.parts;
+        let (links, queue) = &self.parts;
         warp::post()
             .and(with_auth())
-            .and(
-                warp::path("edit").and(
-                    warp::body::content_length_limit(MAX_BODY_SIZE)
-                        .and(warp::body::json())
-                        .and(with_queue(queue.to_owned()))
-                        .then(|body: PatchData, queue: QueueHandle| async {
-                            reply_on_result(WikiRunner::edit(body, queue).await)
-                        }),
-                ),
-            )
+            .and(edit_note(links.to_owned(), queue.to_owned()))
             .boxed()
     }
 
@@ -139,3 +133,504 @@ impl WikiPageRouter {
             .boxed()
     }
 }
+
+/// `/edit`, saves a note. With `?dry_run=true` nothing is saved or queued, the reply lists the notes
+/// that would be relinked instead.
+fn edit_note(links: GlobalBacklinks, queue: QueueHandle) -> BoxedFilter<(impl Reply,)> {
+    warp::path("edit")
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_links(links))
+        .and(with_queue(queue))
+        .then(
+            |body: PatchData,
+             query_params: HashMap<String, String>,
+             links: GlobalBacklinks,
+             queue: QueueHandle| async move {
+                let mut saved = body.clone();
+                saved.title = normalize_title(&saved.title);
+                if query_params
+                    .get("dry_run")
+                    .map_or(false, |dry_run| dry_run == "true")
+                {
+                    return warp::reply::json(&relinked_by(&saved, links).await).into_response();
+                }
+                edit_reply(WikiRunner::edit(body, queue).await, &saved.url())
+            },
+        )
+        .boxed()
+}
+
+/// The notes saving `patch` would rewrite to link to its new title, when it renames a note.
+async fn relinked_by(patch: &PatchData, links: GlobalBacklinks) -> Vec<PathBuf> {
+    if patch.old_title.is_empty() || patch.old_title == patch.title {
+        return Vec::new();
+    }
+    rename_in_global_store(&patch.title, &patch.old_title, links, true).await
+}
+
+/// `/<title>`, a note rendered as a page, or as Markdown or JSON when asked for. `access` says
+/// whether the visitor can edit it.
+fn note_page(links: GlobalBacklinks, access: BoxedFilter<(bool,)>) -> BoxedFilter<(impl Reply,)> {
+    access
+        .and(warp::path::param())
+        .and(with_links(links))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("accept"))
+        .then(
+            |editable: bool,
+             path: String,
+             reflinks: GlobalBacklinks,
+             query_params: HashMap<String, String>,
+             accept: Option<String>| async move {
+                let path = decode(&path).unwrap();
+                let path = path.to_string();
+                let runner = WikiRunner {};
+                // `?redirect=no` shows the redirect page itself, so it can be edited.
+                if query_params.get("redirect").map_or(true, |r| r != "no") {
+                    match runner.follow_redirects(&path).await {
+                        Ok(Some(target)) => {
+                            let location = with_base_path(&format!("/{}", encode(&target)));
+                            return warp::redirect::temporary(location.parse::<Uri>().unwrap())
+                                .into_response();
+                        }
+                        Err(redirect_loop) => {
+                            return html(runner.render_redirect_loop(redirect_loop).await)
+                                .into_response()
+                        }
+                        Ok(None) => {}
+                    }
+                }
+                let format = PageFormat::negotiate(accept.as_deref(), &query_params);
+                if format != PageFormat::Html {
+                    return format.represent_note(&path).await;
+                }
+                let links = reflinks.lock().await;
+                let links = links.get(&normalize_title(&path));
+                let response = runner
+                    .render_file(path, links, query_params, editable)
+                    .await;
+                PageFormat::vary(html(response).into_response())
+            },
+        )
+        .boxed()
+}
+
+/// `/archive/<title>`, the archived copy of the page a bookmark points to. It's under its own
+/// prefix so it can't be mistaken for a nested note.
+fn archive_page() -> BoxedFilter<(impl Reply,)> {
+    warp::path!("archive" / String)
+        .then(|title: String| async move {
+            let runner = WikiRunner {};
+            match decode(&title) {
+                Ok(title) => html(runner.render_archive(title.into_owned()).await).into_response(),
+                Err(_) => StatusCode::BAD_REQUEST.into_response(),
+            }
+        })
+        .boxed()
+}
+
+/// `/delete/<title>`, the page asking whether to delete a note. The title is the rest of the path,
+/// so notes in subdirectories can be deleted too.
+fn delete_confirmation() -> BoxedFilter<(impl Reply,)> {
+    warp::path("delete")
+        .and(warp::path::tail())
+        .then(|tail: Tail| async move {
+            match decode(tail.as_str()) {
+                Ok(title) if !title.is_empty() => {
+                    html(WikiRunner::render_delete(title.into_owned()).await).into_response()
+                }
+                _ => StatusCode::BAD_REQUEST.into_response(),
+            }
+        })
+        .boxed()
+}
+
+/// `/<dir>/<title>`, a note in a subdirectory of the wiki, negotiated like `/<title>`.
+fn nested_page(links: GlobalBacklinks, access: BoxedFilter<(bool,)>) -> BoxedFilter<(impl Reply,)> {
+    access
+        .and(warp::path!(String / String))
+        .and(with_links(links))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("accept"))
+        .then(
+            |editable: bool,
+             main_path: String,
+             sub_path: String,
+             reflinks: GlobalBacklinks,
+             query_params: HashMap<String, String>,
+             accept: Option<String>| async move {
+                let runner = WikiRunner {};
+                let main_path = decode(&main_path).unwrap().to_string();
+                let sub_path = decode(&sub_path).unwrap().to_string();
+                let format = PageFormat::negotiate(accept.as_deref(), &query_params);
+                if format != PageFormat::Html {
+                    return format
+                        .represent_note(&format!("{}/{}", main_path, sub_path))
+                        .await;
+                }
+                let links = reflinks.lock().await;
+                let links = links.get(&*sub_path);
+                let response = runner
+                    .render_nested_file(main_path, sub_path, links, editable)
+                    .await;
+                PageFormat::vary(html(response.unwrap()).into_response())
+            },
+        )
+        .boxed()
+}
+
+/// How a page is sent back: rendered, as the raw note body, or as the note's JSON like
+/// `/api/<note>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PageFormat {
+    Html,
+    Markdown,
+    Json,
+}
+
+impl PageFormat {
+    /// `?format=` wins over the `Accept` header, where the media type with the highest `q` weight
+    /// is picked, or the first listed between equal ones. Anything unrecognized gets the rendered
+    /// page.
+    fn negotiate(accept: Option<&str>, query_params: &HashMap<String, String>) -> Self {
+        if let Some(format) = query_params.get("format") {
+            return match format.as_str() {
+                "md" | "markdown" => PageFormat::Markdown,
+                "json" => PageFormat::Json,
+                _ => PageFormat::Html,
+            };
+        }
+        let mut preferred: Option<(PageFormat, f32)> = None;
+        for (media_type, q) in accepted(accept.unwrap_or_default()) {
+            let format = match media_type {
+                "text/html" => PageFormat::Html,
+                "text/markdown" => PageFormat::Markdown,
+                "application/json" => PageFormat::Json,
+                _ => continue,
+            };
+            if preferred.map_or(true, |(_, preferred_q)| q > preferred_q) {
+                preferred = Some((format, q));
+            }
+        }
+        preferred.map_or(PageFormat::Html, |(format, _)| format)
+    }
+
+    /// The note at `path` as Markdown or JSON.
+    async fn represent_note(self, path: &str) -> Response {
+        let runner = WikiRunner {};
+        let reply = match runner.read_note(path).await {
+            Ok(note) => self.represent(&note),
+            Err(ReadPageError::PageNotFoundError) => StatusCode::NOT_FOUND.into_response(),
+            Err(e) => {
+                eprintln!("Could not read {}: {}", path, e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        };
+        PageFormat::vary(reply)
+    }
+
+    /// Marks a reply as depending on the `Accept` header, so caches keep each format apart.
+    fn vary(mut reply: Response) -> Response {
+        reply
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("Accept"));
+        reply
+    }
+
+    fn represent(self, note: &Note) -> Response {
+        let (content_type, body) = match self {
+            PageFormat::Markdown => ("text/markdown; charset=utf-8", note.content.clone()),
+            PageFormat::Json => ("application/json", serde_json::to_string(note).unwrap()),
+            PageFormat::Html => ("text/html; charset=utf-8", note.to_template().page.body),
+        };
+        let mut reply = body.into_response();
+        reply
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        reply
+    }
+}
+
+/// Acknowledges a save, with `Content-Location` pointing at the saved page so the editor can keep
+/// the directory of nested notes in its address.
+fn edit_reply<E>(result: Result<(), E>, url: &str) -> Response
+where
+    E: Display + std::fmt::Debug,
+{
+    let saved = result.is_ok();
+    let mut reply = reply_on_result(result).into_response();
+    if let (true, Ok(location)) = (saved, HeaderValue::from_str(url)) {
+        reply
+            .headers_mut()
+            .insert(header::CONTENT_LOCATION, location);
+    }
+    reply
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use task_runners::{JobQueue, Queue};
+    use tokio::sync::Mutex;
+    use warp::http::StatusCode;
+    use wikitext::Backlink;
+
+    use super::*;
+
+    fn writer() -> BoxedFilter<(bool,)> {
+        warp::any().map(|| true).boxed()
+    }
+
+    fn reader() -> BoxedFilter<(bool,)> {
+        warp::any().map(|| false).boxed()
+    }
+
+    #[tokio::test]
+    async fn shows_edit_controls_only_to_writers() {
+        let (dir, _wiki) = crate::handlers::init_test_wiki().await;
+        let note = Note {
+            header: HashMap::from([(String::from("title"), String::from("Runner beans"))]),
+            content: String::from("Sow after the last frost"),
+        };
+        std::fs::write(format!("{}Runner beans.txt", dir), String::from(note)).unwrap();
+        let links: GlobalBacklinks = Arc::new(Mutex::new(BTreeMap::new()));
+
+        let response = warp::test::request()
+            .path("/Runner%20beans")
+            .reply(&note_page(links.clone(), writer()))
+            .await;
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.contains("Sow after the last frost"));
+        assert!(body.contains("static/editor.js"));
+        assert!(body.contains("delete-form"));
+
+        let response = warp::test::request()
+            .path("/Runner%20beans")
+            .reply(&note_page(links.clone(), reader()))
+            .await;
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.contains("Sow after the last frost"));
+        assert!(!body.contains("static/editor.js"));
+        assert!(!body.contains("delete-form"));
+
+        let response = warp::test::request()
+            .path("/No%20such%20beans")
+            .reply(&note_page(links, reader()))
+            .await;
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.contains("There's no note called No such beans."));
+        assert!(!body.contains("static/editor.js"));
+    }
+
+    #[tokio::test]
+    async fn serves_nested_notes_named_archive() {
+        let (dir, _wiki) = crate::handlers::init_test_wiki().await;
+        std::fs::create_dir_all(format!("{}Projects", dir)).unwrap();
+        let note = Note {
+            header: HashMap::from([(String::from("title"), String::from("archive"))]),
+            content: String::from("Finished projects go here"),
+        };
+        std::fs::write(format!("{}Projects/archive.txt", dir), String::from(note)).unwrap();
+        let links: GlobalBacklinks = Arc::new(Mutex::new(BTreeMap::new()));
+        let routes = archive_page().or(nested_page(links, writer()));
+
+        let response = warp::test::request()
+            .path("/Projects/archive")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.contains("Finished projects go here"));
+        assert!(!body.contains("(archived)"));
+
+        let response = warp::test::request()
+            .path("/Projects/archive")
+            .header("accept", "text/html;q=0, text/markdown")
+            .reply(&routes)
+            .await;
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/markdown; charset=utf-8"
+        );
+        assert_eq!(response.headers()[header::VARY], "Accept");
+        assert_eq!(response.body(), "Finished projects go here");
+
+        let response = warp::test::request()
+            .path("/archive/Projects")
+            .reply(&routes)
+            .await;
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.contains("There's no archived copy of this page."));
+
+        let response = warp::test::request()
+            .path("/archive/%FF")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn confirms_deleting_nested_notes() {
+        let (_, _wiki) = crate::handlers::init_test_wiki().await;
+        let response = warp::test::request()
+            .path("/delete/Projects/Runner%20beans")
+            .reply(&delete_confirmation())
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.contains(r#"value="Projects/Runner beans""#));
+
+        let response = warp::test::request()
+            .path("/delete/%FF%FE")
+            .reply(&delete_confirmation())
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = warp::test::request()
+            .path("/delete/")
+            .reply(&delete_confirmation())
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn acknowledges_saves() {
+        let reply = edit_reply::<String>(Ok(()), "/note");
+        assert_eq!(reply.status(), StatusCode::OK);
+        assert_eq!(
+            reply.headers().get(header::CONTENT_LOCATION).unwrap(),
+            "/note"
+        );
+        let body = warp::hyper::body::to_bytes(reply.into_body())
+            .await
+            .unwrap();
+        assert_eq!(body, "OK");
+    }
+
+    #[tokio::test]
+    async fn negotiates_page_formats() {
+        let none = HashMap::new();
+        let browser = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+        assert_eq!(
+            PageFormat::negotiate(Some(browser), &none),
+            PageFormat::Html
+        );
+        assert_eq!(PageFormat::negotiate(None, &none), PageFormat::Html);
+        assert_eq!(
+            PageFormat::negotiate(Some("text/markdown"), &none),
+            PageFormat::Markdown
+        );
+        assert_eq!(
+            PageFormat::negotiate(Some("application/json; charset=utf-8, text/html"), &none),
+            PageFormat::Json
+        );
+        assert_eq!(
+            PageFormat::negotiate(Some("text/html;q=0, text/markdown"), &none),
+            PageFormat::Markdown
+        );
+        assert_eq!(
+            PageFormat::negotiate(Some("text/markdown;q=0.5, application/json"), &none),
+            PageFormat::Json
+        );
+        assert_eq!(
+            PageFormat::negotiate(Some("text/html;q=0"), &none),
+            PageFormat::Html
+        );
+        let params = HashMap::from([(String::from("format"), String::from("md"))]);
+        assert_eq!(
+            PageFormat::negotiate(Some("application/json"), &params),
+            PageFormat::Markdown
+        );
+
+        let note = Note {
+            header: HashMap::from([(String::from("title"), String::from("garden"))]),
+            content: String::from("Plant **tomatoes**"),
+        };
+        let markdown = PageFormat::Markdown.represent(&note);
+        assert_eq!(
+            markdown.headers()[header::CONTENT_TYPE],
+            "text/markdown; charset=utf-8"
+        );
+        let body = warp::hyper::body::to_bytes(markdown.into_body())
+            .await
+            .unwrap();
+        assert_eq!(body, "Plant **tomatoes**");
+
+        let json = PageFormat::Json.represent(&note);
+        assert_eq!(json.headers()[header::CONTENT_TYPE], "application/json");
+        let body = warp::hyper::body::to_bytes(json.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["header"]["title"], "garden");
+        assert_eq!(body["content"], "Plant **tomatoes**");
+    }
+
+    #[tokio::test]
+    async fn previews_renames_without_saving() {
+        let (dir, _wiki) = crate::handlers::init_test_wiki().await;
+        let linking = format!("{}Dry run linker.txt", dir);
+        let note = Note {
+            header: HashMap::from([(String::from("title"), String::from("Dry run linker"))]),
+            content: String::from("Water [[Dry run target]] daily"),
+        };
+        std::fs::write(&linking, String::from(note)).unwrap();
+        let before = std::fs::read_to_string(&linking).unwrap();
+        let links: GlobalBacklinks = Arc::new(Mutex::new(BTreeMap::from([(
+            String::from("Dry run target"),
+            vec![Backlink::from("Dry run linker")],
+        )])));
+        let queue = Arc::new(JobQueue::default());
+        let patch = PatchData::from(HashMap::from([
+            (String::from("title"), String::from("Dry run renamed")),
+            (String::from("old_title"), String::from("Dry run target")),
+            (String::from("body"), String::from("Keep it moist")),
+        ]));
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/edit?dry_run=true")
+            .json(&patch)
+            .reply(&edit_note(links.clone(), queue.clone()))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let relinked: Vec<String> = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(relinked, vec![linking.clone()]);
+        assert_eq!(std::fs::read_to_string(&linking).unwrap(), before);
+        assert!(queue.pull(10).await.unwrap().is_empty());
+        assert!(links.lock().await.contains_key("Dry run target"));
+
+        let unrenamed = PatchData::from(HashMap::from([
+            (String::from("title"), String::from("Dry run target")),
+            (String::from("old_title"), String::from("Dry run target")),
+        ]));
+        let response = warp::test::request()
+            .method("POST")
+            .path("/edit?dry_run=true")
+            .json(&unrenamed)
+            .reply(&edit_note(links, queue.clone()))
+            .await;
+        assert_eq!(response.body(), "[]");
+        assert!(queue.pull(10).await.unwrap().is_empty());
+        std::fs::remove_file(linking).unwrap();
+    }
+
+    #[test]
+    fn points_nested_notes_back_to_their_directory() {
+        let patch = PatchData::from(HashMap::from([
+            (String::from("title"), String::from("garden")),
+            (String::from("old_title"), String::from("garden")),
+            (String::from("dir"), String::from("projects")),
+        ]));
+        let reply = edit_reply::<String>(Ok(()), &patch.url());
+        assert_eq!(
+            reply.headers().get(header::CONTENT_LOCATION).unwrap(),
+            "/projects/garden"
+        );
+
+        let reply = edit_reply(Err("could not write"), &patch.url());
+        assert_eq!(reply.status(), StatusCode::BAD_REQUEST);
+        assert!(reply.headers().get(header::CONTENT_LOCATION).is_none());
+    }
+}
@@ -1,13 +1,14 @@
 #[cfg(not(debug_assertions))]
-use ::persistance::fs::utils::get_data_dir_location;
+use ::persistance::fs::utils::get_static_location;
 
 use persistance::fs::{config::General, utils::normalize_wiki_location};
 use std::{path::PathBuf, sync::Arc};
 use task_runners::JobQueue;
 use warp::Filter;
-use wikitext::GlobalBacklinks;
+use wikitext::{parsers::base_path, GlobalBacklinks};
 
 pub mod handlers;
+mod logging;
 pub mod services;
 
 use crate::handlers::*;
@@ -21,37 +22,68 @@ pub async fn server(config: General, parts: RefHubParts) {
         Arc::new(config.user),
         media_location.clone(),
         Arc::new(config.host),
+        Arc::new(config.home_note),
         cloned.0,
     );
     let wiki_router = WikiPageRouter::new(parts.clone());
 
     let task_router = TaskPageRouter::new();
     let static_files_router = StaticFileRouter::new(media_location.clone());
-    let api_router = APIRouter::new();
+    let api_router = APIRouter::new(parts.clone());
     let bookmark_router = bookmark_handler::BookmarkPageRouter::new(parts.1.clone());
-    pretty_env_logger::init();
+    logging::init(config.log_format);
     // Order matters!!
-    let log = warp::log("toplevel");
+    let log = logging::request_logger(config.log_format);
+    // Uploaded media is mostly compressed already, so only pages and API replies are compressed
+    let pages = static_page_router
+        .routes()
+        .or(bookmark_router.routes())
+        .or(api_router.routes())
+        .or(task_router.routes())
+        .or(wiki_router.routes())
+        .or(static_page_router.index())
+        .recover(handle_rejection)
+        .boxed();
     let routes = warp::any()
         .and(
-            static_files_router
-                .routes()
-                .or(static_page_router.routes())
-                .or(bookmark_router.routes())
-                .or(api_router.routes())
-                .or(task_router.routes())
-                .or(wiki_router.routes())
-                .or(static_page_router.index())
-                .recover(handle_rejection)
+            without_trailing_slash(config.redirect_trailing_slash, base_path())
+                .or(static_files_router.routes())
+                .or(with_compression(pages, config.compression))
                 .boxed(),
         )
         .with(log)
         .boxed();
-    let port: u16 = config.port;
+    let address = match config.socket_address() {
+        Ok(address) => address,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // Serve HTTPS when both a certificate and a key are configured and plain HTTP when neither is.
+    // Only one of them is a mistake, which stops the server instead of leaving it on HTTP.
+    let tls = match config.tls() {
+        Ok(tls) => tls,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let scheme = if tls.is_some() { "https" } else { "http" };
     println!("┌──────────────────────────────────────────────┐");
-    println!("│Starting web backend @ http://127.0.0.1:{}  │", port);
+    println!("│Starting web backend @ {}://{}  │", scheme, address);
     println!("└──────────────────────────────────────────────┘");
-    warp::serve(routes).run(([0, 0, 0, 0], port)).await;
+    match tls {
+        Some((cert, key)) => {
+            warp::serve(routes)
+                .tls()
+                .cert_path(cert)
+                .key_path(key)
+                .run(address)
+                .await
+        }
+        None => warp::serve(routes).run(address).await,
+    }
 }
 
 #[cfg(debug_assertions)]
@@ -61,6 +93,5 @@ fn get_static_dir() -> PathBuf {
 
 #[cfg(not(debug_assertions))]
 fn get_static_dir() -> PathBuf {
-    let data_dir = get_data_dir_location();
-    data_dir.join("static")
+    get_static_location()
 }
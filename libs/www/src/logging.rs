@@ -0,0 +1,227 @@
+use std::env;
+
+use chrono::Utc;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use persistance::fs::config::LogFormat;
+use serde_json::{Map, Value};
+use warp::log::Info;
+
+const REQUEST_TARGET: &str = "toplevel";
+
+/// Writes each record to stderr as a single JSON object. Only plain levels like `info` are read
+/// from `RUST_LOG`.
+struct JsonLogger {
+    level: LevelFilter,
+}
+
+impl JsonLogger {
+    fn from_env() -> Self {
+        let level = env::var("RUST_LOG")
+            .ok()
+            .and_then(|level| level.parse().ok())
+            .unwrap_or(LevelFilter::Error);
+        JsonLogger { level }
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "{}",
+                json_line(
+                    record.level(),
+                    record.target(),
+                    &record.args().to_string(),
+                    Vec::new()
+                )
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+pub(crate) fn init(format: LogFormat) {
+    match format {
+        LogFormat::Pretty => pretty_env_logger::init(),
+        LogFormat::Json => {
+            let logger = JsonLogger::from_env();
+            log::set_max_level(logger.level);
+            log::set_boxed_logger(Box::new(logger)).expect("logger is only set once");
+        }
+    }
+}
+
+fn json_line(level: Level, target: &str, message: &str, fields: Vec<(&str, Value)>) -> String {
+    let mut line = Map::new();
+    line.insert("timestamp".into(), Utc::now().to_rfc3339().into());
+    line.insert("level".into(), level.to_string().into());
+    line.insert("target".into(), target.into());
+    line.insert("message".into(), message.into());
+    for (key, value) in fields {
+        line.insert(key.into(), value);
+    }
+    Value::Object(line).to_string()
+}
+
+/// Request logs carry the path, method, and status as their own fields in JSON.
+pub(crate) fn request_logger(format: LogFormat) -> warp::log::Log<impl Fn(Info) + Copy> {
+    warp::log::custom(move |info| log_request(format, info))
+}
+
+fn log_request(format: LogFormat, info: Info) {
+    if !log::log_enabled!(target: REQUEST_TARGET, Level::Info) {
+        return;
+    }
+    let remote_addr = info
+        .remote_addr()
+        .map_or_else(|| String::from("-"), |addr| addr.to_string());
+    match format {
+        LogFormat::Pretty => log::info!(
+            target: REQUEST_TARGET,
+            "{} \"{} {} {:?}\" {} \"{}\" \"{}\" {:?}",
+            remote_addr,
+            info.method(),
+            info.path(),
+            info.version(),
+            info.status().as_u16(),
+            info.referer().unwrap_or("-"),
+            info.user_agent().unwrap_or("-"),
+            info.elapsed(),
+        ),
+        LogFormat::Json => eprintln!(
+            "{}",
+            json_line(
+                Level::Info,
+                REQUEST_TARGET,
+                &format!("{} {} {}", info.method(), info.path(), info.status()),
+                request_fields(
+                    info.method().as_str(),
+                    info.path(),
+                    info.status().as_u16(),
+                    info.elapsed().as_millis(),
+                    &remote_addr,
+                ),
+            )
+        ),
+    }
+}
+
+fn request_fields<'a>(
+    method: &str,
+    path: &str,
+    status: u16,
+    elapsed_ms: u128,
+    remote_addr: &str,
+) -> Vec<(&'a str, Value)> {
+    let mut fields = vec![
+        ("method", method.into()),
+        ("path", path.into()),
+        ("status", status.into()),
+        ("elapsed_ms", (elapsed_ms as u64).into()),
+        ("remote_addr", remote_addr.into()),
+    ];
+    if let Some(title) = note_title(path) {
+        fields.push(("note", title.into()));
+    }
+    fields
+}
+
+/// Top level routes that aren't wiki pages.
+const ROUTES: [&str; 30] = [
+    "all_pages",
+    "api",
+    "archive",
+    "config",
+    "delete",
+    "edit",
+    "error",
+    "feed.xml",
+    "files",
+    "help",
+    "journal",
+    "login",
+    "logout",
+    "metrics",
+    "new",
+    "new_bookmark",
+    "opensearchdescription.xml",
+    "quick-add",
+    "random",
+    "readyz",
+    "recent",
+    "search",
+    "static",
+    "styles",
+    "tags",
+    "tasks",
+    "titles",
+    "update",
+    "upload",
+    "version",
+];
+
+/// The note a request is for, when its path looks like a wiki page rather than an asset or
+/// another route.
+fn note_title(path: &str) -> Option<String> {
+    let mut segments = path.trim_start_matches('/').split('/');
+    let first = segments.next()?;
+    let page = segments.last().unwrap_or(first);
+    if first.is_empty() || ROUTES.contains(&first) || page.contains('.') {
+        return None;
+    }
+    urlencoding::decode(page)
+        .ok()
+        .map(|title| title.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_log_lines_as_json() {
+        let line = json_line(
+            Level::Info,
+            REQUEST_TARGET,
+            "GET /Garden%20Notes 200 OK",
+            request_fields("GET", "/Garden%20Notes", 200, 12, "127.0.0.1:4000"),
+        );
+        let parsed: Value = serde_json::from_str(&line).unwrap();
+        assert!(parsed["timestamp"].is_string());
+        assert_eq!(parsed["level"], "INFO");
+        assert_eq!(parsed["target"], "toplevel");
+        assert_eq!(parsed["message"], "GET /Garden%20Notes 200 OK");
+        assert_eq!(parsed["path"], "/Garden%20Notes");
+        assert_eq!(parsed["status"], 200);
+        assert_eq!(parsed["note"], "Garden Notes");
+
+        let asset = request_fields("GET", "/static/style.css", 200, 1, "-");
+        assert!(asset.iter().all(|(key, _)| *key != "note"));
+    }
+
+    #[test]
+    fn leaves_other_routes_out_of_note_titles() {
+        for path in [
+            "/archive/Garden",
+            "/feed.xml",
+            "/journal",
+            "/random",
+            "/readyz",
+            "/recent",
+            "/delete/Projects/Garden",
+            "/api/Garden",
+        ] {
+            assert_eq!(note_title(path), None, "{}", path);
+        }
+        assert_eq!(
+            note_title("/Projects/Garden%20Notes").as_deref(),
+            Some("Garden Notes")
+        );
+    }
+}
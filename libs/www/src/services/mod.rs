@@ -1,23 +1,39 @@
 use chrono::prelude::*;
 use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
 
-use persistance::fs::config::read_config;
+use persistance::fs::config::{read_config, SameSite, Session};
 use task_runners::verify_password;
 
 use crate::handlers::filters::{AuthError, Claims};
 
-pub const MONTH: usize = 2629800;
-
 pub fn create_jwt(username: &str, password: &str) -> Result<String, AuthError> {
     let config = read_config();
+    issue_jwt(
+        username,
+        password,
+        &config.general.user,
+        &config.general.pass,
+        config.session.max_age,
+    )
+}
 
-    if username != config.general.user {
+/// A session token good for `max_age` seconds, as long as `username` and `password` match the
+/// configured `user` and `pass_hash`. The hash doubles as the signing secret, so changing the
+/// password logs everyone out.
+pub fn issue_jwt(
+    username: &str,
+    password: &str,
+    user: &str,
+    pass_hash: &str,
+    max_age: u64,
+) -> Result<String, AuthError> {
+    if username != user {
         return Err(AuthError::BadCredentials);
     }
-    match verify_password(password.into(), config.general.pass.clone()) {
+    match verify_password(password.into(), pass_hash.into()) {
         Ok(()) => {
             let expiration = Utc::now()
-                .checked_add_signed(chrono::Duration::seconds(MONTH as i64))
+                .checked_add_signed(chrono::Duration::seconds(max_age as i64))
                 .expect("valid timestamp")
                 .timestamp();
             let claims = Claims {
@@ -25,15 +41,65 @@ pub fn create_jwt(username: &str, password: &str) -> Result<String, AuthError> {
                 exp: expiration as usize,
             };
             let header = Header::new(Algorithm::HS512);
-            let encode = encode(
+            encode(
                 &header,
                 &claims,
-                &EncodingKey::from_secret(config.general.pass.as_bytes()),
+                &EncodingKey::from_secret(pass_hash.as_bytes()),
             )
             .map_err(|_| AuthError::JWTTokenCreationError)
-            .unwrap();
-            Ok(encode)
         }
         Err(_) => Err(AuthError::BadCredentials),
     }
 }
+
+/// A `Set-Cookie` value carrying the configured session attributes. A `max_age` of 0 clears the
+/// cookie.
+pub fn session_cookie(
+    name: &str,
+    value: &str,
+    http_only: bool,
+    max_age: u64,
+    session: &Session,
+    path: &str,
+) -> String {
+    let mut cookie = format!("{}={}; Max-Age={}; Path={}", name, value, max_age, path);
+    if session.secure {
+        cookie.push_str("; Secure");
+    }
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    let same_site = match session.same_site {
+        SameSite::Strict => "Strict",
+        SameSite::Lax => "Lax",
+        SameSite::None => "None",
+    };
+    cookie.push_str("; SameSite=");
+    cookie.push_str(same_site);
+    cookie
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_configured_cookie_attributes() {
+        let cookie = session_cookie("token", "abc", true, 60, &Session::default(), "/");
+        assert_eq!(
+            cookie,
+            "token=abc; Max-Age=60; Path=/; Secure; HttpOnly; SameSite=Lax"
+        );
+
+        let relaxed = Session {
+            secure: false,
+            same_site: SameSite::Strict,
+            max_age: 3600,
+        };
+        let cookie = session_cookie("login", "true", false, relaxed.max_age, &relaxed, "/wiki/");
+        assert_eq!(
+            cookie,
+            "login=true; Max-Age=3600; Path=/wiki/; SameSite=Strict"
+        );
+    }
+}
@@ -114,6 +114,7 @@ export class ComponentRegister {
       old_title: CURRENT_TITLE,
       tags,
       metadata,
+      dir: typeof CURRENT_DIR === "string" ? CURRENT_DIR : "",
     };
     fetch("/edit", {
       method: "POST",
@@ -128,7 +129,8 @@ export class ComponentRegister {
             history.pushState(
               {},
               "",
-              encodeURIComponent(constructedBody.title),
+              res.headers.get("content-location") ||
+                encodeURIComponent(constructedBody.title),
             );
           }
           this.#machine.send("COMPLETE");
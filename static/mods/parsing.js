@@ -53,8 +53,10 @@ function parseWikiLinks(text) {
 }
 
 function parseHeadings(text) {
-  if (/^#\s?/.test(text)) {
-    return `<h2>${text.slice(1).trim()}</h2>`;
+  const heading = /^(#+)\s?/.exec(text);
+  if (heading) {
+    const level = Math.min(heading[1].length, 6);
+    return `<h${level}>${text.slice(heading[1].length).trim()}</h${level}>`;
   } else {
     return text;
   }
@@ -137,7 +139,7 @@ export function htmlToText(el) {
     audio.replaceWith(audio.src);
   }
   for (const header of shadow.querySelectorAll("h1,h2,h3,h4,h5,h6")) {
-    header.replaceWith(`# ${header.innerText}`);
+    header.replaceWith(`${"#".repeat(Number(header.tagName[1]))} ${header.innerText}`);
   }
   for (const linebreak of shadow.querySelectorAll("br")) {
     linebreak.replaceWith("\n");
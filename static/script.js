@@ -21,7 +21,7 @@
   function removeLogoutIfNotLoggedIn() {
     if (!document.cookie.login) {
       const footer = document.querySelector(".footer");
-      const logout = footer.querySelector('a[href="/logout"]');
+      const logout = footer.querySelector("form.logout");
       logout.remove();
     }
   }
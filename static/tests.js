@@ -17,6 +17,7 @@ function testParsing() {
     "# some topic",
     "testing #again",
     "# a title\nsome text",
+    "### a subtopic",
     "> testing a quote-that ends",
     "https://www.youtube.com/embed/cf72gMBrsI0",
   ];
@@ -27,9 +28,10 @@ function testParsing() {
     '<a href="mailto:somebody@example.com">somebody@example.com</a> wrote <a href="/article-link">this article</a> about cool things',
     '<a href="/actual-link">alias</a>',
     '<img src="http://127.0.0.1:6683/files/image-1660379904659.png">',
-    "<h2>some topic</h2>",
+    "<h1>some topic</h1>",
     "testing #again",
-    "<h2>a title</h2><br>some text",
+    "<h1>a title</h1><br>some text",
+    "<h3>a subtopic</h3>",
     "<blockquote>testing a quote-that ends</blockquote>",
     '<iframe title="Video player" frameborder="0" allow="autoplay;" allowfullscreen src="https://www.youtube.com/embed/cf72gMBrsI0"></iframe>',
   ];